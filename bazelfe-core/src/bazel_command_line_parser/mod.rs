@@ -58,11 +58,33 @@ impl BazelOption {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CustomAction {
     AutoTest,
+    UnusedDeps,
+    History,
+    Undo,
+    Replay,
+    Watch,
+    Daemon,
 }
 impl CustomAction {
     pub fn action_for_options(&self) -> BuiltInAction {
         match self {
             CustomAction::AutoTest => BuiltInAction::Test,
+            CustomAction::UnusedDeps | CustomAction::Watch => BuiltInAction::Build,
+            CustomAction::History
+            | CustomAction::Undo
+            | CustomAction::Replay
+            | CustomAction::Daemon => BuiltInAction::Info,
+        }
+    }
+
+    /// Actions handled entirely by bazelfe parse their own arguments, rather than taking bazel options.
+    pub fn owns_arguments(&self) -> bool {
+        match self {
+            CustomAction::AutoTest | CustomAction::UnusedDeps | CustomAction::Watch => false,
+            CustomAction::History
+            | CustomAction::Undo
+            | CustomAction::Replay
+            | CustomAction::Daemon => true,
         }
     }
 }
@@ -93,6 +115,12 @@ impl FromStr for Action {
 
         match input {
             "autotest" => Ok(Action::Custom(CustomAction::AutoTest)),
+            "unused-deps" => Ok(Action::Custom(CustomAction::UnusedDeps)),
+            "history" => Ok(Action::Custom(CustomAction::History)),
+            "undo" => Ok(Action::Custom(CustomAction::Undo)),
+            "replay" => Ok(Action::Custom(CustomAction::Replay)),
+            "watch" => Ok(Action::Custom(CustomAction::Watch)),
+            "daemon" => Ok(Action::Custom(CustomAction::Daemon)),
             _ => Err(()),
         }
     }
@@ -166,6 +194,12 @@ impl ParsedCommandLine {
         }
     }
 
+    /// Set the option, replacing any existing values for it.
+    pub fn set_action_option(&mut self, option: BazelOption) {
+        self.action_options.retain(|e| e.name() != option.name());
+        self.action_options.push(option);
+    }
+
     pub fn is_action_option_set(&self, opt: &str) -> bool {
         self.action_options.iter().any(|e| e.name() == opt)
     }
@@ -281,6 +315,19 @@ pub fn parse_bazel_command_line(
 
     let action: Option<Action> = command_line_iter.peek().and_then(|cmd| cmd.parse().ok());
 
+    if let Some(Action::Custom(custom_action)) = action.as_ref() {
+        if custom_action.owns_arguments() {
+            command_line_iter.next();
+            return Ok(ParsedCommandLine {
+                bazel_binary: bazel_path,
+                startup_options,
+                action: action.clone(),
+                action_options: Vec::default(),
+                remaining_args: command_line_iter.cloned().collect(),
+            });
+        }
+    }
+
     if let Some(action) = action.as_ref() {
         command_line_iter.next();
         let options: Vec<BazelOption> = options::ACTION_TO_OPTIONS
@@ -401,6 +448,29 @@ mod tests {
         assert_eq!(remaining, remaining_expected);
     }
 
+    #[test]
+    fn test_set_action_option() {
+        let mut command_line = parse_bazel_command_line(&[
+            "bazel".to_string(),
+            "build".to_string(),
+            "--build_event_binary_file=/tmp/theirs".to_string(),
+            "//...".to_string(),
+        ])
+        .expect("Should be able to parse the cmd line");
+
+        command_line.set_action_option(BazelOption::OptionWithArg(
+            String::from("build_event_binary_file"),
+            String::from("/tmp/ours"),
+        ));
+        assert_eq!(
+            command_line.action_options,
+            vec![BazelOption::OptionWithArg(
+                String::from("build_event_binary_file"),
+                String::from("/tmp/ours")
+            )]
+        );
+    }
+
     #[tokio::test]
     async fn parse_bazel_command_line_1() {
         let passthrough_command_line = vec![
@@ -625,4 +695,32 @@ mod tests {
             },
         }
     }
+
+    #[tokio::test]
+    async fn custom_action_owning_arguments() {
+        let passthrough_command_line = vec![
+            "bazel".to_string(),
+            "history".to_string(),
+            "--target".to_string(),
+            "//foo/...".to_string(),
+            "--json".to_string(),
+        ];
+
+        let parsed = parse_bazel_command_line(&passthrough_command_line)
+            .expect("Should be able to parse the cmd line");
+        assert_eq!(
+            parsed,
+            ParsedCommandLine {
+                bazel_binary: PathBuf::from("bazel"),
+                startup_options: Vec::default(),
+                action: Some(Action::Custom(CustomAction::History)),
+                action_options: Vec::default(),
+                remaining_args: vec![
+                    "--target".to_string(),
+                    "//foo/...".to_string(),
+                    "--json".to_string(),
+                ],
+            }
+        );
+    }
 }
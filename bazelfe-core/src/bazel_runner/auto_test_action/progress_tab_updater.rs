@@ -38,6 +38,8 @@ impl BazelEventHandler for ProgressTabUpdater {
             }
 
             crate::build_events::hydrated_stream::HydratedInfo::BazelAbort(_ba) => {}
+            crate::build_events::hydrated_stream::HydratedInfo::TargetConfigured(_) => {}
+            crate::build_events::hydrated_stream::HydratedInfo::BuildTiming(_) => {}
             crate::build_events::hydrated_stream::HydratedInfo::ActionFailed(af) => {
                 let _ = self
                     .action_event_tx
@@ -95,20 +97,16 @@ impl BazelEventHandler for ProgressTabUpdater {
                     .test_summary_event
                     .failed_files
                     .iter()
-                    .filter_map(|e| match e {
-                        bazelfe_protos::build_event_stream::file::File::Uri(u) => {
-                            if u.ends_with(".log") {
-                                Some(e)
-                            } else {
-                                None
-                            }
+                    .filter(|e| match e.file.as_ref() {
+                        Some(bazelfe_protos::build_event_stream::file::File::Uri(u)) => {
+                            u.ends_with(".log")
                         }
-                        bazelfe_protos::build_event_stream::file::File::Contents(_) => Some(e),
+                        Some(bazelfe_protos::build_event_stream::file::File::Contents(_)) => true,
+                        None => false,
                     })
                     .map(|f| bazelfe_protos::build_event_stream::File {
-                        file: Some(f.clone()),
-                        path_prefix: Vec::default(),
                         name: "stderr".to_string(),
+                        ..f.clone()
                     })
                     .collect();
                 let _ = self
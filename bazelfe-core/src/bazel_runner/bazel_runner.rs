@@ -58,6 +58,40 @@ impl From<ConfiguredBazelRunnerError> for BazelRunnerError {
         }
     }
 }
+pub(super) fn load_index_table(config: &Config) -> crate::index_table::IndexTable {
+    match &config.index_input_location {
+        Some(p) => {
+            if p.exists() {
+                let mut src_f = std::fs::File::open(p).unwrap();
+                crate::index_table::IndexTable::read(&mut src_f)
+            } else {
+                crate::index_table::IndexTable::new()
+            }
+        }
+        None => crate::index_table::IndexTable::new(),
+    }
+}
+
+/// The daemon can only hand back the events, recording or proxying them upstream needs our own server.
+/// As does asking for a specific bind address.
+#[cfg(feature = "bazelfe-daemon")]
+fn shared_build_event_transport<T: Send + Sync>(
+    config: &Config,
+    bes: &crate::build_events::build_event_server::BuildEventService<T>,
+    runner_daemon: &Option<crate::bazel_runner_daemon::daemon_service::RunnerDaemonClient>,
+) -> Option<super::BuildEventTransport> {
+    if !config.daemon_config.share_build_event_service
+        || config.bes_server_bind_address.is_some()
+        || bes.recorder.is_some()
+        || bes.upstream.is_some()
+    {
+        return None;
+    }
+    runner_daemon
+        .as_ref()
+        .map(|d| super::BuildEventTransport::Daemon(d.clone()))
+}
+
 pub struct BazelRunner {
     pub config: Config,
     pub bazel_command_line: ParsedCommandLine,
@@ -69,10 +103,31 @@ impl BazelRunner {
 
         bazel_runner::register_ctrlc_handler();
 
+        if let Some(exit_code) =
+            super::history_action::maybe_history_action(&self.config, &self.bazel_command_line)?
+        {
+            return Ok(exit_code);
+        }
+
+        if let Some(exit_code) =
+            super::undo_action::maybe_undo_action(&self.config, &self.bazel_command_line).await?
+        {
+            return Ok(exit_code);
+        }
+
+        #[cfg(feature = "bazelfe-daemon")]
+        if let Some(exit_code) =
+            super::daemon_action::maybe_daemon_action(&self.config, &self.bazel_command_line)
+                .await?
+        {
+            return Ok(exit_code);
+        }
+
         debug!("Based on custom action if present, overriding the daemon option");
         if let Some(action) = self.bazel_command_line.action.as_ref() {
             if let crate::bazel_command_line_parser::Action::Custom(
-                crate::bazel_command_line_parser::CustomAction::AutoTest,
+                crate::bazel_command_line_parser::CustomAction::AutoTest
+                | crate::bazel_command_line_parser::CustomAction::Watch,
             ) = action
             {
                 self.config.daemon_config.enabled = true;
@@ -81,30 +136,38 @@ impl BazelRunner {
 
         let config = Arc::new(self.config);
 
+        if let Some(exit_code) =
+            super::replay_action::maybe_replay_action(&config, &self.bazel_command_line).await?
+        {
+            return Ok(exit_code);
+        }
+        let invocation_id = super::story_journal::new_invocation_id();
+
         debug!("Loading index..");
-        let index_table = match &config.index_input_location {
-            Some(p) => {
-                if p.exists() {
-                    let mut src_f = std::fs::File::open(p).unwrap();
-                    crate::index_table::IndexTable::read(&mut src_f)
-                } else {
-                    crate::index_table::IndexTable::new()
-                }
-            }
-            None => crate::index_table::IndexTable::new(),
-        };
+        let index_table = load_index_table(&config);
 
         debug!("Index loading complete..");
 
+        let buildozer = buildozer_driver::from_config(&config)?;
+        let dry_run_buildozer = buildozer_driver::DryRunBuildozer::new(
+            buildozer_driver::RecordingBuildozer::new(
+                buildozer.clone(),
+                super::undo_action::edit_log_from_config(&config),
+                invocation_id.clone(),
+            ),
+            config.dry_run,
+        );
+        // User defined actions can have side effects of their own, so a dry run only proposes them too.
+        let dry_run_command_line_runner =
+            crate::hydrated_stream_processors::process_bazel_failures::DryRunCommandLineRunner::new(
+                crate::hydrated_stream_processors::process_bazel_failures::CommandLineRunnerImpl(),
+                config.dry_run,
+            );
+
         let process_build_failures = Arc::new(ProcessBazelFailures::new(
             index_table.clone(),
-            buildozer_driver::from_binary_path(
-                config
-                    .buildozer_path
-                    .as_ref()
-                    .expect("Unable to find a config for buildozer, error."),
-            ),
-            crate::hydrated_stream_processors::process_bazel_failures::CommandLineRunnerImpl(),
+            dry_run_buildozer.clone(),
+            dry_run_command_line_runner.clone(),
             Arc::clone(&config),
         )?);
         let processors: Vec<Arc<dyn BazelEventHandler>> = vec![
@@ -113,36 +176,36 @@ impl BazelRunner {
         ];
         let aes = EventStreamListener::new(processors);
 
-        let default_port = {
-            let rand_v: u16 = rng.gen();
-            40000 + (rand_v % 3000)
-        };
-
-        let addr: std::net::SocketAddr = config
-            .bes_server_bind_address
-            .map(|s| s.to_owned())
-            .unwrap_or_else(|| {
-                env::var("BIND_ADDRESS")
-                    .ok()
-                    .unwrap_or_else(|| format!("127.0.0.1:{}", default_port))
-                    .parse()
-                    .expect("can't parse BIND_ADDRESS variable")
-            });
-
-        debug!("Services listening on {}", addr);
+        // Recording captures the gRPC stream, tailing a file there's no stream to record.
+        if config.build_event_binary_file.is_some()
+            && config.build_event_recording_directory.is_some()
+        {
+            return Err(BazelRunnerError::UserErrorReport(super::UserReportError(
+                String::from("Build events read from a file can't also be recorded, bazel's build event binary file already holds them."),
+            )));
+        }
 
-        let (bes, sender_arc, _) =
+        let (mut bes, sender_arc, _) =
             crate::build_events::build_event_server::build_bazel_build_events_service();
-
-        let bes_port: u16 = addr.port();
-
-        let _service_fut = tokio::spawn(async move {
-            Server::builder()
-                .add_service(PublishBuildEventServer::new(bes))
-                .serve(addr)
-                .await
-                .unwrap();
-        });
+        bes.recorder = config
+            .build_event_recording_directory
+            .clone()
+            .map(crate::build_events::build_event_recorder::BuildEventRecorder::new);
+        let upstream = match &config.bes_upstream {
+            Some(bes_upstream) if config.build_event_binary_file.is_some() => {
+                warn!(
+                    "Ignoring BesUpstream {} as build events are read from a file, not proxied",
+                    bes_upstream.endpoint
+                );
+                None
+            }
+            Some(bes_upstream) => Some(
+                crate::build_events::upstream_forwarder::UpstreamForwarder::new(bes_upstream)
+                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?,
+            ),
+            None => None,
+        };
+        bes.upstream = upstream.clone();
 
         #[cfg(feature = "bazelfe-daemon")]
         let runner_daemon = if let Some(crate::bazel_command_line_parser::Action::BuiltIn(
@@ -162,8 +225,56 @@ impl BazelRunner {
             .await?
         };
 
-        let configured_bazel =
-            super::configured_bazel_runner::ConfiguredBazel::new(&sender_arc, aes, bes_port);
+        #[cfg(feature = "bazelfe-daemon")]
+        let shared_transport = shared_build_event_transport(&config, &bes, &runner_daemon);
+        #[cfg(not(feature = "bazelfe-daemon"))]
+        let shared_transport: Option<super::BuildEventTransport> = None;
+
+        let transport = match (&config.build_event_binary_file, shared_transport) {
+            (Some(path), _) => {
+                debug!("Tailing build events from {}", path.to_string_lossy());
+                super::BuildEventTransport::BinaryFile(path.clone())
+            }
+            (None, Some(shared_transport)) => {
+                debug!("Routing build events through the daemon's build event service");
+                shared_transport
+            }
+            (None, None) => {
+                let default_port = {
+                    let rand_v: u16 = rng.gen();
+                    40000 + (rand_v % 3000)
+                };
+
+                let addr: std::net::SocketAddr = config
+                    .bes_server_bind_address
+                    .map(|s| s.to_owned())
+                    .unwrap_or_else(|| {
+                        env::var("BIND_ADDRESS")
+                            .ok()
+                            .unwrap_or_else(|| format!("127.0.0.1:{}", default_port))
+                            .parse()
+                            .expect("can't parse BIND_ADDRESS variable")
+                    });
+
+                debug!("Services listening on {}", addr);
+
+                let _service_fut = tokio::spawn(async move {
+                    Server::builder()
+                        .add_service(PublishBuildEventServer::new(bes))
+                        .serve(addr)
+                        .await
+                        .unwrap();
+                });
+                super::BuildEventTransport::Grpc(addr.port())
+            }
+        };
+
+        let configured_bazel = super::configured_bazel_runner::ConfiguredBazel::new(
+            &sender_arc,
+            aes,
+            transport,
+            process_build_failures.file_fetcher().clone(),
+        );
 
         let configured_bazel_runner = ConfiguredBazelRunner::new(
             Arc::clone(&config),
@@ -173,10 +284,15 @@ impl BazelRunner {
             index_table.clone(),
             self.bazel_command_line.clone(),
             process_build_failures,
+            invocation_id,
         );
 
         let final_exit_code_res = configured_bazel_runner.run().await;
 
+        if let (Some(upstream), Some(bes_upstream)) = (&upstream, &config.bes_upstream) {
+            upstream.flush(bes_upstream.flush_timeout).await;
+        }
+
         if index_table.is_mutated() {
             debug!("Writing out index file...");
 
@@ -195,6 +311,17 @@ impl BazelRunner {
             }
             debug!("Index write complete.");
         }
-        Ok(final_exit_code_res?)
+        let final_exit_code = final_exit_code_res?;
+        if dry_run_buildozer.enabled() {
+            return Ok(super::suggestion_report::finish_dry_run(
+                &config,
+                &buildozer,
+                &dry_run_buildozer.proposed_edits().await,
+                &dry_run_command_line_runner.proposed_command_lines().await,
+                final_exit_code,
+            )
+            .await?);
+        }
+        Ok(final_exit_code)
     }
 }
@@ -16,7 +16,7 @@ struct Opt {
     index_input_location: Option<PathBuf>,
 
     #[clap(long, env = "BUILDOZER_PATH", parse(from_os_str))]
-    buildozer_path: PathBuf,
+    buildozer_path: Option<PathBuf>,
 
     #[clap(required = true, min_values = 1)]
     passthrough_args: Vec<String>,
@@ -26,6 +26,33 @@ struct Opt {
 
     #[clap(long)]
     config: Option<String>,
+
+    /// Propose fixes without editing BUILD files, exits non-zero if any are found
+    #[clap(long, env = "BAZELFE_DRY_RUN")]
+    dry_run: bool,
+
+    #[clap(long, env = "BAZELFE_DRY_RUN_OUTPUT_DIRECTORY", parse(from_os_str))]
+    dry_run_output_directory: Option<PathBuf>,
+
+    /// Write a machine readable report of the run to this path
+    #[clap(long, env = "BAZELFE_REPORT_PATH", parse(from_os_str))]
+    report_path: Option<PathBuf>,
+
+    /// Format of the run report, json or json_lines
+    #[clap(long, env = "BAZELFE_REPORT_FORMAT")]
+    report_format: Option<bazelfe_core::config::ReportFormat>,
+
+    /// Record the build event streams from bazel into this directory, for later replay
+    #[clap(long, env = "BAZELFE_RECORD_BUILD_EVENTS", parse(from_os_str))]
+    record_build_events: Option<PathBuf>,
+
+    /// Read build events from this file bazel writes, rather than serving BES over gRPC
+    #[clap(long, env = "BAZELFE_BUILD_EVENT_BINARY_FILE", parse(from_os_str))]
+    build_event_binary_file: Option<PathBuf>,
+
+    /// Forward build events on to this Build Event Service, e.g. grpc://bes.example.com:1985
+    #[clap(long, env = "BAZELFE_BES_UPSTREAM")]
+    bes_upstream: Option<String>,
 }
 
 async fn load_config_file(opt: &Opt) -> Result<Config, Box<dyn std::error::Error>> {
@@ -151,7 +178,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut config = load_config_file(&opt).await?;
 
-    config.buildozer_path = Some(opt.buildozer_path);
+    if opt.buildozer_path.is_some() {
+        config.buildozer_path = opt.buildozer_path;
+    }
 
     if opt.index_input_location.is_some() {
         config.index_input_location = opt.index_input_location;
@@ -166,6 +195,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.disable_action_stories_on_success = opt.disable_action_stories_on_success;
     }
 
+    if opt.dry_run {
+        config.dry_run = opt.dry_run;
+    }
+
+    if opt.dry_run_output_directory.is_some() {
+        config.dry_run_output_directory = opt.dry_run_output_directory;
+    }
+
+    if opt.report_path.is_some() {
+        config.report_path = opt.report_path;
+    }
+
+    if let Some(report_format) = opt.report_format {
+        config.report_format = report_format;
+    }
+
+    if opt.record_build_events.is_some() {
+        config.build_event_recording_directory = opt.record_build_events;
+    }
+
+    if opt.build_event_binary_file.is_some() {
+        config.build_event_binary_file = opt.build_event_binary_file;
+    }
+
+    if let Some(endpoint) = opt.bes_upstream {
+        // Keep any headers or tls settings from the config file.
+        match config.bes_upstream.as_mut() {
+            Some(bes_upstream) => bes_upstream.endpoint = endpoint,
+            None => {
+                config.bes_upstream =
+                    Some(bazelfe_core::config::BesUpstream::with_endpoint(endpoint))
+            }
+        }
+    }
+
     let bazel_runner = bazel_runner::bazel_runner::BazelRunner {
         config,
         bazel_command_line: parsed_command_line,
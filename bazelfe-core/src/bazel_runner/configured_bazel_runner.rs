@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use crate::build_events::build_event_file_source::tail_build_event_file;
+use crate::build_events::file_fetcher::FileFetcher;
 use crate::build_events::hydrated_stream::HydratedInfo;
 use crate::buildozer_driver;
 use crate::{
@@ -10,8 +12,13 @@ use crate::{
 use crate::{
     bazel_runner,
     hydrated_stream_processors::{
+        build_timing::{BuildTimingSummary, BuildTimingTracker},
         event_stream_listener::EventStreamListener,
         process_bazel_failures::{ProcessBazelFailures, TargetStory, TargetStoryAction},
+        test_results::{
+            history::{flip_flops, TestHistory, TestHistoryEntry},
+            TestResultTracker, TestResultsSummary,
+        },
     },
 };
 use crate::{build_events::build_event_server::bazel_event, config::Config};
@@ -21,12 +28,15 @@ use thiserror::Error;
 use tokio::sync::{Mutex, RwLock};
 
 use super::processor_activity::*;
+use super::retry_limits::{retry_limit_reached, OscillationDetector, RetryStopReason};
 
 pub struct ConfiguredBazel {
     sender_arc:
         Arc<Mutex<Option<async_channel::Sender<BuildEventAction<bazel_event::BazelBuildEvent>>>>>,
     pub aes: EventStreamListener,
-    bes_port: u16,
+    transport: bazel_runner::BuildEventTransport,
+    pub build_timing: Arc<BuildTimingTracker>,
+    pub test_results: Arc<TestResultTracker>,
 }
 
 impl ConfiguredBazel {
@@ -34,17 +44,24 @@ impl ConfiguredBazel {
         sender_arc: &Arc<
             Mutex<Option<async_channel::Sender<BuildEventAction<bazel_event::BazelBuildEvent>>>>,
         >,
-        aes: EventStreamListener,
-        bes_port: u16,
+        mut aes: EventStreamListener,
+        transport: bazel_runner::BuildEventTransport,
+        file_fetcher: FileFetcher,
     ) -> Self {
+        let build_timing = Arc::new(BuildTimingTracker::default());
+        aes.add_event_handler(build_timing.clone());
+        let test_results = Arc::new(TestResultTracker::new(file_fetcher));
+        aes.add_event_handler(test_results.clone());
         Self {
             sender_arc: sender_arc.clone(),
             aes,
-            bes_port,
+            transport,
+            build_timing,
+            test_results,
         }
     }
 
-    async fn spawn_bazel_attempt(
+    pub(super) async fn spawn_bazel_attempt(
         &self,
         bazel_command_line: &ParsedCommandLine,
         pipe_output: bool,
@@ -52,7 +69,7 @@ impl ConfiguredBazel {
         spawn_bazel_attempt(
             &self.sender_arc,
             &self.aes,
-            self.bes_port,
+            &self.transport,
             bazel_command_line,
             pipe_output,
         )
@@ -60,19 +77,104 @@ impl ConfiguredBazel {
     }
 }
 
+/// Drain the responses from the processors for one build, totalling up what they did.
+pub(super) async fn collect_processor_activity(
+    target_extracted_stream: async_channel::Receiver<
+        crate::hydrated_stream_processors::BuildEventResponse,
+    >,
+) -> ProcessorActivity {
+    let mut jvm_segments_indexed = 0;
+    let mut actions_taken: u32 = 0;
+    let mut target_story_actions = HashMap::new();
+
+    while let Ok(action) = target_extracted_stream.recv().await {
+        match action {
+            crate::hydrated_stream_processors::BuildEventResponse::ProcessedBuildFailures(pbf) => {
+                let current_updates: u32 = pbf
+                    .target_story_entries
+                    .iter()
+                    .map(|e| match e.action {
+                        TargetStoryAction::Success => 0,
+                        _ => 1,
+                    })
+                    .sum();
+                actions_taken += current_updates;
+                for story_entry in pbf.target_story_entries {
+                    match target_story_actions.get_mut(&story_entry.target) {
+                        None => {
+                            target_story_actions
+                                .insert(story_entry.target.clone(), vec![story_entry]);
+                        }
+                        Some(existing) => existing.push(story_entry),
+                    };
+                }
+            }
+            crate::hydrated_stream_processors::BuildEventResponse::IndexedResults(ir) => {
+                jvm_segments_indexed += ir.jvm_segments_indexed
+            }
+        }
+    }
+
+    ProcessorActivity {
+        jvm_segments_indexed,
+        actions_taken,
+        target_story_actions,
+    }
+}
+
 async fn spawn_bazel_attempt(
     sender_arc: &Arc<
         Mutex<Option<async_channel::Sender<BuildEventAction<bazel_event::BazelBuildEvent>>>>,
     >,
     aes: &EventStreamListener,
-    bes_port: u16,
+    transport: &bazel_runner::BuildEventTransport,
     bazel_command_line: &ParsedCommandLine,
     pipe_output: bool,
 ) -> Result<(ProcessorActivity, bazel_runner::ExecuteResult), Box<dyn std::error::Error>> {
     let (tx, rx) = async_channel::unbounded();
-    let _ = {
-        let mut locked = sender_arc.lock().await;
-        *locked = Some(tx);
+    #[allow(unused_mut)]
+    let mut bazel_command_line = bazel_command_line.clone();
+    let event_source = match transport {
+        bazel_runner::BuildEventTransport::Grpc(_) => {
+            *sender_arc.lock().await = Some(tx);
+            None
+        }
+        bazel_runner::BuildEventTransport::BinaryFile(path) => {
+            // Don't pick up events left over from a previous attempt.
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            let (finished_tx, finished_rx) = tokio::sync::oneshot::channel();
+            let tail_task = tokio::spawn(tail_build_event_file(path.clone(), tx, finished_rx));
+            Some((finished_tx, tail_task))
+        }
+        #[cfg(feature = "bazelfe-daemon")]
+        bazel_runner::BuildEventTransport::Daemon(runner_daemon) => {
+            use crate::bazel_command_line_parser::BazelOption;
+            use crate::bazel_runner_daemon::build_event_router;
+
+            // Every attempt gets its own invocation id, so the daemon can tell them apart.
+            let invocation_id = build_event_router::new_bazel_invocation_id();
+            let port = runner_daemon
+                .register_build_events(tarpc::context::current(), invocation_id.clone())
+                .await?;
+            bazel_command_line.add_action_option_if_unset(BazelOption::OptionWithArg(
+                String::from("bes_backend"),
+                format!("grpc://127.0.0.1:{}", port),
+            ));
+            bazel_command_line.set_action_option(BazelOption::OptionWithArg(
+                String::from("invocation_id"),
+                invocation_id.clone(),
+            ));
+            let (finished_tx, finished_rx) = tokio::sync::oneshot::channel();
+            let forward_task = tokio::spawn(build_event_router::forward_routed_build_events(
+                runner_daemon.clone(),
+                invocation_id,
+                tx,
+                finished_rx,
+            ));
+            Some((finished_tx, forward_task))
+        }
     };
     let error_stream = HydratedInfo::build_transformer(rx);
 
@@ -82,55 +184,21 @@ async fn spawn_bazel_attempt(
     let r_data = Arc::clone(&results_data);
     let recv_task = tokio::spawn(async move {
         let mut guard = r_data.write().await;
-
-        let mut jvm_segments_indexed = 0;
-        let mut actions_taken: u32 = 0;
-        let mut target_story_actions = HashMap::new();
-
-        while let Ok(action) = target_extracted_stream.recv().await {
-            match action {
-                crate::hydrated_stream_processors::BuildEventResponse::ProcessedBuildFailures(
-                    pbf,
-                ) => {
-                    let current_updates: u32 = pbf
-                        .target_story_entries
-                        .iter()
-                        .map(|e| match e.action {
-                            TargetStoryAction::Success => 0,
-                            _ => 1,
-                        })
-                        .sum();
-                    actions_taken += current_updates;
-                    for story_entry in pbf.target_story_entries {
-                        match target_story_actions.get_mut(&story_entry.target) {
-                            None => {
-                                target_story_actions
-                                    .insert(story_entry.target.clone(), vec![story_entry]);
-                            }
-                            Some(existing) => existing.push(story_entry),
-                        };
-                    }
-                }
-                crate::hydrated_stream_processors::BuildEventResponse::IndexedResults(ir) => {
-                    jvm_segments_indexed += ir.jvm_segments_indexed
-                }
-            }
-        }
-
-        *guard = Some(ProcessorActivity {
-            jvm_segments_indexed,
-            actions_taken,
-            target_story_actions,
-        });
+        *guard = Some(collect_processor_activity(target_extracted_stream).await);
     });
 
-    let res = bazel_runner::execute_bazel_output_control(bazel_command_line, bes_port, pipe_output)
-        .await?;
+    let res =
+        bazel_runner::execute_bazel_with_transport(&bazel_command_line, transport, pipe_output)
+            .await?;
 
     let _ = {
         let mut locked = sender_arc.lock().await;
         locked.take();
     };
+    if let Some((finished_tx, source_task)) = event_source {
+        let _ = finished_tx.send(());
+        source_task.await.unwrap();
+    }
 
     recv_task.await.unwrap();
     let r = results_data.write().await.take().unwrap();
@@ -141,13 +209,14 @@ pub struct ConfiguredBazelRunner<
     T: buildozer_driver::Buildozer,
     U: crate::hydrated_stream_processors::process_bazel_failures::CommandLineRunner,
 > {
-    config: Arc<Config>,
+    pub(super) config: Arc<Config>,
     pub configured_bazel: ConfiguredBazel,
     #[cfg(feature = "bazelfe-daemon")]
     pub runner_daemon: Option<crate::bazel_runner_daemon::daemon_service::RunnerDaemonClient>,
     _index_table: crate::index_table::IndexTable,
     pub bazel_command_line: ParsedCommandLine,
-    process_build_failures: Arc<ProcessBazelFailures<T, U>>,
+    pub(super) process_build_failures: Arc<ProcessBazelFailures<T, U>>,
+    invocation_id: String,
 }
 
 #[derive(Error, Debug)]
@@ -179,6 +248,28 @@ pub struct RunCompleteState {
     pub total_actions_taken: u32,
     pub final_exit_code: i32,
     pub running_total: ProcessorActivity,
+    /// Every story recorded along with the attempt it happened in, unfiltered, for the journal.
+    pub story_log: Vec<(u16, TargetStory)>,
+    pub stop_reason: RetryStopReason,
+    /// Where the last bazel attempt spent its time.
+    pub build_timing: Option<BuildTimingSummary>,
+    /// Every test run over all of the attempts, None if no tests ran.
+    pub test_results: Option<TestResultsSummary>,
+}
+
+impl RunCompleteState {
+    /// Every target completing emits a success story, this only keeps the stories for targets we acted on.
+    pub fn acted_on_story_log(&self) -> impl Iterator<Item = &(u16, TargetStory)> {
+        let acted_on: std::collections::HashSet<&String> = self
+            .story_log
+            .iter()
+            .filter(|(_, story)| story.action != TargetStoryAction::Success)
+            .map(|(_, story)| &story.target)
+            .collect();
+        self.story_log
+            .iter()
+            .filter(move |(_, story)| acted_on.contains(&story.target))
+    }
 }
 impl<
         T: buildozer_driver::Buildozer,
@@ -194,6 +285,7 @@ impl<
         index_table: crate::index_table::IndexTable,
         bazel_command_line: ParsedCommandLine,
         process_build_failures: Arc<ProcessBazelFailures<T, U>>,
+        invocation_id: String,
     ) -> Self {
         Self {
             config,
@@ -203,6 +295,7 @@ impl<
             _index_table: index_table,
             bazel_command_line,
             process_build_failures,
+            invocation_id,
         }
     }
 
@@ -213,10 +306,14 @@ impl<
         let mut attempts: u16 = 0;
 
         let mut running_total = ProcessorActivity::default();
-        let mut final_exit_code = 0;
+        let mut final_exit_code;
         let disable_action_stories_on_success = self.config.disable_action_stories_on_success;
         let mut total_actions_taken: u32 = 0;
-        while attempts < 15 {
+        let mut story_log = Vec::default();
+        let retry_policy = &self.config.retry_policy;
+        let started_at = std::time::Instant::now();
+        let mut oscillation_detector = OscillationDetector::default();
+        let stop_reason = loop {
             attempts += 1;
             self.process_build_failures.advance_epoch().await;
 
@@ -226,20 +323,156 @@ impl<
                 .await?;
             let actions_taken = processor_activity.actions_taken;
             total_actions_taken += actions_taken;
+            oscillation_detector.record(processor_activity.target_story_actions.values().flatten());
+            story_log.extend(
+                processor_activity
+                    .target_story_actions
+                    .values()
+                    .flatten()
+                    .map(|story| (attempts, story.clone())),
+            );
             running_total.merge(processor_activity, disable_action_stories_on_success);
             final_exit_code = bazel_result.exit_code;
-            if bazel_result.exit_code == 0 || actions_taken == 0 {
-                break;
+            if bazel_result.exit_code == 0 {
+                break RetryStopReason::Succeeded;
             }
-        }
+            if actions_taken == 0 {
+                break RetryStopReason::NoActionsTaken;
+            }
+            // In a dry run nothing was changed, so another attempt would fail the same way.
+            if self.config.dry_run {
+                break RetryStopReason::DryRun;
+            }
+            if let Some(reason) = retry_limit_reached(
+                retry_policy,
+                attempts,
+                started_at.elapsed(),
+                actions_taken,
+                &oscillation_detector,
+            ) {
+                break reason;
+            }
+        };
         Ok(RunCompleteState {
             attempts,
             total_actions_taken,
             final_exit_code,
             running_total,
+            story_log,
+            stop_reason,
+            build_timing: self.configured_bazel.build_timing.latest_summary().await,
+            test_results: self.configured_bazel.test_results.summary().await,
         })
     }
 
+    fn command_line_string(&self) -> String {
+        std::iter::once(
+            self.bazel_command_line
+                .bazel_binary
+                .to_string_lossy()
+                .to_string(),
+        )
+        .chain(
+            self.bazel_command_line
+                .all_args_normalized()
+                .unwrap_or_default(),
+        )
+        .collect::<Vec<String>>()
+        .join(" ")
+    }
+
+    pub(super) fn record_story_journal(&self, res_data: &RunCompleteState) {
+        // Nothing was actually edited in a dry run, the suggestions are reported separately.
+        if self.config.dry_run {
+            return;
+        }
+        let command_line = self.command_line_string();
+
+        let entries: Vec<super::story_journal::JournalEntry> = res_data
+            .acted_on_story_log()
+            .map(|(attempt, story)| {
+                super::story_journal::JournalEntry::from_story(
+                    &self.invocation_id,
+                    &command_line,
+                    *attempt,
+                    res_data.final_exit_code,
+                    story,
+                )
+            })
+            .collect();
+
+        let journal =
+            super::story_journal::StoryJournal::from_daemon_config(&self.config.daemon_config);
+        if let Err(e) = journal.append(&entries) {
+            warn!(
+                "Unable to record target stories to {}: {}",
+                journal.path().to_string_lossy(),
+                e
+            );
+        }
+    }
+
+    /// Record how every test finished, then call out those which have been flip-flopping.
+    pub(super) async fn record_test_history(&self, res_data: &mut RunCompleteState) {
+        let outcomes = self.configured_bazel.test_results.outcomes().await;
+        if outcomes.is_empty() {
+            return;
+        }
+        let timestamp_ms = super::story_journal::instant_to_unix_ms(std::time::Instant::now());
+        let entries: Vec<TestHistoryEntry> = outcomes
+            .into_iter()
+            .map(|(label, outcome)| TestHistoryEntry {
+                timestamp_ms,
+                invocation_id: self.invocation_id.clone(),
+                label,
+                outcome,
+            })
+            .collect();
+
+        let history = TestHistory::from_daemon_config(&self.config.daemon_config);
+        if let Err(e) = history.append(&entries) {
+            warn!(
+                "Unable to record test history to {}: {}",
+                history.path().to_string_lossy(),
+                e
+            );
+        }
+        match history.read_recent() {
+            Ok(all_entries) => {
+                res_data.test_results = self
+                    .configured_bazel
+                    .test_results
+                    .summary_with_flip_flops(&flip_flops(&all_entries))
+                    .await;
+            }
+            Err(e) => warn!(
+                "Unable to read test history from {}: {}",
+                history.path().to_string_lossy(),
+                e
+            ),
+        }
+    }
+
+    fn write_run_report(&self, res_data: &RunCompleteState) {
+        let path = match &self.config.report_path {
+            Some(path) => path,
+            None => return,
+        };
+        let report = super::run_report::RunReport::new(
+            &self.invocation_id,
+            &self.command_line_string(),
+            self.config.dry_run,
+            res_data,
+        );
+        if let Err(e) = report.write(path, self.config.report_format) {
+            warn!(
+                "Unable to write run report to {}: {}",
+                path.to_string_lossy(),
+                e
+            );
+        }
+    }
+
     pub async fn run(mut self) -> Result<i32, ConfiguredBazelRunnerError> {
         super::command_line_rewriter_action::rewrite_command_line(
             &mut self.bazel_command_line,
@@ -253,7 +486,18 @@ impl<
         if super::auto_test_action::maybe_auto_test_mode(&mut self).await? {
             return Ok(0);
         };
-        let res_data = self.run_command_line(true).await?;
+        #[cfg(feature = "bazelfe-daemon")]
+        if super::watch_action::maybe_watch_mode(&mut self).await? {
+            return Ok(0);
+        };
+        let mut res_data =
+            match super::unused_deps_action::maybe_unused_deps_mode(&mut self).await? {
+                Some(res_data) => res_data,
+                None => self.run_command_line(true).await?,
+            };
+        self.record_story_journal(&res_data);
+        self.record_test_history(&mut res_data).await;
+        self.write_run_report(&res_data);
         let disable_action_stories_on_success = self.config.disable_action_stories_on_success;
 
         // we should be very quiet if the build is successful/we added nothing.
@@ -300,7 +544,7 @@ impl<
                                 if execution_result.exit_success {
                                     eprintln!("\tRan user action: {}\n\t\tReason: {}\n\t\tSuccess: true\n\t\tCommand line: {}", user_action_name, why, command_line);
                                 } else {
-                                    eprintln!("\tRan user action: {}\n\t\tReason: {}\n\t\tSuccess: false\n\t\tCommand line: {}\nstdout:\n{}\n\nstderr:\n{}\n\n", user_action_name, why, command_line, execution_result.stdout, execution_result.stderr);
+                                    eprintln!("\tRan user action: {}\n\t\tReason: {}\n\t\tSuccess: false, {}\n\t\tCommand line: {}\nstdout:\n{}\n\nstderr:\n{}\n\n", user_action_name, why, execution_result.exit_code_description(), command_line, execution_result.stdout, execution_result.stderr);
                                 }
                             }
                         }
@@ -309,12 +553,35 @@ impl<
             }
             eprintln!("Bazel exit code: {}", res_data.final_exit_code);
             eprintln!("Bazel build attempts: {}", res_data.attempts);
+            if res_data.final_exit_code != 0 {
+                eprintln!("Stopped retrying as {}", res_data.stop_reason);
+            }
             eprintln!("Actions taken: {}", res_data.running_total.actions_taken);
             eprintln!(
                 "Jvm fragments (classes/packages) added to index: {}",
                 res_data.running_total.jvm_segments_indexed
             );
+            if let Some(test_results) = res_data.test_results.as_ref() {
+                eprint!("{}", test_results);
+            }
+            if let Some(build_timing) = res_data.build_timing.as_ref() {
+                eprint!("{}", build_timing);
+            }
             eprintln!("------------------------------------------------------------\n");
+        } else {
+            // Failing and flip-flopping tests are worth calling out even when we had nothing to do.
+            if let Some(test_results) = res_data
+                .test_results
+                .as_ref()
+                .filter(|t| t.needs_attention())
+            {
+                eprint!("{}", test_results);
+            }
+            if self.config.print_build_timing {
+                if let Some(build_timing) = res_data.build_timing.as_ref() {
+                    eprint!("{}", build_timing);
+                }
+            }
         }
 
         Ok(res_data.final_exit_code)
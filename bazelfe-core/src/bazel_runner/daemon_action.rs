@@ -0,0 +1,358 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+
+use crate::{
+    bazel_command_line_parser::{Action, CustomAction, ParsedCommandLine},
+    bazel_runner_daemon::daemon_service::{
+        ChangedFile, DaemonStatus, FileStatus, RdepsAtDistance, RunnerDaemonClient, Targets,
+        TargetsFromFilesResponse, OWNING_TARGETS_DISTANCE,
+    },
+    config::Config,
+};
+
+#[derive(Parser, Debug)]
+#[clap(name = "daemon")]
+struct DaemonOpt {
+    /// Print the result as json rather than text
+    #[clap(long, global = true)]
+    json: bool,
+
+    #[clap(subcommand)]
+    command: DaemonCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum DaemonCommand {
+    /// Uptime, version and what the daemon is tracking
+    Status,
+    /// Which targets own a source file
+    TargetsFor {
+        #[clap(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Reverse dependencies of a target, grouped by how many hops away they are
+    Rdeps {
+        label: String,
+
+        #[clap(long, default_value = "3")]
+        max_distance: u32,
+    },
+    /// Files the daemon has seen change, most recent first, optionally only those newer than e.g. 10m
+    ChangedSince {
+        #[clap(parse(try_from_str = humantime::parse_duration))]
+        since: Option<Duration>,
+    },
+    /// Stop the daemon, letting it save its state first
+    Stop,
+    /// Stop the daemon and start a fresh one
+    Restart,
+    /// Show the end of the daemon's stdout and stderr logs
+    Logs {
+        #[clap(long, default_value = "50")]
+        lines: usize,
+    },
+}
+
+fn target_line(target: &Targets) -> String {
+    if target.is_test() {
+        format!("{} (test)", target.target_label())
+    } else {
+        target.target_label().clone()
+    }
+}
+
+fn format_status(status: &DaemonStatus) -> String {
+    format!(
+        "Daemon pid {} for {}\n\tVersion: {}\n\tUptime: {}\n\tTracking {} targets, {} source files\n\tPending queries: {}\n\tDirty files: {}\n\tBuild event service port: {}",
+        status.pid,
+        status.workspace.to_string_lossy(),
+        status.executable_id,
+        humantime::format_duration(Duration::from_secs(status.uptime_secs)),
+        status.tracked_targets,
+        status.tracked_source_files,
+        status.pending_hydrations,
+        status.dirty_files,
+        status.build_event_service_port
+    )
+}
+
+fn format_rdeps(label: &str, rdeps: &[RdepsAtDistance]) -> String {
+    let mut lines = vec![label.to_string()];
+    for at_distance in rdeps.iter() {
+        lines.push(format!("Distance {}:", at_distance.distance));
+        lines.extend(
+            at_distance
+                .targets
+                .iter()
+                .map(|t| format!("\t{}", target_line(t))),
+        );
+    }
+    lines.join("\n")
+}
+
+fn format_changed_file(changed: &ChangedFile) -> String {
+    // Sub second precision is just noise here.
+    let age = Duration::from_secs(changed.age_ms / 1000);
+    format!(
+        "{} ago\t{}",
+        humantime::format_duration(age),
+        changed.path.to_string_lossy()
+    )
+}
+
+/// The daemon tracks files relative to the workspace root.
+fn workspace_relative(file: PathBuf) -> PathBuf {
+    let current_dir = std::env::current_dir().expect("Should be able to get the current dir");
+    file.canonicalize()
+        .ok()
+        .and_then(|f| f.strip_prefix(&current_dir).ok().map(|f| f.to_path_buf()))
+        .unwrap_or(file)
+}
+
+async fn owning_targets(
+    daemon_cli: &RunnerDaemonClient,
+    file: PathBuf,
+) -> Result<Vec<Targets>, Box<dyn std::error::Error>> {
+    let mut was_in_query = false;
+    loop {
+        match daemon_cli
+            .targets_from_files(
+                tarpc::context::current(),
+                vec![FileStatus(file.clone(), 0)],
+                OWNING_TARGETS_DISTANCE,
+                was_in_query,
+            )
+            .await?
+        {
+            TargetsFromFilesResponse::InQuery => {
+                was_in_query = true;
+                // Give the daemon's query a chance to finish rather than asking again straight away.
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            TargetsFromFilesResponse::Targets(targets) => return Ok(targets),
+        }
+    }
+}
+
+fn last_lines(content: &str, lines: usize) -> Vec<&str> {
+    let all_lines: Vec<&str> = content.lines().collect();
+    all_lines[all_lines.len().saturating_sub(lines)..].to_vec()
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Handles `bazel-runner <bazel> daemon ...`, managing the daemon for this workspace and showing what it knows.
+/// Useful to find out why autotest did or didn't pick up a target.
+pub async fn maybe_daemon_action(
+    config: &Config,
+    bazel_command_line: &ParsedCommandLine,
+) -> Result<Option<i32>, Box<dyn std::error::Error>> {
+    if bazel_command_line.action != Some(Action::Custom(CustomAction::Daemon)) {
+        return Ok(None);
+    }
+
+    let opt = DaemonOpt::try_parse_from(
+        std::iter::once(String::from("daemon")).chain(bazel_command_line.remaining_args.clone()),
+    )?;
+
+    match opt.command {
+        DaemonCommand::Stop => {
+            match crate::bazel_runner_daemon::daemon_manager::stop_server(&config.daemon_config)
+                .await?
+            {
+                Some(pid) => eprintln!("Stopped daemon pid {}", pid),
+                None => eprintln!("No daemon is running for this workspace."),
+            }
+            return Ok(Some(0));
+        }
+        DaemonCommand::Restart => {
+            crate::bazel_runner_daemon::daemon_manager::stop_server(&config.daemon_config).await?;
+            let mut daemon_config = config.daemon_config.clone();
+            daemon_config.enabled = true;
+            let mut daemon_cli = crate::bazel_runner_daemon::daemon_manager::connect_to_server(
+                &daemon_config,
+                &bazel_command_line.bazel_binary,
+            )
+            .await?;
+            // A fresh daemon can take a moment before it's listening.
+            for _ in 0..50 {
+                if daemon_cli.is_some() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                daemon_cli = crate::bazel_runner_daemon::daemon_manager::connect_to_running_server(
+                    &daemon_config,
+                )
+                .await?;
+            }
+            return match daemon_cli {
+                Some(daemon_cli) => {
+                    let status = daemon_cli.status(tarpc::context::current()).await?;
+                    eprintln!("Started daemon pid {}", status.pid);
+                    Ok(Some(0))
+                }
+                None => {
+                    eprintln!("Failed to start a new daemon.");
+                    Ok(Some(1))
+                }
+            };
+        }
+        DaemonCommand::Logs { lines } => {
+            for log_path in
+                crate::bazel_runner_daemon::daemon_manager::log_paths(&config.daemon_config)
+            {
+                println!("==> {} <==", log_path.to_string_lossy());
+                match std::fs::read_to_string(&log_path) {
+                    Ok(content) => {
+                        for line in last_lines(&content, lines) {
+                            println!("{}", line);
+                        }
+                    }
+                    Err(e) => println!("Unable to read log: {}", e),
+                }
+            }
+            return Ok(Some(0));
+        }
+        _ => (),
+    }
+
+    let daemon_cli = match crate::bazel_runner_daemon::daemon_manager::connect_to_running_server(
+        &config.daemon_config,
+    )
+    .await?
+    {
+        Some(daemon_cli) => daemon_cli,
+        None => {
+            eprintln!("No daemon is running for this workspace.");
+            return Ok(Some(1));
+        }
+    };
+
+    match opt.command {
+        DaemonCommand::Status => {
+            let status = daemon_cli.status(tarpc::context::current()).await?;
+            if opt.json {
+                print_json(&status)?;
+            } else {
+                println!("{}", format_status(&status));
+            }
+        }
+        DaemonCommand::TargetsFor { file } => {
+            let file = workspace_relative(file);
+            let targets = owning_targets(&daemon_cli, file.clone()).await?;
+            if opt.json {
+                print_json(&targets)?;
+            } else if targets.is_empty() {
+                // The daemon only learns about a file's owners when it sees the file change.
+                eprintln!(
+                    "The daemon doesn't know of any targets owning {}, it may not have seen it change yet.",
+                    file.to_string_lossy()
+                );
+            } else {
+                for target in targets.iter() {
+                    println!("{}", target_line(target));
+                }
+            }
+        }
+        DaemonCommand::Rdeps {
+            label,
+            max_distance,
+        } => {
+            let rdeps = daemon_cli
+                .rdeps(tarpc::context::current(), label.clone(), max_distance)
+                .await?;
+            if opt.json {
+                print_json(&rdeps)?;
+            } else if rdeps.is_empty() {
+                eprintln!(
+                    "The daemon doesn't know of any reverse dependencies of {}",
+                    label
+                );
+            } else {
+                println!("{}", format_rdeps(&label, &rdeps));
+            }
+        }
+        DaemonCommand::ChangedSince { since } => {
+            let changed = daemon_cli
+                .changed_files(
+                    tarpc::context::current(),
+                    since.map(|s| s.as_millis() as u64),
+                )
+                .await?;
+            if opt.json {
+                print_json(&changed)?;
+            } else {
+                for changed_file in changed.iter() {
+                    println!("{}", format_changed_file(changed_file));
+                }
+            }
+        }
+        DaemonCommand::Stop | DaemonCommand::Restart | DaemonCommand::Logs { .. } => (),
+    }
+    Ok(Some(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel_runner_daemon::daemon_service::{BuildTarget, TestTarget};
+
+    #[test]
+    fn test_parse_and_format() {
+        let opt =
+            DaemonOpt::try_parse_from(vec!["daemon", "rdeps", "//src/a:a", "--json"]).unwrap();
+        assert!(opt.json);
+        match opt.command {
+            DaemonCommand::Rdeps {
+                label,
+                max_distance,
+            } => {
+                assert_eq!(label, "//src/a:a");
+                assert_eq!(max_distance, 3);
+            }
+            other => panic!("Unexpected command {:?}", other),
+        }
+
+        let opt = DaemonOpt::try_parse_from(vec!["daemon", "changed-since", "10m"]).unwrap();
+        match opt.command {
+            DaemonCommand::ChangedSince { since } => {
+                assert_eq!(since, Some(Duration::from_secs(600)))
+            }
+            other => panic!("Unexpected command {:?}", other),
+        }
+
+        let rdeps = vec![
+            RdepsAtDistance {
+                distance: 1,
+                targets: vec![Targets::Build(BuildTarget {
+                    target_label: String::from("//src/b:b"),
+                })],
+            },
+            RdepsAtDistance {
+                distance: 2,
+                targets: vec![Targets::Test(TestTarget {
+                    target_label: String::from("//src/b:b_test"),
+                })],
+            },
+        ];
+        assert_eq!(
+            format_rdeps("//src/a:a", &rdeps),
+            "//src/a:a\nDistance 1:\n\t//src/b:b\nDistance 2:\n\t//src/b:b_test (test)"
+        );
+
+        assert_eq!(last_lines("a\nb\nc\n", 2), vec!["b", "c"]);
+        assert_eq!(last_lines("a\n", 5), vec!["a"]);
+
+        assert_eq!(
+            format_changed_file(&ChangedFile {
+                path: PathBuf::from("src/a/A.scala"),
+                age_ms: 65_400,
+            }),
+            "1m 5s ago\tsrc/a/A.scala"
+        );
+    }
+}
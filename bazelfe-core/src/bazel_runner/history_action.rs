@@ -0,0 +1,199 @@
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use regex::Regex;
+
+use crate::{
+    bazel_command_line_parser::{Action, CustomAction, ParsedCommandLine},
+    config::Config,
+};
+
+use super::story_journal::{JournalAction, JournalEntry, StoryJournal};
+
+#[derive(Parser, Debug)]
+#[clap(name = "history")]
+struct HistoryOpt {
+    /// Only show entries for targets matching this regex
+    #[clap(long)]
+    target: Option<String>,
+
+    /// Only show entries of this kind, e.g. added_dependency, removed_dependency, ran_user_action or success
+    #[clap(long)]
+    kind: Option<String>,
+
+    /// Only show entries from this invocation
+    #[clap(long)]
+    invocation: Option<String>,
+
+    /// Only show entries newer than this, e.g. 2h or 7days
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    since: Option<Duration>,
+
+    /// Print the matching entries as json lines rather than text
+    #[clap(long)]
+    json: bool,
+
+    /// Write the matching entries as json lines to this path
+    #[clap(long, parse(from_os_str))]
+    export: Option<PathBuf>,
+}
+
+struct HistoryFilter {
+    target: Option<Regex>,
+    kind: Option<String>,
+    invocation: Option<String>,
+    since: Option<std::time::SystemTime>,
+}
+impl HistoryFilter {
+    fn matches(&self, entry: &JournalEntry) -> bool {
+        self.target
+            .as_ref()
+            .map(|r| r.is_match(&entry.target))
+            .unwrap_or(true)
+            && self
+                .kind
+                .as_ref()
+                .map(|k| k == entry.action.kind_name())
+                .unwrap_or(true)
+            && self
+                .invocation
+                .as_ref()
+                .map(|i| i == &entry.invocation_id)
+                .unwrap_or(true)
+            && self.since.map(|s| entry.timestamp() >= s).unwrap_or(true)
+    }
+}
+
+fn format_entry(entry: &JournalEntry) -> String {
+    let description = match &entry.action {
+        JournalAction::AddedDependency { what, why } => {
+            format!("Added Dependency {}\n\t\tReason: {}", what, why)
+        }
+        JournalAction::RemovedDependency { what, why } => {
+            format!("Removed Dependency {}\n\t\tReason: {}", what, why)
+        }
+        JournalAction::RanUserAction {
+            what,
+            why,
+            command_line,
+            success,
+            exit_code,
+        } => format!(
+            "Ran user action: {}\n\t\tReason: {}\n\t\tSuccess: {}{}\n\t\tCommand line: {}",
+            what,
+            why,
+            success,
+            exit_code
+                .map(|exit_code| format!(", exit code {}", exit_code))
+                .unwrap_or_default(),
+            command_line
+        ),
+        JournalAction::Success => String::from("Target suceeded"),
+    };
+    format!(
+        "{} [{} attempt {}, bazel exit code {}] {}\n\t{}",
+        humantime::format_rfc3339_seconds(entry.timestamp()),
+        entry.invocation_id,
+        entry.attempt,
+        entry.final_exit_code,
+        entry.target,
+        description
+    )
+}
+
+/// Handles `bazel-runner <bazel> history ...`, listing, filtering and exporting the target story journal.
+pub fn maybe_history_action(
+    config: &Config,
+    bazel_command_line: &ParsedCommandLine,
+) -> Result<Option<i32>, Box<dyn std::error::Error>> {
+    if bazel_command_line.action != Some(Action::Custom(CustomAction::History)) {
+        return Ok(None);
+    }
+
+    let opt = HistoryOpt::try_parse_from(
+        std::iter::once(String::from("history")).chain(bazel_command_line.remaining_args.clone()),
+    )?;
+
+    let filter = HistoryFilter {
+        target: opt.target.as_deref().map(Regex::new).transpose()?,
+        kind: opt.kind,
+        invocation: opt.invocation,
+        since: opt.since.map(|d| std::time::SystemTime::now() - d),
+    };
+
+    let journal = StoryJournal::from_daemon_config(&config.daemon_config);
+    let entries: Vec<JournalEntry> = journal
+        .read_recent()?
+        .into_iter()
+        .filter(|e| filter.matches(e))
+        .collect();
+
+    if let Some(export_path) = opt.export.as_ref() {
+        let mut file = std::fs::File::create(export_path)?;
+        for entry in entries.iter() {
+            serde_json::to_writer(&mut file, entry)?;
+            file.write_all(b"\n")?;
+        }
+        eprintln!(
+            "Exported {} entries to {}",
+            entries.len(),
+            export_path.to_string_lossy()
+        );
+    } else if opt.json {
+        let stdout = std::io::stdout();
+        let mut lock = stdout.lock();
+        for entry in entries.iter() {
+            serde_json::to_writer(&mut lock, entry)?;
+            lock.write_all(b"\n")?;
+        }
+    } else if entries.is_empty() {
+        eprintln!("No entries found in {}", journal.path().to_string_lossy());
+    } else {
+        for entry in entries.iter() {
+            println!("{}", format_entry(entry));
+        }
+    }
+    Ok(Some(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(target: &str, invocation_id: &str, timestamp_ms: u64) -> JournalEntry {
+        JournalEntry {
+            timestamp_ms,
+            invocation_id: invocation_id.to_string(),
+            command_line: String::from("bazel build //..."),
+            attempt: 1,
+            final_exit_code: 0,
+            target: target.to_string(),
+            action: JournalAction::AddedDependency {
+                what: String::from("//b:b"),
+                why: String::from("Saw missing class"),
+            },
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = HistoryFilter {
+            target: Some(Regex::new("^//src/main/java/.*").unwrap()),
+            kind: Some(String::from("added_dependency")),
+            invocation: None,
+            since: Some(std::time::UNIX_EPOCH + Duration::from_secs(10)),
+        };
+
+        assert!(filter.matches(&entry("//src/main/java/com:com", "a", 20_000)));
+        assert!(!filter.matches(&entry("//src/main/java/com:com", "a", 5_000)));
+        assert!(!filter.matches(&entry("//src/main/scala/com:com", "a", 20_000)));
+
+        let filter = HistoryFilter {
+            target: None,
+            kind: Some(String::from("success")),
+            invocation: Some(String::from("a")),
+            since: None,
+        };
+        assert!(!filter.matches(&entry("//src/main/java/com:com", "a", 20_000)));
+    }
+}
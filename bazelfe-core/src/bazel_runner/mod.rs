@@ -1,4 +1,5 @@
 use std::ffi::OsString;
+use std::path::PathBuf;
 use std::process::Stdio;
 use std::sync::atomic::Ordering;
 use tokio::io::AsyncReadExt;
@@ -13,8 +14,20 @@ mod auto_test_action;
 pub mod bazel_runner;
 mod command_line_rewriter_action;
 mod configured_bazel_runner;
+#[cfg(feature = "bazelfe-daemon")]
+mod daemon_action;
+mod history_action;
 mod processor_activity;
+pub mod replay_action;
+pub mod retry_limits;
+pub mod run_report;
+pub mod story_journal;
+mod suggestion_report;
+mod undo_action;
+mod unused_deps_action;
 mod user_report_error;
+#[cfg(feature = "bazelfe-daemon")]
+mod watch_action;
 pub use user_report_error::UserReportError;
 
 use crate::bazel_command_line_parser::ParsedCommandLine;
@@ -36,7 +49,20 @@ pub fn register_ctrlc_handler() {
     .expect("Error setting Ctrl-C handler");
 }
 
-fn add_custom_args(bazel_command_line: &mut ParsedCommandLine, srv_port: u16) {
+/// How bazel delivers its build events to us.
+#[derive(Clone, Debug)]
+pub enum BuildEventTransport {
+    /// Bazel connects to our BES gRPC server on this port.
+    Grpc(u16),
+    /// Bazel writes the events to this file, which we tail. For when bazel can't connect to a local port.
+    BinaryFile(PathBuf),
+    /// Bazel connects to the daemon's BES server, which routes our invocation's events back to us.
+    /// The port and invocation id are only known once we register each attempt with the daemon.
+    #[cfg(feature = "bazelfe-daemon")]
+    Daemon(crate::bazel_runner_daemon::daemon_service::RunnerDaemonClient),
+}
+
+fn add_custom_args(bazel_command_line: &mut ParsedCommandLine, transport: &BuildEventTransport) {
     bazel_command_line.add_action_option_if_unset(
         crate::bazel_command_line_parser::BazelOption::OptionWithArg(
             String::from("bes_timeout"),
@@ -72,12 +98,27 @@ fn add_custom_args(bazel_command_line: &mut ParsedCommandLine, srv_port: u16) {
         ),
     );
 
-    bazel_command_line.add_action_option_if_unset(
-        crate::bazel_command_line_parser::BazelOption::OptionWithArg(
-            String::from("bes_backend"),
-            format!("grpc://127.0.0.1:{}", srv_port),
-        ),
-    );
+    match transport {
+        BuildEventTransport::Grpc(srv_port) => {
+            bazel_command_line.add_action_option_if_unset(
+                crate::bazel_command_line_parser::BazelOption::OptionWithArg(
+                    String::from("bes_backend"),
+                    format!("grpc://127.0.0.1:{}", srv_port),
+                ),
+            );
+        }
+        BuildEventTransport::BinaryFile(path) => {
+            // We must know where the events go, so this replaces any user supplied value.
+            bazel_command_line.set_action_option(
+                crate::bazel_command_line_parser::BazelOption::OptionWithArg(
+                    String::from("build_event_binary_file"),
+                    path.to_string_lossy().to_string(),
+                ),
+            );
+        }
+        #[cfg(feature = "bazelfe-daemon")]
+        BuildEventTransport::Daemon(_) => (),
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -96,10 +137,23 @@ pub async fn execute_bazel_output_control(
     bazel_command_line: &ParsedCommandLine,
     bes_port: u16,
     show_output: bool,
+) -> Result<ExecuteResult, Box<dyn std::error::Error>> {
+    execute_bazel_with_transport(
+        bazel_command_line,
+        &BuildEventTransport::Grpc(bes_port),
+        show_output,
+    )
+    .await
+}
+
+pub async fn execute_bazel_with_transport(
+    bazel_command_line: &ParsedCommandLine,
+    transport: &BuildEventTransport,
+    show_output: bool,
 ) -> Result<ExecuteResult, Box<dyn std::error::Error>> {
     let mut bazel_command_line = bazel_command_line.clone();
 
-    add_custom_args(&mut bazel_command_line, bes_port);
+    add_custom_args(&mut bazel_command_line, transport);
 
     debug!("{:#?}", bazel_command_line);
 
@@ -0,0 +1,274 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bazelfe_protos::*;
+use clap::Parser;
+use google::devtools::build::v1::PublishBuildToolEventStreamRequest;
+
+use crate::{
+    bazel_command_line_parser::{Action, CustomAction, ParsedCommandLine},
+    build_events::{
+        build_event_recorder::read_recording,
+        build_event_server::{bazel_event::BazelBuildEvent, BuildEventAction},
+        hydrated_stream::HydratedInfo,
+    },
+    buildozer_driver::{self, Buildozer, DryRunBuildozer},
+    config::Config,
+    hydrated_stream_processors::{
+        event_stream_listener::EventStreamListener,
+        process_bazel_failures::{
+            CommandLineRunner, ExecutionResult, ProcessBazelFailures, TargetStoryAction,
+        },
+        BazelEventHandler,
+    },
+};
+
+use super::{configured_bazel_runner::collect_processor_activity, processor_activity::*};
+
+#[derive(Parser, Debug)]
+#[clap(name = "replay")]
+struct ReplayOpt {
+    /// Recorded build event streams, replayed in order as successive attempts.
+    /// Failed action logs are read from the paths bazel reported, so replay soon after recording.
+    #[clap(required = true, parse(from_os_str))]
+    recordings: Vec<PathBuf>,
+}
+
+/// User defined actions are never run during a replay, they could have side effects.
+#[derive(Clone, Debug)]
+pub struct SkippingCommandLineRunner;
+
+#[async_trait]
+impl CommandLineRunner for SkippingCommandLineRunner {
+    async fn execute_command_line<S>(&self, command_line: S) -> ExecutionResult
+    where
+        S: Into<String> + Clone + Send,
+    {
+        ExecutionResult {
+            exit_success: true,
+            exit_code: None,
+            stdout: String::default(),
+            stderr: format!("Not run during replay: {}", command_line.into()),
+        }
+    }
+}
+
+/// Feed a recorded stream through hydration and the processors, as if bazel had just sent it to us.
+pub async fn replay_recording(
+    requests: Vec<PublishBuildToolEventStreamRequest>,
+    aes: &EventStreamListener,
+) -> ProcessorActivity {
+    let (tx, rx) = async_channel::unbounded();
+    let error_stream = HydratedInfo::build_transformer(rx);
+    let target_extracted_stream = aes.handle_stream(error_stream);
+    let collector = tokio::spawn(collect_processor_activity(target_extracted_stream));
+
+    for mut request in requests.into_iter() {
+        if let Some(evt) = BazelBuildEvent::transform_from(&mut request) {
+            // The receivers only go away if hydration has failed, which collection will surface.
+            let _ = tx.send(BuildEventAction::BuildEvent(evt)).await;
+        }
+    }
+    let _ = tx.send(BuildEventAction::BuildCompleted).await;
+    drop(tx);
+
+    collector.await.unwrap()
+}
+
+async fn replay_recordings<B: Buildozer>(
+    config: &Arc<Config>,
+    buildozer: DryRunBuildozer<B>,
+    recordings: Vec<Vec<PublishBuildToolEventStreamRequest>>,
+) -> Result<ProcessorActivity, Box<dyn std::error::Error>> {
+    let process_build_failures = Arc::new(ProcessBazelFailures::new(
+        super::bazel_runner::load_index_table(config),
+        buildozer,
+        SkippingCommandLineRunner,
+        Arc::clone(config),
+    )?);
+    let processors: Vec<Arc<dyn BazelEventHandler>> = vec![process_build_failures.clone()];
+    let aes = EventStreamListener::new(processors);
+
+    let mut running_total = ProcessorActivity::default();
+    for requests in recordings.into_iter() {
+        process_build_failures.advance_epoch().await;
+        let activity = replay_recording(requests, &aes).await;
+        running_total.merge(activity, false);
+    }
+    Ok(running_total)
+}
+
+/// Handles `bazel-runner <bazel> replay <recording>...`, re-running the processors over recorded
+/// build event streams. BUILD file edits are only proposed, never applied.
+pub async fn maybe_replay_action(
+    config: &Arc<Config>,
+    bazel_command_line: &ParsedCommandLine,
+) -> Result<Option<i32>, Box<dyn std::error::Error>> {
+    if bazel_command_line.action != Some(Action::Custom(CustomAction::Replay)) {
+        return Ok(None);
+    }
+
+    let opt = ReplayOpt::try_parse_from(
+        std::iter::once(String::from("replay")).chain(bazel_command_line.remaining_args.clone()),
+    )?;
+
+    let mut recordings = Vec::default();
+    for path in opt.recordings.iter() {
+        recordings.push(read_recording(path).map_err(|e| {
+            format!(
+                "Unable to read build event recording {}: {}",
+                path.to_string_lossy(),
+                e
+            )
+        })?);
+    }
+
+    let buildozer = DryRunBuildozer::new(buildozer_driver::from_config(config)?, true);
+    let activity = replay_recordings(config, buildozer.clone(), recordings).await?;
+
+    let mut stories: Vec<_> = activity.target_story_actions.into_iter().collect();
+    stories.sort_by(|a, b| a.0.cmp(&b.0));
+    for (target, mut entries) in stories.into_iter() {
+        entries.sort_by_key(|e| e.when);
+        for entry in entries.into_iter() {
+            match entry.action {
+                TargetStoryAction::AddedDependency { added_what, why } => {
+                    println!("{}\tadded {}\t{}", target, added_what, why)
+                }
+                TargetStoryAction::RemovedDependency { removed_what, why } => {
+                    println!("{}\tremoved {}\t{}", target, removed_what, why)
+                }
+                TargetStoryAction::RanUserAction {
+                    user_action_name,
+                    why,
+                    ..
+                } => println!(
+                    "{}\tskipped user action {}\t{}",
+                    target, user_action_name, why
+                ),
+                TargetStoryAction::Success => (),
+            }
+        }
+    }
+
+    let edits = buildozer.proposed_edits().await;
+    eprintln!(
+        "Replayed {} recordings, {} actions taken, {} BUILD file edits proposed:",
+        opt.recordings.len(),
+        activity.actions_taken,
+        edits.len()
+    );
+    for edit in edits.iter() {
+        eprintln!("\t{}", edit.to_command_line());
+    }
+    Ok(Some(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_events::build_event_recorder::BuildEventRecorder;
+    use crate::buildozer_driver::{EditKind, ExecuteResultError, ProposedEdit, Result};
+    use google::devtools::build::v1::{build_event, BuildEvent, OrderedBuildEvent, StreamId};
+    use prost::Message;
+
+    #[derive(Clone, Debug)]
+    struct EmptyBuildozer;
+
+    #[async_trait]
+    impl Buildozer for EmptyBuildozer {
+        async fn print_deps(&self, _label: &String) -> Result<Vec<String>> {
+            Ok(Vec::default())
+        }
+
+        async fn add_dependency(&self, _target: &str, _label: &String) -> Result<()> {
+            Err(ExecuteResultError {
+                exit_code: 1,
+                stdout: String::default(),
+                stderr: String::from("Replays should never edit BUILD files"),
+            })
+        }
+
+        async fn remove_dependency(&self, _target: &String, _label: &String) -> Result<()> {
+            Err(ExecuteResultError {
+                exit_code: 1,
+                stdout: String::default(),
+                stderr: String::from("Replays should never edit BUILD files"),
+            })
+        }
+    }
+
+    fn request(sequence_number: i64, stderr: &str) -> PublishBuildToolEventStreamRequest {
+        let bazel_event = build_event_stream::BuildEvent {
+            id: Some(build_event_stream::BuildEventId {
+                id: Some(build_event_stream::build_event_id::Id::Progress(
+                    build_event_stream::build_event_id::ProgressId {
+                        opaque_count: sequence_number as i32,
+                    },
+                )),
+            }),
+            payload: Some(build_event_stream::build_event::Payload::Progress(
+                build_event_stream::Progress {
+                    stdout: String::default(),
+                    stderr: stderr.to_string(),
+                },
+            )),
+            ..Default::default()
+        };
+        PublishBuildToolEventStreamRequest {
+            ordered_build_event: Some(OrderedBuildEvent {
+                stream_id: Some(StreamId {
+                    build_id: String::from("build"),
+                    invocation_id: String::from("replayed"),
+                    component: 0,
+                }),
+                sequence_number,
+                event: Some(BuildEvent {
+                    event_time: None,
+                    event: Some(build_event::Event::BazelEvent(prost_types::Any {
+                        type_url: String::from("type.googleapis.com/build_event_stream.BuildEvent"),
+                        value: bazel_event.encode_to_vec(),
+                    })),
+                }),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_is_deterministic() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = BuildEventRecorder::new(dir.path().to_path_buf());
+        let mut stream = recorder.start_stream();
+        stream
+            .record(&request(1, "Loading: 0 packages loaded"))
+            .await;
+        stream.record(&request(2, "ERROR: no such target '//src/foo:foo': target 'foo' not declared in package 'src/foo' defined by /tmp/ws/src/foo/BUILD and referenced by '//src/a:a'")).await;
+        stream.finish().await;
+        let recording = read_recording(&dir.path().join("replayed.bep")).unwrap();
+        assert_eq!(recording.len(), 2);
+
+        let config = Arc::new(Config::default());
+        let mut proposals = Vec::default();
+        for _ in 0..2 {
+            let buildozer = DryRunBuildozer::new(EmptyBuildozer, true);
+            let activity = replay_recordings(&config, buildozer.clone(), vec![recording.clone()])
+                .await
+                .unwrap();
+            assert_eq!(activity.actions_taken, 1);
+            proposals.push(buildozer.proposed_edits().await);
+        }
+
+        assert_eq!(
+            proposals[0],
+            vec![ProposedEdit {
+                target: String::from("//src/a:a"),
+                attribute: String::from("deps"),
+                kind: EditKind::RemoveDependency,
+                label: String::from("//src/foo:foo"),
+            }]
+        );
+        assert_eq!(proposals[0], proposals[1]);
+    }
+}
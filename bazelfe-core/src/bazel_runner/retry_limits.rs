@@ -0,0 +1,215 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::RetryPolicy,
+    hydrated_stream_processors::process_bazel_failures::{TargetStory, TargetStoryAction},
+    label_utils::sanitize_label,
+};
+
+/// Why `run_command_line` stopped re-running bazel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum RetryStopReason {
+    Succeeded,
+    NoActionsTaken,
+    DryRun,
+    MaxAttempts {
+        max_attempts: u16,
+    },
+    WallClockBudget {
+        budget_ms: u64,
+    },
+    MaxActionsPerAttempt {
+        actions_taken: u32,
+        max_actions_per_attempt: u32,
+    },
+    Oscillation {
+        target: String,
+        dependency: String,
+    },
+}
+
+impl std::fmt::Display for RetryStopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetryStopReason::Succeeded => write!(f, "the build succeeded"),
+            RetryStopReason::NoActionsTaken => write!(f, "no further actions could be taken"),
+            RetryStopReason::DryRun => write!(f, "dry run, only a single attempt is made"),
+            RetryStopReason::MaxAttempts { max_attempts } => {
+                write!(f, "reached the maximum of {} attempts", max_attempts)
+            }
+            RetryStopReason::WallClockBudget { budget_ms } => write!(
+                f,
+                "ran out of the {} time budget",
+                humantime::format_duration(Duration::from_millis(*budget_ms))
+            ),
+            RetryStopReason::MaxActionsPerAttempt {
+                actions_taken,
+                max_actions_per_attempt,
+            } => write!(
+                f,
+                "an attempt took {} actions, more than the limit of {}",
+                actions_taken, max_actions_per_attempt
+            ),
+            RetryStopReason::Oscillation { target, dependency } => write!(
+                f,
+                "{} kept being added to and removed from {}",
+                dependency, target
+            ),
+        }
+    }
+}
+
+/// Tracks the dependency edits made per target across attempts, to spot us flip flopping.
+#[derive(Debug, Default)]
+pub struct OscillationDetector {
+    // Whether each edit was an add, in the order they were made.
+    edits: HashMap<(String, String), Vec<bool>>,
+}
+
+impl OscillationDetector {
+    pub fn record<'a>(&mut self, stories: impl Iterator<Item = &'a TargetStory>) {
+        let mut stories: Vec<&TargetStory> = stories.collect();
+        stories.sort_by_key(|s| s.when);
+        for story in stories {
+            let (dependency, added) = match &story.action {
+                TargetStoryAction::AddedDependency { added_what, .. } => (added_what, true),
+                TargetStoryAction::RemovedDependency { removed_what, .. } => (removed_what, false),
+                _ => continue,
+            };
+            self.edits
+                .entry((
+                    sanitize_label(story.target.clone()),
+                    sanitize_label(dependency.clone()),
+                ))
+                .or_default()
+                .push(added);
+        }
+    }
+
+    /// A dependency that has been added, removed and then added again (or the reverse).
+    pub fn oscillating(&self) -> Option<(String, String)> {
+        let mut found: Vec<&(String, String)> = self
+            .edits
+            .iter()
+            .filter(|(_, edits)| edits.windows(2).filter(|w| w[0] != w[1]).count() >= 2)
+            .map(|(key, _)| key)
+            .collect();
+        found.sort();
+        found.first().map(|(t, d)| (t.clone(), d.clone()))
+    }
+}
+
+/// Check whether the policy forbids another attempt after one that took actions but didn't succeed.
+pub fn retry_limit_reached(
+    policy: &RetryPolicy,
+    attempts: u16,
+    elapsed: Duration,
+    actions_taken_last_attempt: u32,
+    oscillation: &OscillationDetector,
+) -> Option<RetryStopReason> {
+    if let Some(max_actions_per_attempt) = policy.max_actions_per_attempt {
+        if actions_taken_last_attempt > max_actions_per_attempt {
+            return Some(RetryStopReason::MaxActionsPerAttempt {
+                actions_taken: actions_taken_last_attempt,
+                max_actions_per_attempt,
+            });
+        }
+    }
+    if policy.detect_oscillation {
+        if let Some((target, dependency)) = oscillation.oscillating() {
+            return Some(RetryStopReason::Oscillation { target, dependency });
+        }
+    }
+    if attempts >= policy.max_attempts {
+        return Some(RetryStopReason::MaxAttempts {
+            max_attempts: policy.max_attempts,
+        });
+    }
+    if let Some(budget) = policy.max_duration {
+        if elapsed >= budget {
+            return Some(RetryStopReason::WallClockBudget {
+                budget_ms: budget.as_millis() as u64,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn story(target: &str, dependency: &str, added: bool) -> TargetStory {
+        TargetStory {
+            target: target.to_string(),
+            action: if added {
+                TargetStoryAction::AddedDependency {
+                    added_what: dependency.to_string(),
+                    why: String::default(),
+                }
+            } else {
+                TargetStoryAction::RemovedDependency {
+                    removed_what: dependency.to_string(),
+                    why: String::default(),
+                }
+            },
+            when: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_oscillation_detector() {
+        let mut detector = OscillationDetector::default();
+        // Trying one candidate then swapping it for another is normal.
+        detector.record(vec![story("//a:a", "//b:b", true), story("//a:a", "//c", true)].iter());
+        detector.record(vec![story("//a:a", "//b", false)].iter());
+        assert_eq!(detector.oscillating(), None);
+
+        detector.record(vec![story("//a", "//b:b", true)].iter());
+        assert_eq!(
+            detector.oscillating(),
+            Some((String::from("//a:a"), String::from("//b:b")))
+        );
+    }
+
+    #[test]
+    fn test_retry_limit_reached() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            max_duration: Some(Duration::from_secs(60)),
+            max_actions_per_attempt: Some(10),
+            detect_oscillation: true,
+        };
+        let detector = OscillationDetector::default();
+
+        assert_eq!(
+            retry_limit_reached(&policy, 1, Duration::from_secs(1), 5, &detector),
+            None
+        );
+        assert_eq!(
+            retry_limit_reached(&policy, 3, Duration::from_secs(1), 5, &detector),
+            Some(RetryStopReason::MaxAttempts { max_attempts: 3 })
+        );
+        assert_eq!(
+            retry_limit_reached(&policy, 1, Duration::from_secs(61), 5, &detector),
+            Some(RetryStopReason::WallClockBudget { budget_ms: 60000 })
+        );
+        assert_eq!(
+            retry_limit_reached(&policy, 1, Duration::from_secs(1), 11, &detector),
+            Some(RetryStopReason::MaxActionsPerAttempt {
+                actions_taken: 11,
+                max_actions_per_attempt: 10
+            })
+        );
+
+        assert_eq!(
+            RetryStopReason::WallClockBudget { budget_ms: 60000 }.to_string(),
+            "ran out of the 1m time budget"
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    configured_bazel_runner::RunCompleteState,
+    retry_limits::RetryStopReason,
+    story_journal::{instant_to_unix_ms, JournalAction},
+};
+use crate::config::ReportFormat;
+use crate::hydrated_stream_processors::{
+    build_timing::BuildTimingSummary, test_results::TestResultsSummary,
+};
+
+/// Bumped whenever a field is removed or changes meaning, adding fields is not a breaking change.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RunSummary {
+    pub schema_version: u32,
+    pub invocation_id: String,
+    pub command_line: String,
+    pub attempts: u16,
+    pub final_exit_code: i32,
+    pub actions_taken: u32,
+    pub jvm_segments_indexed: u32,
+    pub dry_run: bool,
+    /// Which limit, or outcome, ended the retries.
+    pub stop_reason: RetryStopReason,
+    /// Where the last bazel attempt spent its time, when bazel reported it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_timing: Option<BuildTimingSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_results: Option<TestResultsSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReportedStory {
+    pub target: String,
+    pub attempt: u16,
+    /// Milliseconds since the unix epoch.
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub action: JournalAction,
+}
+
+/// The whole run as a single json document.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RunReport {
+    #[serde(flatten)]
+    pub summary: RunSummary,
+    pub target_stories: Vec<ReportedStory>,
+}
+
+/// In the json lines format the summary comes first, followed by a line per story.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum ReportLine {
+    Run(Box<RunSummary>),
+    TargetStory(ReportedStory),
+}
+
+impl RunReport {
+    pub fn new(
+        invocation_id: &str,
+        command_line: &str,
+        dry_run: bool,
+        res_data: &RunCompleteState,
+    ) -> Self {
+        Self {
+            summary: RunSummary {
+                schema_version: REPORT_SCHEMA_VERSION,
+                invocation_id: invocation_id.to_string(),
+                command_line: command_line.to_string(),
+                attempts: res_data.attempts,
+                final_exit_code: res_data.final_exit_code,
+                actions_taken: res_data.total_actions_taken,
+                jvm_segments_indexed: res_data.running_total.jvm_segments_indexed,
+                dry_run,
+                stop_reason: res_data.stop_reason.clone(),
+                build_timing: res_data.build_timing.clone(),
+                test_results: res_data.test_results.clone(),
+            },
+            // Every story, including targets which only succeeded, so the report accounts for the whole run.
+            target_stories: res_data
+                .story_log
+                .iter()
+                .map(|(attempt, story)| ReportedStory {
+                    target: story.target.clone(),
+                    attempt: *attempt,
+                    timestamp_ms: instant_to_unix_ms(story.when),
+                    action: (&story.action).into(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn render(&self, format: ReportFormat) -> Result<String, serde_json::Error> {
+        match format {
+            ReportFormat::Json => {
+                let mut output = serde_json::to_string_pretty(self)?;
+                output.push('\n');
+                Ok(output)
+            }
+            ReportFormat::JsonLines => {
+                let mut output = String::default();
+                let lines = std::iter::once(ReportLine::Run(Box::new(self.summary.clone()))).chain(
+                    self.target_stories
+                        .iter()
+                        .cloned()
+                        .map(ReportLine::TargetStory),
+                );
+                for line in lines {
+                    output.push_str(&serde_json::to_string(&line)?);
+                    output.push('\n');
+                }
+                Ok(output)
+            }
+        }
+    }
+
+    /// Replace any previous report at the path, going via a temporary file so readers never see a partial report.
+    pub fn write(
+        &self,
+        path: &Path,
+        format: ReportFormat,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, self.render(format)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::bazel_runner::processor_activity::ProcessorActivity;
+    use crate::hydrated_stream_processors::process_bazel_failures::{
+        TargetStory, TargetStoryAction,
+    };
+
+    fn story(target: &str, action: TargetStoryAction) -> TargetStory {
+        TargetStory {
+            target: target.to_string(),
+            action,
+            when: Instant::now(),
+        }
+    }
+
+    fn sample_report() -> RunReport {
+        let res_data = RunCompleteState {
+            attempts: 2,
+            total_actions_taken: 1,
+            final_exit_code: 0,
+            running_total: ProcessorActivity {
+                jvm_segments_indexed: 12,
+                actions_taken: 1,
+                target_story_actions: Default::default(),
+            },
+            story_log: vec![
+                (
+                    1,
+                    story(
+                        "//a:a",
+                        TargetStoryAction::AddedDependency {
+                            added_what: String::from("//b:b"),
+                            why: String::from("Saw missing dependency"),
+                        },
+                    ),
+                ),
+                (1, story("//c:c", TargetStoryAction::Success)),
+                (2, story("//a:a", TargetStoryAction::Success)),
+            ],
+            stop_reason: RetryStopReason::Succeeded,
+            build_timing: Some(BuildTimingSummary {
+                wall_time_ms: Some(8_000),
+                critical_path_ms: Some(5_500),
+                ..Default::default()
+            }),
+            test_results: None,
+        };
+        RunReport::new("inv", "bazel build //...", false, &res_data)
+    }
+
+    #[test]
+    fn test_json_report() {
+        let report = sample_report();
+        let rendered = report.render(ReportFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(value["schema_version"], REPORT_SCHEMA_VERSION);
+        assert_eq!(value["attempts"], 2);
+        assert_eq!(value["jvm_segments_indexed"], 12);
+        assert_eq!(value["stop_reason"]["reason"], "succeeded");
+        assert_eq!(value["build_timing"]["critical_path_ms"], 5_500);
+        assert!(value.get("test_results").is_none());
+        assert_eq!(value["target_stories"].as_array().unwrap().len(), 3);
+        assert_eq!(value["target_stories"][0]["kind"], "added_dependency");
+        assert_eq!(value["target_stories"][0]["what"], "//b:b");
+        assert_eq!(value["target_stories"][1]["target"], "//c:c");
+        assert_eq!(value["target_stories"][1]["kind"], "success");
+        assert_eq!(value["target_stories"][2]["attempt"], 2);
+
+        let parsed: RunReport = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    #[test]
+    fn test_json_lines_report() {
+        let report = sample_report();
+        let rendered = report.render(ReportFormat::JsonLines).unwrap();
+        let lines: Vec<ReportLine> = rendered
+            .lines()
+            .map(|ln| serde_json::from_str(ln).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], ReportLine::Run(Box::new(report.summary.clone())));
+        assert_eq!(
+            lines[1],
+            ReportLine::TargetStory(report.target_stories[0].clone())
+        );
+        assert!(rendered.starts_with("{\"record\":\"run\",\"schema_version\":1,"));
+    }
+
+    #[test]
+    fn test_write_replaces_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reports/report.json");
+        let report = sample_report();
+        report.write(&path, ReportFormat::JsonLines).unwrap();
+        report.write(&path, ReportFormat::JsonLines).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 4);
+    }
+}
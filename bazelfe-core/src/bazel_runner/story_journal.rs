@@ -0,0 +1,227 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::DaemonConfig,
+    hydrated_stream_processors::process_bazel_failures::{TargetStory, TargetStoryAction},
+    jsonl_file::BoundedJsonLines,
+};
+
+const JOURNAL_FILE_NAME: &str = "target_stories.jsonl";
+
+/// Stories we keep, older ones are dropped as new ones are recorded.
+const MAX_JOURNAL_ENTRIES: usize = 20_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalAction {
+    AddedDependency {
+        what: String,
+        why: String,
+    },
+    RemovedDependency {
+        what: String,
+        why: String,
+    },
+    RanUserAction {
+        what: String,
+        why: String,
+        command_line: String,
+        success: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
+    },
+    Success,
+}
+impl JournalAction {
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            JournalAction::AddedDependency { .. } => "added_dependency",
+            JournalAction::RemovedDependency { .. } => "removed_dependency",
+            JournalAction::RanUserAction { .. } => "ran_user_action",
+            JournalAction::Success => "success",
+        }
+    }
+}
+
+impl From<&TargetStoryAction> for JournalAction {
+    fn from(action: &TargetStoryAction) -> Self {
+        match action {
+            TargetStoryAction::AddedDependency { added_what, why } => {
+                JournalAction::AddedDependency {
+                    what: added_what.clone(),
+                    why: why.clone(),
+                }
+            }
+            TargetStoryAction::RemovedDependency { removed_what, why } => {
+                JournalAction::RemovedDependency {
+                    what: removed_what.clone(),
+                    why: why.clone(),
+                }
+            }
+            TargetStoryAction::RanUserAction {
+                user_action_name,
+                why,
+                command_line,
+                execution_result,
+            } => JournalAction::RanUserAction {
+                what: user_action_name.clone(),
+                why: why.clone(),
+                command_line: command_line.clone(),
+                success: execution_result.exit_success,
+                exit_code: execution_result.exit_code,
+            },
+            TargetStoryAction::Success => JournalAction::Success,
+        }
+    }
+}
+
+/// A single story entry as persisted, carrying enough about the invocation it came from
+/// to be useful long after the bazel run has finished.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Milliseconds since the unix epoch.
+    pub timestamp_ms: u64,
+    pub invocation_id: String,
+    pub command_line: String,
+    pub attempt: u16,
+    pub final_exit_code: i32,
+    pub target: String,
+    #[serde(flatten)]
+    pub action: JournalAction,
+}
+
+impl JournalEntry {
+    pub fn from_story(
+        invocation_id: &str,
+        command_line: &str,
+        attempt: u16,
+        final_exit_code: i32,
+        story: &TargetStory,
+    ) -> Self {
+        Self {
+            timestamp_ms: instant_to_unix_ms(story.when),
+            invocation_id: invocation_id.to_string(),
+            command_line: command_line.to_string(),
+            attempt,
+            final_exit_code,
+            target: story.target.clone(),
+            action: (&story.action).into(),
+        }
+    }
+
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + std::time::Duration::from_millis(self.timestamp_ms)
+    }
+}
+
+pub(super) fn instant_to_unix_ms(when: Instant) -> u64 {
+    let wall_clock = SystemTime::now() - Instant::now().saturating_duration_since(when);
+    wall_clock
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Generate an id to group the journal entries of one bazel-runner invocation.
+pub fn new_invocation_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!("{}-{:08x}", now, rand::random::<u32>())
+}
+
+/// Journal of recent target stories, stored as json lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoryJournal {
+    file: BoundedJsonLines,
+}
+
+impl StoryJournal {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            file: BoundedJsonLines::new(path, MAX_JOURNAL_ENTRIES),
+        }
+    }
+
+    pub fn from_daemon_config(daemon_config: &DaemonConfig) -> Self {
+        Self::new(
+            daemon_config
+                .daemon_communication_folder
+                .join(JOURNAL_FILE_NAME),
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    pub fn append(&self, entries: &[JournalEntry]) -> Result<(), Box<dyn std::error::Error>> {
+        self.file.append(entries)
+    }
+
+    /// Read the most recent entries in the journal, lines which fail to parse are skipped.
+    pub fn read_recent(&self) -> Result<Vec<JournalEntry>, Box<dyn std::error::Error>> {
+        self.file.read_recent()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = StoryJournal::new(dir.path().join("nested").join(JOURNAL_FILE_NAME));
+
+        let story = TargetStory {
+            target: String::from("//src/main/java/com/example:example"),
+            action: TargetStoryAction::AddedDependency {
+                added_what: String::from("//src/main/java/com/other:other"),
+                why: String::from("Saw missing class com.other.Other"),
+            },
+            when: Instant::now(),
+        };
+        let first = JournalEntry::from_story("inv-1", "bazel build //...", 2, 0, &story);
+        let second = JournalEntry::from_story(
+            "inv-2",
+            "bazel build //...",
+            1,
+            1,
+            &TargetStory {
+                action: TargetStoryAction::Success,
+                ..story.clone()
+            },
+        );
+
+        journal.append(&[first.clone()]).unwrap();
+        journal.append(&[second.clone()]).unwrap();
+        assert_eq!(journal.read_recent().unwrap(), vec![first, second]);
+    }
+
+    #[test]
+    fn test_serialized_format() {
+        let entry = JournalEntry {
+            timestamp_ms: 1000,
+            invocation_id: String::from("inv"),
+            command_line: String::from("bazel build //..."),
+            attempt: 1,
+            final_exit_code: 0,
+            target: String::from("//a:a"),
+            action: JournalAction::RemovedDependency {
+                what: String::from("//b:b"),
+                why: String::from("unused"),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"timestamp_ms":1000,"invocation_id":"inv","command_line":"bazel build //...","attempt":1,"final_exit_code":0,"target":"//a:a","kind":"removed_dependency","what":"//b:b","why":"unused"}"#
+        );
+    }
+}
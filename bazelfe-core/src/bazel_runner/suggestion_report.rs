@@ -0,0 +1,209 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    buildozer_driver::{ConfiguredBuildozer, ProposedEdit},
+    config::Config,
+};
+
+const COMMAND_FILE_NAME: &str = "bazelfe_suggestions.buildozer";
+const DIFF_FILE_NAME: &str = "bazelfe_suggestions.diff";
+
+pub struct SuggestionFiles {
+    pub command_file: PathBuf,
+    pub diff_file: PathBuf,
+    pub diff: String,
+}
+
+/// The package a target lives in, for local targets only.
+fn package_of(target: &str) -> Option<&str> {
+    let target = target.strip_prefix("//")?;
+    Some(target.split(':').next().unwrap_or(target))
+}
+
+/// Group the edits by the BUILD file they'd touch, keeping the order edits were proposed in.
+fn edits_by_package(edits: &[ProposedEdit]) -> Vec<(String, Vec<&ProposedEdit>)> {
+    let mut grouped: Vec<(String, Vec<&ProposedEdit>)> = Vec::default();
+    for edit in edits.iter() {
+        let package = match package_of(&edit.target) {
+            Some(p) => p,
+            None => continue,
+        };
+        match grouped.iter_mut().find(|(p, _)| p == package) {
+            Some((_, existing)) => existing.push(edit),
+            None => grouped.push((package.to_string(), vec![edit])),
+        }
+    }
+    grouped
+}
+
+fn build_file_for_package(workspace_root: &Path, package: &str) -> Option<PathBuf> {
+    ["BUILD.bazel", "BUILD"]
+        .iter()
+        .map(|name| {
+            if package.is_empty() {
+                PathBuf::from(name)
+            } else {
+                PathBuf::from(package).join(name)
+            }
+        })
+        .find(|p| workspace_root.join(p).exists())
+}
+
+fn unified_diff(relative_path: &Path, original: &str, modified: &str) -> String {
+    let path = relative_path.to_string_lossy();
+    similar::TextDiff::from_lines(original, modified)
+        .unified_diff()
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+/// Write out the proposed edits as a buildozer command file, and as a unified diff of the BUILD files
+/// produced by having the configured buildozer render each edited file.
+pub async fn write_suggestions(
+    buildozer: &ConfiguredBuildozer,
+    workspace_root: &Path,
+    edits: &[ProposedEdit],
+    output_directory: &Path,
+) -> Result<SuggestionFiles, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(output_directory)?;
+
+    let mut command_file_content = edits
+        .iter()
+        .map(|e| e.to_command_line())
+        .collect::<Vec<String>>()
+        .join("\n");
+    command_file_content.push('\n');
+    let command_file = output_directory.join(COMMAND_FILE_NAME);
+    std::fs::write(&command_file, command_file_content)?;
+
+    let mut diff = String::default();
+    for (package, package_edits) in edits_by_package(edits).into_iter() {
+        let build_file = match build_file_for_package(workspace_root, &package) {
+            Some(f) => f,
+            None => {
+                warn!("Unable to find a BUILD file for package //{}", package);
+                continue;
+            }
+        };
+        let original = std::fs::read_to_string(workspace_root.join(&build_file))?;
+        match buildozer.preview_edits(&package_edits).await {
+            Ok(modified) => diff.push_str(&unified_diff(&build_file, &original, &modified)),
+            Err(e) => warn!(
+                "Unable to render proposed edits for //{}: {}",
+                package, e.stderr
+            ),
+        }
+    }
+    let diff_file = output_directory.join(DIFF_FILE_NAME);
+    std::fs::write(&diff_file, &diff)?;
+
+    Ok(SuggestionFiles {
+        command_file,
+        diff_file,
+        diff,
+    })
+}
+
+/// At the end of a dry run, emit what we would have changed and the user defined actions we would have run.
+/// If there are fixes available we always exit non-zero so CI can flag them.
+pub async fn finish_dry_run(
+    config: &Config,
+    buildozer: &ConfiguredBuildozer,
+    edits: &[ProposedEdit],
+    user_action_command_lines: &[String],
+    final_exit_code: i32,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    if edits.is_empty() && user_action_command_lines.is_empty() {
+        return Ok(final_exit_code);
+    }
+
+    eprintln!("--------------------Bazel Runner Suggestions--------------------");
+    if !edits.is_empty() {
+        let output_directory = config
+            .dry_run_output_directory
+            .clone()
+            .unwrap_or_else(|| config.daemon_config.daemon_communication_folder.clone());
+        let suggestion_files = write_suggestions(
+            buildozer,
+            &std::env::current_dir()?,
+            edits,
+            &output_directory,
+        )
+        .await?;
+
+        eprintln!("{}", suggestion_files.diff);
+        eprintln!(
+            "Dry run, {} BUILD file edits proposed but not applied.",
+            edits.len()
+        );
+        eprintln!(
+            "Buildozer commands: {}\nApply with: buildozer -f {}",
+            suggestion_files.command_file.to_string_lossy(),
+            suggestion_files.command_file.to_string_lossy()
+        );
+        eprintln!(
+            "Unified diff: {}",
+            suggestion_files.diff_file.to_string_lossy()
+        );
+    }
+    if !user_action_command_lines.is_empty() {
+        eprintln!(
+            "Dry run, {} user defined actions proposed but not run:",
+            user_action_command_lines.len()
+        );
+        for command_line in user_action_command_lines.iter() {
+            eprintln!("\t{}", command_line);
+        }
+    }
+    eprintln!("------------------------------------------------------------\n");
+
+    Ok(if final_exit_code != 0 {
+        final_exit_code
+    } else {
+        1
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildozer_driver::EditKind;
+
+    fn edit(target: &str, kind: EditKind, label: &str) -> ProposedEdit {
+        ProposedEdit {
+            target: target.to_string(),
+            attribute: String::from("deps"),
+            kind,
+            label: label.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_edits_by_package() {
+        let edits = vec![
+            edit("//a/b:c", EditKind::AddDependency, "//d:d"),
+            edit("@ext//a:a", EditKind::AddDependency, "//d:d"),
+            edit("//e:e", EditKind::RemoveDependency, ":f"),
+            edit("//a/b:g", EditKind::AddDependency, "//h:h"),
+        ];
+        let grouped: Vec<(String, usize)> = edits_by_package(&edits)
+            .into_iter()
+            .map(|(p, e)| (p, e.len()))
+            .collect();
+        assert_eq!(
+            grouped,
+            vec![(String::from("a/b"), 2), (String::from("e"), 1)]
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let original =
+            "java_library(\n    name = \"a\",\n    deps = [\n        \"//b\",\n    ],\n)\n";
+        let modified = "java_library(\n    name = \"a\",\n    deps = [\n        \"//b\",\n        \"//c\",\n    ],\n)\n";
+        assert_eq!(
+            unified_diff(Path::new("a/BUILD"), original, modified),
+            "--- a/a/BUILD\n+++ b/a/BUILD\n@@ -2,5 +2,6 @@\n     name = \"a\",\n     deps = [\n         \"//b\",\n+        \"//c\",\n     ],\n )\n"
+        );
+    }
+}
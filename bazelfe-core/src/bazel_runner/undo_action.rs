@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+
+use clap::Parser;
+
+use crate::{
+    bazel_command_line_parser::{Action, CustomAction, ParsedCommandLine},
+    buildozer_driver::{self, Buildozer, EditKind, EditLog, EditLogRecord, RecordedEdit},
+    config::Config,
+    label_utils::sanitize_label,
+};
+
+const EDIT_LOG_FILE_NAME: &str = "buildozer_edits.jsonl";
+
+pub fn edit_log_from_config(config: &Config) -> EditLog {
+    EditLog::new(
+        config
+            .daemon_config
+            .daemon_communication_folder
+            .join(EDIT_LOG_FILE_NAME),
+    )
+}
+
+#[derive(Parser, Debug)]
+#[clap(name = "undo")]
+struct UndoOpt {
+    /// Undo the most recent invocation which edited BUILD files, this is the default
+    #[clap(long, conflicts_with = "invocation")]
+    last: bool,
+
+    /// Undo the edits made by this invocation, as listed by the history command
+    #[clap(long)]
+    invocation: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UndoStep {
+    target: String,
+    attribute: String,
+    /// The edit to apply to revert the original change.
+    kind: EditKind,
+    label: String,
+}
+
+/// Target, attribute and sanitized label an edit applies to.
+type EditKey = (String, String, String);
+
+/// Collapse the edits of an invocation down to the net change per target/dependency,
+/// returning the inverse edits in reverse order of when they were last touched.
+fn plan_undo(edits: &[RecordedEdit]) -> Vec<UndoStep> {
+    let mut net: HashMap<EditKey, (EditKind, EditKind, usize, String)> = HashMap::default();
+    for (idx, edit) in edits.iter().enumerate() {
+        let key = (
+            edit.target.clone(),
+            edit.attribute.clone(),
+            sanitize_label(edit.label.clone()),
+        );
+        match net.get_mut(&key) {
+            Some(existing) => {
+                existing.1 = edit.kind;
+                existing.2 = idx;
+            }
+            None => {
+                net.insert(key, (edit.kind, edit.kind, idx, edit.label.clone()));
+            }
+        }
+    }
+
+    let mut steps: Vec<(usize, UndoStep)> = net
+        .into_iter()
+        .filter(|(_, (first, last, _, _))| first == last)
+        .map(|((target, attribute, _), (_, last, idx, label))| {
+            let kind = match last {
+                EditKind::AddDependency => EditKind::RemoveDependency,
+                EditKind::RemoveDependency => EditKind::AddDependency,
+            };
+            (
+                idx,
+                UndoStep {
+                    target,
+                    attribute,
+                    kind,
+                    label,
+                },
+            )
+        })
+        .collect();
+    steps.sort_by_key(|s| std::cmp::Reverse(s.0));
+    steps.into_iter().map(|(_, step)| step).collect()
+}
+
+/// Check the BUILD files still look as the invocation left them, returning a description
+/// of every step that can no longer be cleanly reverted.
+async fn find_conflicts<B: Buildozer>(buildozer: &B, steps: &[UndoStep]) -> Vec<String> {
+    let mut conflicts = Vec::default();
+    for step in steps.iter() {
+        let current_deps = match buildozer
+            .print_attribute(&step.target, &step.attribute)
+            .await
+        {
+            Ok(deps) => deps,
+            Err(_) => {
+                conflicts.push(format!("{} can no longer be found", step.target));
+                continue;
+            }
+        };
+        let present = current_deps.contains(&sanitize_label(step.label.clone()));
+        match step.kind {
+            EditKind::RemoveDependency if !present => conflicts.push(format!(
+                "{} was added to {} but has since been removed",
+                step.label, step.target
+            )),
+            EditKind::AddDependency if present => conflicts.push(format!(
+                "{} was removed from {} but has since been added back",
+                step.label, step.target
+            )),
+            _ => (),
+        }
+    }
+    conflicts
+}
+
+async fn apply_undo<B: Buildozer>(
+    buildozer: &B,
+    steps: &[UndoStep],
+) -> Result<(), buildozer_driver::ExecuteResultError> {
+    for step in steps.iter() {
+        match step.kind {
+            EditKind::AddDependency => {
+                eprintln!("Adding back {} to {}", step.label, step.target);
+                buildozer
+                    .add_to_attribute(&step.target, &step.attribute, &step.label)
+                    .await?
+            }
+            EditKind::RemoveDependency => {
+                eprintln!("Removing {} from {}", step.label, step.target);
+                buildozer
+                    .remove_from_attribute(&step.target, &step.attribute, &step.label)
+                    .await?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handles `bazel-runner <bazel> undo [--last|--invocation ID]`, reverting the BUILD file edits of an invocation.
+pub async fn maybe_undo_action(
+    config: &Config,
+    bazel_command_line: &ParsedCommandLine,
+) -> Result<Option<i32>, Box<dyn std::error::Error>> {
+    if bazel_command_line.action != Some(Action::Custom(CustomAction::Undo)) {
+        return Ok(None);
+    }
+
+    let opt = UndoOpt::try_parse_from(
+        std::iter::once(String::from("undo")).chain(bazel_command_line.remaining_args.clone()),
+    )?;
+
+    let edit_log = edit_log_from_config(config);
+    let invocation_id = match opt.invocation {
+        Some(invocation_id) => {
+            if edit_log.is_undone(&invocation_id)? {
+                eprintln!("Invocation {} has already been undone", invocation_id);
+                return Ok(Some(1));
+            }
+            invocation_id
+        }
+        None => match edit_log.last_undoable_invocation()? {
+            Some(invocation_id) => invocation_id,
+            None => {
+                eprintln!("No BUILD file edits found to undo");
+                return Ok(Some(1));
+            }
+        },
+    };
+
+    let steps = plan_undo(&edit_log.edits_for_invocation(&invocation_id)?);
+    if steps.is_empty() {
+        eprintln!(
+            "Invocation {} made no net BUILD file edits, nothing to undo",
+            invocation_id
+        );
+        return Ok(Some(0));
+    }
+
+    let buildozer = buildozer_driver::from_config(config)?;
+
+    let conflicts = find_conflicts(&buildozer, &steps).await;
+    if !conflicts.is_empty() {
+        eprintln!(
+            "Refusing to undo invocation {}, BUILD files have changed since:",
+            invocation_id
+        );
+        for conflict in conflicts.iter() {
+            eprintln!("\t{}", conflict);
+        }
+        return Ok(Some(1));
+    }
+
+    if let Err(e) = apply_undo(&buildozer, &steps).await {
+        eprintln!(
+            "Buildozer failed part way through undoing invocation {}:\n{}",
+            invocation_id, e.stderr
+        );
+        return Ok(Some(1));
+    }
+    edit_log.append(&EditLogRecord::Undone {
+        invocation_id: invocation_id.clone(),
+    })?;
+    eprintln!(
+        "Undid {} BUILD file edits from invocation {}",
+        steps.len(),
+        invocation_id
+    );
+    Ok(Some(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildozer_driver::ExecuteResultError;
+
+    fn edit(target: &str, kind: EditKind, label: &str) -> RecordedEdit {
+        RecordedEdit {
+            target: target.to_string(),
+            attribute: String::from("deps"),
+            kind,
+            label: label.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_plan_undo() {
+        let steps = plan_undo(&[
+            edit("//a:a", EditKind::AddDependency, "//b:b"),
+            edit("//a:a", EditKind::RemoveDependency, ":c"),
+            edit("//a:a", EditKind::AddDependency, ":c"),
+            edit("//d:d", EditKind::RemoveDependency, "//e"),
+        ]);
+        assert_eq!(
+            steps,
+            vec![
+                UndoStep {
+                    target: String::from("//d:d"),
+                    attribute: String::from("deps"),
+                    kind: EditKind::AddDependency,
+                    label: String::from("//e"),
+                },
+                UndoStep {
+                    target: String::from("//a:a"),
+                    attribute: String::from("deps"),
+                    kind: EditKind::RemoveDependency,
+                    label: String::from("//b:b"),
+                },
+            ]
+        );
+    }
+
+    #[derive(Clone, Debug)]
+    struct FakeBuildozer {
+        deps: HashMap<String, Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Buildozer for FakeBuildozer {
+        async fn print_deps(&self, label: &String) -> Result<Vec<String>, ExecuteResultError> {
+            self.deps.get(label).cloned().ok_or(ExecuteResultError {
+                exit_code: 2,
+                stdout: String::default(),
+                stderr: String::default(),
+            })
+        }
+
+        async fn add_dependency(
+            &self,
+            _target_to_operate_on: &str,
+            _label_to_add: &String,
+        ) -> Result<(), ExecuteResultError> {
+            Ok(())
+        }
+
+        async fn remove_dependency(
+            &self,
+            _target_to_operate_on: &String,
+            _label_to_add: &String,
+        ) -> Result<(), ExecuteResultError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_conflicts() {
+        let mut deps = HashMap::new();
+        deps.insert(String::from("//a:a"), vec![String::from("//b:b")]);
+        deps.insert(String::from("//d:d"), vec![String::from("//e:e")]);
+        let buildozer = FakeBuildozer { deps };
+
+        let clean = vec![UndoStep {
+            target: String::from("//a:a"),
+            attribute: String::from("deps"),
+            kind: EditKind::RemoveDependency,
+            label: String::from("//b:b"),
+        }];
+        assert!(find_conflicts(&buildozer, &clean).await.is_empty());
+
+        let conflicting = vec![
+            UndoStep {
+                target: String::from("//d:d"),
+                attribute: String::from("deps"),
+                kind: EditKind::AddDependency,
+                label: String::from("//e"),
+            },
+            UndoStep {
+                target: String::from("//missing:missing"),
+                attribute: String::from("deps"),
+                kind: EditKind::RemoveDependency,
+                label: String::from("//b:b"),
+            },
+        ];
+        assert_eq!(find_conflicts(&buildozer, &conflicting).await.len(), 2);
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+use bazelfe_protos::*;
+use prost::Message;
+
+use crate::{
+    bazel_command_line_parser::{Action, BuiltInAction, CustomAction},
+    buildozer_driver::Buildozer,
+    hydrated_stream_processors::{
+        jdeps_tracker::{CompletedTargetJdeps, JdepsTracker},
+        process_bazel_failures::{TargetStory, TargetStoryAction},
+    },
+};
+
+use super::{
+    configured_bazel_runner::{ConfiguredBazelRunner, RunCompleteState},
+    processor_activity::ProcessorActivity,
+};
+
+/// Strip the output root and the compiler specific decorations from a jar path in a jdeps file,
+/// yielding the keys (package/name) of the targets which could have produced it.
+/// We yield both with and without a `lib` prefix since java rules add one and others don't.
+fn target_keys_for_jar(jar_path: &str) -> Vec<String> {
+    let relative = match jar_path.rfind("/bin/") {
+        Some(idx) => &jar_path[idx + 5..],
+        None => return Vec::default(),
+    };
+    let (package, file_name) = match relative.rsplit_once('/') {
+        Some((package, file_name)) => (package, file_name),
+        None => ("", relative),
+    };
+    let mut name = match file_name.strip_suffix(".jar") {
+        Some(n) => n,
+        None => return Vec::default(),
+    };
+    for suffix in ["-hjar", "-ijar", ".abi", "-class"] {
+        if let Some(n) = name.strip_suffix(suffix) {
+            name = n;
+        }
+    }
+
+    let mut names = vec![name];
+    if let Some(n) = name.strip_prefix("lib") {
+        names.push(n);
+    }
+    names
+        .into_iter()
+        .map(|n| {
+            if package.is_empty() {
+                n.to_string()
+            } else {
+                format!("{}/{}", package, n)
+            }
+        })
+        .collect()
+}
+
+/// Convert a dependency as listed in a BUILD file into the same package/name key form,
+/// only local (`//` or relative) labels are returned since external jars can't be edited.
+fn target_key_for_dep(target_label: &str, dep: &str) -> Option<String> {
+    let (package, name) = if let Some(absolute) = dep.strip_prefix("//") {
+        match absolute.split_once(':') {
+            Some((package, name)) => (package.to_string(), name.to_string()),
+            None => (
+                absolute.to_string(),
+                absolute.rsplit('/').next().unwrap_or(absolute).to_string(),
+            ),
+        }
+    } else if dep.contains("//") {
+        return None;
+    } else {
+        let target_package = target_label
+            .strip_prefix("//")?
+            .split(':')
+            .next()
+            .unwrap_or("");
+        let name = dep.rsplit(':').next().unwrap_or(dep);
+        (target_package.to_string(), name.to_string())
+    };
+
+    if package.is_empty() {
+        Some(name)
+    } else {
+        Some(format!("{}/{}", package, name))
+    }
+}
+
+fn used_target_keys(jdeps: &[blaze_deps::Dependencies]) -> HashSet<String> {
+    jdeps
+        .iter()
+        .flat_map(|d| d.dependency.iter())
+        .filter(|d| {
+            d.kind() == blaze_deps::dependency::Kind::Explicit
+                || d.kind() == blaze_deps::dependency::Kind::Implicit
+        })
+        .flat_map(|d| target_keys_for_jar(&d.path))
+        .collect()
+}
+
+/// Keys of every target whose jar shows up in the jdeps, used or not. Deps whose jars never show up, like java_import
+/// or external jars, or ones only needed at runtime, can't be judged from jdeps at all.
+fn classpath_target_keys<'a>(
+    jdeps: impl Iterator<Item = &'a blaze_deps::Dependencies>,
+) -> HashSet<String> {
+    jdeps
+        .flat_map(|d| d.dependency.iter())
+        .flat_map(|d| target_keys_for_jar(&d.path))
+        .collect()
+}
+
+/// The deps of a target we can be sure are unused: their jar is known to appear in jdeps yet this target's jdeps
+/// never used it, and it isn't also exported or a runtime dependency.
+fn removable_deps(
+    label: &str,
+    deps: Vec<String>,
+    used: &HashSet<String>,
+    classpath: &HashSet<String>,
+    also_needed_by: &[String],
+) -> Vec<String> {
+    let kept: HashSet<String> = also_needed_by
+        .iter()
+        .filter_map(|dep| target_key_for_dep(label, dep))
+        .collect();
+    deps.into_iter()
+        .filter(|dep| match target_key_for_dep(label, dep) {
+            Some(key) => classpath.contains(&key) && !used.contains(&key) && !kept.contains(&key),
+            None => false,
+        })
+        .collect()
+}
+
+fn load_jdeps(path: &Path) -> Option<blaze_deps::Dependencies> {
+    let content = std::fs::read(path).ok()?;
+    blaze_deps::Dependencies::decode(&*content).ok()
+}
+
+/// Labels the target exports or needs at runtime, none if either attribute can't be read.
+async fn also_needed_by<T: Buildozer>(buildozer: &T, label: &String) -> Option<Vec<String>> {
+    let mut also_needed_by = Vec::default();
+    for attribute in ["exports", "runtime_deps"] {
+        match buildozer.print_attribute(label, attribute).await {
+            Ok(labels) => also_needed_by.extend(labels),
+            Err(_) => {
+                debug!("Unable to read {} of {}, skipping", attribute, label);
+                return None;
+            }
+        }
+    }
+    Some(also_needed_by)
+}
+
+async fn remove_unused_deps<T: Buildozer>(
+    buildozer: &T,
+    completed_targets: &HashMap<String, CompletedTargetJdeps>,
+) -> HashMap<String, Vec<String>> {
+    let mut removed: HashMap<String, Vec<String>> = HashMap::default();
+
+    let mut target_jdeps: Vec<(&String, Vec<blaze_deps::Dependencies>)> = Vec::default();
+    for (label, completed) in completed_targets.iter() {
+        if !completed.success
+            || completed.jdeps_files.is_empty()
+            || !label.starts_with("//")
+            || label.contains("_auto_gen_")
+        {
+            continue;
+        }
+
+        let jdeps: Option<Vec<blaze_deps::Dependencies>> = completed
+            .jdeps_files
+            .iter()
+            .map(|p| load_jdeps(p))
+            .collect();
+        match jdeps {
+            Some(jdeps) => target_jdeps.push((label, jdeps)),
+            None => debug!("Missing or unreadable jdeps for {}, skipping", label),
+        }
+    }
+
+    for (label, jdeps) in target_jdeps.iter() {
+        let used = used_target_keys(jdeps);
+        let classpath = classpath_target_keys(jdeps.iter());
+        let deps = match buildozer.print_deps(label).await {
+            Ok(deps) => deps,
+            Err(_) => continue,
+        };
+        // Without knowing what else the target needs a dep for we can't say it's unused.
+        let also_needed_by = match also_needed_by(buildozer, label).await {
+            Some(also_needed_by) => also_needed_by,
+            None => continue,
+        };
+
+        for dep in removable_deps(label, deps, &used, &classpath, &also_needed_by).into_iter() {
+            debug!(
+                "Buildozer action: remove dependency {:?} from {:?}",
+                dep, label
+            );
+            match buildozer.remove_dependency(label, &dep).await {
+                Ok(_) => removed.entry((*label).clone()).or_default().push(dep),
+                Err(_) => warn!("Buildozer command failed"),
+            }
+        }
+    }
+    removed
+}
+
+/// When requested, build the targets, remove any deps the compiler reports as unused via jdeps,
+/// and then rebuild restoring the deps on any target which no longer builds.
+pub async fn maybe_unused_deps_mode<
+    T: Buildozer,
+    U: crate::hydrated_stream_processors::process_bazel_failures::CommandLineRunner,
+>(
+    configured_bazel_runner: &mut ConfiguredBazelRunner<T, U>,
+) -> Result<Option<RunCompleteState>, Box<dyn std::error::Error>> {
+    if configured_bazel_runner.bazel_command_line.action
+        != Some(Action::Custom(CustomAction::UnusedDeps))
+    {
+        return Ok(None);
+    }
+    configured_bazel_runner.bazel_command_line.action = Some(Action::BuiltIn(BuiltInAction::Build));
+
+    let jdeps_tracker = JdepsTracker::new();
+    configured_bazel_runner
+        .configured_bazel
+        .aes
+        .add_event_handler(Arc::new(jdeps_tracker.clone()));
+
+    let mut res_data = configured_bazel_runner.run_command_line(true).await?;
+    if res_data.final_exit_code != 0 {
+        eprintln!("Build failed, not attempting to remove unused dependencies.");
+        return Ok(Some(res_data));
+    }
+
+    let buildozer = configured_bazel_runner.process_build_failures.buildozer();
+    let completed_targets: HashMap<String, CompletedTargetJdeps> = jdeps_tracker
+        .completed_targets
+        .lock()
+        .await
+        .drain()
+        .collect();
+    let removed = remove_unused_deps(buildozer, &completed_targets).await;
+    if removed.is_empty() {
+        return Ok(Some(res_data));
+    }
+
+    let mut activity = ProcessorActivity::default();
+    for (label, deps) in removed.iter() {
+        let stories = deps
+            .iter()
+            .map(|dep| TargetStory {
+                target: label.clone(),
+                action: TargetStoryAction::RemovedDependency {
+                    removed_what: dep.clone(),
+                    why: String::from("Not referenced in the compiler's jdeps output"),
+                },
+                when: Instant::now(),
+            })
+            .collect::<Vec<TargetStory>>();
+        activity.actions_taken += stories.len() as u32;
+        activity.target_story_actions.insert(label.clone(), stories);
+    }
+
+    if configured_bazel_runner.config.dry_run {
+        res_data.total_actions_taken += activity.actions_taken;
+        let attempt = res_data.attempts;
+        res_data.story_log.extend(
+            activity
+                .target_story_actions
+                .values()
+                .flatten()
+                .map(|story| (attempt, story.clone())),
+        );
+        res_data.running_total.merge(activity, false);
+        return Ok(Some(res_data));
+    }
+
+    let (verify_activity, verify_result) = configured_bazel_runner
+        .configured_bazel
+        .spawn_bazel_attempt(&configured_bazel_runner.bazel_command_line, true)
+        .await?;
+    res_data.attempts += 1;
+    res_data.final_exit_code = verify_result.exit_code;
+    activity.merge(verify_activity, false);
+
+    if verify_result.exit_code != 0 {
+        let verified_targets = jdeps_tracker.completed_targets.lock().await;
+        for (label, deps) in removed.iter() {
+            if verified_targets.get(label).map(|e| e.success) == Some(true) {
+                continue;
+            }
+            let current_deps = buildozer.print_deps(label).await.unwrap_or_default();
+            for dep in deps.iter() {
+                if current_deps.contains(dep) {
+                    continue;
+                }
+                match buildozer.add_dependency(label, dep).await {
+                    Ok(_) => {
+                        activity.actions_taken += 1;
+                        activity
+                            .target_story_actions
+                            .entry(label.clone())
+                            .or_default()
+                            .push(TargetStory {
+                                target: label.clone(),
+                                action: TargetStoryAction::AddedDependency {
+                                    added_what: dep.clone(),
+                                    why: String::from(
+                                        "Restoring dependency, target failed to build without it",
+                                    ),
+                                },
+                                when: Instant::now(),
+                            });
+                    }
+                    Err(_) => warn!("Buildozer command failed"),
+                }
+            }
+        }
+    }
+
+    res_data.total_actions_taken += activity.actions_taken;
+    let verify_attempt = res_data.attempts;
+    res_data.story_log.extend(
+        activity
+            .target_story_actions
+            .values()
+            .flatten()
+            .map(|story| (verify_attempt, story.clone())),
+    );
+    res_data.running_total.merge(
+        activity,
+        configured_bazel_runner
+            .config
+            .disable_action_stories_on_success,
+    );
+
+    if verify_result.exit_code != 0 {
+        let final_state = configured_bazel_runner.run_command_line(true).await?;
+        let attempt_offset = res_data.attempts;
+        res_data.story_log.extend(
+            final_state
+                .story_log
+                .into_iter()
+                .map(|(attempt, story)| (attempt_offset + attempt, story)),
+        );
+        res_data.attempts += final_state.attempts;
+        res_data.final_exit_code = final_state.final_exit_code;
+        res_data.stop_reason = final_state.stop_reason;
+        res_data.total_actions_taken += final_state.total_actions_taken;
+        res_data.running_total.merge(
+            final_state.running_total,
+            configured_bazel_runner
+                .config
+                .disable_action_stories_on_success,
+        );
+    }
+
+    Ok(Some(res_data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_keys_for_jar() {
+        assert_eq!(
+            target_keys_for_jar(
+                "bazel-out/k8-fastbuild/bin/src/main/java/com/example/libfoo-hjar.jar"
+            ),
+            vec![
+                String::from("src/main/java/com/example/libfoo"),
+                String::from("src/main/java/com/example/foo"),
+            ]
+        );
+        assert_eq!(
+            target_keys_for_jar(
+                "bazel-out/k8-fastbuild/bin/src/main/scala/com/example/bar-ijar.jar"
+            ),
+            vec![String::from("src/main/scala/com/example/bar")]
+        );
+        assert_eq!(
+            target_keys_for_jar("external/maven/v1/https/repo1.maven.org/guava.jar"),
+            Vec::<String>::default()
+        );
+    }
+
+    #[test]
+    fn test_target_key_for_dep() {
+        let label = "//src/main/java/com/example:example";
+        assert_eq!(
+            target_key_for_dep(label, ":foo"),
+            Some(String::from("src/main/java/com/example/foo"))
+        );
+        assert_eq!(
+            target_key_for_dep(label, "//src/main/java/com/other"),
+            Some(String::from("src/main/java/com/other/other"))
+        );
+        assert_eq!(
+            target_key_for_dep(label, "//src/main/java/com/other:bar"),
+            Some(String::from("src/main/java/com/other/bar"))
+        );
+        assert_eq!(target_key_for_dep(label, "@maven//:guava"), None);
+    }
+
+    #[test]
+    fn test_used_target_keys() {
+        let jdeps = blaze_deps::Dependencies {
+            dependency: vec![
+                blaze_deps::Dependency {
+                    path: String::from(
+                        "bazel-out/k8-fastbuild/bin/src/main/java/com/other/libbar-hjar.jar",
+                    ),
+                    kind: blaze_deps::dependency::Kind::Explicit as i32,
+                    location: vec![],
+                },
+                blaze_deps::Dependency {
+                    path: String::from(
+                        "bazel-out/k8-fastbuild/bin/src/main/java/com/other/libunused-hjar.jar",
+                    ),
+                    kind: blaze_deps::dependency::Kind::Unused as i32,
+                    location: vec![],
+                },
+            ],
+            rule_label: Some(String::from("//src/main/java/com/example:example")),
+            success: Some(true),
+            contained_package: vec![],
+        };
+        let used = used_target_keys(std::slice::from_ref(&jdeps));
+        let label = "//src/main/java/com/example:example";
+        assert!(used.contains(&target_key_for_dep(label, "//src/main/java/com/other:bar").unwrap()));
+        assert!(
+            !used.contains(&target_key_for_dep(label, "//src/main/java/com/other:unused").unwrap())
+        );
+
+        let classpath = classpath_target_keys(std::iter::once(&jdeps));
+        let deps = vec![
+            String::from("//src/main/java/com/other:bar"),
+            String::from("//src/main/java/com/other:unused"),
+            // A java_import, its jar never shows up under bin/.
+            String::from("//third_party:lib"),
+            String::from("@maven//:guava"),
+        ];
+        assert_eq!(
+            removable_deps(label, deps.clone(), &used, &classpath, &[]),
+            vec![String::from("//src/main/java/com/other:unused")]
+        );
+        assert!(removable_deps(
+            label,
+            deps,
+            &used,
+            &classpath,
+            &[String::from("//src/main/java/com/other:unused")]
+        )
+        .is_empty());
+    }
+}
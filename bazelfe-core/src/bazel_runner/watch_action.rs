@@ -0,0 +1,291 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+    time::Instant,
+};
+
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::{
+    bazel_command_line_parser::{Action, BuiltInAction, CustomAction},
+    bazel_runner_daemon::daemon_service::{
+        FileStatus, RunnerDaemonClient, TargetsFromFilesResponse, OWNING_TARGETS_DISTANCE,
+    },
+    build_events::hydrated_stream::HydratedInfo,
+    buildozer_driver::Buildozer,
+    hydrated_stream_processors::{
+        process_bazel_failures::{TargetStory, TargetStoryAction},
+        BazelEventHandler, BuildEventResponse,
+    },
+};
+
+use super::configured_bazel_runner::ConfiguredBazelRunner;
+
+#[derive(Error, Debug)]
+pub enum WatchActionError {
+    #[error("Requested watch, but the daemon isn't running")]
+    NoDaemon,
+}
+
+/// Whether each target completed successfully in the current build, later attempts overwrite earlier ones.
+#[derive(Clone, Debug, Default)]
+struct TargetOutcomes {
+    outcomes: Arc<Mutex<HashMap<String, bool>>>,
+}
+
+impl TargetOutcomes {
+    async fn take(&self) -> HashMap<String, bool> {
+        std::mem::take(&mut *self.outcomes.lock().await)
+    }
+}
+
+#[async_trait::async_trait]
+impl BazelEventHandler for TargetOutcomes {
+    async fn process_event(
+        &self,
+        _bazel_run_id: usize,
+        event: &HydratedInfo,
+    ) -> Vec<BuildEventResponse> {
+        if let HydratedInfo::TargetComplete(tce) = event {
+            if tce.aspect.is_none() {
+                self.outcomes
+                    .lock()
+                    .await
+                    .insert(tce.label.clone(), tce.success);
+            }
+        }
+        Vec::default()
+    }
+}
+
+/// Collapse repeated changes to the same file down to the latest, most recently changed first.
+fn latest_changes(files: Vec<FileStatus>) -> Vec<FileStatus> {
+    let mut latest: HashMap<_, u128> = HashMap::default();
+    for FileStatus(path, when) in files.into_iter() {
+        let entry = latest.entry(path).or_default();
+        *entry = (*entry).max(when);
+    }
+    let mut files: Vec<FileStatus> = latest
+        .into_iter()
+        .map(|(path, when)| FileStatus(path, when))
+        .collect();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    files
+}
+
+/// A pass/fail line per target, targets bazel never reported on take the result of the build as a whole.
+fn outcome_lines(
+    targets: &[String],
+    outcomes: &HashMap<String, bool>,
+    exit_code: i32,
+) -> Vec<String> {
+    targets
+        .iter()
+        .map(|label| {
+            let success = outcomes.get(label).copied().unwrap_or(exit_code == 0);
+            format!("{} {}", if success { "✓" } else { "✗" }, label)
+        })
+        .collect()
+}
+
+fn repair_line(story: &TargetStory) -> Option<String> {
+    match &story.action {
+        TargetStoryAction::AddedDependency { added_what, .. } => {
+            Some(format!("  + added {} to {}", added_what, story.target))
+        }
+        TargetStoryAction::RemovedDependency { removed_what, .. } => Some(format!(
+            "  - removed {} from {}",
+            removed_what, story.target
+        )),
+        TargetStoryAction::RanUserAction {
+            user_action_name,
+            execution_result,
+            ..
+        } => Some(format!(
+            "  ~ ran {} for {}{}",
+            user_action_name,
+            story.target,
+            if execution_result.exit_success {
+                String::default()
+            } else {
+                format!(" (failed, {})", execution_result.exit_code_description())
+            }
+        )),
+        TargetStoryAction::Success => None,
+    }
+}
+
+/// The targets directly owning the files, waiting out any query the daemon has in flight.
+async fn owning_targets(
+    daemon_cli: &RunnerDaemonClient,
+    files: Vec<FileStatus>,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut was_in_query = false;
+    loop {
+        match daemon_cli
+            .targets_from_files(
+                tarpc::context::current(),
+                files.clone(),
+                OWNING_TARGETS_DISTANCE,
+                was_in_query,
+            )
+            .await?
+        {
+            TargetsFromFilesResponse::InQuery => {
+                if !was_in_query {
+                    eprintln!("Waiting on the daemon to finish querying targets..");
+                }
+                was_in_query = true;
+            }
+            TargetsFromFilesResponse::Targets(targets) => {
+                let labels: BTreeSet<String> =
+                    targets.iter().map(|t| t.target_label().clone()).collect();
+                return Ok(labels.into_iter().collect());
+            }
+        }
+    }
+}
+
+/// Headless alternative to autotest, on each settled set of file changes build only the targets owning them.
+/// Failures go through the usual repair loop, we print a line per target rather than the full report.
+/// Any targets given on the command line are replaced by the owning targets.
+pub async fn maybe_watch_mode<
+    T: Buildozer,
+    U: crate::hydrated_stream_processors::process_bazel_failures::CommandLineRunner,
+>(
+    configured_bazel_runner: &mut ConfiguredBazelRunner<T, U>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if configured_bazel_runner.bazel_command_line.action
+        != Some(Action::Custom(CustomAction::Watch))
+    {
+        return Ok(false);
+    }
+    configured_bazel_runner.bazel_command_line.action = Some(Action::BuiltIn(BuiltInAction::Build));
+
+    let daemon_cli = configured_bazel_runner
+        .runner_daemon
+        .clone()
+        .ok_or(WatchActionError::NoDaemon)?;
+    let debounce = configured_bazel_runner.config.daemon_config.watch_debounce;
+
+    let target_outcomes = TargetOutcomes::default();
+    configured_bazel_runner
+        .configured_bazel
+        .aes
+        .add_event_handler(Arc::new(target_outcomes.clone()));
+
+    let mut invalid_since_when = daemon_cli
+        .request_instant(tarpc::context::current())
+        .await?;
+    eprintln!("Watching for changes, ctrl-c to stop.");
+
+    loop {
+        let first_changes = daemon_cli
+            .wait_for_files(tarpc::context::current(), invalid_since_when)
+            .await?;
+        if first_changes.is_empty() {
+            continue;
+        }
+
+        // Editors and formatters tend to touch several files in quick succession, let them settle.
+        tokio::time::sleep(debounce).await;
+        let next_invalid_since_when = daemon_cli
+            .request_instant(tarpc::context::current())
+            .await?;
+        let mut changed_files = daemon_cli
+            .recently_changed_files(tarpc::context::current(), invalid_since_when)
+            .await?;
+        invalid_since_when = next_invalid_since_when;
+        changed_files.extend(first_changes);
+        let changed_files = latest_changes(changed_files);
+
+        let changed_file_count = changed_files.len();
+        let targets = owning_targets(&daemon_cli, changed_files).await?;
+        if targets.is_empty() {
+            eprintln!(
+                "{} file(s) changed, no targets own them.",
+                changed_file_count
+            );
+            continue;
+        }
+
+        configured_bazel_runner.bazel_command_line.remaining_args = targets.clone();
+        target_outcomes.take().await;
+        configured_bazel_runner
+            .configured_bazel
+            .test_results
+            .clear()
+            .await;
+        let started_at = Instant::now();
+        let mut res_data = configured_bazel_runner.run_command_line(false).await?;
+        let outcomes = target_outcomes.take().await;
+        configured_bazel_runner.record_story_journal(&res_data);
+        configured_bazel_runner
+            .record_test_history(&mut res_data)
+            .await;
+
+        for (_, story) in res_data.acted_on_story_log() {
+            if let Some(line) = repair_line(story) {
+                eprintln!("{}", line);
+            }
+        }
+        for line in outcome_lines(&targets, &outcomes, res_data.final_exit_code) {
+            eprintln!("{}", line);
+        }
+        if res_data.final_exit_code == 0 {
+            eprintln!(
+                "Built {} target(s) in {:.1}s",
+                targets.len(),
+                started_at.elapsed().as_secs_f64()
+            );
+        } else {
+            eprintln!(
+                "Build failed in {:.1}s, bazel exit code {}, {}",
+                started_at.elapsed().as_secs_f64(),
+                res_data.final_exit_code,
+                res_data.stop_reason
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_latest_changes_and_outcome_lines() {
+        let changes = latest_changes(vec![
+            FileStatus(PathBuf::from("src/a/A.scala"), 10),
+            FileStatus(PathBuf::from("src/b/B.scala"), 20),
+            FileStatus(PathBuf::from("src/a/A.scala"), 30),
+        ]);
+        assert_eq!(
+            changes,
+            vec![
+                FileStatus(PathBuf::from("src/a/A.scala"), 30),
+                FileStatus(PathBuf::from("src/b/B.scala"), 20),
+            ]
+        );
+
+        let targets = vec![
+            String::from("//src/a:a"),
+            String::from("//src/b:b"),
+            String::from("//src/c:c"),
+        ];
+        let mut outcomes = HashMap::default();
+        outcomes.insert(String::from("//src/a:a"), true);
+        outcomes.insert(String::from("//src/b:b"), false);
+
+        assert_eq!(
+            outcome_lines(&targets, &outcomes, 1),
+            vec!["✓ //src/a:a", "✗ //src/b:b", "✗ //src/c:c"]
+        );
+        assert_eq!(
+            outcome_lines(&targets[2..], &outcomes, 0),
+            vec!["✓ //src/c:c"]
+        );
+    }
+}
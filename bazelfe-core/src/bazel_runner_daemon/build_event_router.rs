@@ -0,0 +1,701 @@
+//! The daemon hosts a single BES server for every bazel-runner in the workspace.
+//! Each runner registers the invocation id it hands bazel, and pulls that invocation's events back over the daemon RPC.
+//! Bazel's build level lifecycle events only carry a build id, so we learn which invocation a build id belongs to
+//! from its tool event stream, those arriving before that stream starts are held until it does.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bazelfe_protos::*;
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use google::devtools::build::v1::publish_build_event_server::PublishBuildEvent;
+use google::devtools::build::v1::{
+    build_event, PublishBuildToolEventStreamRequest, PublishBuildToolEventStreamResponse,
+    PublishLifecycleEventRequest, StreamId,
+};
+use prost::Message;
+use tokio::sync::oneshot;
+use tonic::{Request, Response, Status};
+
+use super::daemon_service::{RoutedBuildEvent, RoutedBuildEvents, RunnerDaemonClient};
+use crate::build_events::build_event_server::{bazel_event::BazelBuildEvent, BuildEventAction};
+
+/// How many events we hand back in one call, a busy build can send thousands a second.
+const MAX_EVENTS_PER_CALL: usize = 1000;
+
+const POLL_WAIT: Duration = Duration::from_millis(500);
+
+/// How long we hold lifecycle events for a build whose tool event stream hasn't started, and how long a released
+/// invocation's build id is kept for bazel's BuildFinished, before giving up on them.
+const BUILD_ID_TTL: Duration = Duration::from_secs(300);
+
+/// Runners poll every POLL_WAIT while bazel runs, one that hasn't polled for this long has gone away.
+const ABANDONED_ROUTE_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+enum RoutedMessage {
+    Event(RoutedBuildEvent),
+    StreamFinished,
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    tx: flume::Sender<RoutedMessage>,
+    rx: flume::Receiver<RoutedMessage>,
+    last_polled: Instant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct BuildRoute {
+    invocation_id: String,
+    /// Set once the invocation's runner is done, bazel's BuildFinished can still be on its way.
+    released_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone)]
+struct PendingBuild {
+    first_seen: Instant,
+    lifecycle_events: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildEventRouter {
+    routes: Arc<DashMap<String, Route>>,
+    build_id_to_invocation: Arc<DashMap<String, BuildRoute>>,
+    /// Encoded lifecycle events by build id, received before we knew which invocation the build belongs to.
+    pending_lifecycle_events: Arc<DashMap<String, PendingBuild>>,
+    most_recent_call: Arc<AtomicUsize>,
+}
+
+impl BuildEventRouter {
+    /// Every event received counts as activity, so the daemon stays up while builds are running.
+    pub fn new(most_recent_call: Arc<AtomicUsize>) -> Self {
+        Self {
+            routes: Arc::new(DashMap::new()),
+            build_id_to_invocation: Arc::new(DashMap::new()),
+            pending_lifecycle_events: Arc::new(DashMap::new()),
+            most_recent_call,
+        }
+    }
+
+    pub fn register(&self, invocation_id: &str) {
+        self.evict_stale();
+        let (tx, rx) = flume::unbounded();
+        self.routes.insert(
+            invocation_id.to_string(),
+            Route {
+                tx,
+                rx,
+                last_polled: Instant::now(),
+            },
+        );
+    }
+
+    /// The invocation's build ids stay mapped until bazel finishes the build, so its last lifecycle events
+    /// aren't mistaken for those of a build we haven't seen yet.
+    pub fn release(&self, invocation_id: &str) {
+        self.routes.remove(invocation_id);
+        let now = Instant::now();
+        for mut build_route in self.build_id_to_invocation.iter_mut() {
+            if build_route.invocation_id == invocation_id && build_route.released_at.is_none() {
+                build_route.released_at = Some(now);
+            }
+        }
+    }
+
+    /// Forget runners that stopped polling, and builds we've waited on for too long.
+    fn evict_stale(&self) {
+        let now = Instant::now();
+        let abandoned: Vec<String> = self
+            .routes
+            .iter()
+            .filter(|route| now.duration_since(route.last_polled) > ABANDONED_ROUTE_AFTER)
+            .map(|route| route.key().clone())
+            .collect();
+        for invocation_id in abandoned.iter() {
+            warn!(
+                "Dropping the route for invocation {}, its runner stopped asking for events",
+                invocation_id
+            );
+            self.release(invocation_id);
+        }
+        self.build_id_to_invocation.retain(|_, build_route| {
+            build_route
+                .released_at
+                .map(|at| now.duration_since(at) < BUILD_ID_TTL)
+                .unwrap_or(true)
+        });
+        self.pending_lifecycle_events
+            .retain(|_, pending| now.duration_since(pending.first_seen) < BUILD_ID_TTL);
+    }
+
+    pub async fn next_events(&self, invocation_id: &str, wait: Duration) -> RoutedBuildEvents {
+        let rx = match self.routes.get_mut(invocation_id) {
+            Some(mut route) => {
+                route.last_polled = Instant::now();
+                route.rx.clone()
+            }
+            None => {
+                return RoutedBuildEvents {
+                    events: Vec::default(),
+                    finished: true,
+                }
+            }
+        };
+
+        let mut events = Vec::default();
+        let mut next = tokio::time::timeout(wait, rx.recv_async())
+            .await
+            .ok()
+            .and_then(|r| r.ok());
+        while let Some(message) = next.take() {
+            match message {
+                RoutedMessage::Event(e) => events.push(e),
+                RoutedMessage::StreamFinished => {
+                    self.release(invocation_id);
+                    return RoutedBuildEvents {
+                        events,
+                        finished: true,
+                    };
+                }
+            }
+            if events.len() >= MAX_EVENTS_PER_CALL {
+                break;
+            }
+            next = rx.try_recv().ok();
+        }
+        RoutedBuildEvents {
+            events,
+            finished: false,
+        }
+    }
+
+    fn route_for(&self, stream_id: &StreamId) -> Option<flume::Sender<RoutedMessage>> {
+        let invocation_id = if stream_id.invocation_id.is_empty() {
+            self.build_id_to_invocation
+                .get(&stream_id.build_id)?
+                .invocation_id
+                .clone()
+        } else {
+            stream_id.invocation_id.clone()
+        };
+        self.routes.get(&invocation_id).map(|r| r.tx.clone())
+    }
+
+    /// Called with the first event of each tool event stream.
+    fn start_stream(&self, stream_id: &StreamId) -> Option<flume::Sender<RoutedMessage>> {
+        self.evict_stale();
+        let route = self.route_for(stream_id);
+        let pending = self
+            .pending_lifecycle_events
+            .remove(&stream_id.build_id)
+            .map(|(_, pending)| pending.lifecycle_events)
+            .unwrap_or_default();
+        if let Some(tx) = route.as_ref() {
+            if !stream_id.build_id.is_empty() && !stream_id.invocation_id.is_empty() {
+                self.build_id_to_invocation.insert(
+                    stream_id.build_id.clone(),
+                    BuildRoute {
+                        invocation_id: stream_id.invocation_id.clone(),
+                        released_at: None,
+                    },
+                );
+            }
+            for event in pending.into_iter() {
+                let _ = tx.send(RoutedMessage::Event(RoutedBuildEvent::LifecycleEvent(
+                    event,
+                )));
+            }
+            debug!(
+                "Routing build events for invocation {} (build {})",
+                stream_id.invocation_id, stream_id.build_id
+            );
+        } else {
+            warn!(
+                "No runner registered for invocation {} (build {}), its events won't be routed",
+                stream_id.invocation_id, stream_id.build_id
+            );
+        }
+        route
+    }
+
+    /// Hold a lifecycle event until the build's tool event stream tells us which invocation it's for.
+    fn queue_lifecycle_event(&self, build_id: &str, event: Vec<u8>) {
+        if build_id.is_empty() {
+            return;
+        }
+        self.pending_lifecycle_events
+            .entry(build_id.to_string())
+            .or_insert_with(|| PendingBuild {
+                first_seen: Instant::now(),
+                lifecycle_events: Vec::default(),
+            })
+            .lifecycle_events
+            .push(event);
+    }
+}
+
+#[tonic::async_trait]
+impl PublishBuildEvent for BuildEventRouter {
+    type PublishBuildToolEventStreamStream = Pin<
+        Box<
+            dyn Stream<Item = Result<PublishBuildToolEventStreamResponse, Status>> + Send + 'static,
+        >,
+    >;
+
+    async fn publish_build_tool_event_stream(
+        &self,
+        request: Request<tonic::Streaming<PublishBuildToolEventStreamRequest>>,
+    ) -> Result<Response<Self::PublishBuildToolEventStreamStream>, Status> {
+        let mut stream = request.into_inner();
+        let router = self.clone();
+
+        let output = async_stream::try_stream! {
+            let mut route = None;
+            let mut started = false;
+            while let Some(inbound_evt) = stream.next().await {
+                let inbound_evt = inbound_evt?;
+                router.most_recent_call.fetch_add(1, Ordering::Release);
+
+                let ack = inbound_evt.ordered_build_event.as_ref().map(|build_event| {
+                    if !started {
+                        started = true;
+                        route = build_event
+                            .stream_id
+                            .as_ref()
+                            .and_then(|stream_id| router.start_stream(stream_id));
+                    }
+                    PublishBuildToolEventStreamResponse {
+                        stream_id: build_event.stream_id.clone(),
+                        sequence_number: build_event.sequence_number,
+                    }
+                });
+
+                // Routed before we ack, so anything bazel sends after the ack is queued behind it.
+                if let Some(tx) = route.as_ref() {
+                    let _ = tx.send(RoutedMessage::Event(RoutedBuildEvent::BuildToolEvent(
+                        inbound_evt.encode_to_vec(),
+                    )));
+                }
+                if let Some(ack) = ack {
+                    yield ack;
+                }
+            }
+
+            if let Some(tx) = route {
+                let _ = tx.send(RoutedMessage::StreamFinished);
+            }
+        };
+
+        Ok(Response::new(
+            Box::pin(output) as Self::PublishBuildToolEventStreamStream
+        ))
+    }
+
+    async fn publish_lifecycle_event(
+        &self,
+        request: Request<PublishLifecycleEventRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.most_recent_call.fetch_add(1, Ordering::Release);
+        self.evict_stale();
+        let inner = request.into_inner();
+        let build_finished = matches!(
+            inner
+                .build_event
+                .as_ref()
+                .and_then(|e| e.event.as_ref())
+                .and_then(|e| e.event.as_ref()),
+            Some(build_event::Event::BuildFinished(_))
+        );
+        let stream_id = match inner
+            .build_event
+            .as_ref()
+            .and_then(|e| e.stream_id.as_ref())
+        {
+            Some(stream_id) => stream_id,
+            None => return Ok(Response::new(())),
+        };
+        match self.route_for(stream_id) {
+            Some(tx) => {
+                let _ = tx.send(RoutedMessage::Event(RoutedBuildEvent::LifecycleEvent(
+                    inner.encode_to_vec(),
+                )));
+            }
+            // Bazel can announce a build before it starts the tool event stream we learn the invocation from.
+            None if stream_id.invocation_id.is_empty()
+                && !self
+                    .build_id_to_invocation
+                    .contains_key(&stream_id.build_id) =>
+            {
+                self.queue_lifecycle_event(&stream_id.build_id, inner.encode_to_vec())
+            }
+            None => (),
+        }
+        if build_finished {
+            self.build_id_to_invocation.remove(&stream_id.build_id);
+            self.pending_lifecycle_events.remove(&stream_id.build_id);
+        }
+        Ok(Response::new(()))
+    }
+}
+
+/// Bazel insists the invocation id is a UUID.
+pub fn new_bazel_invocation_id() -> String {
+    let mut v = rand::random::<u128>();
+    // Version 4, variant 1.
+    v = (v & !(0xf_u128 << 76)) | (0x4_u128 << 76);
+    v = (v & !(0x3_u128 << 62)) | (0x2_u128 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        v >> 96,
+        (v >> 80) & 0xffff,
+        (v >> 64) & 0xffff,
+        (v >> 48) & 0xffff,
+        v & 0xffff_ffff_ffff
+    )
+}
+
+/// The runner side of the routing, feeds the events the daemon routes to this invocation into the same channel
+/// our own BES server would. Once bazel has exited it has finished uploading, so we stop at the first empty poll after that.
+pub async fn forward_routed_build_events(
+    client: RunnerDaemonClient,
+    invocation_id: String,
+    tx: async_channel::Sender<BuildEventAction<BazelBuildEvent>>,
+    mut bazel_finished: oneshot::Receiver<()>,
+) {
+    loop {
+        let finished = !matches!(
+            bazel_finished.try_recv(),
+            Err(oneshot::error::TryRecvError::Empty)
+        );
+
+        let routed = match client
+            .next_build_events(
+                tarpc::context::current(),
+                invocation_id.clone(),
+                POLL_WAIT.as_millis() as u64,
+            )
+            .await
+        {
+            Ok(routed) => routed,
+            Err(e) => {
+                warn!(
+                    "Failed fetching build events for {} from the daemon: {}",
+                    invocation_id, e
+                );
+                break;
+            }
+        };
+        let received_events = !routed.events.is_empty();
+
+        for event in routed.events.into_iter() {
+            let action = match event {
+                RoutedBuildEvent::BuildToolEvent(bytes) => {
+                    PublishBuildToolEventStreamRequest::decode(&bytes[..])
+                        .ok()
+                        .and_then(|mut e| BazelBuildEvent::transform_from(&mut e))
+                        .map(BuildEventAction::BuildEvent)
+                }
+                RoutedBuildEvent::LifecycleEvent(bytes) => {
+                    PublishLifecycleEventRequest::decode(&bytes[..])
+                        .ok()
+                        .map(BuildEventAction::LifecycleEvent)
+                }
+            };
+            if let Some(action) = action {
+                if tx.send(action).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        if routed.finished || (finished && !received_events) {
+            break;
+        }
+    }
+    let _ = client
+        .release_build_events(tarpc::context::current(), invocation_id)
+        .await;
+    let _ = tx.send(BuildEventAction::BuildCompleted).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use google::devtools::build::v1::publish_build_event_client::PublishBuildEventClient;
+    use google::devtools::build::v1::publish_build_event_server::PublishBuildEventServer;
+    use google::devtools::build::v1::{BuildEvent, OrderedBuildEvent};
+
+    fn stream_id(build_id: &str, invocation_id: &str) -> Option<StreamId> {
+        Some(StreamId {
+            build_id: build_id.to_string(),
+            invocation_id: invocation_id.to_string(),
+            component: 0,
+        })
+    }
+
+    fn build_finished(build_id: &str) -> PublishLifecycleEventRequest {
+        PublishLifecycleEventRequest {
+            build_event: Some(OrderedBuildEvent {
+                stream_id: stream_id(build_id, ""),
+                sequence_number: 2,
+                event: Some(BuildEvent {
+                    event_time: None,
+                    event: Some(build_event::Event::BuildFinished(
+                        build_event::BuildFinished::default(),
+                    )),
+                }),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn tool_event(
+        build_id: &str,
+        invocation_id: &str,
+        seq: i64,
+    ) -> PublishBuildToolEventStreamRequest {
+        PublishBuildToolEventStreamRequest {
+            ordered_build_event: Some(OrderedBuildEvent {
+                stream_id: stream_id(build_id, invocation_id),
+                sequence_number: seq,
+                event: Some(BuildEvent::default()),
+            }),
+            ..Default::default()
+        }
+    }
+
+    async fn drain(router: &BuildEventRouter, invocation_id: &str) -> Vec<RoutedBuildEvent> {
+        let mut events = Vec::default();
+        loop {
+            let routed = router
+                .next_events(invocation_id, Duration::from_secs(5))
+                .await;
+            events.extend(routed.events);
+            if routed.finished {
+                return events;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_demultiplexes_concurrent_streams() {
+        let router = BuildEventRouter::new(Arc::new(AtomicUsize::new(0)));
+        router.register("invocation-a");
+        router.register("invocation-b");
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = router.clone();
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(PublishBuildEventServer::new(server))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+                .await
+                .unwrap();
+        });
+        let mut client = PublishBuildEventClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+
+        // Keep invocation a's stream open while the others run.
+        let (a_tx, a_rx) = tokio::sync::mpsc::unbounded_channel();
+        a_tx.send(tool_event("build-a", "invocation-a", 1)).unwrap();
+        let mut a_acks = client
+            .publish_build_tool_event_stream(tokio_stream::wrappers::UnboundedReceiverStream::new(
+                a_rx,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(a_acks.message().await.unwrap().unwrap().sequence_number, 1);
+
+        for invocation_id in ["invocation-b", "not-registered"] {
+            let events: Vec<PublishBuildToolEventStreamRequest> = (1..=3)
+                .map(|seq| tool_event("build-b", invocation_id, seq))
+                .collect();
+            let acks: Vec<i64> = client
+                .publish_build_tool_event_stream(futures::stream::iter(events))
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|r| r.unwrap().sequence_number)
+                .collect()
+                .await;
+            assert_eq!(acks, vec![1, 2, 3]);
+        }
+
+        // Build level lifecycle events only carry the build id.
+        let lifecycle = PublishLifecycleEventRequest {
+            build_event: Some(OrderedBuildEvent {
+                stream_id: stream_id("build-a", ""),
+                sequence_number: 1,
+                event: None,
+            }),
+            ..Default::default()
+        };
+        client
+            .publish_lifecycle_event(lifecycle.clone())
+            .await
+            .unwrap();
+        a_tx.send(tool_event("build-a", "invocation-a", 2)).unwrap();
+        drop(a_tx);
+        while a_acks.message().await.unwrap().is_some() {}
+
+        assert_eq!(
+            drain(&router, "invocation-a").await,
+            vec![
+                RoutedBuildEvent::BuildToolEvent(
+                    tool_event("build-a", "invocation-a", 1).encode_to_vec()
+                ),
+                RoutedBuildEvent::LifecycleEvent(lifecycle.encode_to_vec()),
+                RoutedBuildEvent::BuildToolEvent(
+                    tool_event("build-a", "invocation-a", 2).encode_to_vec()
+                ),
+            ]
+        );
+        assert_eq!(
+            drain(&router, "invocation-b").await,
+            (1..=3)
+                .map(|seq| RoutedBuildEvent::BuildToolEvent(
+                    tool_event("build-b", "invocation-b", seq).encode_to_vec()
+                ))
+                .collect::<Vec<_>>()
+        );
+
+        // Finished invocations are forgotten.
+        assert_eq!(
+            router
+                .next_events("invocation-a", Duration::from_millis(10))
+                .await,
+            RoutedBuildEvents {
+                events: Vec::default(),
+                finished: true
+            }
+        );
+        assert!(router.routes.is_empty());
+
+        // The build ids are kept until bazel says the builds finished.
+        assert_eq!(router.build_id_to_invocation.len(), 2);
+        assert!(router
+            .build_id_to_invocation
+            .iter()
+            .all(|build_route| build_route.released_at.is_some()));
+        for build_id in ["build-a", "build-b"] {
+            client
+                .publish_lifecycle_event(build_finished(build_id))
+                .await
+                .unwrap();
+        }
+        assert!(router.build_id_to_invocation.is_empty());
+        assert!(router.pending_lifecycle_events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stale_routes_and_builds_are_evicted() {
+        let router = BuildEventRouter::new(Arc::new(AtomicUsize::new(0)));
+        router.register("invocation-crashed");
+        router.register("invocation-live");
+        router.start_stream(&stream_id("build-crashed", "invocation-crashed").unwrap());
+        router
+            .publish_lifecycle_event(Request::new(PublishLifecycleEventRequest {
+                build_event: Some(OrderedBuildEvent {
+                    stream_id: stream_id("build-never-streamed", ""),
+                    sequence_number: 1,
+                    event: None,
+                }),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+
+        let long_ago = |age: Duration| Instant::now().checked_sub(age * 2).unwrap();
+        router
+            .routes
+            .get_mut("invocation-crashed")
+            .unwrap()
+            .last_polled = long_ago(ABANDONED_ROUTE_AFTER);
+        router.evict_stale();
+        assert!(!router.routes.contains_key("invocation-crashed"));
+        assert!(router.routes.contains_key("invocation-live"));
+        assert!(router
+            .build_id_to_invocation
+            .get("build-crashed")
+            .unwrap()
+            .released_at
+            .is_some());
+        assert_eq!(router.pending_lifecycle_events.len(), 1);
+
+        router
+            .build_id_to_invocation
+            .get_mut("build-crashed")
+            .unwrap()
+            .released_at = Some(long_ago(BUILD_ID_TTL));
+        router
+            .pending_lifecycle_events
+            .get_mut("build-never-streamed")
+            .unwrap()
+            .first_seen = long_ago(BUILD_ID_TTL);
+        router.evict_stale();
+        assert!(router.build_id_to_invocation.is_empty());
+        assert!(router.pending_lifecycle_events.is_empty());
+        assert!(router.routes.contains_key("invocation-live"));
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events_before_the_tool_stream_are_held() {
+        let router = BuildEventRouter::new(Arc::new(AtomicUsize::new(0)));
+        router.register("invocation-c");
+
+        let lifecycle = |build_id: &str| PublishLifecycleEventRequest {
+            build_event: Some(OrderedBuildEvent {
+                stream_id: stream_id(build_id, ""),
+                sequence_number: 1,
+                event: None,
+            }),
+            ..Default::default()
+        };
+        for build_id in ["build-c", "build-unregistered"] {
+            router
+                .publish_lifecycle_event(Request::new(lifecycle(build_id)))
+                .await
+                .unwrap();
+        }
+        assert_eq!(router.pending_lifecycle_events.len(), 2);
+
+        let tx = router
+            .start_stream(&stream_id("build-c", "invocation-c").unwrap())
+            .unwrap();
+        assert!(router
+            .start_stream(&stream_id("build-unregistered", "not-registered").unwrap())
+            .is_none());
+        assert!(router.pending_lifecycle_events.is_empty());
+
+        tx.send(RoutedMessage::Event(RoutedBuildEvent::BuildToolEvent(
+            tool_event("build-c", "invocation-c", 1).encode_to_vec(),
+        )))
+        .unwrap();
+        tx.send(RoutedMessage::StreamFinished).unwrap();
+        assert_eq!(
+            drain(&router, "invocation-c").await,
+            vec![
+                RoutedBuildEvent::LifecycleEvent(lifecycle("build-c").encode_to_vec()),
+                RoutedBuildEvent::BuildToolEvent(
+                    tool_event("build-c", "invocation-c", 1).encode_to_vec()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invocation_id_is_a_uuid() {
+        let id = new_bazel_invocation_id();
+        let groups: Vec<usize> = id.split('-').map(|g| g.len()).collect();
+        assert_eq!(groups, vec![8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]));
+    }
+}
@@ -7,6 +7,9 @@ use serde::{Deserialize, Serialize};
 
 use super::DaemonPaths;
 
+// Written into each communication folder so we can tell which workspace it belongs to.
+const WORKSPACE_MARKER: &str = "workspace_path";
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct HydratedDaemonConfig {
     pub daemon_config: DaemonConfig,
@@ -20,6 +23,7 @@ async fn start_server(
 ) -> Result<(), Box<dyn Error>> {
     let _ = std::fs::remove_file(&paths.pid_path);
     let _ = std::fs::remove_file(&paths.socket_path);
+    remove_stale_communication_folders(daemon_config);
 
     let merged_config = HydratedDaemonConfig {
         daemon_config: daemon_config.clone(),
@@ -42,7 +46,7 @@ async fn start_server(
     Ok(())
 }
 
-pub(in crate) async fn try_kill_server_from_cfg(daemon_config: &DaemonConfig) {
+pub(crate) async fn try_kill_server_from_cfg(daemon_config: &DaemonConfig) {
     if let Ok(daemon_communication_ptr) = configure_communication_ptr(&daemon_config) {
         let paths = daemon_paths_from_access(&daemon_communication_ptr);
 
@@ -52,6 +56,84 @@ pub(in crate) async fn try_kill_server_from_cfg(daemon_config: &DaemonConfig) {
     }
 }
 
+/// Stop the daemon for this workspace, returning its pid if one was running.
+/// We ask it to exit first so it can save its state, only killing it if it doesn't.
+pub async fn stop_server(daemon_config: &DaemonConfig) -> Result<Option<i32>, Box<dyn Error>> {
+    let daemon_communication_ptr = configure_communication_ptr(daemon_config)?;
+    let paths = daemon_paths_from_access(&daemon_communication_ptr);
+
+    let pid = match super::read_pid(&paths) {
+        Some(pid) if signal_mgr::process_is_alive(pid) => pid,
+        _ => return Ok(None),
+    };
+
+    if let Ok(Some(cli)) = maybe_connect_to_server(&paths, &super::current_executable_id()).await {
+        let _ = cli.shutdown(tarpc::context::current()).await;
+        for _ in 0..50 {
+            if !signal_mgr::process_is_alive(pid) {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+    }
+
+    if signal_mgr::process_is_alive(pid) {
+        signal_mgr::kill(pid);
+    }
+    let _ = std::fs::remove_file(&paths.pid_path);
+    let _ = std::fs::remove_file(&paths.socket_path);
+    Ok(Some(pid))
+}
+
+/// The daemon's stdout and stderr logs.
+pub fn log_paths(daemon_config: &DaemonConfig) -> Vec<PathBuf> {
+    super::make_paths(&daemon_config.daemon_communication_folder).collect()
+}
+
+/// Communication folders, other than our own, whose workspace has since been deleted.
+/// Folders without a workspace marker aren't ours to judge, so are left alone.
+fn stale_communication_folders(daemons_root: &Path, current: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(daemons_root) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::default(),
+    };
+    entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p != current && p.is_dir())
+        .filter(
+            |p| match std::fs::read_to_string(p.join(WORKSPACE_MARKER)) {
+                Ok(workspace) => !Path::new(workspace.trim()).exists(),
+                Err(_) => false,
+            },
+        )
+        .collect()
+}
+
+/// Remove the communication folders of workspaces which no longer exist.
+/// We don't signal whatever pid a folder names, it may since belong to something else. A daemon still
+/// running there quits by itself once its pid file is gone.
+pub fn remove_stale_communication_folders(daemon_config: &DaemonConfig) {
+    let current = &daemon_config.daemon_communication_folder;
+    let daemons_root = match current.parent() {
+        Some(root) => root,
+        None => return,
+    };
+    for folder in stale_communication_folders(daemons_root, current) {
+        match std::fs::remove_dir_all(&folder) {
+            Ok(_) => eprintln!(
+                "Removed daemon folder {} as its workspace no longer exists",
+                folder.to_string_lossy()
+            ),
+            Err(e) => eprintln!(
+                "Failed to remove stale daemon folder {}: {}",
+                folder.to_string_lossy(),
+                e
+            ),
+        }
+    }
+}
+
 async fn try_kill_server(paths: &DaemonPaths) {
     if let Some(pid) = super::read_pid(paths) {
         signal_mgr::kill(pid)
@@ -144,6 +226,12 @@ fn configure_communication_ptr(daemon_config: &DaemonConfig) -> Result<PathBuf,
         )
         .into());
     }
+    std::fs::write(
+        daemon_config
+            .daemon_communication_folder
+            .join(WORKSPACE_MARKER),
+        current_dir.to_string_lossy().as_bytes(),
+    )?;
 
     let bazelfe_path = current_dir.join("bazel-bazelfe");
 
@@ -166,6 +254,15 @@ fn configure_communication_ptr(daemon_config: &DaemonConfig) -> Result<PathBuf,
     }
     Ok(bazelfe_path)
 }
+/// Connect to the daemon for this workspace if one is already up, never starting one.
+pub async fn connect_to_running_server(
+    daemon_config: &DaemonConfig,
+) -> Result<Option<super::daemon_service::RunnerDaemonClient>, Box<dyn Error>> {
+    let daemon_communication_ptr = configure_communication_ptr(daemon_config)?;
+    let paths = daemon_paths_from_access(&daemon_communication_ptr);
+    maybe_connect_to_server(&paths, &super::current_executable_id()).await
+}
+
 pub async fn connect_to_server(
     daemon_config: &DaemonConfig,
     bazel_binary_path: &Path,
@@ -197,3 +294,37 @@ pub async fn connect_to_server(
 
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_communication_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path().join("workspace");
+        let daemons_root = dir.path().join("daemons");
+        std::fs::create_dir_all(&workspace).unwrap();
+
+        let marked = |name: &str, workspace: &Path| {
+            let folder = daemons_root.join(name);
+            std::fs::create_dir_all(&folder).unwrap();
+            std::fs::write(
+                folder.join(WORKSPACE_MARKER),
+                workspace.to_string_lossy().as_bytes(),
+            )
+            .unwrap();
+            folder
+        };
+        let current = marked("current", &dir.path().join("gone"));
+        marked("live", &workspace);
+        let stale = marked("stale", &dir.path().join("gone"));
+        std::fs::create_dir_all(daemons_root.join("unmarked")).unwrap();
+
+        assert_eq!(
+            stale_communication_folders(&daemons_root, &current),
+            vec![stale]
+        );
+        assert!(stale_communication_folders(&dir.path().join("missing"), &current).is_empty());
+    }
+}
@@ -5,7 +5,7 @@ use std::{
     collections::{HashMap, HashSet},
     ops::{Add, Sub},
     path::PathBuf,
-    sync::atomic::{AtomicU32, AtomicUsize},
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize},
     time::Duration,
 };
 
@@ -15,6 +15,8 @@ use tarpc::serde_transport as transport;
 use tarpc::server::Channel;
 use tokio::{sync::Mutex, task::JoinHandle};
 
+use super::query_graph::{self, GraphChange};
+use super::target_state_snapshot::{self, TargetStatePersistence, TargetStateSnapshot};
 use crate::config::DaemonConfig;
 use crate::{
     bazel_runner_daemon::daemon_service::RunnerDaemon, config::daemon_config::NotifyRegexes,
@@ -33,25 +35,25 @@ struct Daemon {
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Distance(pub u16);
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, serde::Deserialize, serde::Serialize)]
 pub enum TargetType {
     Rule(RuleTarget),
     Src(SrcFileTarget),
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, serde::Deserialize, serde::Serialize)]
 pub struct RuleTarget {
     pub target_label: String,
     pub target_kind: String,
     pub is_test: bool,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, serde::Deserialize, serde::Serialize)]
 pub struct SrcFileTarget {
     pub target_label: String,
 }
 
-#[derive(Debug, Copy, PartialEq, Eq, Hash, Clone)]
-pub struct TargetId(u32);
+#[derive(Debug, Copy, PartialEq, Eq, Hash, Clone, serde::Deserialize, serde::Serialize)]
+pub struct TargetId(pub(in crate::bazel_runner_daemon) u32);
 
 #[derive(Debug)]
 struct TargetState {
@@ -60,6 +62,12 @@ struct TargetState {
     target_id_to_details: DashMap<TargetId, TargetType>,
     label_string_to_id: DashMap<String, TargetId>,
     max_target_id: AtomicU32,
+    // Reverse dependencies of the targets of dropped packages, by label on both ends. Querying a package again only
+    // finds its own deps, so these get reattached once the targets are back under their new ids.
+    detached_rdeps: DashMap<String, HashSet<String>>,
+    // Held for the whole of each graph update, a refresh drops, hydrates then reattaches and nothing else may
+    // change the graph in between.
+    graph_updates: Mutex<()>,
 }
 impl Default for TargetState {
     fn default() -> Self {
@@ -69,6 +77,8 @@ impl Default for TargetState {
             target_id_to_details: Default::default(),
             label_string_to_id: Default::default(),
             max_target_id: AtomicU32::new(0),
+            detached_rdeps: Default::default(),
+            graph_updates: Default::default(),
         }
     }
 }
@@ -90,12 +100,314 @@ fn target_as_path(s: &String) -> Option<PathBuf> {
         None
     }
 }
+impl TargetType {
+    pub(in crate::bazel_runner_daemon) fn target_label(&self) -> &String {
+        match self {
+            TargetType::Rule(r) => &r.target_label,
+            TargetType::Src(s) => &s.target_label,
+        }
+    }
+
+    /// Rules as clients see them, source files aren't something to build.
+    fn as_service_target(&self) -> Option<super::daemon_service::Targets> {
+        match self {
+            TargetType::Rule(r) => {
+                if r.is_test {
+                    Some(super::daemon_service::Targets::Test(
+                        super::daemon_service::TestTarget {
+                            target_label: r.target_label.clone(),
+                        },
+                    ))
+                } else {
+                    Some(super::daemon_service::Targets::Build(
+                        super::daemon_service::BuildTarget {
+                            target_label: r.target_label.clone(),
+                        },
+                    ))
+                }
+            }
+            TargetType::Src(_) => None,
+        }
+    }
+}
+
 impl TargetState {
+    fn from_snapshot(snapshot: TargetStateSnapshot) -> Self {
+        Self {
+            src_file_to_target: snapshot.src_file_to_target.into_iter().collect(),
+            target_to_rdeps: snapshot
+                .target_to_rdeps
+                .into_iter()
+                .map(|(id, rdeps)| (id, rdeps.into_iter().collect()))
+                .collect(),
+            target_id_to_details: snapshot.target_id_to_details.into_iter().collect(),
+            label_string_to_id: snapshot.label_string_to_id.into_iter().collect(),
+            max_target_id: AtomicU32::new(snapshot.max_target_id),
+            detached_rdeps: Default::default(),
+            graph_updates: Default::default(),
+        }
+    }
+
+    fn snapshot(&self, persistence: &TargetStatePersistence) -> TargetStateSnapshot {
+        let mut snapshot = persistence.new_snapshot();
+        snapshot.package_hashes = self
+            .known_packages()
+            .into_iter()
+            .map(|package| {
+                let hash = target_state_snapshot::build_file_hash(&persistence.workspace, &package);
+                (package, hash)
+            })
+            .collect();
+        snapshot.src_file_to_target = self
+            .src_file_to_target
+            .iter()
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+        snapshot.target_to_rdeps = self
+            .target_to_rdeps
+            .iter()
+            .map(|e| (*e.key(), e.value().iter().copied().collect()))
+            .collect();
+        snapshot.target_id_to_details = self
+            .target_id_to_details
+            .iter()
+            .map(|e| (*e.key(), e.value().clone()))
+            .collect();
+        snapshot.label_string_to_id = self
+            .label_string_to_id
+            .iter()
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+        snapshot.max_target_id = self.max_target_id.load(Ordering::Acquire);
+        snapshot
+    }
+
+    /// The rules distance hops up the reverse dependencies of the files.
+    /// Files are targets of their own, so the rules owning them are at OWNING_TARGETS_DISTANCE.
+    fn targets_for_files(
+        &self,
+        files: &[super::daemon_service::FileStatus],
+        distance: u32,
+    ) -> Vec<super::daemon_service::Targets> {
+        let mut active_targets_ids: HashSet<TargetId> = files
+            .iter()
+            .filter_map(|f| self.src_file_to_target.get(&f.0).map(|e| *e.value()))
+            .collect();
+
+        for _ in 0..distance {
+            let mut next_targets: HashSet<TargetId> = HashSet::default();
+
+            for e in active_targets_ids.iter() {
+                if let Some(rdeps) = self.target_to_rdeps.get(e) {
+                    for rdep in rdeps.value() {
+                        next_targets.insert(*rdep);
+                    }
+                }
+            }
+            active_targets_ids = next_targets;
+        }
+
+        active_targets_ids
+            .into_iter()
+            .filter_map(|rt| {
+                self.target_id_to_details
+                    .get(&rt)
+                    .and_then(|t| t.value().as_service_target())
+            })
+            .collect()
+    }
+
+    /// Breadth first over the reverse dependencies, each target only appears at the first distance it's reached.
+    fn rdeps_by_distance(&self, target: TargetId, max_distance: u32) -> Vec<Vec<TargetId>> {
+        let mut visited: HashSet<TargetId> = std::iter::once(target).collect();
+        let mut frontier = vec![target];
+        let mut by_distance = Vec::default();
+        for _ in 0..max_distance {
+            let mut next: Vec<TargetId> = Vec::default();
+            for id in frontier.iter() {
+                if let Some(rdeps) = self.target_to_rdeps.get(id) {
+                    for rdep in rdeps.value() {
+                        if visited.insert(*rdep) {
+                            next.push(*rdep);
+                        }
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            by_distance.push(next.clone());
+            frontier = next;
+        }
+        by_distance
+    }
+
+    fn known_packages(&self) -> HashSet<PathBuf> {
+        self.target_id_to_details
+            .iter()
+            .filter_map(|e| target_state_snapshot::package_of_label(e.value().target_label()))
+            .collect()
+    }
+
+    /// Forget every target in these packages along with their edges, hydrating the package again then queries it
+    /// from scratch. Their reverse dependencies are kept aside to be reattached by label.
+    /// Returns the packages we actually held targets for.
+    fn drop_packages(&self, packages: &HashSet<PathBuf>) -> HashSet<PathBuf> {
+        let mut dropped_packages: HashSet<PathBuf> = HashSet::default();
+        let dropped: HashSet<TargetId> = self
+            .target_id_to_details
+            .iter()
+            .filter_map(|e| {
+                let package = target_state_snapshot::package_of_label(e.value().target_label())?;
+                if packages.contains(&package) {
+                    dropped_packages.insert(package);
+                    Some(*e.key())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for id in dropped.iter() {
+            let rdep_labels: HashSet<String> = self
+                .target_to_rdeps
+                .get(id)
+                .map(|rdeps| {
+                    rdeps
+                        .value()
+                        .iter()
+                        .filter_map(|rdep| {
+                            self.target_id_to_details
+                                .get(rdep)
+                                .map(|details| details.value().target_label().clone())
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            if let Some(details) = self.target_id_to_details.get(id) {
+                if !rdep_labels.is_empty() {
+                    self.detached_rdeps
+                        .entry(details.value().target_label().clone())
+                        .or_default()
+                        .extend(rdep_labels);
+                }
+            }
+        }
+
+        self.target_id_to_details
+            .retain(|id, _| !dropped.contains(id));
+        self.label_string_to_id
+            .retain(|_, id| !dropped.contains(id));
+        self.src_file_to_target
+            .retain(|_, id| !dropped.contains(id));
+        self.target_to_rdeps.retain(|id, _| !dropped.contains(id));
+        for mut rdeps in self.target_to_rdeps.iter_mut() {
+            rdeps.value_mut().retain(|id| !dropped.contains(id));
+        }
+        dropped_packages
+    }
+
+    /// Query again the packages a BUILD, .bzl or WORKSPACE change affects. Their old targets are dropped first
+    /// so anything deleted from a BUILD file doesn't linger in the graph.
+    pub async fn refresh_graph(
+        self: Arc<TargetState>,
+        bazel_query: Arc<Mutex<Box<dyn BazelQuery>>>,
+        change: &GraphChange,
+    ) -> Result<(), Box<dyn Error>> {
+        let _graph_update = self.graph_updates.lock().await;
+        let affected_packages: HashSet<PathBuf> = match change {
+            GraphChange::BuildFile { package } => std::iter::once(package.clone()).collect(),
+            GraphChange::Starlark(bzl_file) => {
+                let bazel_query = bazel_query.lock().await;
+                query_graph::packages_loading(bazel_query.as_ref(), bzl_file).await?
+            }
+            GraphChange::Workspace => self.known_packages(),
+            GraphChange::Packages(packages) => packages.clone(),
+        };
+
+        // Only bring back packages we were tracking, a new BUILD file is the exception as it may move sources out of
+        // a package we were tracking.
+        let mut packages = self.drop_packages(&affected_packages);
+        match change {
+            GraphChange::BuildFile { package } => {
+                packages.insert(package.clone());
+            }
+            GraphChange::Packages(given) => packages.extend(given.iter().cloned()),
+            GraphChange::Starlark(_) | GraphChange::Workspace => (),
+        }
+        eprintln!(
+            "Graph change {:?}, querying {} package(s) again",
+            change,
+            packages.len()
+        );
+        let res = self.clone().hydrate_packages(bazel_query, packages).await;
+        self.reattach_detached_rdeps();
+        res
+    }
+
+    async fn hydrate_packages(
+        self: Arc<TargetState>,
+        bazel_query: Arc<Mutex<Box<dyn BazelQuery>>>,
+        packages: HashSet<PathBuf>,
+    ) -> Result<(), Box<dyn Error>> {
+        for package in packages.into_iter() {
+            if target_state_snapshot::has_build_file(&package) {
+                self.clone()
+                    .hydrate_file_data(bazel_query.clone(), &package)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Attach the detached reverse dependencies of a target that are known right now, the rest stay detached.
+    fn reattach_rdeps(&self, label: &str, id: TargetId) {
+        if let Some((_, rdep_labels)) = self.detached_rdeps.remove(label) {
+            let (known, unknown): (HashSet<String>, HashSet<String>) = rdep_labels
+                .into_iter()
+                .partition(|rdep| self.label_string_to_id.contains_key(rdep));
+            let rdeps: HashSet<TargetId> = known
+                .iter()
+                .filter_map(|rdep| self.label_string_to_id.get(rdep).map(|e| *e.value()))
+                .collect();
+            if !rdeps.is_empty() {
+                self.target_to_rdeps.entry(id).or_default().extend(rdeps);
+            }
+            if !unknown.is_empty() {
+                self.detached_rdeps.insert(label.to_string(), unknown);
+            }
+        }
+    }
+
+    /// Once the dropped packages have all been queried again, bring back the edges between them.
+    /// Anything still detached after that no longer exists.
+    fn reattach_detached_rdeps(&self) {
+        let labels: Vec<String> = self
+            .detached_rdeps
+            .iter()
+            .map(|e| e.key().clone())
+            .collect();
+        for label in labels.iter() {
+            let id = self.label_string_to_id.get(label).map(|e| *e.value());
+            if let Some(id) = id {
+                self.reattach_rdeps(label, id);
+            }
+        }
+        self.detached_rdeps.clear();
+    }
+
+    /// A fresh id for a target, picking up any reverse dependencies it had before its package was dropped.
+    fn new_target_id(&self, label: &str) -> TargetId {
+        let id = TargetId(self.max_target_id.fetch_add(1, Ordering::AcqRel));
+        self.reattach_rdeps(label, id);
+        id
+    }
+
     async fn ingest_new_deps(&self, dependencies_calculated: &blaze_query::QueryResult) {
         for target in dependencies_calculated.target.iter() {
             if let Some(rule) = target.rule.as_ref() {
                 if !self.label_string_to_id.contains_key(&rule.name) {
-                    let cur_id = TargetId(self.max_target_id.fetch_add(1, Ordering::AcqRel));
+                    let cur_id = self.new_target_id(&rule.name);
 
                     let target_data = RuleTarget {
                         target_label: rule.name.clone(),
@@ -118,7 +430,7 @@ impl TargetState {
             if let Some(src_file) = target.source_file.as_ref() {
                 eprintln!("Looking at src file {:#?}", src_file);
                 if let Some(path) = target_as_path(&src_file.name) {
-                    let cur_id = TargetId(self.max_target_id.fetch_add(1, Ordering::AcqRel));
+                    let cur_id = self.new_target_id(&src_file.name);
                     self.src_file_to_target.insert(path, cur_id);
                     self.label_string_to_id
                         .insert(src_file.name.clone(), cur_id);
@@ -134,24 +446,21 @@ impl TargetState {
 
         for target in dependencies_calculated.target.iter() {
             if let Some(rule) = target.rule.as_ref() {
-                let rdep_src: TargetId = *self
-                    .label_string_to_id
-                    .get(&rule.name)
-                    .expect("Expected to find target")
-                    .value();
+                let rdep_src: TargetId = match self.label_string_to_id.get(&rule.name) {
+                    Some(id) => *id.value(),
+                    None => {
+                        eprintln!(
+                            "{} is no longer in the graph, skipping its inputs",
+                            rule.name
+                        );
+                        continue;
+                    }
+                };
 
                 for rdep in rule.rule_input.iter() {
-                    if let Some(id) = self.label_string_to_id.get(rdep) {
-                        let id: TargetId = *id.value();
-                        if !self.target_to_rdeps.contains_key(&id) {
-                            self.target_to_rdeps.insert(id, Default::default());
-                        }
-                        let mut t = self
-                            .target_to_rdeps
-                            .get_mut(&id)
-                            .expect("We guaranteed its here.");
-
-                        t.insert(rdep_src);
+                    let id = self.label_string_to_id.get(rdep).map(|id| *id.value());
+                    if let Some(id) = id {
+                        self.target_to_rdeps.entry(id).or_default().insert(rdep_src);
                     } else {
                         eprintln!("For rule {}, skipping input: {}", rule.name, rdep);
                     }
@@ -163,6 +472,15 @@ impl TargetState {
         self: Arc<TargetState>,
         bazel_query: Arc<Mutex<Box<dyn BazelQuery>>>,
         path: &PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let _graph_update = self.graph_updates.lock().await;
+        self.clone().hydrate_file_data(bazel_query, path).await
+    }
+
+    async fn hydrate_file_data(
+        self: Arc<TargetState>,
+        bazel_query: Arc<Mutex<Box<dyn BazelQuery>>>,
+        path: &PathBuf,
     ) -> Result<(), Box<dyn Error>> {
         if self.src_file_to_target.contains_key(path) {
             return Ok(());
@@ -171,7 +489,7 @@ impl TargetState {
         let mut cur_path = Some(path.as_path());
         loop {
             if let Some(p) = cur_path {
-                if p.join("BUILD").exists() || p.join("WORKSPACE").exists() {
+                if target_state_snapshot::has_build_file(p) || p.join("WORKSPACE").exists() {
                     break;
                 } else {
                     cur_path = p.parent();
@@ -196,11 +514,10 @@ impl TargetState {
 
             for target in dependencies_calculated.target.iter() {
                 if let Some(rule) = &target.rule {
-                    let rdep_src: TargetId = *self
-                        .label_string_to_id
-                        .get(&rule.name)
-                        .expect("Expected to find target")
-                        .value();
+                    let rdep_src: TargetId = match self.label_string_to_id.get(&rule.name) {
+                        Some(id) => *id.value(),
+                        None => continue,
+                    };
 
                     let need_query: bool = self
                         .target_to_rdeps
@@ -230,6 +547,7 @@ use crate::jvm_indexer::bazel_query::BazelQuery;
 #[derive(Debug)]
 struct TargetCache {
     target_state: Arc<TargetState>,
+    persistence: Option<Arc<TargetStatePersistence>>,
     last_files_updated: Arc<Mutex<HashMap<PathBuf, (u128, Instant, Option<Vec<u8>>)>>>,
     inotify_ignore_regexes: NotifyRegexes,
     pending_hydrations: Arc<AtomicUsize>,
@@ -239,15 +557,28 @@ struct TargetCache {
     last_update_ts: Arc<Mutex<u128>>,
 }
 
+fn persist_target_state(target_state: &TargetState, persistence: &TargetStatePersistence) {
+    if let Err(e) = persistence.save(&target_state.snapshot(persistence)) {
+        eprintln!(
+            "Failed to write target state snapshot to {}: {}",
+            persistence.path.to_string_lossy(),
+            e
+        );
+    }
+}
+
 impl TargetCache {
     pub fn new(
         daemon_config: &DaemonConfig,
         bazel_query: &Arc<Mutex<Box<dyn BazelQuery>>>,
+        target_state: TargetState,
+        persistence: Option<TargetStatePersistence>,
     ) -> Self {
         let (inotify_event_occured, inotify_receiver) = flume::unbounded::<u128>();
 
         Self {
-            target_state: Default::default(),
+            target_state: Arc::new(target_state),
+            persistence: persistence.map(Arc::new),
             last_files_updated: Default::default(),
             inotify_ignore_regexes: daemon_config.inotify_ignore_regexes.clone(),
             pending_hydrations: Arc::new(AtomicUsize::new(0)),
@@ -258,24 +589,65 @@ impl TargetCache {
         }
     }
 
+    /// Bring the graph up to date with a changed path, in the background.
+    /// Source files are added to the graph if unknown, BUILD, .bzl and WORKSPACE files refresh the packages they affect.
     async fn hydrate_new_file_data(&self, path: PathBuf) {
+        let change = GraphChange::for_path(&path);
+        self.update_graph(path.to_string_lossy().to_string(), path, change)
+            .await;
+    }
+
+    /// Query these packages again in the background, on startup those whose BUILD files changed while we were down.
+    async fn refresh_packages(&self, packages: HashSet<PathBuf>) {
+        let description = format!("{} changed package(s)", packages.len());
+        self.update_graph(
+            description,
+            PathBuf::default(),
+            Some(GraphChange::Packages(packages)),
+        )
+        .await;
+    }
+
+    async fn update_graph(&self, description: String, path: PathBuf, change: Option<GraphChange>) {
         self.pending_hydrations.fetch_add(1, Ordering::Release);
 
         let pending_hydrations = self.pending_hydrations.clone();
         let target_state = self.target_state.clone();
         let bazel_query = self.bazel_query.clone();
+        let persistence = self.persistence.clone();
         tokio::task::spawn(async move {
-            if let Err(e) = target_state.hydrate_new_file_data(bazel_query, &path).await {
-                eprintln!(
-                    "Failed to hydrate {}, error:\n{:#?}",
-                    path.to_string_lossy(),
-                    e
-                );
+            let res = match change {
+                Some(change) => {
+                    target_state
+                        .clone()
+                        .refresh_graph(bazel_query, &change)
+                        .await
+                }
+                None => {
+                    target_state
+                        .clone()
+                        .hydrate_new_file_data(bazel_query, &path)
+                        .await
+                }
+            };
+            if let Err(e) = res {
+                eprintln!("Failed to hydrate {}, error:\n{:#?}", description, e);
+            }
+            // Snapshot once the graph settles rather than after every single hydration.
+            if pending_hydrations.fetch_sub(1, Ordering::AcqRel) == 1 {
+                if let Some(persistence) = persistence {
+                    persist_target_state(&target_state, &persistence);
+                }
             }
-            pending_hydrations.fetch_sub(1, Ordering::Release);
         });
     }
 
+    pub fn persist(&self) {
+        if let Some(persistence) = self.persistence.as_ref() {
+            persist_target_state(&self.target_state, persistence);
+        }
+    }
+
     pub async fn register_new_files(&self, paths: Vec<PathBuf>, event_kind: notify::EventKind) {
         let current_path = std::env::current_dir().expect("Should be able to get the current dir");
         let mut lock = self.last_files_updated.lock().await;
@@ -315,6 +687,12 @@ impl TargetCache {
                 continue;
             }
 
+            // A deleted BUILD file takes its package's targets with it.
+            if !real_path.exists() && GraphChange::for_path(&real_path).is_some() {
+                self.hydrate_new_file_data(real_path).await;
+                continue;
+            }
+
             let real_metadata = if let Ok(m) = std::fs::symlink_metadata(&real_path) {
                 m
             } else {
@@ -420,6 +798,11 @@ struct DaemonServerInstance {
     pub target_cache: Arc<TargetCache>,
     pub daemon_config: Arc<DaemonConfig>,
     pub bazel_binary_path: Arc<PathBuf>,
+    pub build_event_router: super::build_event_router::BuildEventRouter,
+    pub build_event_service_port: u16,
+    pub started_at: Instant,
+    pub workspace: Arc<PathBuf>,
+    pub shutdown_requested: Arc<AtomicBool>,
 }
 
 #[tarpc::server]
@@ -490,63 +873,122 @@ impl super::daemon_service::RunnerDaemon for DaemonServerInstance {
             }
             tokio::time::sleep(Duration::from_millis(5)).await;
         }
-        let target_ids = files.iter().filter_map(|f| {
-            let path = &f.0;
+        super::daemon_service::TargetsFromFilesResponse::Targets(
             self.target_cache
                 .target_state
-                .src_file_to_target
-                .get(path)
-                .map(|e| *e.value())
-        });
+                .targets_for_files(&files, distance),
+        )
+    }
 
-        let mut active_targets_ids: HashSet<TargetId> = target_ids.collect();
+    async fn request_instant(self, _: tarpc::context::Context) -> u128 {
+        monotonic_current_time()
+    }
 
-        for _ in 0..distance {
-            let mut next_targets: HashSet<TargetId> = HashSet::default();
+    async fn register_build_events(self, _: tarpc::context::Context, invocation_id: String) -> u16 {
+        self.most_recent_call
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        self.build_event_router.register(&invocation_id);
+        self.build_event_service_port
+    }
 
-            for e in active_targets_ids.iter() {
-                if let Some(rdeps) = self.target_cache.target_state.target_to_rdeps.get(e) {
-                    for rdep in rdeps.value() {
-                        next_targets.insert(*rdep);
-                    }
-                }
-            }
-            active_targets_ids = next_targets;
-        }
+    async fn next_build_events(
+        self,
+        _: tarpc::context::Context,
+        invocation_id: String,
+        wait_ms: u64,
+    ) -> super::daemon_service::RoutedBuildEvents {
+        self.most_recent_call
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        self.build_event_router
+            .next_events(&invocation_id, Duration::from_millis(wait_ms))
+            .await
+    }
 
-        let mut result_targets = Vec::default();
+    async fn release_build_events(self, _: tarpc::context::Context, invocation_id: String) {
+        self.build_event_router.release(&invocation_id);
+    }
 
-        for rt in active_targets_ids.into_iter() {
-            let target_data = self
-                .target_cache
-                .target_state
+    async fn shutdown(self, _: tarpc::context::Context) {
+        eprintln!("Shutdown requested");
+        self.shutdown_requested.store(true, Ordering::Release);
+    }
+
+    async fn status(self, _: tarpc::context::Context) -> super::daemon_service::DaemonStatus {
+        self.most_recent_call
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        let target_state = &self.target_cache.target_state;
+        super::daemon_service::DaemonStatus {
+            executable_id: self.executable_id.as_ref().clone(),
+            pid: std::process::id(),
+            workspace: self.workspace.as_ref().clone(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            tracked_targets: target_state
                 .target_id_to_details
-                .get(&rt)
-                .unwrap();
-            match target_data.value() {
-                TargetType::Rule(r) => {
-                    if r.is_test {
-                        result_targets.push(super::daemon_service::Targets::Test(
-                            super::daemon_service::TestTarget {
-                                target_label: r.target_label.clone(),
-                            },
-                        ));
-                    } else {
-                        result_targets.push(super::daemon_service::Targets::Build(
-                            super::daemon_service::BuildTarget {
-                                target_label: r.target_label.clone(),
-                            },
-                        ));
-                    }
-                }
-                TargetType::Src(_) => {}
-            }
+                .iter()
+                .filter(|e| matches!(e.value(), TargetType::Rule(_)))
+                .count(),
+            tracked_source_files: target_state.src_file_to_target.len(),
+            pending_hydrations: self.target_cache.pending_hydrations.load(Ordering::Acquire),
+            dirty_files: self.target_cache.last_files_updated.lock().await.len(),
+            build_event_service_port: self.build_event_service_port,
         }
-        super::daemon_service::TargetsFromFilesResponse::Targets(result_targets)
     }
 
-    async fn request_instant(self, _: tarpc::context::Context) -> u128 {
-        monotonic_current_time()
+    async fn changed_files(
+        self,
+        _: tarpc::context::Context,
+        within_ms: Option<u64>,
+    ) -> Vec<super::daemon_service::ChangedFile> {
+        self.most_recent_call
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        let lock = self.target_cache.last_files_updated.lock().await;
+        let mut changed: Vec<super::daemon_service::ChangedFile> = lock
+            .iter()
+            .map(|(path, (_, when, _))| super::daemon_service::ChangedFile {
+                path: path.clone(),
+                age_ms: when.elapsed().as_millis() as u64,
+            })
+            .filter(|c| within_ms.is_none_or(|w| c.age_ms <= w))
+            .collect();
+        changed.sort_by_key(|c| c.age_ms);
+        changed
+    }
+
+    async fn rdeps(
+        self,
+        _: tarpc::context::Context,
+        label: String,
+        max_distance: u32,
+    ) -> Vec<super::daemon_service::RdepsAtDistance> {
+        self.most_recent_call
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        let target_state = &self.target_cache.target_state;
+        let target = match target_state.label_string_to_id.get(&label) {
+            Some(id) => *id.value(),
+            None => return Vec::default(),
+        };
+
+        target_state
+            .rdeps_by_distance(target, max_distance)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, ids)| {
+                let mut targets: Vec<super::daemon_service::Targets> = ids
+                    .iter()
+                    .filter_map(|id| {
+                        target_state
+                            .target_id_to_details
+                            .get(id)
+                            .and_then(|t| t.value().as_service_target())
+                    })
+                    .collect();
+                targets.sort_by(|a, b| a.target_label().cmp(b.target_label()));
+                super::daemon_service::RdepsAtDistance {
+                    distance: idx as u32 + 1,
+                    targets,
+                }
+            })
+            .collect()
     }
 }
 
@@ -583,6 +1025,26 @@ where
     }))
 }
 
+/// Bazel only speaks BES over TCP, so unlike our RPC this listens on a local port, picked by the OS.
+async fn start_build_event_server(
+    router: super::build_event_router::BuildEventRouter,
+) -> Result<u16, Box<dyn Error>> {
+    use bazelfe_protos::google::devtools::build::v1::publish_build_event_server::PublishBuildEventServer;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(PublishBuildEventServer::new(router))
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+            .await
+        {
+            eprintln!("Build event service failed: {:#}", e);
+        }
+    });
+    Ok(port)
+}
+
 pub async fn main_from_config(config_path: &PathBuf) -> Result<(), Box<dyn Error>> {
     use std::fs::File;
     use std::io::BufReader;
@@ -635,9 +1097,41 @@ pub async fn main(
     println!("Starting up bazelfe daemon");
     let executable_id = Arc::new(super::current_executable_id());
 
-    let target_cache = Arc::new(TargetCache::new(daemon_config, &bazel_query));
     let current_dir = std::env::current_dir().expect("Failed to determine current directory");
 
+    let persistence = if daemon_config.persist_target_state {
+        Some(TargetStatePersistence::new(
+            &daemon_config.daemon_communication_folder,
+            &current_dir,
+            &daemon_config.inotify_ignore_regexes,
+        ))
+    } else {
+        None
+    };
+    let mut invalidated_packages: HashSet<PathBuf> = HashSet::default();
+    let target_state = match persistence.as_ref().and_then(|p| p.load()) {
+        Some(snapshot) => {
+            invalidated_packages = snapshot.invalidated_packages().into_iter().collect();
+            let target_state = TargetState::from_snapshot(snapshot);
+            println!(
+                "Loaded target state snapshot, {} package(s) changed since",
+                invalidated_packages.len()
+            );
+            target_state
+        }
+        None => TargetState::default(),
+    };
+
+    let target_cache = Arc::new(TargetCache::new(
+        daemon_config,
+        &bazel_query,
+        target_state,
+        persistence,
+    ));
+    if !invalidated_packages.is_empty() {
+        target_cache.refresh_packages(invalidated_packages).await;
+    }
+
     let most_recent_call = Arc::new(AtomicUsize::new(0));
 
     let captured_most_recent_call = most_recent_call.clone();
@@ -646,6 +1140,16 @@ pub async fn main(
 
     let captured_daemon_config = Arc::new(daemon_config.clone());
     let captured_bazel_binary_path = Arc::new(bazel_binary_path.to_path_buf());
+
+    let build_event_router =
+        super::build_event_router::BuildEventRouter::new(most_recent_call.clone());
+    println!("Starting build event service");
+    let build_event_service_port = start_build_event_server(build_event_router.clone()).await?;
+
+    let started_at = Instant::now();
+    let workspace = Arc::new(current_dir.clone());
+    let shutdown_requested = Arc::new(AtomicBool::new(false));
+    let captured_shutdown_requested = shutdown_requested.clone();
     println!("Starting tarpc");
     start_tarpc_server(&paths.socket_path, move || DaemonServerInstance {
         executable_id: executable_id.clone(),
@@ -653,6 +1157,11 @@ pub async fn main(
         target_cache: captured_target_cache.clone(),
         daemon_config: captured_daemon_config.clone(),
         bazel_binary_path: captured_bazel_binary_path.clone(),
+        build_event_router: build_event_router.clone(),
+        build_event_service_port,
+        started_at,
+        workspace: workspace.clone(),
+        shutdown_requested: captured_shutdown_requested.clone(),
     })
     .await?;
 
@@ -767,12 +1276,17 @@ pub async fn main(
     let mut last_call = usize::MAX;
     let mut last_seen = Instant::now();
 
-    let max_delay = Duration::from_secs(3600);
+    let max_delay = daemon_config.idle_timeout;
 
     println!("Looping to track activity.");
     loop {
         tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
+        if shutdown_requested.load(Ordering::Acquire) {
+            eprintln!("Quitting as requested");
+            break;
+        }
+
         let current_v = most_recent_call.load(std::sync::atomic::Ordering::Acquire);
 
         if current_v == last_call {
@@ -808,5 +1322,188 @@ pub async fn main(
         }
     }
 
+    target_cache.persist();
+
+    // Only tidy up after ourselves, if another daemon has taken over these are its files.
+    if super::read_pid(paths) == Some(std::process::id() as i32) {
+        let _ = std::fs::remove_file(&paths.socket_path);
+        let _ = std::fs::remove_file(&paths.pid_path);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(label: &str) -> TargetType {
+        TargetType::Rule(RuleTarget {
+            target_label: label.to_string(),
+            target_kind: String::from("scala_library"),
+            is_test: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_drop_packages_keeps_edges_from_other_packages() {
+        // //src/a:a <- //src/b:b <- //src/c:c, with //src/b:B.scala owned by //src/b:b
+        let snapshot = TargetStateSnapshot {
+            target_id_to_details: vec![
+                (TargetId(0), rule("//src/a:a")),
+                (TargetId(1), rule("//src/b:b")),
+                (
+                    TargetId(2),
+                    TargetType::Src(SrcFileTarget {
+                        target_label: String::from("//src/b:B.scala"),
+                    }),
+                ),
+                (TargetId(3), rule("//src/c:c")),
+            ],
+            label_string_to_id: vec![
+                (String::from("//src/a:a"), TargetId(0)),
+                (String::from("//src/b:b"), TargetId(1)),
+                (String::from("//src/b:libb.jar"), TargetId(1)),
+                (String::from("//src/b:B.scala"), TargetId(2)),
+                (String::from("//src/c:c"), TargetId(3)),
+            ],
+            src_file_to_target: vec![(PathBuf::from("src/b/B.scala"), TargetId(2))],
+            target_to_rdeps: vec![
+                (TargetId(0), vec![TargetId(1)]),
+                (TargetId(1), vec![TargetId(3)]),
+                (TargetId(2), vec![TargetId(1)]),
+            ],
+            max_target_id: 4,
+            ..Default::default()
+        };
+        let target_state = TargetState::from_snapshot(snapshot);
+
+        let dropped = target_state.drop_packages(
+            &[PathBuf::from("src/b"), PathBuf::from("src/d")]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(dropped, std::iter::once(PathBuf::from("src/b")).collect());
+
+        let mut known: Vec<PathBuf> = target_state.known_packages().into_iter().collect();
+        known.sort();
+        assert_eq!(known, vec![PathBuf::from("src/a"), PathBuf::from("src/c")]);
+
+        let mut labels: Vec<String> = target_state
+            .label_string_to_id
+            .iter()
+            .map(|e| e.key().clone())
+            .collect();
+        labels.sort();
+        assert_eq!(labels, vec!["//src/a:a", "//src/c:c"]);
+        assert!(target_state.src_file_to_target.is_empty());
+        assert_eq!(target_state.target_to_rdeps.len(), 1);
+        assert!(target_state
+            .target_to_rdeps
+            .get(&TargetId(0))
+            .unwrap()
+            .is_empty());
+        assert_eq!(target_state.max_target_id.load(Ordering::Acquire), 4);
+
+        // Querying //src/b again brings back its own deps, and //src/c:c depending on it.
+        let query_result = blaze_query::QueryResult {
+            target: vec![blaze_query::Target {
+                rule: Some(blaze_query::Rule {
+                    name: String::from("//src/b:b"),
+                    rule_class: String::from("scala_library"),
+                    rule_input: vec![String::from("//src/a:a")],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+        };
+        target_state.ingest_new_deps(&query_result).await;
+        let b = *target_state.label_string_to_id.get("//src/b:b").unwrap();
+        assert_eq!(b, TargetId(4));
+        assert_eq!(
+            target_state.rdeps_by_distance(TargetId(0), 2),
+            vec![vec![b], vec![TargetId(3)]]
+        );
+        // //src/b:B.scala wasn't part of the query, so the edge onto it only comes back once all are queried.
+        assert_eq!(target_state.detached_rdeps.len(), 1);
+        target_state.reattach_detached_rdeps();
+        assert!(target_state.detached_rdeps.is_empty());
+    }
+
+    #[test]
+    fn test_owning_targets_of_a_file() {
+        // //src/b:B.scala <- //src/b:b <- //src/c:c_test
+        let snapshot = TargetStateSnapshot {
+            target_id_to_details: vec![
+                (TargetId(0), rule("//src/b:b")),
+                (
+                    TargetId(1),
+                    TargetType::Src(SrcFileTarget {
+                        target_label: String::from("//src/b:B.scala"),
+                    }),
+                ),
+                (
+                    TargetId(2),
+                    TargetType::Rule(RuleTarget {
+                        target_label: String::from("//src/c:c_test"),
+                        target_kind: String::from("scala_test"),
+                        is_test: true,
+                    }),
+                ),
+            ],
+            src_file_to_target: vec![(PathBuf::from("src/b/B.scala"), TargetId(1))],
+            target_to_rdeps: vec![
+                (TargetId(1), vec![TargetId(0)]),
+                (TargetId(0), vec![TargetId(2)]),
+            ],
+            max_target_id: 3,
+            ..Default::default()
+        };
+        let target_state = TargetState::from_snapshot(snapshot);
+        let files = vec![super::super::daemon_service::FileStatus(
+            PathBuf::from("src/b/B.scala"),
+            0,
+        )];
+        let labels = |distance: u32| -> Vec<String> {
+            target_state
+                .targets_for_files(&files, distance)
+                .iter()
+                .map(|t| t.target_label().clone())
+                .collect()
+        };
+
+        assert_eq!(
+            labels(super::super::daemon_service::OWNING_TARGETS_DISTANCE),
+            vec!["//src/b:b"]
+        );
+        assert_eq!(
+            labels(super::super::daemon_service::OWNING_TARGETS_DISTANCE + 1),
+            vec!["//src/c:c_test"]
+        );
+    }
+
+    #[test]
+    fn test_rdeps_by_distance() {
+        // 0 <- 1 <- 2 <- 3, with 0 <- 2 as a shortcut
+        let snapshot = TargetStateSnapshot {
+            target_to_rdeps: vec![
+                (TargetId(0), vec![TargetId(1), TargetId(2)]),
+                (TargetId(1), vec![TargetId(2)]),
+                (TargetId(2), vec![TargetId(3)]),
+            ],
+            max_target_id: 4,
+            ..Default::default()
+        };
+        let target_state = TargetState::from_snapshot(snapshot);
+
+        let mut by_distance = target_state.rdeps_by_distance(TargetId(0), 5);
+        by_distance
+            .iter_mut()
+            .for_each(|ids| ids.sort_by_key(|id| id.0));
+        assert_eq!(
+            by_distance,
+            vec![vec![TargetId(1), TargetId(2)], vec![TargetId(3)]]
+        );
+        assert_eq!(target_state.rdeps_by_distance(TargetId(0), 1).len(), 1);
+        assert!(target_state.rdeps_by_distance(TargetId(3), 5).is_empty());
+    }
+}
@@ -2,8 +2,10 @@ use std::path::PathBuf;
 
 pub mod query_graph;
 
+pub mod build_event_router;
 pub mod daemon_manager;
 pub mod daemon_server;
+pub mod target_state_snapshot;
 
 use fork::Fork;
 use thiserror::Error;
@@ -127,6 +129,16 @@ pub struct ExecutableId {
     git_sha: String,
 }
 
+impl std::fmt::Display for ExecutableId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}, built {})",
+            self.git_sha, self.git_branch, self.build_timestamp
+        )
+    }
+}
+
 pub mod daemon_service {
     use serde::{Deserialize, Serialize};
     use std::path::PathBuf;
@@ -134,6 +146,9 @@ pub mod daemon_service {
     #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Hash)]
     pub struct FileStatus(pub PathBuf, pub u128);
 
+    /// Source files are targets of their own in the daemon's graph, the rules owning them are one hop away.
+    pub const OWNING_TARGETS_DISTANCE: u32 = 1;
+
     #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
     pub enum TargetsFromFilesResponse {
         Targets(Vec<Targets>),
@@ -169,6 +184,49 @@ pub mod daemon_service {
         pub target_label: String,
     }
 
+    /// A build event bazel sent to the daemon's BES server, still encoded as its BES request.
+    #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+    pub enum RoutedBuildEvent {
+        BuildToolEvent(Vec<u8>),
+        LifecycleEvent(Vec<u8>),
+    }
+
+    /// What the daemon is up to, for `bazel-runner daemon status`.
+    #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+    pub struct DaemonStatus {
+        pub executable_id: super::ExecutableId,
+        pub pid: u32,
+        pub workspace: PathBuf,
+        pub uptime_secs: u64,
+        pub tracked_targets: usize,
+        pub tracked_source_files: usize,
+        /// Queries still running to bring the target graph up to date.
+        pub pending_hydrations: usize,
+        /// Changed files the daemon is still holding on to, see changed_files.
+        pub dirty_files: usize,
+        pub build_event_service_port: u16,
+    }
+
+    #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+    pub struct ChangedFile {
+        pub path: PathBuf,
+        pub age_ms: u64,
+    }
+
+    /// The reverse dependencies first reached at this many hops from a target.
+    #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+    pub struct RdepsAtDistance {
+        pub distance: u32,
+        pub targets: Vec<Targets>,
+    }
+
+    #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+    pub struct RoutedBuildEvents {
+        pub events: Vec<RoutedBuildEvent>,
+        /// Bazel closed the stream, or the invocation isn't registered, no more events will follow.
+        pub finished: bool,
+    }
+
     #[tarpc::service]
     pub trait RunnerDaemon {
         async fn request_instant() -> u128;
@@ -183,6 +241,20 @@ pub mod daemon_service {
         async fn recently_invalidated_targets(distance: u32) -> Vec<Targets>;
 
         async fn ping() -> super::ExecutableId;
+
+        /// Start routing events for this invocation id to us, returns the port of the daemon's BES server.
+        async fn register_build_events(invocation_id: String) -> u16;
+        /// Waits up to wait_ms for the next events of a registered invocation.
+        async fn next_build_events(invocation_id: String, wait_ms: u64) -> RoutedBuildEvents;
+        async fn release_build_events(invocation_id: String);
+
+        async fn status() -> DaemonStatus;
+        /// Changed files the daemon knows of, most recent first, optionally only those within the last within_ms.
+        async fn changed_files(within_ms: Option<u64>) -> Vec<ChangedFile>;
+        /// Every reverse dependency of the label up to max_distance hops away, empty if the daemon doesn't know it.
+        async fn rdeps(label: String, max_distance: u32) -> Vec<RdepsAtDistance>;
+        /// Ask the daemon to save its state and exit.
+        async fn shutdown();
     }
 }
 
@@ -1,6 +1,8 @@
 use crate::jvm_indexer::bazel_query::BazelQuery;
 use ::prost::Message;
 use bazelfe_protos::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 pub async fn graph_query<B: BazelQuery + ?Sized, Q: AsRef<str>>(
     bazel_query: &B,
@@ -18,3 +20,97 @@ pub async fn graph_query<B: BazelQuery + ?Sized, Q: AsRef<str>>(
 
     Ok(blaze_query::QueryResult::decode(&*res.stdout_raw)?)
 }
+
+/// What a changed file means for the query graph the daemon holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphChange {
+    /// A BUILD file changed, only the targets of its own package need querying again.
+    BuildFile { package: PathBuf },
+    /// Every package loading this .bzl file, directly or not, needs querying again.
+    Starlark(PathBuf),
+    /// The WORKSPACE can change anything.
+    Workspace,
+    /// These packages need querying again, whether or not we held targets for them, e.g. on startup.
+    Packages(HashSet<PathBuf>),
+}
+
+impl GraphChange {
+    /// None for paths that don't alter the graph by themselves, i.e. source files.
+    pub fn for_path(path: &Path) -> Option<GraphChange> {
+        let file_name = path.file_name()?.to_string_lossy();
+        match file_name.as_ref() {
+            "BUILD" | "BUILD.bazel" => Some(GraphChange::BuildFile {
+                package: path.parent().map(|p| p.to_path_buf()).unwrap_or_default(),
+            }),
+            "WORKSPACE" | "WORKSPACE.bazel" => Some(GraphChange::Workspace),
+            f if f.ends_with(".bzl") => Some(GraphChange::Starlark(path.to_path_buf())),
+            _ => None,
+        }
+    }
+}
+
+/// Packages whose BUILD files load the .bzl file, going through other .bzl files.
+pub async fn packages_loading<B: BazelQuery + ?Sized>(
+    bazel_query: &B,
+    bzl_file: &Path,
+) -> Result<HashSet<PathBuf>, Box<dyn std::error::Error>> {
+    // rbuildfiles is only available to sky query, which needs a universe to search.
+    let res = bazel_query
+        .execute(&vec![
+            String::from("query"),
+            String::from("--keep_going"),
+            String::from("--universe_scope=//..."),
+            String::from("--order_output=no"),
+            String::from("--output"),
+            String::from("proto"),
+            format!("rbuildfiles({})", bzl_file.to_string_lossy()),
+        ])
+        .await;
+    let build_files = blaze_query::QueryResult::decode(&*res.stdout_raw)?;
+
+    Ok(build_files
+        .target
+        .iter()
+        .filter_map(|t| t.source_file.as_ref())
+        .filter_map(|s| super::target_state_snapshot::package_of_label(&s.name))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classifies_changed_paths() {
+        assert_eq!(
+            GraphChange::for_path(Path::new("src/main/scala/BUILD")),
+            Some(GraphChange::BuildFile {
+                package: PathBuf::from("src/main/scala")
+            })
+        );
+        assert_eq!(
+            GraphChange::for_path(Path::new("BUILD.bazel")),
+            Some(GraphChange::BuildFile {
+                package: PathBuf::from("")
+            })
+        );
+        assert_eq!(
+            GraphChange::for_path(Path::new("tools/rules/scala.bzl")),
+            Some(GraphChange::Starlark(PathBuf::from(
+                "tools/rules/scala.bzl"
+            )))
+        );
+        assert_eq!(
+            GraphChange::for_path(Path::new("WORKSPACE")),
+            Some(GraphChange::Workspace)
+        );
+        assert_eq!(
+            GraphChange::for_path(Path::new("src/main/scala/BUILD_NOTES.md")),
+            None
+        );
+        assert_eq!(
+            GraphChange::for_path(Path::new("src/main/scala/Foo.scala")),
+            None
+        );
+    }
+}
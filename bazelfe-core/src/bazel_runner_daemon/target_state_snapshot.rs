@@ -0,0 +1,292 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::daemon_server::{TargetId, TargetType};
+use crate::config::daemon_config::NotifyRegexes;
+
+// Bump whenever the layout below changes, older snapshots are then just ignored.
+const SNAPSHOT_VERSION: u32 = 1;
+
+// Bazel prefers BUILD.bazel when both are present.
+const BUILD_FILE_NAMES: [&str; 2] = ["BUILD.bazel", "BUILD"];
+
+/// The daemon's target graph as written to disk, so a restarted daemon doesn't have to query it all again.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TargetStateSnapshot {
+    pub version: u32,
+    pub workspace: PathBuf,
+    pub starlark_hash: String,
+    /// Hash of the BUILD file of every package with targets in the graph, None if it had no BUILD file.
+    pub package_hashes: Vec<(PathBuf, Option<String>)>,
+    pub src_file_to_target: Vec<(PathBuf, TargetId)>,
+    pub target_to_rdeps: Vec<(TargetId, Vec<TargetId>)>,
+    pub target_id_to_details: Vec<(TargetId, TargetType)>,
+    pub label_string_to_id: Vec<(String, TargetId)>,
+    pub max_target_id: u32,
+}
+
+impl TargetStateSnapshot {
+    /// Packages whose BUILD file changed since the snapshot was taken, their targets need querying again.
+    /// A BUILD file added since takes sources away from the package that owned them, so both are included.
+    pub fn invalidated_packages(&self) -> Vec<PathBuf> {
+        let mut invalidated: Vec<PathBuf> = self
+            .package_hashes
+            .iter()
+            .filter(|(package, hash)| build_file_hash(&self.workspace, package) != *hash)
+            .map(|(package, _)| package.clone())
+            .collect();
+
+        let labels: HashMap<TargetId, &str> = self
+            .target_id_to_details
+            .iter()
+            .map(|(id, details)| (*id, details.target_label().as_str()))
+            .collect();
+        for (path, id) in self.src_file_to_target.iter() {
+            if !self.workspace.join(path).exists() {
+                continue;
+            }
+            let owner = labels.get(id).and_then(|label| package_of_label(label));
+            let nearest = nearest_package(&self.workspace, path);
+            if owner != nearest {
+                invalidated.extend(owner);
+                invalidated.extend(nearest);
+            }
+        }
+
+        let mut seen: HashSet<PathBuf> = HashSet::default();
+        invalidated.retain(|package| seen.insert(package.clone()));
+        invalidated
+    }
+}
+
+/// Where the snapshot for a workspace lives, and what it has to match to still be usable.
+#[derive(Debug, Clone)]
+pub struct TargetStatePersistence {
+    pub path: PathBuf,
+    pub workspace: PathBuf,
+    /// Covers every .bzl file and the WORKSPACE, changes there can alter any package so invalidate everything.
+    pub starlark_hash: String,
+}
+
+impl TargetStatePersistence {
+    pub fn new(
+        communication_folder: &Path,
+        workspace: &Path,
+        ignore_regexes: &NotifyRegexes,
+    ) -> Self {
+        Self {
+            path: communication_folder.join("target_state.json"),
+            workspace: workspace.to_path_buf(),
+            starlark_hash: starlark_hash(workspace, ignore_regexes),
+        }
+    }
+
+    pub fn new_snapshot(&self) -> TargetStateSnapshot {
+        TargetStateSnapshot {
+            version: SNAPSHOT_VERSION,
+            workspace: self.workspace.clone(),
+            starlark_hash: self.starlark_hash.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn load(&self) -> Option<TargetStateSnapshot> {
+        let content = std::fs::read(&self.path).ok()?;
+        let snapshot: TargetStateSnapshot = match serde_json::from_slice(&content) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                eprintln!("Ignoring unreadable target state snapshot: {}", e);
+                return None;
+            }
+        };
+        if snapshot.version != SNAPSHOT_VERSION || snapshot.workspace != self.workspace {
+            eprintln!("Ignoring target state snapshot from another version or workspace");
+            return None;
+        }
+        if snapshot.starlark_hash != self.starlark_hash {
+            eprintln!("Ignoring target state snapshot, .bzl or WORKSPACE files have changed");
+            return None;
+        }
+        Some(snapshot)
+    }
+
+    pub fn save(&self, snapshot: &TargetStateSnapshot) -> Result<(), Box<dyn std::error::Error>> {
+        // Write then rename, a daemon dying mid write shouldn't leave a truncated snapshot behind.
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(snapshot)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// The package directory of a label in the main repository, //src/main:lib -> src/main.
+pub fn package_of_label(label: &str) -> Option<PathBuf> {
+    let package = label.strip_prefix("//")?.split(':').next()?;
+    Some(PathBuf::from(package))
+}
+
+pub fn has_build_file(package_dir: &Path) -> bool {
+    BUILD_FILE_NAMES
+        .iter()
+        .any(|build_file| package_dir.join(build_file).exists())
+}
+
+/// The package a file in the workspace belongs to, the closest directory above it with a BUILD file.
+fn nearest_package(workspace: &Path, file: &Path) -> Option<PathBuf> {
+    file.ancestors()
+        .skip(1)
+        .find(|package| has_build_file(&workspace.join(package)))
+        .map(|package| package.to_path_buf())
+}
+
+fn hash_file(hasher: &mut Sha256, path: &Path) -> bool {
+    match std::fs::File::open(path) {
+        Ok(mut file) => std::io::copy(&mut file, hasher).is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub fn build_file_hash(workspace: &Path, package: &Path) -> Option<String> {
+    BUILD_FILE_NAMES.iter().find_map(|build_file| {
+        let mut hasher = Sha256::new();
+        if hash_file(&mut hasher, &workspace.join(package).join(build_file)) {
+            Some(format!("{:x}", hasher.finalize()))
+        } else {
+            None
+        }
+    })
+}
+
+fn starlark_hash(workspace: &Path, ignore_regexes: &NotifyRegexes) -> String {
+    let mut paths: Vec<PathBuf> = walkdir::WalkDir::new(workspace)
+        .into_iter()
+        .filter_entry(|e| {
+            let file_name = e.file_name().to_string_lossy();
+            e.depth() == 0
+                || !(file_name.starts_with('.')
+                    || file_name.starts_with("bazel-")
+                    || ignore_regexes.0.iter().any(|r| r.is_match(&file_name)))
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let file_name = e.file_name().to_string_lossy();
+            e.file_type().is_file()
+                && (file_name.ends_with(".bzl")
+                    || file_name == "WORKSPACE"
+                    || file_name == "WORKSPACE.bazel")
+        })
+        .map(|e| e.into_path())
+        .collect();
+    paths.sort();
+
+    let mut hasher = Sha256::new();
+    for path in paths.iter() {
+        hasher.update(
+            path.strip_prefix(workspace)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .as_bytes(),
+        );
+        hash_file(&mut hasher, path);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel_runner_daemon::daemon_server::{RuleTarget, SrcFileTarget, TargetId};
+
+    #[test]
+    fn test_snapshot_invalidation() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path().join("workspace");
+        let communication_folder = dir.path().join("daemon");
+        std::fs::create_dir_all(workspace.join("src/a")).unwrap();
+        std::fs::create_dir_all(workspace.join("src/b")).unwrap();
+        std::fs::create_dir_all(&communication_folder).unwrap();
+        std::fs::write(workspace.join("WORKSPACE"), "").unwrap();
+        std::fs::write(workspace.join("src/a/BUILD"), "scala_library(name = 'a')").unwrap();
+        std::fs::write(
+            workspace.join("src/b/BUILD.bazel"),
+            "scala_library(name = 'b')",
+        )
+        .unwrap();
+        std::fs::write(workspace.join("src/rules.bzl"), "").unwrap();
+        std::fs::create_dir_all(workspace.join("src/a/sub")).unwrap();
+        std::fs::write(workspace.join("src/a/sub/A.scala"), "").unwrap();
+
+        let ignore = NotifyRegexes(Vec::default());
+        let persistence = TargetStatePersistence::new(&communication_folder, &workspace, &ignore);
+        assert!(persistence.load().is_none());
+
+        let mut snapshot = persistence.new_snapshot();
+        snapshot.package_hashes = ["src/a", "src/b", "src/c"]
+            .iter()
+            .map(|p| (PathBuf::from(p), build_file_hash(&workspace, Path::new(p))))
+            .collect();
+        snapshot.target_id_to_details = vec![
+            (
+                TargetId(0),
+                TargetType::Rule(RuleTarget {
+                    target_label: String::from("//src/a:a"),
+                    target_kind: String::from("scala_library"),
+                    is_test: false,
+                }),
+            ),
+            (
+                TargetId(1),
+                TargetType::Src(SrcFileTarget {
+                    target_label: String::from("//src/a:sub/A.scala"),
+                }),
+            ),
+        ];
+        snapshot.src_file_to_target = vec![(PathBuf::from("src/a/sub/A.scala"), TargetId(1))];
+        snapshot.max_target_id = 2;
+        persistence.save(&snapshot).unwrap();
+
+        let loaded = persistence.load().unwrap();
+        assert_eq!(loaded, snapshot);
+        assert!(loaded.invalidated_packages().is_empty());
+
+        std::fs::write(
+            workspace.join("src/b/BUILD.bazel"),
+            "scala_library(name = 'c')",
+        )
+        .unwrap();
+        std::fs::create_dir_all(workspace.join("src/c")).unwrap();
+        std::fs::write(workspace.join("src/c/BUILD"), "").unwrap();
+        assert_eq!(
+            loaded.invalidated_packages(),
+            vec![PathBuf::from("src/b"), PathBuf::from("src/c")]
+        );
+
+        // A new package taking over a source file.
+        std::fs::write(workspace.join("src/a/sub/BUILD.bazel"), "").unwrap();
+        assert_eq!(
+            loaded.invalidated_packages(),
+            vec![
+                PathBuf::from("src/b"),
+                PathBuf::from("src/c"),
+                PathBuf::from("src/a"),
+                PathBuf::from("src/a/sub")
+            ]
+        );
+
+        std::fs::write(workspace.join("src/rules.bzl"), "# changed").unwrap();
+        let persistence = TargetStatePersistence::new(&communication_folder, &workspace, &ignore);
+        assert!(persistence.load().is_none());
+
+        assert_eq!(
+            package_of_label("//src/main/scala:lib"),
+            Some(PathBuf::from("src/main/scala"))
+        );
+        assert_eq!(package_of_label("//:root"), Some(PathBuf::from("")));
+        assert_eq!(package_of_label("@maven//:guava"), None);
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bazelfe_protos::*;
+use prost::Message;
+use tokio::io::AsyncReadExt;
+use tokio::sync::oneshot;
+
+use super::build_event_server::{bazel_event::BazelBuildEvent, BuildEventAction};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Split as many complete varint delimited events as possible off the front of the buffer,
+/// leaving any partially written event behind for the next read.
+fn decode_available(
+    buf: &mut Vec<u8>,
+) -> Result<Vec<build_event_stream::BuildEvent>, prost::DecodeError> {
+    let mut events = Vec::default();
+    let mut consumed = 0;
+    loop {
+        let remaining = &buf[consumed..];
+        // A varint is at most 10 bytes, it is complete once we see a byte without the continuation bit.
+        if !remaining.iter().take(10).any(|b| b & 0x80 == 0) {
+            if remaining.len() >= 10 {
+                return Err(prost::DecodeError::new("invalid varint"));
+            }
+            break;
+        }
+        let mut cursor = remaining;
+        let len = prost::encoding::decode_varint(&mut cursor)? as usize;
+        let header_len = remaining.len() - cursor.len();
+        if cursor.len() < len {
+            break;
+        }
+        events.push(build_event_stream::BuildEvent::decode(&cursor[..len])?);
+        consumed += header_len + len;
+    }
+    buf.drain(..consumed);
+    Ok(events)
+}
+
+/// Tails the file bazel writes with `--build_event_binary_file`, feeding events into the same channel
+/// the BES server would. Bazel only creates the file once the build starts, and may still be writing to it,
+/// so we poll until bazel sends its last message or we are told bazel has exited and the file is drained.
+pub async fn tail_build_event_file(
+    path: PathBuf,
+    tx: async_channel::Sender<BuildEventAction<BazelBuildEvent>>,
+    mut bazel_finished: oneshot::Receiver<()>,
+) {
+    let mut file: Option<tokio::fs::File> = None;
+    let mut buf = Vec::default();
+    loop {
+        let finished = !matches!(
+            bazel_finished.try_recv(),
+            Err(oneshot::error::TryRecvError::Empty)
+        );
+
+        if file.is_none() {
+            file = tokio::fs::File::open(&path).await.ok();
+        }
+        if let Some(f) = file.as_mut() {
+            if let Err(e) = f.read_to_end(&mut buf).await {
+                warn!(
+                    "Failed reading build events from {}: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                break;
+            }
+        }
+
+        let events = match decode_available(&mut buf) {
+            Ok(events) => events,
+            Err(e) => {
+                warn!(
+                    "Unable to decode build events from {}: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                break;
+            }
+        };
+        let mut last_message = false;
+        for event in events.into_iter() {
+            last_message |= event.last_message;
+            if tx
+                .send(BuildEventAction::BuildEvent(
+                    BazelBuildEvent::from_build_event(event),
+                ))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+
+        if last_message {
+            break;
+        }
+        if finished {
+            if !buf.is_empty() {
+                warn!(
+                    "Bazel exited leaving a partially written build event in {}",
+                    path.to_string_lossy()
+                );
+            }
+            break;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    let _ = tx.send(BuildEventAction::BuildCompleted).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_events::build_event_server::bazel_event::{Evt, ProgressEvt};
+    use tokio::io::AsyncWriteExt;
+
+    fn progress(idx: i32, last_message: bool) -> Vec<u8> {
+        build_event_stream::BuildEvent {
+            id: Some(build_event_stream::BuildEventId {
+                id: Some(build_event_stream::build_event_id::Id::Progress(
+                    build_event_stream::build_event_id::ProgressId { opaque_count: idx },
+                )),
+            }),
+            payload: Some(build_event_stream::build_event::Payload::Progress(
+                build_event_stream::Progress {
+                    stdout: String::default(),
+                    stderr: format!("line {}", idx),
+                },
+            )),
+            last_message,
+            ..Default::default()
+        }
+        .encode_length_delimited_to_vec()
+    }
+
+    #[test]
+    fn test_decode_available_keeps_partial_events() {
+        let first = progress(1, false);
+        let second = progress(2, false);
+        let mut buf = first.clone();
+        buf.extend_from_slice(&second[..3]);
+
+        assert_eq!(decode_available(&mut buf).unwrap().len(), 1);
+        assert_eq!(buf, second[..3].to_vec());
+
+        buf.extend_from_slice(&second[3..]);
+        let events = decode_available(&mut buf).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(buf.is_empty());
+
+        assert!(decode_available(&mut vec![0xff; 11]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tails_file_as_it_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build_events.bin");
+        let (tx, rx) = async_channel::unbounded();
+        let (finished_tx, finished_rx) = oneshot::channel();
+        let tailer = tokio::spawn(tail_build_event_file(path.clone(), tx, finished_rx));
+
+        // Bazel creates the file after we start tailing, and writes events in pieces.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let mut file = tokio::fs::File::create(&path).await.unwrap();
+        let mut data = progress(1, false);
+        data.extend(progress(2, false));
+        data.extend(progress(3, true));
+        let (head, tail) = data.split_at(data.len() / 2);
+        file.write_all(head).await.unwrap();
+        file.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        file.write_all(tail).await.unwrap();
+        file.flush().await.unwrap();
+
+        tailer.await.unwrap();
+        drop(finished_tx);
+
+        let mut received = Vec::default();
+        while let Ok(action) = rx.recv().await {
+            received.push(action);
+        }
+        assert_eq!(received.len(), 4);
+        assert_eq!(
+            received[0],
+            BuildEventAction::BuildEvent(BazelBuildEvent {
+                event: Evt::Progress(ProgressEvt {
+                    stdout: String::default(),
+                    stderr: String::from("line 1"),
+                })
+            })
+        );
+        assert_eq!(received[3], BuildEventAction::BuildCompleted);
+    }
+
+    #[tokio::test]
+    async fn test_stops_when_bazel_exits_without_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, rx) = async_channel::unbounded();
+        let (finished_tx, finished_rx) = oneshot::channel();
+        let tailer = tokio::spawn(tail_build_event_file(
+            dir.path().join("never_written.bin"),
+            tx,
+            finished_rx,
+        ));
+        finished_tx.send(()).unwrap();
+        tailer.await.unwrap();
+        assert_eq!(rx.recv().await, Ok(BuildEventAction::BuildCompleted));
+    }
+}
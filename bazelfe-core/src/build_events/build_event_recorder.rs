@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use bazelfe_protos::*;
+use google::devtools::build::v1::PublishBuildToolEventStreamRequest;
+use prost::Message;
+use tokio::io::AsyncWriteExt;
+
+/// File extension used for recorded build event streams.
+pub const RECORDING_EXTENSION: &str = "bep";
+
+/// Records the raw build event streams bazel sends us, one length delimited file per bazel invocation.
+/// This is the same format as the build-events dev binary writes, so either can be replayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildEventRecorder {
+    directory: PathBuf,
+}
+
+impl BuildEventRecorder {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    pub fn start_stream(&self) -> StreamRecorder {
+        StreamRecorder {
+            directory: self.directory.clone(),
+            file: None,
+            failed: false,
+        }
+    }
+}
+
+/// Records a single stream, the file is named after the invocation id of the first event seen.
+#[derive(Debug)]
+pub struct StreamRecorder {
+    directory: PathBuf,
+    file: Option<tokio::fs::File>,
+    failed: bool,
+}
+
+impl StreamRecorder {
+    async fn open(
+        &self,
+        request: &PublishBuildToolEventStreamRequest,
+    ) -> std::io::Result<tokio::fs::File> {
+        let invocation_id = request
+            .ordered_build_event
+            .as_ref()
+            .and_then(|e| e.stream_id.as_ref())
+            .map(|s| s.invocation_id.clone())
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| String::from("unknown_invocation"));
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let path = self
+            .directory
+            .join(format!("{}.{}", invocation_id, RECORDING_EXTENSION));
+        debug!("Recording build events to {}", path.to_string_lossy());
+        tokio::fs::File::create(path).await
+    }
+
+    /// Failing to record shouldn't fail the build, we warn once and stop recording this stream.
+    pub async fn record(&mut self, request: &PublishBuildToolEventStreamRequest) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.try_record(request).await {
+            self.record_failed(e);
+        }
+    }
+
+    /// Flush what we've recorded, tokio may otherwise still be writing it out once the stream is done.
+    pub async fn finish(&mut self) {
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = file.flush().await {
+                self.record_failed(e);
+            }
+        }
+    }
+
+    fn record_failed(&mut self, e: std::io::Error) {
+        warn!("Unable to record build events: {}", e);
+        self.failed = true;
+        self.file = None;
+    }
+
+    async fn try_record(
+        &mut self,
+        request: &PublishBuildToolEventStreamRequest,
+    ) -> std::io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(self.open(request).await?);
+        }
+        let mut buf = Vec::with_capacity(request.encoded_len() + 10);
+        request.encode_length_delimited(&mut buf)?;
+        match self.file.as_mut() {
+            Some(file) => file.write_all(&buf).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Read back every request from a recorded stream.
+pub fn read_recording(path: &Path) -> std::io::Result<Vec<PublishBuildToolEventStreamRequest>> {
+    let data = std::fs::read(path)?;
+    let mut buf: &[u8] = &data;
+    let mut requests = Vec::default();
+    while !buf.is_empty() {
+        requests.push(
+            PublishBuildToolEventStreamRequest::decode_length_delimited(&mut buf)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+        );
+    }
+    Ok(requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("resources/tests/build_events/no_op_build.proto")
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let requests = read_recording(&fixture()).unwrap();
+        let invocation_id = requests[0]
+            .ordered_build_event
+            .as_ref()
+            .and_then(|e| e.stream_id.as_ref())
+            .map(|s| s.invocation_id.clone())
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = BuildEventRecorder::new(dir.path().join("recordings"));
+        let mut stream = recorder.start_stream();
+        for request in requests.iter() {
+            stream.record(request).await;
+        }
+        stream.finish().await;
+
+        let recorded = dir
+            .path()
+            .join("recordings")
+            .join(format!("{}.{}", invocation_id, RECORDING_EXTENSION));
+        assert_eq!(read_recording(&recorded).unwrap(), requests);
+        assert_eq!(
+            std::fs::read(&recorded).unwrap(),
+            std::fs::read(fixture()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_truncated_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("truncated.bep");
+        let data = std::fs::read(fixture()).unwrap();
+        std::fs::write(&path, &data[..data.len() - 3]).unwrap();
+        assert_eq!(
+            read_recording(&path).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+}
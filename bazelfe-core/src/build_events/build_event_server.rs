@@ -9,6 +9,9 @@ use google::devtools::build::v1::{
     PublishLifecycleEventRequest,
 };
 use std::pin::Pin;
+
+use super::build_event_recorder::BuildEventRecorder;
+use super::upstream_forwarder::UpstreamForwarder;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -28,266 +31,352 @@ pub mod bazel_event {
                 .take()
                 .as_mut()
                 .and_then(|inner| inner.event.take());
-            let _event_time = inner_data.as_mut().and_then(|e| e.event_time.take());
+            let event_time_millis = inner_data
+                .as_mut()
+                .and_then(|e| e.event_time.take())
+                .map(|t| t.seconds * 1000 + i64::from(t.nanos) / 1_000_000);
             let _event = inner_data.and_then(|mut e| e.event.take());
 
-            let decoded_evt = match _event {
-                Some(inner) => {
-                    match inner {
-                        google::devtools::build::v1::build_event::Event::BazelEvent(e) => {
-                            use prost::Message;
-
-                            let v = build_event_stream::BuildEvent::decode(&*e.value).unwrap();
-
-                            let target_configured_evt: Option<TargetConfiguredEvt> = {
-                                let target_kind_opt = v.payload.as_ref().and_then(|e| match e {
-                                    build_event_stream::build_event::Payload::Configured(cfg) => {
-                                        Some(cfg.target_kind.replace(" rule", ""))
-                                    }
-                                    _ => None,
-                                });
-                                let target_label_opt =
-                                    v.id.as_ref().and_then(|e| e.id.as_ref()).and_then(|e| {
-                                        match e {
-                                    build_event_stream::build_event_id::Id::TargetConfigured(
-                                        target_configured_id,
-                                    ) => Some(target_configured_id.label.clone()),
-                                    _ => None,
-                                }
-                                    });
-
-                                target_kind_opt.and_then(|e| {
-                                    target_label_opt.map(|u| TargetConfiguredEvt {
-                                        rule_kind: e,
-                                        label: u,
-                                    })
-                                })
-                            };
-
-                            let aborted: Option<Evt> = {
-                                let abort_info = v.payload.as_ref().and_then(|e| match e {
-                                    build_event_stream::build_event::Payload::Aborted(cfg) => {
-                                        Some((
-                                            build_event_stream::aborted::AbortReason::from_i32(
-                                                cfg.reason,
-                                            ),
-                                            cfg.description.clone(),
-                                        ))
-                                    }
-                                    _ => None,
-                                });
-                                let target_label_opt = v
-                                    .id
-                                    .as_ref()
-                                    .and_then(|e| e.id.as_ref())
-                                    .and_then(|e| match e {
-                                        build_event_stream::build_event_id::Id::ConfiguredLabel(
-                                            configured_label_id,
-                                        ) => Some(configured_label_id.label.clone()),
-                                        _ => None,
-                                    });
-
-                                abort_info.map(|(reason, description)| {
-                                    Evt::Aborted(AbortedEvt {
-                                        label: target_label_opt,
-                                        reason,
-                                        description,
-                                    })
-                                })
-                            };
-
-                            let progress_info: Option<Evt> =
-                                v.payload.as_ref().and_then(|e| match e {
-                                    build_event_stream::build_event::Payload::Progress(cfg) => {
-                                        if cfg.stdout.is_empty() && cfg.stderr.is_empty() {
-                                            None
-                                        } else {
-                                            Some(Evt::Progress(ProgressEvt {
-                                                stdout: cfg.stdout.clone(),
-                                                stderr: cfg.stderr.clone(),
-                                            }))
-                                        }
-                                    }
-                                    _ => None,
-                                });
-
-                            let action_info: Option<Evt> = {
-                                let target_label_opt = v
-                                    .id
-                                    .as_ref()
-                                    .and_then(|e| e.id.as_ref())
-                                    .and_then(|e| match e {
-                                        build_event_stream::build_event_id::Id::ActionCompleted(
-                                            action_completed_id,
-                                        ) => Some(action_completed_id.label.clone()),
-                                        _ => None,
-                                    });
-
-                                target_label_opt.and_then(|label| {
-                                    v.payload.as_ref().and_then(|e| match e {
-                                        build_event_stream::build_event::Payload::Action(
-                                            action_executed,
-                                        ) => {
-                                            let stdout = action_executed
-                                                .stdout
-                                                .as_ref()
-                                                .and_then(|e| e.file.clone());
-                                            let stderr = action_executed
-                                                .stderr
-                                                .as_ref()
-                                                .and_then(|e| e.file.clone());
-
-                                            Some(Evt::ActionCompleted(ActionCompletedEvt {
-                                                success: action_executed.success,
-                                                label,
-                                                stdout,
-                                                stderr,
-                                            }))
-                                        }
-                                        _ => None,
-                                    })
-                                })
-                            };
-
-                            let named_set_of_files: Option<Evt> = {
-                                let fileset_id =
-                                    v.id.as_ref().and_then(|e| e.id.as_ref()).and_then(
-                                        |e| match e {
-                                            build_event_stream::build_event_id::Id::NamedSet(
-                                                fileset_id,
-                                            ) => Some(fileset_id.id.clone()),
-                                            _ => None,
-                                        },
-                                    );
-
-                                fileset_id.and_then(|id| {
-                                    v.payload.as_ref().and_then(|e| {
-                                        match e {
-                                    build_event_stream::build_event::Payload::NamedSetOfFiles(
-                                        named_set_of_files,
-                                    ) => Some(Evt::NamedSetOfFiles {
-                                        id,
-                                        named_set_of_files: named_set_of_files.clone(),
-                                    }),
-                                    _ => None,
-                                }
-                                    })
-                                })
-                            };
-
-                            let target_complete: Option<Evt> = {
-                                let target_label_opt = v
-                                    .id
-                                    .as_ref()
-                                    .and_then(|e| e.id.as_ref())
-                                    .and_then(|e| match e {
-                                        build_event_stream::build_event_id::Id::TargetCompleted(
-                                            target_completed_id,
-                                        ) => Some((
-                                            target_completed_id.label.clone(),
-                                            Some(target_completed_id.aspect.clone())
-                                                .filter(|e| !e.is_empty()),
-                                        )),
-                                        _ => None,
-                                    });
-
-                                target_label_opt.and_then(|(label, aspect)| {
-                                    v.payload.as_ref().and_then(|e| match e {
-                                        build_event_stream::build_event::Payload::Completed(
-                                            target_completed,
-                                        ) => Some(Evt::TargetCompleted(TargetCompletedEvt {
-                                            success: target_completed.success,
-                                            label,
-                                            aspect,
-                                            output_groups: target_completed.output_group.clone(),
-                                        })),
-                                        _ => None,
-                                    })
-                                })
-                            };
-
-                            let test_outputs: Option<Evt> = {
-                                let failed_file_data: Option<(
-                                    build_event_stream::TestStatus,
-                                    Vec<build_event_stream::file::File>,
-                                )> = v.payload.as_ref().and_then(|e| match e {
-                                    build_event_stream::build_event::Payload::TestResult(cfg) => {
-                                        Some((
-                                            cfg.status(),
-                                            cfg.test_action_output
-                                                .iter()
-                                                .flat_map(|e| e.file.clone().into_iter())
-                                                .collect(),
-                                        ))
-                                    }
-                                    _ => None,
-                                });
-
-                                let target_label_opt =
-                                    v.id.as_ref().and_then(|e| e.id.as_ref()).and_then(
-                                        |e| match e {
-                                            build_event_stream::build_event_id::Id::TestResult(
-                                                test_summary_id,
-                                            ) => Some(test_summary_id.label.clone()),
-                                            _ => None,
-                                        },
-                                    );
-
-                                failed_file_data.and_then(|(test_status, failed_files)| {
-                                target_label_opt.map(|u| {
-
-                                    let test_status= match test_status {
-                                        build_event_stream::TestStatus::NoStatus => todo!(),
-                                        build_event_stream::TestStatus::Passed => TestStatus::Passed,
-                                        build_event_stream::TestStatus::Flaky => TestStatus::Flaky,
-                                        build_event_stream::TestStatus::Timeout => TestStatus::Timeout,
-                                        build_event_stream::TestStatus::Failed => TestStatus::Failed,
-                                        build_event_stream::TestStatus::Incomplete => TestStatus::Incomplete,
-                                        build_event_stream::TestStatus::RemoteFailure => TestStatus::RemoteFailure,
-                                        build_event_stream::TestStatus::FailedToBuild => TestStatus::FailedToBuild,
-                                        build_event_stream::TestStatus::ToolHaltedBeforeTesting => TestStatus::ToolHaltedBeforeTesting,
-                                    };
-                                    Evt::TestResult(TestResultEvt {
-                                        label: u,
-                                        test_status,
-                                        failed_files,
-                                    })
-                                })
-                            })
-                            };
-
-                            if let Some(e) = target_configured_evt {
-                                Evt::TargetConfigured(e)
-                            } else if let Some(e) = action_info {
-                                e
-                            } else if let Some(e) = target_complete {
-                                e
-                            } else if let Some(e) = test_outputs {
-                                e
-                            } else if let Some(e) = named_set_of_files {
-                                e
-                            } else if let Some(e) = aborted {
-                                e
-                            } else if let Some(e) = progress_info {
-                                e
-                            } else {
-                                Evt::BazelEvent(v)
-                            }
-                        }
-                        other => Evt::UnknownEvent(format!("{:?}", other)),
+            let mut decoded_evt = match _event {
+                Some(inner) => match inner {
+                    google::devtools::build::v1::build_event::Event::BazelEvent(e) => {
+                        use prost::Message;
+
+                        let v = build_event_stream::BuildEvent::decode(&*e.value).unwrap();
+                        Self::decode_evt(v)
                     }
-                }
+                    other => Evt::UnknownEvent(format!("{:?}", other)),
+                },
                 None => Evt::UnknownEvent("Missing Event".to_string()),
             };
+            match &mut decoded_evt {
+                Evt::TargetConfigured(e) => e.event_time_millis = event_time_millis,
+                Evt::TargetCompleted(e) => e.event_time_millis = event_time_millis,
+                _ => (),
+            }
 
             info!("Decoded evt: {:?}", decoded_evt);
             Some(BazelBuildEvent { event: decoded_evt })
         }
+
+        /// For events read straight from bazel, such as from a --build_event_binary_file, rather than via the BES protocol.
+        pub fn from_build_event(v: build_event_stream::BuildEvent) -> BazelBuildEvent {
+            let decoded_evt = Self::decode_evt(v);
+            info!("Decoded evt: {:?}", decoded_evt);
+            BazelBuildEvent { event: decoded_evt }
+        }
+
+        fn decode_evt(v: build_event_stream::BuildEvent) -> Evt {
+            let target_configured_evt: Option<TargetConfiguredEvt> = {
+                let configured_opt = v.payload.as_ref().and_then(|e| match e {
+                    build_event_stream::build_event::Payload::Configured(cfg) => Some(cfg),
+                    _ => None,
+                });
+                let target_label_opt =
+                    v.id.as_ref()
+                        .and_then(|e| e.id.as_ref())
+                        .and_then(|e| match e {
+                            build_event_stream::build_event_id::Id::TargetConfigured(
+                                target_configured_id,
+                            ) => Some((
+                                target_configured_id.label.clone(),
+                                Some(target_configured_id.aspect.clone()).filter(|e| !e.is_empty()),
+                            )),
+                            _ => None,
+                        });
+
+                configured_opt.and_then(|cfg| {
+                    target_label_opt.map(|(label, aspect)| TargetConfiguredEvt {
+                        rule_kind: cfg.target_kind.replace(" rule", ""),
+                        label,
+                        aspect,
+                        tags: cfg.tag.clone(),
+                        test_size: Some(cfg.test_size())
+                            .filter(|s| *s != build_event_stream::TestSize::Unknown),
+                        event_time_millis: None,
+                    })
+                })
+            };
+
+            let aborted: Option<Evt> = {
+                let abort_info = v.payload.as_ref().and_then(|e| match e {
+                    build_event_stream::build_event::Payload::Aborted(cfg) => Some((
+                        build_event_stream::aborted::AbortReason::from_i32(cfg.reason),
+                        cfg.description.clone(),
+                    )),
+                    _ => None,
+                });
+                let target_label_opt =
+                    v.id.as_ref()
+                        .and_then(|e| e.id.as_ref())
+                        .and_then(|e| match e {
+                            build_event_stream::build_event_id::Id::ConfiguredLabel(
+                                configured_label_id,
+                            ) => Some(configured_label_id.label.clone()),
+                            _ => None,
+                        });
+
+                abort_info.map(|(reason, description)| {
+                    Evt::Aborted(AbortedEvt {
+                        label: target_label_opt,
+                        reason,
+                        description,
+                    })
+                })
+            };
+
+            let progress_info: Option<Evt> = v.payload.as_ref().and_then(|e| match e {
+                build_event_stream::build_event::Payload::Progress(cfg) => {
+                    if cfg.stdout.is_empty() && cfg.stderr.is_empty() {
+                        None
+                    } else {
+                        Some(Evt::Progress(ProgressEvt {
+                            stdout: cfg.stdout.clone(),
+                            stderr: cfg.stderr.clone(),
+                        }))
+                    }
+                }
+                _ => None,
+            });
+
+            let action_info: Option<Evt> = {
+                let target_label_opt =
+                    v.id.as_ref()
+                        .and_then(|e| e.id.as_ref())
+                        .and_then(|e| match e {
+                            build_event_stream::build_event_id::Id::ActionCompleted(
+                                action_completed_id,
+                            ) => Some((
+                                action_completed_id.label.clone(),
+                                configuration_id(&action_completed_id.configuration),
+                            )),
+                            _ => None,
+                        });
+
+                target_label_opt.and_then(|(label, configuration)| {
+                    v.payload.as_ref().and_then(|e| match e {
+                        build_event_stream::build_event::Payload::Action(action_executed) => {
+                            let stdout =
+                                action_executed.stdout.as_ref().and_then(|e| e.file.clone());
+                            let stderr =
+                                action_executed.stderr.as_ref().and_then(|e| e.file.clone());
+
+                            Some(Evt::ActionCompleted(ActionCompletedEvt {
+                                success: action_executed.success,
+                                label,
+                                mnemonic: Some(action_executed.r#type.clone())
+                                    .filter(|e| !e.is_empty()),
+                                configuration,
+                                stdout,
+                                stderr,
+                            }))
+                        }
+                        _ => None,
+                    })
+                })
+            };
+
+            let named_set_of_files: Option<Evt> = {
+                let fileset_id =
+                    v.id.as_ref()
+                        .and_then(|e| e.id.as_ref())
+                        .and_then(|e| match e {
+                            build_event_stream::build_event_id::Id::NamedSet(fileset_id) => {
+                                Some(fileset_id.id.clone())
+                            }
+                            _ => None,
+                        });
+
+                fileset_id.and_then(|id| {
+                    v.payload.as_ref().and_then(|e| match e {
+                        build_event_stream::build_event::Payload::NamedSetOfFiles(
+                            named_set_of_files,
+                        ) => Some(Evt::NamedSetOfFiles {
+                            id,
+                            named_set_of_files: named_set_of_files.clone(),
+                        }),
+                        _ => None,
+                    })
+                })
+            };
+
+            let target_complete: Option<Evt> = {
+                let target_label_opt =
+                    v.id.as_ref()
+                        .and_then(|e| e.id.as_ref())
+                        .and_then(|e| match e {
+                            build_event_stream::build_event_id::Id::TargetCompleted(
+                                target_completed_id,
+                            ) => Some((
+                                target_completed_id.label.clone(),
+                                Some(target_completed_id.aspect.clone()).filter(|e| !e.is_empty()),
+                                configuration_id(&target_completed_id.configuration),
+                            )),
+                            _ => None,
+                        });
+
+                target_label_opt.and_then(|(label, aspect, configuration)| {
+                    v.payload.as_ref().and_then(|e| match e {
+                        build_event_stream::build_event::Payload::Completed(target_completed) => {
+                            Some(Evt::TargetCompleted(TargetCompletedEvt {
+                                success: target_completed.success,
+                                label,
+                                aspect,
+                                configuration,
+                                output_groups: target_completed.output_group.clone(),
+                                event_time_millis: None,
+                            }))
+                        }
+                        _ => None,
+                    })
+                })
+            };
+
+            let test_outputs: Option<Evt> = {
+                let failed_file_data: Option<(
+                    build_event_stream::TestStatus,
+                    Vec<build_event_stream::File>,
+                    bool,
+                )> = v.payload.as_ref().and_then(|e| match e {
+                    build_event_stream::build_event::Payload::TestResult(cfg) => Some((
+                        cfg.status(),
+                        cfg.test_action_output.clone(),
+                        cfg.cached_locally
+                            || cfg
+                                .execution_info
+                                .as_ref()
+                                .map(|e| e.cached_remotely)
+                                .unwrap_or(false),
+                    )),
+                    _ => None,
+                });
+
+                let target_label_opt =
+                    v.id.as_ref()
+                        .and_then(|e| e.id.as_ref())
+                        .and_then(|e| match e {
+                            build_event_stream::build_event_id::Id::TestResult(test_summary_id) => {
+                                Some(test_summary_id.clone())
+                            }
+                            _ => None,
+                        });
+
+                failed_file_data.and_then(|(test_status, failed_files, cached)| {
+                    target_label_opt.map(|test_result_id| {
+                        let test_status = match test_status {
+                            build_event_stream::TestStatus::NoStatus => todo!(),
+                            build_event_stream::TestStatus::Passed => TestStatus::Passed,
+                            build_event_stream::TestStatus::Flaky => TestStatus::Flaky,
+                            build_event_stream::TestStatus::Timeout => TestStatus::Timeout,
+                            build_event_stream::TestStatus::Failed => TestStatus::Failed,
+                            build_event_stream::TestStatus::Incomplete => TestStatus::Incomplete,
+                            build_event_stream::TestStatus::RemoteFailure => {
+                                TestStatus::RemoteFailure
+                            }
+                            build_event_stream::TestStatus::FailedToBuild => {
+                                TestStatus::FailedToBuild
+                            }
+                            build_event_stream::TestStatus::ToolHaltedBeforeTesting => {
+                                TestStatus::ToolHaltedBeforeTesting
+                            }
+                        };
+                        Evt::TestResult(TestResultEvt {
+                            configuration: configuration_id(&test_result_id.configuration),
+                            label: test_result_id.label,
+                            test_status,
+                            failed_files,
+                            cached,
+                            run: test_result_id.run,
+                            shard: test_result_id.shard,
+                            attempt: test_result_id.attempt,
+                        })
+                    })
+                })
+            };
+
+            let configuration: Option<Evt> =
+                v.id.as_ref()
+                    .and_then(|e| e.id.as_ref())
+                    .and_then(|e| match e {
+                        build_event_stream::build_event_id::Id::Configuration(configuration_id) => {
+                            Some(configuration_id.id.clone())
+                        }
+                        _ => None,
+                    })
+                    .and_then(|id| {
+                        v.payload.as_ref().and_then(|e| match e {
+                            build_event_stream::build_event::Payload::Configuration(
+                                configuration,
+                            ) => Some(Evt::Configuration(ConfigurationEvt {
+                                id,
+                                mnemonic: configuration.mnemonic.clone(),
+                            })),
+                            _ => None,
+                        })
+                    });
+
+            let build_timing: Option<Evt> = v.payload.as_ref().and_then(|e| {
+                match e {
+                    build_event_stream::build_event::Payload::Started(started) => {
+                        Some(BuildTimingEvt::BuildStarted {
+                            start_time_millis: started.start_time_millis,
+                        })
+                    }
+                    build_event_stream::build_event::Payload::Finished(finished) => {
+                        Some(BuildTimingEvt::BuildFinished {
+                            finish_time_millis: finished.finish_time_millis,
+                        })
+                    }
+                    build_event_stream::build_event::Payload::BuildMetrics(build_metrics) => {
+                        Some(BuildTimingEvt::BuildMetrics(build_metrics.clone()))
+                    }
+                    build_event_stream::build_event::Payload::BuildToolLogs(build_tool_logs) => {
+                        Some(BuildTimingEvt::BuildToolLogs(build_tool_logs.log.clone()))
+                    }
+                    _ => None,
+                }
+                .map(Evt::BuildTiming)
+            });
+
+            if let Some(e) = target_configured_evt {
+                Evt::TargetConfigured(e)
+            } else if let Some(e) = action_info {
+                e
+            } else if let Some(e) = target_complete {
+                e
+            } else if let Some(e) = test_outputs {
+                e
+            } else if let Some(e) = named_set_of_files {
+                e
+            } else if let Some(e) = aborted {
+                e
+            } else if let Some(e) = progress_info {
+                e
+            } else if let Some(e) = build_timing {
+                e
+            } else if let Some(e) = configuration {
+                e
+            } else {
+                Evt::BazelEvent(v)
+            }
+        }
+    }
+    fn configuration_id(
+        configuration: &Option<build_event_stream::build_event_id::ConfigurationId>,
+    ) -> Option<String> {
+        configuration
+            .as_ref()
+            .map(|c| c.id.clone())
+            .filter(|id| !id.is_empty())
     }
+
     #[derive(Clone, PartialEq, Debug)]
     pub struct ActionCompletedEvt {
         pub success: bool,
         pub label: String,
+        /// The kind of action, e.g. Javac or Scalac, aspect actions are only told apart from the target's own by this.
+        pub mnemonic: Option<String>,
+        pub configuration: Option<String>,
         pub stdout: Option<build_event_stream::file::File>,
         pub stderr: Option<build_event_stream::file::File>,
     }
@@ -320,21 +409,54 @@ pub mod bazel_event {
     pub struct TestResultEvt {
         pub label: String,
         pub test_status: TestStatus,
-        pub failed_files: Vec<build_event_stream::file::File>,
+        /// Every output of the test action, e.g. the test.log and test.xml.
+        pub failed_files: Vec<build_event_stream::File>,
+        pub cached: bool,
+        pub run: i32,
+        pub shard: i32,
+        pub attempt: i32,
+        pub configuration: Option<String>,
     }
     #[derive(Clone, PartialEq, Debug)]
     pub struct TargetConfiguredEvt {
         pub label: String,
         pub rule_kind: String,
+        /// Set when this is the expansion of the target for an aspect rather than the target itself.
+        pub aspect: Option<String>,
+        pub tags: Vec<String>,
+        pub test_size: Option<build_event_stream::TestSize>,
+        /// When bazel sent the event, only known when it came over the build event service.
+        pub event_time_millis: Option<i64>,
+    }
+
+    /// Maps the opaque configuration ids used by other events to something readable.
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct ConfigurationEvt {
+        pub id: String,
+        /// e.g. k8-fastbuild
+        pub mnemonic: String,
     }
 
     #[derive(Clone, PartialEq, Debug)]
     pub struct TargetCompletedEvt {
         pub label: String,
         pub aspect: Option<String>,
+        pub configuration: Option<String>,
         pub success: bool,
         pub output_groups: Vec<build_event_stream::OutputGroup>,
+        /// When bazel sent the event, only known when it came over the build event service.
+        pub event_time_millis: Option<i64>,
+    }
+
+    /// The build wide events bazel sends describing how long the build took and where the time went.
+    #[derive(Clone, PartialEq, Debug)]
+    pub enum BuildTimingEvt {
+        BuildStarted { start_time_millis: i64 },
+        BuildFinished { finish_time_millis: i64 },
+        BuildMetrics(build_event_stream::BuildMetrics),
+        BuildToolLogs(Vec<build_event_stream::File>),
     }
+
     #[derive(Clone, PartialEq, Debug)]
     pub enum Evt {
         BazelEvent(build_event_stream::BuildEvent),
@@ -344,6 +466,8 @@ pub mod bazel_event {
         Progress(ProgressEvt),
         Aborted(AbortedEvt),
         TargetCompleted(TargetCompletedEvt),
+        BuildTiming(BuildTimingEvt),
+        Configuration(ConfigurationEvt),
         NamedSetOfFiles {
             id: String,
             named_set_of_files: build_event_stream::NamedSetOfFiles,
@@ -366,6 +490,10 @@ where
     pub write_channel: Arc<Mutex<Option<async_channel::Sender<BuildEventAction<T>>>>>,
     pub transform_fn:
         Arc<dyn Fn(&mut PublishBuildToolEventStreamRequest) -> Option<T> + Send + Sync>,
+    /// When set every inbound stream is written out, untransformed, so it can be replayed later.
+    pub recorder: Option<BuildEventRecorder>,
+    /// When set every lifecycle event and stream is also proxied to another Build Event Service.
+    pub upstream: Option<UpstreamForwarder>,
 }
 
 fn transform_queue_error_to_status() -> Status {
@@ -382,6 +510,8 @@ pub fn build_bazel_build_events_service() -> (
     let server_instance = BuildEventService {
         write_channel: Arc::clone(&write_channel_arc),
         transform_fn: Arc::new(bazel_event::BazelBuildEvent::transform_from),
+        recorder: None,
+        upstream: None,
     };
     (server_instance, write_channel_arc, rx)
 }
@@ -411,9 +541,17 @@ where
         let cloned_v = sender_ref.clone();
         let second_writer = sender_ref;
         let transform_fn = Arc::clone(&self.transform_fn);
+        let mut stream_recorder = self.recorder.as_ref().map(|r| r.start_stream());
+        let mut upstream_stream = self.upstream.as_ref().map(|u| u.start_stream());
         let output = async_stream::try_stream! {
             while let Some(inbound_evt) = stream.next().await {
                 let mut inbound_evt = inbound_evt?;
+                if let Some(stream_recorder) = stream_recorder.as_mut() {
+                    stream_recorder.record(&inbound_evt).await;
+                }
+                if let Some(upstream_stream) = upstream_stream.as_mut() {
+                    upstream_stream.forward(&inbound_evt);
+                }
 
                 match inbound_evt.ordered_build_event.as_ref() {
                     Some(build_event) => {
@@ -444,6 +582,13 @@ where
                 }
             }
 
+            if let Some(stream_recorder) = stream_recorder.as_mut() {
+                stream_recorder.finish().await;
+            }
+            // Any earlier exit drops the upstream stream unfinished, which aborts it.
+            if let Some(upstream_stream) = upstream_stream.take() {
+                upstream_stream.finish();
+            }
 
             if let Some(tx) = second_writer {
                 tx.send(BuildEventAction::BuildCompleted).await.map_err(|_| transform_queue_error_to_status())?;
@@ -461,6 +606,9 @@ where
         &self,
         request: tonic::Request<PublishLifecycleEventRequest>,
     ) -> Result<tonic::Response<()>, tonic::Status> {
+        if let Some(upstream) = self.upstream.as_ref() {
+            upstream.forward_lifecycle_event(request.get_ref().clone());
+        }
         let cloned_v = {
             let e = Arc::clone(&self.write_channel);
             let m = e.lock().await;
@@ -648,6 +796,9 @@ mod tests {
                     event: bazel_event::Evt::TargetCompleted(bazel_event::TargetCompletedEvt {
                         label: label_name,
                         aspect: None,
+                        configuration: Some(String::from(
+                            "f54e660a7cb725ba2ffd16661381d64a939d3960a300b13986455f04fe08ca5f",
+                        )),
                         success: true,
                         output_groups: vec![OutputGroup {
                             name: String::from("default"),
@@ -655,6 +806,7 @@ mod tests {
                                 id: String::from("16"),
                             }],
                         }],
+                        event_time_millis: Some(1601574236411),
                     }),
                 }
             };
@@ -45,6 +45,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let greeter = BuildEventService {
         write_channel: Arc::new(Mutex::new(Some(tx))),
         transform_fn: std::sync::Arc::new(transform_fn),
+        recorder: None,
+        upstream: None,
     };
 
     tokio::spawn(async move {
@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bazelfe_protos::*;
+use build::bazel::remote::execution::v2::content_addressable_storage_client::ContentAddressableStorageClient;
+use build::bazel::remote::execution::v2::{BatchReadBlobsRequest, Digest};
+use google::bytestream::byte_stream_client::ByteStreamClient;
+use google::bytestream::ReadRequest;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tonic::{Code, Request};
+
+use crate::config::RemoteCache;
+use crate::remote_cache_proxy::RequestFilesClient;
+
+#[derive(Error, Debug)]
+pub enum FetchError {
+    #[error("Unsupported file uri {0}")]
+    UnsupportedUri(String),
+    #[error("Invalid bytestream uri {0}")]
+    InvalidByteStreamUri(String),
+    #[error("{0} is {1} bytes, larger than our limit of {2} bytes")]
+    TooLarge(String, u64, u64),
+    #[error("Unable to read {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("Invalid remote cache header {0}")]
+    InvalidHeader(String),
+    #[error("Unable to read remote cache CA certificate: {0}")]
+    CaCertificate(std::io::Error),
+    #[error("Invalid remote cache endpoint {0}: {1}")]
+    InvalidEndpoint(String, String),
+    #[error("Fetching {0} from the remote cache failed: {1}")]
+    Remote(String, String),
+}
+
+/// A blob in a CAS as bazel reports it: `bytestream://<host>/[<instance_name>/]blobs/<hash>/<size>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteStreamUri {
+    pub authority: String,
+    pub instance_name: String,
+    pub hash: String,
+    pub size_bytes: i64,
+}
+
+impl ByteStreamUri {
+    pub fn parse(uri: &str) -> Result<Self, FetchError> {
+        let invalid = || FetchError::InvalidByteStreamUri(uri.to_string());
+        let rest = uri.strip_prefix("bytestream://").ok_or_else(invalid)?;
+        let (authority, resource_name) = rest.split_once('/').ok_or_else(invalid)?;
+        let segments: Vec<&str> = resource_name.split('/').collect();
+        let blobs_idx = segments
+            .iter()
+            .position(|s| *s == "blobs")
+            .ok_or_else(invalid)?;
+        match &segments[blobs_idx + 1..] {
+            [hash, size] if !authority.is_empty() && !hash.is_empty() => Ok(Self {
+                authority: authority.to_string(),
+                instance_name: segments[..blobs_idx].join("/"),
+                hash: hash.to_string(),
+                size_bytes: size.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The resource name to hand the ByteStream service.
+    pub fn resource_name(&self) -> String {
+        if self.instance_name.is_empty() {
+            format!("blobs/{}/{}", self.hash, self.size_bytes)
+        } else {
+            format!(
+                "{}/blobs/{}/{}",
+                self.instance_name, self.hash, self.size_bytes
+            )
+        }
+    }
+
+    pub fn digest(&self) -> Digest {
+        Digest {
+            hash: self.hash.clone(),
+            size_bytes: self.size_bytes,
+        }
+    }
+}
+
+/// Loads the contents of files referenced from the build event stream, wherever bazel left them:
+/// on local disk, inline in the event or in a remote CAS.
+#[derive(Clone, Debug)]
+pub struct FileFetcher {
+    config: RemoteCache,
+    headers: MetadataMap,
+    tls_ca_certificate: Option<Certificate>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>,
+    request_files: Option<RequestFilesClient>,
+}
+
+/// The path a file:// uri points at, bazel percent encodes characters such as spaces in them.
+pub fn local_path(uri: &str) -> Option<PathBuf> {
+    uri.strip_prefix("file://").map(|path| {
+        PathBuf::from(
+            percent_encoding::percent_decode_str(path)
+                .decode_utf8_lossy()
+                .into_owned(),
+        )
+    })
+}
+
+impl FileFetcher {
+    pub fn new(config: &RemoteCache) -> Result<Self, FetchError> {
+        let mut headers = MetadataMap::new();
+        for (k, v) in config.headers.iter() {
+            let key = AsciiMetadataKey::from_bytes(k.as_bytes())
+                .map_err(|_| FetchError::InvalidHeader(k.clone()))?;
+            let value = AsciiMetadataValue::from_str(v)
+                .map_err(|_| FetchError::InvalidHeader(k.clone()))?;
+            headers.insert(key, value);
+        }
+        let tls_ca_certificate = match config.tls_ca_certificate.as_ref() {
+            Some(path) => Some(Certificate::from_pem(
+                std::fs::read(path).map_err(FetchError::CaCertificate)?,
+            )),
+            None => None,
+        };
+        let request_files = match config.request_files_endpoint.as_ref() {
+            Some(endpoint) => Some(
+                RequestFilesClient::connect_lazy(endpoint)
+                    .map_err(|e| FetchError::InvalidEndpoint(endpoint.clone(), e.to_string()))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            headers,
+            tls_ca_certificate,
+            channels: Arc::new(Mutex::new(HashMap::default())),
+            request_files,
+        })
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        *request.metadata_mut() = self.headers.clone();
+        request
+    }
+
+    /// One lazily connected channel per cache host, shared by everything we fetch from it.
+    async fn channel(&self, authority: &str) -> Result<Channel, FetchError> {
+        let mut channels = self.channels.lock().await;
+        if let Some(channel) = channels.get(authority) {
+            return Ok(channel.clone());
+        }
+        let scheme = if self.tls_ca_certificate.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let invalid_endpoint = |e: String| FetchError::InvalidEndpoint(authority.to_string(), e);
+        let mut endpoint = Endpoint::from_shared(format!("{}://{}", scheme, authority))
+            .map_err(|e| invalid_endpoint(e.to_string()))?
+            .connect_timeout(self.config.connect_timeout);
+        if let Some(ca) = self.tls_ca_certificate.as_ref() {
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().ca_certificate(ca.clone()))
+                .map_err(|e| invalid_endpoint(e.to_string()))?;
+        }
+        let channel = endpoint.connect_lazy();
+        channels.insert(authority.to_string(), channel.clone());
+        Ok(channel)
+    }
+
+    fn check_size(&self, uri: &str, size: u64) -> Result<(), FetchError> {
+        if size > self.config.max_file_size {
+            Err(FetchError::TooLarge(
+                uri.to_string(),
+                size,
+                self.config.max_file_size,
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn fetch(
+        &self,
+        file: &build_event_stream::file::File,
+    ) -> Result<Vec<u8>, FetchError> {
+        match file {
+            build_event_stream::file::File::Contents(contents) => Ok(contents.clone()),
+            build_event_stream::file::File::Uri(uri) => {
+                if let Some(path) = local_path(uri) {
+                    let io_error = |e| FetchError::Io(uri.clone(), e);
+                    let file_len = tokio::fs::metadata(&path).await.map_err(io_error)?.len();
+                    self.check_size(uri, file_len)?;
+                    tokio::fs::read(path).await.map_err(io_error)
+                } else if uri.starts_with("bytestream://") {
+                    self.fetch_blob(&ByteStreamUri::parse(uri)?).await
+                } else {
+                    Err(FetchError::UnsupportedUri(uri.clone()))
+                }
+            }
+        }
+    }
+
+    /// As fetch, for the text logs we parse errors out of.
+    pub async fn fetch_string(
+        &self,
+        file: &build_event_stream::file::File,
+    ) -> Result<String, FetchError> {
+        let bytes = self.fetch(file).await?;
+        Ok(String::from_utf8(bytes)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+    }
+
+    /// Asks the RequestFilesService for the blob when one is configured, otherwise reads it over the ByteStream API,
+    /// falling back to the CAS batch API for caches which don't serve ByteStream.
+    pub async fn fetch_blob(&self, uri: &ByteStreamUri) -> Result<Vec<u8>, FetchError> {
+        let resource_name = uri.resource_name();
+        self.check_size(&resource_name, uri.size_bytes.max(0) as u64)?;
+        if let Some(request_files) = self.request_files.as_ref() {
+            match request_files
+                .request_file(&uri.instance_name, &uri.digest())
+                .await
+            {
+                Ok(path) => {
+                    return tokio::fs::read(&path)
+                        .await
+                        .map_err(|e| FetchError::Io(path.to_string_lossy().to_string(), e))
+                }
+                Err(status) => debug!(
+                    "RequestFiles couldn't provide {}, reading it from the cache directly: {}",
+                    resource_name, status
+                ),
+            }
+        }
+        let channel = self.channel(&uri.authority).await?;
+
+        let remote_error =
+            |e: tonic::Status| FetchError::Remote(resource_name.clone(), e.to_string());
+        let mut client = ByteStreamClient::new(channel.clone());
+        let read = client
+            .read(self.request(ReadRequest {
+                resource_name: resource_name.clone(),
+                read_offset: 0,
+                read_limit: 0,
+            }))
+            .await;
+        match read {
+            Ok(response) => {
+                let mut stream = response.into_inner();
+                let mut data = Vec::with_capacity(uri.size_bytes.max(0) as usize);
+                while let Some(chunk) = stream.message().await.map_err(remote_error)? {
+                    data.extend(chunk.data);
+                }
+                Ok(data)
+            }
+            Err(status) if status.code() == Code::Unimplemented => {
+                debug!(
+                    "ByteStream isn't served by {}, falling back to the CAS",
+                    uri.authority
+                );
+                let mut client = ContentAddressableStorageClient::new(channel);
+                let response = client
+                    .batch_read_blobs(self.request(BatchReadBlobsRequest {
+                        instance_name: uri.instance_name.clone(),
+                        digests: vec![uri.digest()],
+                    }))
+                    .await
+                    .map_err(remote_error)?
+                    .into_inner();
+                let blob = response
+                    .responses
+                    .into_iter()
+                    .find(|r| r.digest.as_ref() == Some(&uri.digest()))
+                    .ok_or_else(|| {
+                        FetchError::Remote(resource_name.clone(), String::from("Blob missing"))
+                    })?;
+                match blob.status {
+                    Some(status) if status.code != Code::Ok as i32 => {
+                        Err(FetchError::Remote(resource_name.clone(), status.message))
+                    }
+                    _ => Ok(blob.data),
+                }
+            }
+            Err(status) => Err(remote_error(status)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote_cache_proxy::in_memory_cas::{start_cas, start_request_files, InMemoryCas};
+    use crate::remote_cache_proxy::{local_cas::digest_of, LocalCas, RequestFilesProxy};
+
+    fn uri(s: &str) -> build_event_stream::file::File {
+        build_event_stream::file::File::Uri(s.to_string())
+    }
+
+    #[test]
+    fn test_parse_bytestream_uri() {
+        let parsed =
+            ByteStreamUri::parse("bytestream://cache.example.com:443/main/instance/blobs/abc/12")
+                .unwrap();
+        assert_eq!(
+            parsed,
+            ByteStreamUri {
+                authority: String::from("cache.example.com:443"),
+                instance_name: String::from("main/instance"),
+                hash: String::from("abc"),
+                size_bytes: 12,
+            }
+        );
+        assert_eq!(parsed.resource_name(), "main/instance/blobs/abc/12");
+        assert_eq!(
+            ByteStreamUri::parse("bytestream://cache/blobs/abc/12")
+                .unwrap()
+                .resource_name(),
+            "blobs/abc/12"
+        );
+
+        for invalid in [
+            "bytestream://cache/blobs/abc",
+            "bytestream://cache/blobs/abc/twelve",
+            "bytestream://cache/uploads/abc/12",
+            "bytestream:///blobs/abc/12",
+            "file:///blobs/abc/12",
+        ] {
+            assert!(ByteStreamUri::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_local_and_inline() {
+        let fetcher = FileFetcher::new(&RemoteCache::default()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stderr");
+        std::fs::write(&path, "error: oops").unwrap();
+
+        assert_eq!(
+            fetcher
+                .fetch_string(&uri(&format!("file://{}", path.to_string_lossy())))
+                .await
+                .unwrap(),
+            "error: oops"
+        );
+        assert_eq!(
+            fetcher
+                .fetch(&build_event_stream::file::File::Contents(
+                    b"inline".to_vec()
+                ))
+                .await
+                .unwrap(),
+            b"inline".to_vec()
+        );
+        let spaced = dir.path().join("my test");
+        std::fs::create_dir(&spaced).unwrap();
+        std::fs::write(spaced.join("test.log"), "spaced").unwrap();
+        assert_eq!(
+            fetcher
+                .fetch_string(&uri(&format!(
+                    "file://{}/my%20test/test.log",
+                    dir.path().to_string_lossy()
+                )))
+                .await
+                .unwrap(),
+            "spaced"
+        );
+        assert!(matches!(
+            fetcher.fetch(&uri("remote_uri://foo/bar")).await,
+            Err(FetchError::UnsupportedUri(_))
+        ));
+
+        let small = FileFetcher::new(&RemoteCache {
+            max_file_size: 4,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            small
+                .fetch(&uri(&format!("file://{}", path.to_string_lossy())))
+                .await,
+            Err(FetchError::TooLarge(_, 11, 4))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_cas() {
+        let cas = InMemoryCas::default();
+        let content = b"src/Foo.scala:3: error: not found: type Bar";
+        cas.insert("abc", content);
+        let authority = start_cas(cas.clone(), true).await;
+
+        let mut config = RemoteCache::default();
+        config
+            .headers
+            .insert(String::from("x-api-key"), String::from("secret"));
+        let fetcher = FileFetcher::new(&config).unwrap();
+
+        let fetched = fetcher
+            .fetch(&uri(&format!(
+                "bytestream://{}/instance/blobs/abc/{}",
+                authority,
+                content.len()
+            )))
+            .await
+            .unwrap();
+        assert_eq!(fetched, content.to_vec());
+        assert_eq!(*cas.api_keys.lock().unwrap(), vec![String::from("secret")]);
+
+        assert!(matches!(
+            fetcher
+                .fetch(&uri(&format!("bytestream://{}/blobs/missing/3", authority)))
+                .await,
+            Err(FetchError::Remote(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_batch_reads() {
+        let cas = InMemoryCas::default();
+        cas.insert("abc", b"hello");
+        let authority = start_cas(cas, false).await;
+
+        let fetcher = FileFetcher::new(&RemoteCache::default()).unwrap();
+        assert_eq!(
+            fetcher
+                .fetch_string(&uri(&format!("bytestream://{}/blobs/abc/5", authority)))
+                .await
+                .unwrap(),
+            "hello"
+        );
+        assert!(matches!(
+            fetcher
+                .fetch(&uri(&format!("bytestream://{}/blobs/missing/3", authority)))
+                .await,
+            Err(FetchError::Remote(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_through_request_files() {
+        let content = b"src/Foo.scala:3: error: not found: type Bar";
+        let digest = digest_of(content);
+        let dir = tempfile::tempdir().unwrap();
+        let cas = LocalCas::new(dir.path().to_path_buf());
+        cas.put(&digest, content).await.unwrap();
+
+        let fetcher = FileFetcher::new(&RemoteCache {
+            request_files_endpoint: Some(
+                start_request_files(RequestFilesProxy::new(cas, None)).await,
+            ),
+            ..Default::default()
+        })
+        .unwrap();
+        // Nothing is listening on the cache in the uri, so this can only come from the local CAS.
+        let unused_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert_eq!(
+            fetcher
+                .fetch(&uri(&format!(
+                    "bytestream://{}/blobs/{}/{}",
+                    unused_addr, digest.hash, digest.size_bytes
+                )))
+                .await
+                .unwrap(),
+            content.to_vec()
+        );
+    }
+}
@@ -15,12 +15,59 @@ use bazelfe_protos::*;
 // This is keeping some state as we go through a stream to hydrate values with things like rule kinds
 // not on the indvidual events.
 
+/// What the stream told us about the target an event is for, beyond its kind.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct TargetDetails {
+    pub tags: Vec<String>,
+    pub test_size: Option<build_event_stream::TestSize>,
+    /// The configuration's mnemonic, e.g. k8-fastbuild, or its id if bazel never described it.
+    pub configuration: Option<String>,
+}
+
+impl TargetDetails {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+#[derive(Default)]
+struct TargetLookup {
+    // Only the targets themselves, an aspect's expansion of a target would otherwise clobber them.
+    configured_targets: HashMap<String, bazel_event::TargetConfiguredEvt>,
+    configurations: HashMap<String, String>,
+}
+
+impl TargetLookup {
+    fn target_kind(&self, label: &str) -> Option<String> {
+        self.configured_targets
+            .get(label)
+            .map(|t| t.rule_kind.clone())
+    }
+
+    fn details(&self, label: &str, configuration: Option<String>) -> TargetDetails {
+        let configured = self.configured_targets.get(label);
+        TargetDetails {
+            tags: configured.map(|t| t.tags.clone()).unwrap_or_default(),
+            test_size: configured.and_then(|t| t.test_size),
+            configuration: configuration
+                .map(|id| self.configurations.get(&id).cloned().unwrap_or(id)),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.configured_targets.clear();
+        self.configurations.clear();
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct ActionFailedErrorInfo {
     pub label: String,
     pub stdout: Option<build_event_stream::File>,
     pub stderr: Option<build_event_stream::File>,
     pub target_kind: Option<String>,
+    pub mnemonic: Option<String>,
+    pub details: TargetDetails,
 }
 impl ActionFailedErrorInfo {
     pub fn files(&self) -> Vec<build_event_stream::File> {
@@ -40,6 +87,40 @@ impl ActionFailedErrorInfo {
 pub struct TestResultInfo {
     pub test_summary_event: TestResultEvt,
     pub target_kind: Option<String>,
+    pub details: TargetDetails,
+    /// The test's outputs, local files or bytestream uris when they were left in a remote cache.
+    pub test_log: Option<build_event_stream::file::File>,
+    pub test_xml: Option<build_event_stream::file::File>,
+}
+
+impl TestResultInfo {
+    /// Bazel names the outputs test.log and test.xml, for events without names we go by the uri's extension.
+    fn test_output(
+        tfe: &TestResultEvt,
+        file_name: &str,
+        extension: &str,
+    ) -> Option<build_event_stream::file::File> {
+        tfe.failed_files
+            .iter()
+            .find(|f| f.name == file_name)
+            .or_else(|| {
+                tfe.failed_files.iter().find(|f| {
+                    matches!(f.file.as_ref(), Some(build_event_stream::file::File::Uri(uri)) if uri.ends_with(extension))
+                })
+            })
+            .and_then(|f| f.file.clone())
+    }
+
+    pub fn new(tfe: TestResultEvt, target_kind: Option<String>, details: TargetDetails) -> Self {
+        Self {
+            // Retried attempts are written as test_attempts/attempt_N.log, alongside their xml.
+            test_log: Self::test_output(&tfe, "test.log", ".log"),
+            test_xml: Self::test_output(&tfe, "test.xml", ".xml"),
+            test_summary_event: tfe,
+            target_kind,
+            details,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -55,6 +136,8 @@ pub struct ActionSuccessInfo {
     pub stdout: Option<build_event_stream::File>,
     pub stderr: Option<build_event_stream::File>,
     pub target_kind: Option<String>,
+    pub mnemonic: Option<String>,
+    pub details: TargetDetails,
 }
 
 impl ActionSuccessInfo {
@@ -75,10 +158,13 @@ impl ActionSuccessInfo {
 #[derive(Clone, PartialEq, Debug)]
 pub struct TargetCompleteInfo {
     pub label: String,
+    /// Set when this is an aspect applied to the target completing, e.g. //tools:lint.bzl%scalafix_aspect
     pub aspect: Option<String>,
     pub success: bool,
     pub target_kind: Option<String>,
     pub output_files: Vec<build_event_stream::File>,
+    pub details: TargetDetails,
+    pub event_time_millis: Option<i64>,
 }
 
 // Broad strokes of the failure occured inside an action (most common)
@@ -91,6 +177,49 @@ pub enum HydratedInfo {
     TestResult(TestResultInfo),
     ActionSuccess(ActionSuccessInfo),
     TargetComplete(TargetCompleteInfo),
+    TargetConfigured(bazel_event::TargetConfiguredEvt),
+    BuildTiming(bazel_event::BuildTimingEvt),
+}
+
+impl HydratedInfo {
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            HydratedInfo::BazelAbort(e) => e.label.as_deref(),
+            HydratedInfo::ActionFailed(e) => Some(&e.label),
+            HydratedInfo::TestResult(e) => Some(&e.test_summary_event.label),
+            HydratedInfo::ActionSuccess(e) => Some(&e.label),
+            HydratedInfo::TargetComplete(e) => Some(&e.label),
+            HydratedInfo::TargetConfigured(e) => Some(&e.label),
+            HydratedInfo::Progress(_) | HydratedInfo::BuildTiming(_) => None,
+        }
+    }
+
+    /// Only target level events know their aspect, bazel doesn't say which aspect an action came from.
+    pub fn aspect(&self) -> Option<&str> {
+        match self {
+            HydratedInfo::TargetComplete(e) => e.aspect.as_deref(),
+            HydratedInfo::TargetConfigured(e) => e.aspect.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn mnemonic(&self) -> Option<&str> {
+        match self {
+            HydratedInfo::ActionFailed(e) => e.mnemonic.as_deref(),
+            HydratedInfo::ActionSuccess(e) => e.mnemonic.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn details(&self) -> Option<&TargetDetails> {
+        match self {
+            HydratedInfo::ActionFailed(e) => Some(&e.details),
+            HydratedInfo::TestResult(e) => Some(&e.details),
+            HydratedInfo::ActionSuccess(e) => Some(&e.details),
+            HydratedInfo::TargetComplete(e) => Some(&e.details),
+            _ => None,
+        }
+    }
 }
 
 async fn recursive_lookup(
@@ -113,7 +242,7 @@ async fn recursive_lookup(
 
 async fn tce_event(
     tce: bazel_event::TargetCompletedEvt,
-    rule_kind_lookup: &HashMap<String, String>,
+    target_lookup: &TargetLookup,
     named_set_of_files_lookup: &HashMap<String, build_event_stream::NamedSetOfFiles>,
     to_revisit: &mut Vec<bazel_event::TargetCompletedEvt>,
 ) -> Option<TargetCompleteInfo> {
@@ -137,10 +266,12 @@ async fn tce_event(
     if found_everything {
         let target_complete_info = TargetCompleteInfo {
             output_files,
-            target_kind: rule_kind_lookup.get(&tce.label).cloned(),
+            target_kind: target_lookup.target_kind(&tce.label),
+            details: target_lookup.details(&tce.label, tce.configuration),
             aspect: tce.aspect,
             label: tce.label,
             success: tce.success,
+            event_time_millis: tce.event_time_millis,
         };
         Some(target_complete_info)
     } else {
@@ -156,21 +287,34 @@ impl HydratedInfo {
         let (tx, next_rx) = async_channel::unbounded();
 
         let mut named_set_of_files_lookup = HashMap::new();
-        let mut rule_kind_lookup = HashMap::new();
+        let mut target_lookup = TargetLookup::default();
         let mut buffered_tce: Vec<bazel_event::TargetCompletedEvt> = Vec::default();
 
         tokio::spawn(async move {
             while let Ok(action) = rx.recv().await {
                 match action {
                     BuildEventAction::BuildCompleted => {
-                        rule_kind_lookup.clear();
+                        target_lookup.clear();
                         tx.send(None).await.unwrap();
                     }
                     BuildEventAction::LifecycleEvent(_) => (),
                     BuildEventAction::BuildEvent(msg) => match msg.event {
                         bazel_event::Evt::BazelEvent(_) => {}
                         bazel_event::Evt::TargetConfigured(tgt_cfg) => {
-                            rule_kind_lookup.insert(tgt_cfg.label, tgt_cfg.rule_kind);
+                            if tgt_cfg.aspect.is_none() {
+                                target_lookup
+                                    .configured_targets
+                                    .insert(tgt_cfg.label.clone(), tgt_cfg.clone());
+                            }
+                            tx.send(Some(HydratedInfo::TargetConfigured(tgt_cfg)))
+                                .await
+                                .unwrap();
+                        }
+
+                        bazel_event::Evt::Configuration(configuration) => {
+                            target_lookup
+                                .configurations
+                                .insert(configuration.id, configuration.mnemonic);
                         }
 
                         bazel_event::Evt::NamedSetOfFiles {
@@ -185,7 +329,7 @@ impl HydratedInfo {
                             for tce in tmp_v.into_iter() {
                                 if let Some(target_complete_info) = tce_event(
                                     tce,
-                                    &rule_kind_lookup,
+                                    &target_lookup,
                                     &named_set_of_files_lookup,
                                     &mut buffered_tce,
                                 )
@@ -202,7 +346,7 @@ impl HydratedInfo {
                         bazel_event::Evt::TargetCompleted(tce) => {
                             if let Some(target_complete_info) = tce_event(
                                 tce,
-                                &rule_kind_lookup,
+                                &target_lookup,
                                 &named_set_of_files_lookup,
                                 &mut buffered_tce,
                             )
@@ -227,7 +371,9 @@ impl HydratedInfo {
                                         path_prefix: vec![],
                                         name: String::from("stderr"),
                                     }),
-                                    target_kind: rule_kind_lookup.get(&ace.label).cloned(),
+                                    target_kind: target_lookup.target_kind(&ace.label),
+                                    details: target_lookup.details(&ace.label, ace.configuration),
+                                    mnemonic: ace.mnemonic,
                                     label: ace.label,
                                 };
                                 tx.send(Some(HydratedInfo::ActionFailed(err_info)))
@@ -246,7 +392,9 @@ impl HydratedInfo {
                                         name: String::from("stderr"),
                                     }),
 
-                                    target_kind: rule_kind_lookup.get(&ace.label).cloned(),
+                                    target_kind: target_lookup.target_kind(&ace.label),
+                                    details: target_lookup.details(&ace.label, ace.configuration),
+                                    mnemonic: ace.mnemonic,
                                     label: ace.label,
                                 };
                                 tx.send(Some(HydratedInfo::ActionSuccess(act_info)))
@@ -256,10 +404,10 @@ impl HydratedInfo {
                         }
 
                         bazel_event::Evt::TestResult(tfe) => {
-                            let tst_info = TestResultInfo {
-                                target_kind: rule_kind_lookup.get(&tfe.label).cloned(),
-                                test_summary_event: tfe,
-                            };
+                            let target_kind = target_lookup.target_kind(&tfe.label);
+                            let details =
+                                target_lookup.details(&tfe.label, tfe.configuration.clone());
+                            let tst_info = TestResultInfo::new(tfe, target_kind, details);
                             tx.send(Some(HydratedInfo::TestResult(tst_info)))
                                 .await
                                 .unwrap();
@@ -279,6 +427,11 @@ impl HydratedInfo {
                                 .await
                                 .unwrap();
                         }
+                        bazel_event::Evt::BuildTiming(build_timing) => {
+                            tx.send(Some(HydratedInfo::BuildTiming(build_timing)))
+                                .await
+                                .unwrap();
+                        }
                         bazel_event::Evt::UnknownEvent(_) => (),
                     },
                 }
@@ -300,6 +453,8 @@ mod tests {
 
         tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
             event: bazel_event::Evt::ActionCompleted(bazel_event::ActionCompletedEvt {
+                mnemonic: None,
+                configuration: None,
                 stdout: None,
                 stderr: None,
                 label: String::from("foo_bar_baz"),
@@ -314,6 +469,8 @@ mod tests {
         assert_eq!(
             received_res,
             Some(HydratedInfo::ActionFailed(ActionFailedErrorInfo {
+                mnemonic: None,
+                details: TargetDetails::default(),
                 target_kind: None,
                 label: String::from("foo_bar_baz"),
                 stderr: None,
@@ -329,6 +486,8 @@ mod tests {
 
         tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
             event: bazel_event::Evt::ActionCompleted(bazel_event::ActionCompletedEvt {
+                mnemonic: None,
+                configuration: None,
                 stdout: Some(build_event_stream::file::File::Uri(String::from(
                     "path-to-stdout",
                 ))),
@@ -347,6 +506,8 @@ mod tests {
         assert_eq!(
             received_res,
             Some(HydratedInfo::ActionFailed(ActionFailedErrorInfo {
+                mnemonic: None,
+                details: TargetDetails::default(),
                 target_kind: None,
                 label: String::from("foo_bar_baz"),
                 stderr: Some(build_event_stream::File {
@@ -375,6 +536,10 @@ mod tests {
 
         tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
             event: bazel_event::Evt::TargetConfigured(bazel_event::TargetConfiguredEvt {
+                event_time_millis: None,
+                aspect: None,
+                tags: Vec::default(),
+                test_size: None,
                 label: String::from("foo_bar_baz"),
                 rule_kind: String::from("my_madeup_rule"),
             }),
@@ -384,6 +549,8 @@ mod tests {
 
         tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
             event: bazel_event::Evt::ActionCompleted(bazel_event::ActionCompletedEvt {
+                mnemonic: None,
+                configuration: None,
                 stdout: Some(build_event_stream::file::File::Uri(String::from(
                     "path-to-stdout",
                 ))),
@@ -397,11 +564,28 @@ mod tests {
         .await
         .unwrap();
 
+        let received_res = child_rx.next().await.unwrap();
+        assert_eq!(
+            received_res,
+            Some(HydratedInfo::TargetConfigured(
+                bazel_event::TargetConfiguredEvt {
+                    event_time_millis: None,
+                    aspect: None,
+                    tags: Vec::default(),
+                    test_size: None,
+                    label: String::from("foo_bar_baz"),
+                    rule_kind: String::from("my_madeup_rule"),
+                }
+            ))
+        );
+
         let received_res = child_rx.next().await.unwrap();
 
         assert_eq!(
             received_res,
             Some(HydratedInfo::ActionFailed(ActionFailedErrorInfo {
+                mnemonic: None,
+                details: TargetDetails::default(),
                 target_kind: Some(String::from("my_madeup_rule")),
                 label: String::from("foo_bar_baz"),
                 stderr: Some(build_event_stream::File {
@@ -430,6 +614,10 @@ mod tests {
 
         tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
             event: bazel_event::Evt::TargetConfigured(bazel_event::TargetConfiguredEvt {
+                event_time_millis: None,
+                aspect: None,
+                tags: Vec::default(),
+                test_size: None,
                 label: String::from("foo_bar_baz"),
                 rule_kind: String::from("my_madeup_rule"),
             }),
@@ -441,6 +629,8 @@ mod tests {
 
         tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
             event: bazel_event::Evt::ActionCompleted(bazel_event::ActionCompletedEvt {
+                mnemonic: None,
+                configuration: None,
                 stdout: Some(build_event_stream::file::File::Uri(String::from(
                     "path-to-stdout",
                 ))),
@@ -455,8 +645,14 @@ mod tests {
         .unwrap();
 
         let received_res = child_rx.next().await.unwrap();
+        assert!(matches!(
+            received_res,
+            Some(HydratedInfo::TargetConfigured(_))
+        ));
 
-        // First event is a None to indicate the build is completed.
+        let received_res = child_rx.next().await.unwrap();
+
+        // Next event is a None to indicate the build is completed.
         assert_eq!(received_res, None);
 
         let received_res = child_rx.next().await.unwrap();
@@ -464,6 +660,8 @@ mod tests {
         assert_eq!(
             received_res,
             Some(HydratedInfo::ActionFailed(ActionFailedErrorInfo {
+                mnemonic: None,
+                details: TargetDetails::default(),
                 target_kind: None,
                 label: String::from("foo_bar_baz"),
                 stderr: Some(build_event_stream::File {
@@ -484,4 +682,160 @@ mod tests {
             }))
         );
     }
+
+    #[tokio::test]
+    async fn test_resolves_test_outputs() {
+        let (tx, rx) = async_channel::unbounded();
+        let mut child_rx = HydratedInfo::build_transformer(rx);
+
+        tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
+            event: bazel_event::Evt::TestResult(bazel_event::TestResultEvt {
+                configuration: None,
+                label: String::from("//a:test"),
+                test_status: bazel_event::TestStatus::Failed,
+                failed_files: vec![
+                    build_event_stream::File {
+                        name: String::from("test.outputs__outputs.zip"),
+                        path_prefix: Vec::default(),
+                        file: Some(build_event_stream::file::File::Uri(String::from(
+                            "bytestream://remote/blobs/def/34",
+                        ))),
+                    },
+                    build_event_stream::File {
+                        name: String::from("test.log"),
+                        path_prefix: Vec::default(),
+                        file: Some(build_event_stream::file::File::Uri(String::from(
+                            "bytestream://remote/blobs/abc/12",
+                        ))),
+                    },
+                    // Without a name we go by the extension.
+                    build_event_stream::File {
+                        name: String::default(),
+                        path_prefix: Vec::default(),
+                        file: Some(build_event_stream::file::File::Uri(String::from(
+                            "file:///out/testlogs/a/my%20test/test.xml",
+                        ))),
+                    },
+                ],
+                cached: false,
+                run: 1,
+                shard: 0,
+                attempt: 1,
+            }),
+        }))
+        .await
+        .unwrap();
+
+        match child_rx.next().await.unwrap() {
+            Some(HydratedInfo::TestResult(tst)) => {
+                assert_eq!(
+                    tst.test_log,
+                    Some(build_event_stream::file::File::Uri(String::from(
+                        "bytestream://remote/blobs/abc/12"
+                    )))
+                );
+                assert_eq!(
+                    tst.test_xml,
+                    Some(build_event_stream::file::File::Uri(String::from(
+                        "file:///out/testlogs/a/my%20test/test.xml"
+                    )))
+                );
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keeps_target_details_and_aspects() {
+        let (tx, rx) = async_channel::unbounded();
+        let mut child_rx = HydratedInfo::build_transformer(rx);
+
+        let events = vec![
+            bazel_event::Evt::Configuration(bazel_event::ConfigurationEvt {
+                id: String::from("abc123"),
+                mnemonic: String::from("k8-fastbuild"),
+            }),
+            bazel_event::Evt::TargetConfigured(bazel_event::TargetConfiguredEvt {
+                event_time_millis: None,
+                label: String::from("//a:lib"),
+                rule_kind: String::from("scala_library"),
+                aspect: None,
+                tags: vec![String::from("strict")],
+                test_size: None,
+            }),
+            // The aspect's expansion of the target mustn't replace what we know of the target itself.
+            bazel_event::Evt::TargetConfigured(bazel_event::TargetConfiguredEvt {
+                event_time_millis: None,
+                label: String::from("//a:lib"),
+                rule_kind: String::default(),
+                aspect: Some(String::from("//tools:lint.bzl%scalafix_aspect")),
+                tags: Vec::default(),
+                test_size: None,
+            }),
+            bazel_event::Evt::ActionCompleted(bazel_event::ActionCompletedEvt {
+                stdout: None,
+                stderr: None,
+                label: String::from("//a:lib"),
+                mnemonic: Some(String::from("Scalafix")),
+                configuration: Some(String::from("abc123")),
+                success: false,
+            }),
+            bazel_event::Evt::TargetCompleted(bazel_event::TargetCompletedEvt {
+                event_time_millis: None,
+                label: String::from("//a:lib"),
+                aspect: Some(String::from("//tools:lint.bzl%scalafix_aspect")),
+                configuration: Some(String::from("unannounced")),
+                success: false,
+                output_groups: Vec::default(),
+            }),
+        ];
+        for event in events.into_iter() {
+            tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
+                event,
+            }))
+            .await
+            .unwrap();
+        }
+
+        let mut received = Vec::default();
+        for _ in 0..4 {
+            received.push(child_rx.next().await.unwrap().unwrap());
+        }
+        assert_eq!(
+            received[1].aspect(),
+            Some("//tools:lint.bzl%scalafix_aspect")
+        );
+
+        let expected_details = TargetDetails {
+            tags: vec![String::from("strict")],
+            test_size: None,
+            configuration: Some(String::from("k8-fastbuild")),
+        };
+        assert_eq!(
+            received[2],
+            HydratedInfo::ActionFailed(ActionFailedErrorInfo {
+                label: String::from("//a:lib"),
+                stdout: None,
+                stderr: None,
+                target_kind: Some(String::from("scala_library")),
+                mnemonic: Some(String::from("Scalafix")),
+                details: expected_details.clone(),
+            })
+        );
+        assert_eq!(
+            received[3],
+            HydratedInfo::TargetComplete(TargetCompleteInfo {
+                event_time_millis: None,
+                label: String::from("//a:lib"),
+                aspect: Some(String::from("//tools:lint.bzl%scalafix_aspect")),
+                success: false,
+                target_kind: Some(String::from("scala_library")),
+                output_files: Vec::default(),
+                details: TargetDetails {
+                    configuration: Some(String::from("unannounced")),
+                    ..expected_details
+                },
+            })
+        );
+    }
 }
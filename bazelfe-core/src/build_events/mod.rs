@@ -1,2 +1,6 @@
+pub mod build_event_file_source;
+pub mod build_event_recorder;
 pub mod build_event_server;
+pub mod file_fetcher;
 pub mod hydrated_stream;
+pub mod upstream_forwarder;
@@ -0,0 +1,451 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bazelfe_protos::*;
+use futures::FutureExt;
+use google::devtools::build::v1::publish_build_event_client::PublishBuildEventClient;
+use google::devtools::build::v1::{
+    PublishBuildToolEventStreamRequest, PublishLifecycleEventRequest,
+};
+use thiserror::Error;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tonic::Request;
+
+use crate::config::BesUpstream;
+
+#[derive(Error, Debug)]
+pub enum UpstreamError {
+    #[error("Invalid BES upstream endpoint {0}: {1}")]
+    InvalidEndpoint(String, String),
+    #[error("Invalid BES upstream header {0}")]
+    InvalidHeader(String),
+    #[error("BES upstream {0} uses grpcs, which requires tls_ca_certificate to be set")]
+    MissingCaCertificate(String),
+    #[error("Unable to read BES upstream CA certificate: {0}")]
+    CaCertificate(#[from] std::io::Error),
+}
+
+/// Proxies everything bazel publishes to us on to another Build Event Service.
+/// The upstream is best effort, it is never allowed to slow down or fail the build:
+/// each stream gets a bounded buffer, and if that fills or the upstream errors we stop forwarding that stream.
+/// A stream we stop feeding is aborted, so the upstream never mistakes what it got for the whole build.
+#[derive(Clone, Debug)]
+pub struct UpstreamForwarder {
+    endpoint: String,
+    channel: Channel,
+    headers: MetadataMap,
+    buffer_size: usize,
+    pending: Arc<std::sync::Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl UpstreamForwarder {
+    /// The connection is made lazily, so an unavailable upstream only shows up as warnings once events flow.
+    pub fn new(config: &BesUpstream) -> Result<Self, UpstreamError> {
+        let (uri, use_tls) = if let Some(rest) = config.endpoint.strip_prefix("grpc://") {
+            (format!("http://{}", rest), false)
+        } else if let Some(rest) = config.endpoint.strip_prefix("grpcs://") {
+            (format!("https://{}", rest), true)
+        } else {
+            (
+                config.endpoint.clone(),
+                config.endpoint.starts_with("https://"),
+            )
+        };
+
+        let invalid_endpoint = |e: tonic::transport::Error| {
+            UpstreamError::InvalidEndpoint(config.endpoint.clone(), e.to_string())
+        };
+        let mut endpoint = Endpoint::from_shared(uri)
+            .map_err(|e| UpstreamError::InvalidEndpoint(config.endpoint.clone(), e.to_string()))?
+            .connect_timeout(config.connect_timeout);
+        if use_tls {
+            let ca_path = config
+                .tls_ca_certificate
+                .as_ref()
+                .ok_or_else(|| UpstreamError::MissingCaCertificate(config.endpoint.clone()))?;
+            let ca = Certificate::from_pem(std::fs::read(ca_path)?);
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().ca_certificate(ca))
+                .map_err(invalid_endpoint)?;
+        }
+
+        let mut headers = MetadataMap::new();
+        for (k, v) in config.headers.iter() {
+            let key = AsciiMetadataKey::from_bytes(k.as_bytes())
+                .map_err(|_| UpstreamError::InvalidHeader(k.clone()))?;
+            let value = AsciiMetadataValue::from_str(v)
+                .map_err(|_| UpstreamError::InvalidHeader(k.clone()))?;
+            headers.insert(key, value);
+        }
+
+        Ok(Self {
+            endpoint: config.endpoint.clone(),
+            channel: endpoint.connect_lazy(),
+            headers,
+            buffer_size: config.buffer_size.max(1),
+            pending: Arc::new(std::sync::Mutex::new(Vec::default())),
+        })
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        *request.metadata_mut() = self.headers.clone();
+        request
+    }
+
+    fn track(&self, handle: JoinHandle<()>) {
+        let mut pending = self.pending.lock().unwrap();
+        // A daemon forwards build after build, only hold on to what's still running.
+        pending.retain_mut(|h| h.now_or_never().is_none());
+        pending.push(handle);
+    }
+
+    /// Opens a stream to the upstream which is fed by the returned handle. The stream only finishes cleanly once
+    /// the handle is finished, dropping it before then aborts the stream.
+    pub fn start_stream(&self) -> UpstreamStream {
+        let (tx, rx) = mpsc::channel(self.buffer_size);
+        let (abort, abort_requested) = oneshot::channel();
+        let mut client = PublishBuildEventClient::new(self.channel.clone());
+        let request = self.request(ReceiverStream::new(rx));
+        let endpoint = self.endpoint.clone();
+        self.track(tokio::spawn(async move {
+            let publish = async {
+                match client.publish_build_tool_event_stream(request).await {
+                    Ok(response) => {
+                        let mut acks = response.into_inner();
+                        loop {
+                            match acks.message().await {
+                                Ok(Some(_)) => (),
+                                Ok(None) => break Ok(()),
+                                Err(e) => break Err(e),
+                            }
+                        }
+                    }
+                    Err(e) => Err(e),
+                }
+            };
+            // Dropping the call resets the stream, whereas closing the sender would finish it cleanly.
+            tokio::select! {
+                biased;
+                Ok(()) = abort_requested => (),
+                result = publish => {
+                    if let Err(e) = result {
+                        warn!("Failed forwarding build events to {}: {}", endpoint, e);
+                    }
+                }
+            }
+        }));
+        UpstreamStream {
+            endpoint: self.endpoint.clone(),
+            buffer_size: self.buffer_size,
+            tx: Some(tx),
+            abort: Some(abort),
+        }
+    }
+
+    pub fn forward_lifecycle_event(&self, event: PublishLifecycleEventRequest) {
+        let mut client = PublishBuildEventClient::new(self.channel.clone());
+        let request = self.request(event);
+        let endpoint = self.endpoint.clone();
+        self.track(tokio::spawn(async move {
+            if let Err(e) = client.publish_lifecycle_event(request).await {
+                warn!("Failed forwarding lifecycle event to {}: {}", endpoint, e);
+            }
+        }));
+    }
+
+    /// Give buffered events a chance to reach the upstream before we exit, returns false if we gave up waiting.
+    pub async fn flush(&self, timeout: Duration) -> bool {
+        let handles: Vec<JoinHandle<()>> = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.drain(..).collect()
+        };
+        let completed = tokio::time::timeout(timeout, futures::future::join_all(handles))
+            .await
+            .is_ok();
+        if !completed {
+            warn!(
+                "Timed out after {:?} waiting for build events to reach {}",
+                timeout, self.endpoint
+            );
+        }
+        completed
+    }
+}
+
+/// Forwarding half of a single upstream stream.
+#[derive(Debug)]
+pub struct UpstreamStream {
+    endpoint: String,
+    buffer_size: usize,
+    tx: Option<mpsc::Sender<PublishBuildToolEventStreamRequest>>,
+    abort: Option<oneshot::Sender<()>>,
+}
+
+impl UpstreamStream {
+    /// Never waits on the upstream, if it has fallen too far behind we abort this stream.
+    pub fn forward(&mut self, request: &PublishBuildToolEventStreamRequest) {
+        if let Some(tx) = self.tx.as_ref() {
+            match tx.try_send(request.clone()) {
+                Ok(_) => (),
+                Err(TrySendError::Full(_)) => {
+                    warn!(
+                        "BES upstream {} is more than {} events behind, aborting the stream of this build's events",
+                        self.endpoint, self.buffer_size
+                    );
+                    if let Some(abort) = self.abort.take() {
+                        let _ = abort.send(());
+                    }
+                    self.tx = None;
+                }
+                // The forwarding task has already reported why it stopped.
+                Err(TrySendError::Closed(_)) => self.tx = None,
+            }
+        }
+    }
+
+    /// Tell the upstream it has been sent all of this build's events.
+    pub fn finish(mut self) {
+        self.tx = None;
+        self.abort = None;
+    }
+}
+
+impl Drop for UpstreamStream {
+    // Bazel's stream to us broke off, or we stopped reading it, so what we forwarded isn't the whole build.
+    fn drop(&mut self) {
+        if let Some(abort) = self.abort.take() {
+            let _ = abort.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_events::build_event_recorder::read_recording;
+    use futures::{Stream, StreamExt};
+    use google::devtools::build::v1::publish_build_event_server::{
+        PublishBuildEvent, PublishBuildEventServer,
+    };
+    use google::devtools::build::v1::PublishBuildToolEventStreamResponse;
+    use std::path::PathBuf;
+    use std::pin::Pin;
+    use tonic::{Response, Status};
+
+    #[derive(Clone, Default)]
+    struct StandInUpstream {
+        stream_events: Arc<std::sync::Mutex<Vec<PublishBuildToolEventStreamRequest>>>,
+        lifecycle_events: Arc<std::sync::Mutex<Vec<PublishLifecycleEventRequest>>>,
+        api_keys: Arc<std::sync::Mutex<Vec<String>>>,
+        completed_streams: Arc<std::sync::Mutex<usize>>,
+    }
+
+    impl StandInUpstream {
+        fn record_key<T>(&self, request: &Request<T>) {
+            if let Some(v) = request.metadata().get("x-api-key") {
+                self.api_keys
+                    .lock()
+                    .unwrap()
+                    .push(v.to_str().unwrap().to_string());
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl PublishBuildEvent for StandInUpstream {
+        type PublishBuildToolEventStreamStream = Pin<
+            Box<
+                dyn Stream<Item = Result<PublishBuildToolEventStreamResponse, Status>>
+                    + Send
+                    + 'static,
+            >,
+        >;
+
+        async fn publish_build_tool_event_stream(
+            &self,
+            request: Request<tonic::Streaming<PublishBuildToolEventStreamRequest>>,
+        ) -> Result<Response<Self::PublishBuildToolEventStreamStream>, Status> {
+            self.record_key(&request);
+            let mut stream = request.into_inner();
+            let stream_events = Arc::clone(&self.stream_events);
+            let completed_streams = Arc::clone(&self.completed_streams);
+            let output = async_stream::try_stream! {
+                while let Some(evt) = stream.next().await {
+                    let evt = evt?;
+                    let ack = PublishBuildToolEventStreamResponse {
+                        stream_id: evt.ordered_build_event.as_ref().and_then(|e| e.stream_id.clone()),
+                        sequence_number: evt.ordered_build_event.as_ref().map(|e| e.sequence_number).unwrap_or_default(),
+                    };
+                    stream_events.lock().unwrap().push(evt);
+                    yield ack;
+                }
+                *completed_streams.lock().unwrap() += 1;
+            };
+            Ok(Response::new(
+                Box::pin(output) as Self::PublishBuildToolEventStreamStream
+            ))
+        }
+
+        async fn publish_lifecycle_event(
+            &self,
+            request: Request<PublishLifecycleEventRequest>,
+        ) -> Result<Response<()>, Status> {
+            self.record_key(&request);
+            self.lifecycle_events
+                .lock()
+                .unwrap()
+                .push(request.into_inner());
+            Ok(Response::new(()))
+        }
+    }
+
+    async fn start_stand_in() -> (StandInUpstream, String) {
+        let upstream = StandInUpstream::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("grpc://{}", listener.local_addr().unwrap());
+        let service = PublishBuildEventServer::new(upstream.clone());
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+                .await
+                .unwrap();
+        });
+        (upstream, endpoint)
+    }
+
+    fn load_requests() -> Vec<PublishBuildToolEventStreamRequest> {
+        read_recording(
+            &PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("resources/tests/build_events/no_op_build.proto"),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_forwards_events_and_headers() {
+        let (upstream, endpoint) = start_stand_in().await;
+        let mut config = BesUpstream::with_endpoint(endpoint);
+        config
+            .headers
+            .insert(String::from("x-api-key"), String::from("secret"));
+        let forwarder = UpstreamForwarder::new(&config).unwrap();
+
+        let requests = load_requests();
+        let mut stream = forwarder.start_stream();
+        for request in requests.iter() {
+            stream.forward(request);
+        }
+        stream.finish();
+        forwarder.forward_lifecycle_event(PublishLifecycleEventRequest::default());
+
+        assert!(forwarder.flush(Duration::from_secs(10)).await);
+        assert_eq!(*upstream.stream_events.lock().unwrap(), requests);
+        assert_eq!(*upstream.completed_streams.lock().unwrap(), 1);
+        assert_eq!(upstream.lifecycle_events.lock().unwrap().len(), 1);
+        assert_eq!(
+            *upstream.api_keys.lock().unwrap(),
+            vec![String::from("secret"), String::from("secret")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_upstream_fails_open() {
+        // Grab a free port and release it, so nothing is listening.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let forwarder =
+            UpstreamForwarder::new(&BesUpstream::with_endpoint(format!("grpc://{}", addr)))
+                .unwrap();
+
+        let mut stream = forwarder.start_stream();
+        for request in load_requests().iter() {
+            stream.forward(request);
+        }
+        stream.finish();
+        forwarder.forward_lifecycle_event(PublishLifecycleEventRequest::default());
+        assert!(forwarder.flush(Duration::from_secs(10)).await);
+    }
+
+    #[tokio::test]
+    async fn test_slow_upstream_stream_is_aborted_when_buffer_is_full() {
+        let (upstream, endpoint) = start_stand_in().await;
+        let mut config = BesUpstream::with_endpoint(endpoint);
+        config.buffer_size = 2;
+        let forwarder = UpstreamForwarder::new(&config).unwrap();
+
+        let requests = load_requests();
+        assert!(requests.len() > 2);
+        // We never yield between events, so the upstream can't drain anything and the buffer overflows.
+        let mut stream = forwarder.start_stream();
+        for request in requests.iter() {
+            stream.forward(request);
+        }
+        drop(stream);
+
+        assert!(forwarder.flush(Duration::from_secs(10)).await);
+        // Whatever made it through, the upstream must never see the stream finish as if it were complete.
+        assert!(upstream.stream_events.lock().unwrap().len() <= 2);
+        assert_eq!(*upstream.completed_streams.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unfinished_stream_is_aborted() {
+        let (upstream, endpoint) = start_stand_in().await;
+        let forwarder = UpstreamForwarder::new(&BesUpstream::with_endpoint(endpoint)).unwrap();
+
+        let requests = load_requests();
+        let mut stream = forwarder.start_stream();
+        stream.forward(&requests[0]);
+        // As when bazel's own stream to us fails part way through.
+        drop(stream);
+
+        assert!(forwarder.flush(Duration::from_secs(10)).await);
+        assert_eq!(*upstream.completed_streams.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_finished_tasks_are_not_held() {
+        let (upstream, endpoint) = start_stand_in().await;
+        let forwarder = UpstreamForwarder::new(&BesUpstream::with_endpoint(endpoint)).unwrap();
+
+        for _ in 0..5 {
+            forwarder.forward_lifecycle_event(PublishLifecycleEventRequest::default());
+        }
+        while upstream.lifecycle_events.lock().unwrap().len() < 5 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Give the tasks a moment to wrap up once the upstream has replied.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        forwarder.forward_lifecycle_event(PublishLifecycleEventRequest::default());
+        assert_eq!(forwarder.pending.lock().unwrap().len(), 1);
+        assert!(forwarder.flush(Duration::from_secs(10)).await);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config() {
+        assert!(matches!(
+            UpstreamForwarder::new(&BesUpstream::with_endpoint(String::from(
+                "grpcs://bes.example.com"
+            ))),
+            Err(UpstreamError::MissingCaCertificate(_))
+        ));
+
+        let mut config = BesUpstream::with_endpoint(String::from("grpc://localhost:1985"));
+        config
+            .headers
+            .insert(String::from("bad header"), String::from("v"));
+        assert!(matches!(
+            UpstreamForwarder::new(&config),
+            Err(UpstreamError::InvalidHeader(_))
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use super::{Buildozer, EditKind, Result, DEPS_ATTRIBUTE};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProposedEdit {
+    pub target: String,
+    pub attribute: String,
+    pub kind: EditKind,
+    pub label: String,
+}
+
+impl ProposedEdit {
+    /// The edit as a line of a buildozer command file, as consumed by `buildozer -f`.
+    pub fn to_command_line(&self) -> String {
+        let command = match self.kind {
+            EditKind::AddDependency => "add",
+            EditKind::RemoveDependency => "remove",
+        };
+        format!(
+            "{} {} {}|{}",
+            command, self.attribute, self.label, self.target
+        )
+    }
+}
+
+/// Wraps another buildozer, when enabled edits are only collected as proposals and never applied.
+/// Reads reflect the proposed edits so processors see a consistent view of the BUILD files.
+#[derive(Debug, Clone)]
+pub struct DryRunBuildozer<B: Buildozer> {
+    inner: B,
+    enabled: bool,
+    proposed_edits: Arc<Mutex<Vec<ProposedEdit>>>,
+}
+
+impl<B: Buildozer> DryRunBuildozer<B> {
+    pub fn new(inner: B, enabled: bool) -> Self {
+        Self {
+            inner,
+            enabled,
+            proposed_edits: Arc::new(Mutex::new(Vec::default())),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub async fn proposed_edits(&self) -> Vec<ProposedEdit> {
+        self.proposed_edits.lock().await.clone()
+    }
+
+    async fn propose(&self, target: &str, attribute: &str, kind: EditKind, label: &str) {
+        self.proposed_edits.lock().await.push(ProposedEdit {
+            target: target.to_string(),
+            attribute: attribute.to_string(),
+            kind,
+            label: label.to_string(),
+        });
+    }
+}
+
+#[async_trait]
+impl<B: Buildozer> Buildozer for DryRunBuildozer<B> {
+    async fn print_deps(&self, label: &String) -> Result<Vec<String>> {
+        self.print_attribute(label, DEPS_ATTRIBUTE).await
+    }
+
+    async fn add_dependency(
+        &self,
+        target_to_operate_on: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.add_to_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn remove_dependency(
+        &self,
+        target_to_operate_on: &String,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.remove_from_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn print_attribute(&self, label: &String, attribute: &str) -> Result<Vec<String>> {
+        let mut deps = self.inner.print_attribute(label, attribute).await?;
+        if self.enabled {
+            let target = crate::label_utils::sanitize_label(label.clone());
+            for edit in self.proposed_edits.lock().await.iter() {
+                if crate::label_utils::sanitize_label(edit.target.clone()) != target
+                    || edit.attribute != attribute
+                {
+                    continue;
+                }
+                let dep = crate::label_utils::sanitize_label(edit.label.clone());
+                match edit.kind {
+                    EditKind::AddDependency => {
+                        if !deps.contains(&dep) {
+                            deps.push(dep);
+                        }
+                    }
+                    EditKind::RemoveDependency => deps.retain(|d| d != &dep),
+                }
+            }
+        }
+        Ok(deps)
+    }
+
+    async fn add_to_attribute(
+        &self,
+        target_to_operate_on: &str,
+        attribute: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        if self.enabled {
+            self.propose(
+                target_to_operate_on,
+                attribute,
+                EditKind::AddDependency,
+                label_to_add,
+            )
+            .await;
+            Ok(())
+        } else {
+            self.inner
+                .add_to_attribute(target_to_operate_on, attribute, label_to_add)
+                .await
+        }
+    }
+
+    async fn remove_from_attribute(
+        &self,
+        target_to_operate_on: &String,
+        attribute: &str,
+        label_to_remove: &String,
+    ) -> Result<()> {
+        if self.enabled {
+            self.propose(
+                target_to_operate_on,
+                attribute,
+                EditKind::RemoveDependency,
+                label_to_remove,
+            )
+            .await;
+            Ok(())
+        } else {
+            self.inner
+                .remove_from_attribute(target_to_operate_on, attribute, label_to_remove)
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct StaticBuildozer {
+        applied: Arc<Mutex<u32>>,
+    }
+
+    #[async_trait]
+    impl Buildozer for StaticBuildozer {
+        async fn print_deps(&self, _label: &String) -> Result<Vec<String>> {
+            Ok(vec![String::from("//b:b"), String::from("//c:c")])
+        }
+
+        async fn add_dependency(&self, _target: &str, _label: &String) -> Result<()> {
+            *self.applied.lock().await += 1;
+            Ok(())
+        }
+
+        async fn remove_dependency(&self, _target: &String, _label: &String) -> Result<()> {
+            *self.applied.lock().await += 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proposes_without_applying() {
+        let inner = StaticBuildozer {
+            applied: Arc::new(Mutex::new(0)),
+        };
+        let buildozer = DryRunBuildozer::new(inner.clone(), true);
+
+        buildozer
+            .add_dependency("//a:a", &String::from("//d"))
+            .await
+            .unwrap();
+        buildozer
+            .remove_dependency(&String::from("//a:a"), &String::from("//c:c"))
+            .await
+            .unwrap();
+
+        assert_eq!(*inner.applied.lock().await, 0);
+        assert_eq!(
+            buildozer.print_deps(&String::from("//a")).await.unwrap(),
+            vec![String::from("//b:b"), String::from("//d:d")]
+        );
+        assert_eq!(
+            buildozer
+                .proposed_edits()
+                .await
+                .iter()
+                .map(|e| e.to_command_line())
+                .collect::<Vec<String>>(),
+            vec![
+                String::from("add deps //d|//a:a"),
+                String::from("remove deps //c:c|//a:a"),
+            ]
+        );
+
+        let passthrough = DryRunBuildozer::new(inner.clone(), false);
+        passthrough
+            .add_dependency("//a:a", &String::from("//d"))
+            .await
+            .unwrap();
+        assert_eq!(*inner.applied.lock().await, 1);
+        assert!(passthrough.proposed_edits().await.is_empty());
+    }
+}
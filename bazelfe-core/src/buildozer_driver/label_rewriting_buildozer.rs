@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::{Buildozer, Result};
+use crate::label_utils::LabelRewriter;
+
+/// Wraps another buildozer, mapping every target operated on through the configured label
+/// rewrite rules. Dependency edits go to the attribute the matching rule names.
+#[derive(Debug, Clone)]
+pub struct LabelRewritingBuildozer<B: Buildozer> {
+    inner: B,
+    rewriter: Arc<LabelRewriter>,
+}
+
+impl<B: Buildozer> LabelRewritingBuildozer<B> {
+    pub fn new(inner: B, rewriter: LabelRewriter) -> Self {
+        Self {
+            inner,
+            rewriter: Arc::new(rewriter),
+        }
+    }
+}
+
+#[async_trait]
+impl<B: Buildozer> Buildozer for LabelRewritingBuildozer<B> {
+    async fn print_deps(&self, label: &String) -> Result<Vec<String>> {
+        let target = self.rewriter.rewrite(label);
+        self.inner
+            .print_attribute(&target.label, &target.attribute)
+            .await
+    }
+
+    async fn add_dependency(
+        &self,
+        target_to_operate_on: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        let target = self.rewriter.rewrite(target_to_operate_on);
+        self.inner
+            .add_to_attribute(&target.label, &target.attribute, label_to_add)
+            .await
+    }
+
+    async fn remove_dependency(
+        &self,
+        target_to_operate_on: &String,
+        label_to_add: &String,
+    ) -> Result<()> {
+        let target = self.rewriter.rewrite(target_to_operate_on);
+        self.inner
+            .remove_from_attribute(&target.label, &target.attribute, label_to_add)
+            .await
+    }
+
+    async fn print_attribute(&self, label: &String, attribute: &str) -> Result<Vec<String>> {
+        let target = self.rewriter.rewrite(label);
+        self.inner.print_attribute(&target.label, attribute).await
+    }
+
+    async fn add_to_attribute(
+        &self,
+        target_to_operate_on: &str,
+        attribute: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        let target = self.rewriter.rewrite(target_to_operate_on);
+        self.inner
+            .add_to_attribute(&target.label, attribute, label_to_add)
+            .await
+    }
+
+    async fn remove_from_attribute(
+        &self,
+        target_to_operate_on: &String,
+        attribute: &str,
+        label_to_remove: &String,
+    ) -> Result<()> {
+        let target = self.rewriter.rewrite(target_to_operate_on);
+        self.inner
+            .remove_from_attribute(&target.label, attribute, label_to_remove)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LabelRewriteRule;
+    use tokio::sync::Mutex;
+
+    #[derive(Clone, Debug, Default)]
+    struct LoggingBuildozer {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Buildozer for LoggingBuildozer {
+        async fn print_deps(&self, _label: &String) -> Result<Vec<String>> {
+            Ok(Vec::default())
+        }
+
+        async fn add_dependency(&self, _target: &str, _label: &String) -> Result<()> {
+            Ok(())
+        }
+
+        async fn remove_dependency(&self, _target: &String, _label: &String) -> Result<()> {
+            Ok(())
+        }
+
+        async fn print_attribute(&self, label: &String, attribute: &str) -> Result<Vec<String>> {
+            self.calls
+                .lock()
+                .await
+                .push(format!("print {} {}", attribute, label));
+            Ok(Vec::default())
+        }
+
+        async fn add_to_attribute(
+            &self,
+            target: &str,
+            attribute: &str,
+            label: &String,
+        ) -> Result<()> {
+            self.calls
+                .lock()
+                .await
+                .push(format!("add {} {}|{}", attribute, label, target));
+            Ok(())
+        }
+
+        async fn remove_from_attribute(
+            &self,
+            target: &String,
+            attribute: &str,
+            label: &String,
+        ) -> Result<()> {
+            self.calls
+                .lock()
+                .await
+                .push(format!("remove {} {}|{}", attribute, label, target));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rewrites_targets() {
+        let inner = LoggingBuildozer::default();
+        let buildozer = LabelRewritingBuildozer::new(
+            inner.clone(),
+            LabelRewriter::new(&[LabelRewriteRule {
+                regex_match: String::from("^(.*)_suite_[0-9]+$"),
+                replacement: String::from("$1"),
+                attribute: String::from("suite_deps"),
+            }])
+            .unwrap(),
+        );
+
+        buildozer
+            .print_deps(&String::from("//a:tests_suite_3"))
+            .await
+            .unwrap();
+        buildozer
+            .add_dependency("//a:tests_suite_3", &String::from("//b:b"))
+            .await
+            .unwrap();
+        buildozer
+            .remove_dependency(&String::from("//c:c_auto_gen_1"), &String::from("//d:d"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *inner.calls.lock().await,
+            vec![
+                String::from("print suite_deps //a:tests"),
+                String::from("add suite_deps //b:b|//a:tests"),
+                String::from("remove deps //d:d|//c:c"),
+            ]
+        );
+    }
+}
@@ -5,6 +5,15 @@ use async_trait::async_trait;
 use std::{ffi::OsString, path::PathBuf};
 use tokio::process::Command;
 
+mod dry_run_buildozer;
+mod label_rewriting_buildozer;
+mod native_buildozer;
+mod recording_buildozer;
+pub use dry_run_buildozer::{DryRunBuildozer, ProposedEdit};
+pub use label_rewriting_buildozer::LabelRewritingBuildozer;
+pub use native_buildozer::{find_workspace_root, NativeBuildozer};
+pub use recording_buildozer::{EditKind, EditLog, EditLogRecord, RecordedEdit, RecordingBuildozer};
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct ExecuteResultError {
     pub exit_code: i32,
@@ -29,6 +38,17 @@ pub type Result<T> = std::result::Result<T, ExecuteResultError>;
 //     Success(devtools::buildozer::Output),
 // }
 
+/// The attribute dependencies are added to unless a label rewrite rule says otherwise.
+pub const DEPS_ATTRIBUTE: &str = "deps";
+
+fn unsupported_attribute(attribute: &str) -> ExecuteResultError {
+    ExecuteResultError {
+        exit_code: 2,
+        stdout: String::default(),
+        stderr: format!("Editing the {} attribute is not supported", attribute),
+    }
+}
+
 #[async_trait]
 pub trait Buildozer: Clone + Send + Sync + std::fmt::Debug + 'static {
     async fn print_deps(&self, label: &String) -> Result<Vec<String>>;
@@ -40,6 +60,149 @@ pub trait Buildozer: Clone + Send + Sync + std::fmt::Debug + 'static {
         target_to_operate_on: &String,
         label_to_add: &String,
     ) -> Result<()>;
+
+    // Macros can take their dependencies under another name, implementations which
+    // can edit arbitrary list attributes override these, otherwise only deps works.
+    async fn print_attribute(&self, label: &String, attribute: &str) -> Result<Vec<String>> {
+        if attribute == DEPS_ATTRIBUTE {
+            self.print_deps(label).await
+        } else {
+            Err(unsupported_attribute(attribute))
+        }
+    }
+
+    async fn add_to_attribute(
+        &self,
+        target_to_operate_on: &str,
+        attribute: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        if attribute == DEPS_ATTRIBUTE {
+            self.add_dependency(target_to_operate_on, label_to_add)
+                .await
+        } else {
+            Err(unsupported_attribute(attribute))
+        }
+    }
+
+    async fn remove_from_attribute(
+        &self,
+        target_to_operate_on: &String,
+        attribute: &str,
+        label_to_remove: &String,
+    ) -> Result<()> {
+        if attribute == DEPS_ATTRIBUTE {
+            self.remove_dependency(target_to_operate_on, label_to_remove)
+                .await
+        } else {
+            Err(unsupported_attribute(attribute))
+        }
+    }
+}
+
+/// The buildozer implementation selected by the `buildozer_mode` in the config.
+#[derive(Clone, Debug)]
+pub enum ConfiguredBuildozer {
+    Binary(BuildozerBinaryImpl),
+    InProcess(NativeBuildozer),
+}
+
+pub fn from_config(
+    config: &crate::config::Config,
+) -> std::result::Result<ConfiguredBuildozer, Box<dyn std::error::Error>> {
+    match config.buildozer_mode {
+        crate::config::BuildozerMode::Binary => match &config.buildozer_path {
+            Some(path) => Ok(ConfiguredBuildozer::Binary(from_binary_path(path))),
+            None => Err(
+                "No buildozer_path configured, set one or use buildozer_mode = \"in_process\""
+                    .into(),
+            ),
+        },
+        crate::config::BuildozerMode::InProcess => Ok(ConfiguredBuildozer::InProcess(
+            NativeBuildozer::from_current_dir()?,
+        )),
+    }
+}
+
+impl ConfiguredBuildozer {
+    /// Render what a BUILD file would look like with these edits applied, they should all target one BUILD file.
+    pub async fn preview_edits(&self, edits: &[&ProposedEdit]) -> Result<String> {
+        match self {
+            ConfiguredBuildozer::Binary(b) => {
+                let commands: Vec<String> = edits.iter().map(|e| e.to_command_line()).collect();
+                b.preview_commands(&commands).await
+            }
+            ConfiguredBuildozer::InProcess(b) => b.preview_edits(edits).await,
+        }
+    }
+}
+
+#[async_trait]
+impl Buildozer for ConfiguredBuildozer {
+    async fn print_deps(&self, label: &String) -> Result<Vec<String>> {
+        self.print_attribute(label, DEPS_ATTRIBUTE).await
+    }
+
+    async fn add_dependency(
+        &self,
+        target_to_operate_on: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.add_to_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn remove_dependency(
+        &self,
+        target_to_operate_on: &String,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.remove_from_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn print_attribute(&self, label: &String, attribute: &str) -> Result<Vec<String>> {
+        match self {
+            ConfiguredBuildozer::Binary(b) => b.print_attribute(label, attribute).await,
+            ConfiguredBuildozer::InProcess(b) => b.print_attribute(label, attribute).await,
+        }
+    }
+
+    async fn add_to_attribute(
+        &self,
+        target_to_operate_on: &str,
+        attribute: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        match self {
+            ConfiguredBuildozer::Binary(b) => {
+                b.add_to_attribute(target_to_operate_on, attribute, label_to_add)
+                    .await
+            }
+            ConfiguredBuildozer::InProcess(b) => {
+                b.add_to_attribute(target_to_operate_on, attribute, label_to_add)
+                    .await
+            }
+        }
+    }
+
+    async fn remove_from_attribute(
+        &self,
+        target_to_operate_on: &String,
+        attribute: &str,
+        label_to_remove: &String,
+    ) -> Result<()> {
+        match self {
+            ConfiguredBuildozer::Binary(b) => {
+                b.remove_from_attribute(target_to_operate_on, attribute, label_to_remove)
+                    .await
+            }
+            ConfiguredBuildozer::InProcess(b) => {
+                b.remove_from_attribute(target_to_operate_on, attribute, label_to_remove)
+                    .await
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -92,12 +255,67 @@ impl BuildozerBinaryImpl {
         let out = devtools::buildozer::Output::decode(&*command_result.stdout).unwrap();
         Ok((command, out))
     }
+
+    /// Run a set of commands, in the `buildozer -f` format, which should all target one BUILD file.
+    /// Returns what that file would contain afterwards without modifying it.
+    pub async fn preview_commands(&self, commands: &[String]) -> Result<String> {
+        let mut cmd = Command::new(&self.buildozer_executable_path);
+        cmd.args(["-stdout", "-f", "-"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        let mut child = cmd.spawn()?;
+
+        let mut input = commands.join("\n");
+        input.push('\n');
+        {
+            use tokio::io::AsyncWriteExt;
+            let mut stdin = child.stdin.take().expect("Child didn't have a stdin");
+            stdin.write_all(input.as_bytes()).await?;
+        }
+
+        let command_result = child.wait_with_output().await?;
+        // buildozer exits with 3 when the commands resulted in no changes.
+        let exit_code = command_result.status.code().unwrap_or(-1);
+        if exit_code != 0 && exit_code != 3 {
+            return Err(ExecuteResultError {
+                exit_code,
+                stdout: BuildozerBinaryImpl::decode_str(&command_result.stdout),
+                stderr: BuildozerBinaryImpl::decode_str(&command_result.stderr),
+            });
+        }
+        Ok(BuildozerBinaryImpl::decode_str(&command_result.stdout))
+    }
 }
 
 #[async_trait]
 impl Buildozer for BuildozerBinaryImpl {
     async fn print_deps(&self, label: &String) -> Result<Vec<String>> {
-        let (_raw_args, cmd_result) = self.execute_command(vec!["print deps", label]).await?;
+        self.print_attribute(label, DEPS_ATTRIBUTE).await
+    }
+
+    async fn add_dependency(
+        &self,
+        target_to_operate_on: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.add_to_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn remove_dependency(
+        &self,
+        target_to_operate_on: &String,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.remove_from_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn print_attribute(&self, label: &String, attribute: &str) -> Result<Vec<String>> {
+        let (_raw_args, cmd_result) = self
+            .execute_command(vec![format!("print {}", attribute), label.clone()])
+            .await?;
 
         Ok(cmd_result
             .records
@@ -126,31 +344,33 @@ impl Buildozer for BuildozerBinaryImpl {
             .collect())
     }
 
-    async fn add_dependency(
+    async fn add_to_attribute(
         &self,
         target_to_operate_on: &str,
+        attribute: &str,
         label_to_add: &String,
     ) -> Result<()> {
         // buildozer 'add deps //base' //pkg:rule //pkg:rule2
         let _ = self
             .execute_command(vec![
-                &format!("add deps {}", label_to_add),
-                &target_to_operate_on.to_string(),
+                format!("add {} {}", attribute, label_to_add),
+                target_to_operate_on.to_string(),
             ])
             .await?;
         Ok(())
     }
 
-    async fn remove_dependency(
+    async fn remove_from_attribute(
         &self,
         target_to_operate_on: &String,
-        label_to_add: &String,
+        attribute: &str,
+        label_to_remove: &String,
     ) -> Result<()> {
-        // buildozer 'add deps //base' //pkg:rule //pkg:rule2
+        // buildozer 'remove deps //base' //pkg:rule //pkg:rule2
         let _ = self
             .execute_command(vec![
-                &format!("remove deps {}", label_to_add),
-                target_to_operate_on,
+                format!("remove {} {}", attribute, label_to_remove),
+                target_to_operate_on.clone(),
             ])
             .await?;
         Ok(())
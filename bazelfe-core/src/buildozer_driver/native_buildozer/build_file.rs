@@ -0,0 +1,1114 @@
+use std::cmp::Ordering;
+
+use thiserror::Error;
+
+use super::lexer::{string_value, tokenize, LexError, Token, TokenKind};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum EditError {
+    #[error(transparent)]
+    Lex(#[from] LexError),
+
+    #[error("Unbalanced brackets starting at byte {0}")]
+    Unbalanced(usize),
+
+    #[error("No rule named {0} found")]
+    RuleNotFound(String),
+
+    #[error(
+        "{0} only appears inside a select() or non literal deps expression, refusing to edit it"
+    )]
+    NotInLiteralList(String),
+
+    #[error("Unable to find where to add deps to rule {0}")]
+    NoAttributeAnchor(String),
+}
+
+/// Resolve a label as written in a BUILD file into its fully qualified form, so
+/// `:b`, `b`, `//a` and `//a:a` can all be compared.
+pub fn canonical_label(label: &str, package: &str) -> String {
+    let (repo, rest) = match label.strip_prefix('@') {
+        Some(external) => match external.split_once("//") {
+            Some((repo, rest)) => (format!("@{}", repo), format!("//{}", rest)),
+            None => return format!("@{}//:{}", external, external),
+        },
+        None => (String::default(), label.to_string()),
+    };
+
+    let rest = if rest.starts_with("//") {
+        match rest.split_once(':') {
+            Some(_) => rest,
+            None => {
+                let last_segment = rest.rsplit('/').next().unwrap_or("").to_string();
+                format!("{}:{}", rest, last_segment)
+            }
+        }
+    } else {
+        format!("//{}:{}", package, rest.trim_start_matches(':'))
+    };
+    format!("{}{}", repo, rest)
+}
+
+/// The shortest way to write a canonical label from inside the given package, the way buildozer would.
+pub fn shorten_label(canonical: &str, package: &str) -> String {
+    let (repo, rest) = match canonical.find("//") {
+        Some(idx) => (&canonical[0..idx], &canonical[idx..]),
+        None => return canonical.to_string(),
+    };
+    let (label_package, name) = match rest[2..].split_once(':') {
+        Some(e) => e,
+        None => return canonical.to_string(),
+    };
+    if repo.is_empty() && label_package == package {
+        return format!(":{}", name);
+    }
+    if label_package.is_empty() && repo.strip_prefix('@') == Some(name) {
+        repo.to_string()
+    } else if label_package.rsplit('/').next() == Some(name) {
+        format!("{}//{}", repo, label_package)
+    } else {
+        canonical.to_string()
+    }
+}
+
+/// Sort order for string list entries, matching buildifier:
+/// plain strings, then `:local` labels, then `//absolute` labels, then `@external` labels,
+/// followed by comparing the segments split on `.` and `:`.
+pub fn compare_list_entries(a: &str, b: &str) -> Ordering {
+    fn phase(v: &str) -> u8 {
+        if v.starts_with(':') {
+            1
+        } else if v.starts_with("//") {
+            2
+        } else if v.starts_with('@') {
+            3
+        } else {
+            0
+        }
+    }
+    let split_a: Vec<&str> = a.split(['.', ':']).collect();
+    let split_b: Vec<&str> = b.split(['.', ':']).collect();
+    phase(a)
+        .cmp(&phase(b))
+        .then_with(|| {
+            for (x, y) in split_a.iter().zip(split_b.iter()) {
+                if x != y {
+                    return x.cmp(y);
+                }
+            }
+            split_a.len().cmp(&split_b.len())
+        })
+        .then_with(|| a.cmp(b))
+}
+
+#[derive(Debug, Clone)]
+struct Arg {
+    keyword: Option<String>,
+    /// First token of the argument, the keyword if there is one.
+    first: usize,
+    /// Token range of the value, excluding comments at either end.
+    value_start: usize,
+    value_end: usize,
+    comma: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Call {
+    args: Vec<Arg>,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    List { open: usize, close: usize },
+    Select { start: usize, end: usize },
+    Other,
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    first: usize,
+    last: usize,
+    comma: Option<usize>,
+    value: Option<String>,
+}
+
+struct Edit {
+    start: usize,
+    end: usize,
+    replacement: String,
+}
+
+struct BuildFile<'a> {
+    content: &'a str,
+    tokens: Vec<Token>,
+}
+
+impl<'a> BuildFile<'a> {
+    fn parse(content: &'a str) -> Result<Self, EditError> {
+        Ok(Self {
+            content,
+            tokens: tokenize(content)?,
+        })
+    }
+
+    fn kind(&self, idx: usize) -> TokenKind {
+        self.tokens[idx].kind
+    }
+
+    fn text(&self, idx: usize) -> &'a str {
+        &self.content[self.tokens[idx].start..self.tokens[idx].end]
+    }
+
+    fn matching(&self, open: usize) -> Result<usize, EditError> {
+        let mut depth = 0;
+        for idx in open..self.tokens.len() {
+            match self.kind(idx) {
+                TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => depth += 1,
+                TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(idx);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Err(EditError::Unbalanced(self.tokens[open].start))
+    }
+
+    /// Split the tokens strictly between `start` and `end` on top level commas,
+    /// returning the (first, last) non comment token of each part and its comma.
+    fn split_commas(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> Result<Vec<(usize, usize, Option<usize>)>, EditError> {
+        let mut parts = Vec::default();
+        let mut idx = start;
+        while idx < end {
+            let mut first = None;
+            let mut last = None;
+            while idx < end && self.kind(idx) != TokenKind::Comma {
+                match self.kind(idx) {
+                    TokenKind::Comment => idx += 1,
+                    TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => {
+                        first = first.or(Some(idx));
+                        idx = self.matching(idx)?;
+                        last = Some(idx);
+                        idx += 1;
+                    }
+                    _ => {
+                        first = first.or(Some(idx));
+                        last = Some(idx);
+                        idx += 1;
+                    }
+                }
+            }
+            let comma = if idx < end { Some(idx) } else { None };
+            if let (Some(first), Some(last)) = (first, last) {
+                parts.push((first, last, comma));
+            }
+            idx += 1;
+        }
+        Ok(parts)
+    }
+
+    fn next_code_token(&self, idx: usize, end: usize) -> Option<usize> {
+        (idx..end).find(|i| self.kind(*i) != TokenKind::Comment)
+    }
+
+    fn top_level_calls(&self) -> Result<Vec<Call>, EditError> {
+        let mut calls = Vec::default();
+        let mut idx = 0;
+        while idx < self.tokens.len() {
+            match self.kind(idx) {
+                TokenKind::Ident
+                    if idx + 1 < self.tokens.len() && self.kind(idx + 1) == TokenKind::LParen =>
+                {
+                    let close = self.matching(idx + 1)?;
+                    let mut args = Vec::default();
+                    for (first, last, comma) in self.split_commas(idx + 2, close)? {
+                        let second = self.next_code_token(first + 1, last + 1);
+                        let (keyword, value_start) = match second {
+                            Some(second)
+                                if self.kind(first) == TokenKind::Ident
+                                    && self.kind(second) == TokenKind::Equals =>
+                            {
+                                let value_start = self.next_code_token(second + 1, last + 1);
+                                (
+                                    Some(self.text(first).to_string()),
+                                    value_start.unwrap_or(last),
+                                )
+                            }
+                            _ => (None, first),
+                        };
+                        args.push(Arg {
+                            keyword,
+                            first,
+                            value_start,
+                            value_end: last,
+                            comma,
+                        });
+                    }
+                    calls.push(Call { args });
+                    idx = close + 1;
+                }
+                TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => {
+                    idx = self.matching(idx)? + 1;
+                }
+                _ => idx += 1,
+            }
+        }
+        Ok(calls)
+    }
+
+    fn find_rule(&self, name: &str) -> Result<Call, EditError> {
+        self.top_level_calls()?
+            .into_iter()
+            .find(|call| {
+                call.args.iter().any(|arg| {
+                    arg.keyword.as_deref() == Some("name")
+                        && arg.value_start == arg.value_end
+                        && self.kind(arg.value_start) == TokenKind::String
+                        && string_value(self.content, &self.tokens[arg.value_start]) == name
+                })
+            })
+            .ok_or_else(|| EditError::RuleNotFound(name.to_string()))
+    }
+
+    fn operands(&self, arg: &Arg) -> Result<Vec<Operand>, EditError> {
+        let mut operands = Vec::default();
+        let mut idx = arg.value_start;
+        while idx <= arg.value_end {
+            let start = idx;
+            let mut end = idx;
+            while idx <= arg.value_end && self.kind(idx) != TokenKind::Plus {
+                match self.kind(idx) {
+                    TokenKind::Comment => (),
+                    TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => {
+                        idx = self.matching(idx)?;
+                        end = idx;
+                    }
+                    _ => end = idx,
+                }
+                idx += 1;
+            }
+            let start = self.next_code_token(start, end + 1).unwrap_or(start);
+
+            let operand = if self.kind(start) == TokenKind::LBracket
+                && self.matching(start)? == end
+                && !(start..end).any(|i| self.kind(i) == TokenKind::Ident && self.text(i) == "for")
+            {
+                Operand::List {
+                    open: start,
+                    close: end,
+                }
+            } else if self.kind(start) == TokenKind::Ident && self.text(start) == "select" {
+                Operand::Select { start, end }
+            } else {
+                Operand::Other
+            };
+            operands.push(operand);
+            idx += 1;
+        }
+        Ok(operands)
+    }
+
+    fn list_elements(&self, open: usize, close: usize) -> Result<Vec<Element>, EditError> {
+        Ok(self
+            .split_commas(open + 1, close)?
+            .into_iter()
+            .map(|(first, last, comma)| Element {
+                first,
+                last,
+                comma,
+                value: if first == last && self.kind(first) == TokenKind::String {
+                    Some(string_value(self.content, &self.tokens[first]).to_string())
+                } else {
+                    None
+                },
+            })
+            .collect())
+    }
+
+    /// String values in a select, skipping the condition keys.
+    fn select_values(&self, start: usize, end: usize) -> Vec<String> {
+        (start..=end)
+            .filter(|i| self.kind(*i) == TokenKind::String)
+            .filter(|i| {
+                self.next_code_token(i + 1, end + 1)
+                    .map(|n| self.kind(n) != TokenKind::Colon)
+                    .unwrap_or(true)
+            })
+            .map(|i| string_value(self.content, &self.tokens[i]).to_string())
+            .collect()
+    }
+
+    fn deps_values(&self, arg: &Arg) -> Result<(Vec<String>, Vec<String>), EditError> {
+        let mut list_values = Vec::default();
+        let mut select_values = Vec::default();
+        for operand in self.operands(arg)? {
+            match operand {
+                Operand::List { open, close } => list_values.extend(
+                    self.list_elements(open, close)?
+                        .into_iter()
+                        .filter_map(|e| e.value),
+                ),
+                Operand::Select { start, end } => {
+                    select_values.extend(self.select_values(start, end))
+                }
+                Operand::Other => (),
+            }
+        }
+        Ok((list_values, select_values))
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.content[..pos].rfind('\n').map(|e| e + 1).unwrap_or(0)
+    }
+
+    fn line_end(&self, pos: usize) -> usize {
+        self.content[pos..]
+            .find('\n')
+            .map(|e| e + pos)
+            .unwrap_or(self.content.len())
+    }
+
+    fn indent_of(&self, pos: usize) -> &'a str {
+        let start = self.line_start(pos);
+        let line = &self.content[start..self.line_end(start)];
+        &line[..line.len() - line.trim_start().len()]
+    }
+
+    /// Is the span from token `first` to token `last`, plus an optional trailing comma and comment,
+    /// the only thing on its line(s).
+    fn alone_on_line(&self, first: usize, last: usize) -> bool {
+        let start = self.tokens[first].start;
+        let end = self.tokens[last].end;
+        let after = self.content[end..self.line_end(end)].trim();
+        self.content[self.line_start(start)..start]
+            .trim()
+            .is_empty()
+            && (after.is_empty() || after.starts_with('#'))
+    }
+}
+
+fn apply_edits(content: &str, mut edits: Vec<Edit>) -> String {
+    edits.sort_by_key(|e| std::cmp::Reverse(e.start));
+    let mut result = content.to_string();
+    for edit in edits {
+        result.replace_range(edit.start..edit.end, &edit.replacement);
+    }
+    result
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value)
+}
+
+fn insert_into_list(
+    file: &BuildFile,
+    arg: &Arg,
+    open: usize,
+    close: usize,
+    display: &str,
+) -> Result<Vec<Edit>, EditError> {
+    let elements = file.list_elements(open, close)?;
+    let has_comments = (open..close).any(|i| file.kind(i) == TokenKind::Comment);
+    let open_pos = file.tokens[open].start;
+    let close_pos = file.tokens[close].end;
+    let multi_line = file.content[open_pos..close_pos].contains('\n');
+    let insert_before = elements.iter().position(|e| {
+        e.value
+            .as_ref()
+            .map(|v| compare_list_entries(v, display) == Ordering::Greater)
+            .unwrap_or(false)
+    });
+
+    if elements.is_empty() && !has_comments {
+        return Ok(vec![Edit {
+            start: open_pos,
+            end: close_pos,
+            replacement: format!("[{}]", quote(display)),
+        }]);
+    }
+
+    if !multi_line {
+        // Buildifier puts lists of more than one dependency one per line.
+        let indent = file.indent_of(file.tokens[arg.first].start);
+        let mut entries: Vec<String> = elements
+            .iter()
+            .map(|e| file.content[file.tokens[e.first].start..file.tokens[e.last].end].to_string())
+            .collect();
+        entries.insert(insert_before.unwrap_or(entries.len()), quote(display));
+        let mut replacement = String::from("[\n");
+        for entry in entries {
+            replacement.push_str(&format!("{}    {},\n", indent, entry));
+        }
+        replacement.push_str(&format!("{}]", indent));
+        return Ok(vec![Edit {
+            start: open_pos,
+            end: close_pos,
+            replacement,
+        }]);
+    }
+
+    let element_indent = elements
+        .iter()
+        .find(|e| file.alone_on_line(e.first, e.comma.unwrap_or(e.last)))
+        .map(|e| file.indent_of(file.tokens[e.first].start).to_string())
+        .unwrap_or_else(|| format!("{}    ", file.indent_of(file.tokens[arg.first].start)));
+
+    match (insert_before, elements.last()) {
+        (Some(idx), _) => {
+            let element = &elements[idx];
+            let element_pos = file.tokens[element.first].start;
+            if file.alone_on_line(element.first, element.comma.unwrap_or(element.last)) {
+                // Comments on the lines directly above an element belong to it.
+                let mut insert_pos = file.line_start(element_pos);
+                while insert_pos > 0 {
+                    let previous_line = file.line_start(insert_pos - 1);
+                    if file.content[previous_line..insert_pos]
+                        .trim()
+                        .starts_with('#')
+                    {
+                        insert_pos = previous_line;
+                    } else {
+                        break;
+                    }
+                }
+                Ok(vec![Edit {
+                    start: insert_pos,
+                    end: insert_pos,
+                    replacement: format!("{}{},\n", element_indent, quote(display)),
+                }])
+            } else {
+                Ok(vec![Edit {
+                    start: element_pos,
+                    end: element_pos,
+                    replacement: format!("{}, ", quote(display)),
+                }])
+            }
+        }
+        (None, Some(last)) => {
+            // Edits at the same offset would be applied in the wrong order, so each position gets a single edit.
+            let last_end = file.tokens[last.last].end;
+            let missing_comma = if last.comma.is_none() { "," } else { "" };
+            if file.alone_on_line(last.first, last.comma.unwrap_or(last.last)) {
+                let line_end = file.line_end(last_end);
+                let entry = format!("\n{}{},", element_indent, quote(display));
+                if line_end == last_end {
+                    Ok(vec![Edit {
+                        start: last_end,
+                        end: last_end,
+                        replacement: format!("{}{}", missing_comma, entry),
+                    }])
+                } else {
+                    let mut edits = vec![Edit {
+                        start: line_end,
+                        end: line_end,
+                        replacement: entry,
+                    }];
+                    if last.comma.is_none() {
+                        edits.push(Edit {
+                            start: last_end,
+                            end: last_end,
+                            replacement: String::from(","),
+                        });
+                    }
+                    Ok(edits)
+                }
+            } else {
+                Ok(vec![match last.comma {
+                    Some(comma) => Edit {
+                        start: file.tokens[comma].end,
+                        end: file.tokens[comma].end,
+                        replacement: format!(" {},", quote(display)),
+                    },
+                    None => Edit {
+                        start: last_end,
+                        end: last_end,
+                        replacement: format!(", {}", quote(display)),
+                    },
+                }])
+            }
+        }
+        (None, None) => {
+            // Only comments in the list, add ourselves before the closing bracket.
+            let close_line = file.line_start(file.tokens[close].start);
+            Ok(vec![Edit {
+                start: close_line,
+                end: close_line,
+                replacement: format!("{}{},\n", element_indent, quote(display)),
+            }])
+        }
+    }
+}
+
+fn insert_attribute(
+    file: &BuildFile,
+    call: &Call,
+    rule_name: &str,
+    attribute_name: &str,
+    display: &str,
+) -> Result<Vec<Edit>, EditError> {
+    let anchor = call
+        .args
+        .iter()
+        .find(|a| a.keyword.as_deref() == Some("srcs"))
+        .or_else(|| {
+            call.args
+                .iter()
+                .find(|a| a.keyword.as_deref() == Some("name"))
+        })
+        .ok_or_else(|| EditError::NoAttributeAnchor(rule_name.to_string()))?;
+
+    let value_end = file.tokens[anchor.value_end].end;
+    let attribute = format!("{} = [{}]", attribute_name, quote(display));
+    let mut edits = Vec::default();
+    if anchor.comma.is_none() {
+        edits.push(Edit {
+            start: value_end,
+            end: value_end,
+            replacement: String::from(","),
+        });
+    }
+    if file.alone_on_line(anchor.first, anchor.comma.unwrap_or(anchor.value_end)) {
+        let line_end = file.line_end(value_end);
+        let indent = file.indent_of(file.tokens[anchor.first].start);
+        edits.push(Edit {
+            start: line_end,
+            end: line_end,
+            replacement: format!("\n{}{},", indent, attribute),
+        });
+    } else {
+        let pos = anchor
+            .comma
+            .map(|c| file.tokens[c].end)
+            .unwrap_or(value_end);
+        edits.push(Edit {
+            start: pos,
+            end: pos,
+            replacement: format!(" {}", attribute),
+        });
+    }
+    Ok(edits)
+}
+
+/// All entries of a list attribute of the named rule, as written in the file. Entries inside a select() are included.
+pub fn list_attribute(
+    content: &str,
+    rule_name: &str,
+    attribute: &str,
+) -> Result<Vec<String>, EditError> {
+    let file = BuildFile::parse(content)?;
+    let call = file.find_rule(rule_name)?;
+    match call
+        .args
+        .iter()
+        .find(|a| a.keyword.as_deref() == Some(attribute))
+    {
+        Some(arg) => {
+            let (mut values, select_values) = file.deps_values(arg)?;
+            values.extend(select_values);
+            Ok(values)
+        }
+        None => Ok(Vec::default()),
+    }
+}
+
+/// Add a label to a list attribute of the named rule, returning the new file content or None if it was already present.
+/// Entries in a select() are left alone, concatenated expressions get the label added to their first list literal.
+pub fn add_to_attribute(
+    content: &str,
+    rule_name: &str,
+    attribute: &str,
+    package: &str,
+    label: &str,
+) -> Result<Option<String>, EditError> {
+    let file = BuildFile::parse(content)?;
+    let call = file.find_rule(rule_name)?;
+    let canonical = canonical_label(label, package);
+    let display = shorten_label(&canonical, package);
+
+    let arg = match call
+        .args
+        .iter()
+        .find(|a| a.keyword.as_deref() == Some(attribute))
+    {
+        Some(arg) => arg,
+        None => {
+            return Ok(Some(apply_edits(
+                content,
+                insert_attribute(&file, &call, rule_name, attribute, &display)?,
+            )))
+        }
+    };
+
+    let (list_values, select_values) = file.deps_values(arg)?;
+    if list_values
+        .iter()
+        .chain(select_values.iter())
+        .any(|v| canonical_label(v, package) == canonical)
+    {
+        return Ok(None);
+    }
+
+    let edits = match file.operands(arg)?.into_iter().find_map(|o| match o {
+        Operand::List { open, close } => Some((open, close)),
+        _ => None,
+    }) {
+        Some((open, close)) => insert_into_list(&file, arg, open, close, &display)?,
+        None => {
+            let value_end = file.tokens[arg.value_end].end;
+            vec![Edit {
+                start: value_end,
+                end: value_end,
+                replacement: format!(" + [{}]", quote(&display)),
+            }]
+        }
+    };
+    Ok(Some(apply_edits(content, edits)))
+}
+
+/// Remove a label from the literal lists of a list attribute of the named rule, returning None if it wasn't present.
+/// If the label is only found in a select() or other expression we refuse rather than guess.
+pub fn remove_from_attribute(
+    content: &str,
+    rule_name: &str,
+    attribute: &str,
+    package: &str,
+    label: &str,
+) -> Result<Option<String>, EditError> {
+    let canonical = canonical_label(label, package);
+    let mut current = content.to_string();
+    let mut changed = false;
+
+    loop {
+        let file = BuildFile::parse(&current)?;
+        let call = file.find_rule(rule_name)?;
+        let arg = match call
+            .args
+            .iter()
+            .find(|a| a.keyword.as_deref() == Some(attribute))
+        {
+            Some(arg) => arg,
+            None => break,
+        };
+        let operands = file.operands(arg)?;
+
+        let mut found = None;
+        for operand in operands.iter() {
+            if let Operand::List { open, close } = operand {
+                let elements = file.list_elements(*open, *close)?;
+                if let Some(idx) = elements.iter().position(|e| {
+                    e.value
+                        .as_ref()
+                        .map(|v| canonical_label(v, package) == canonical)
+                        .unwrap_or(false)
+                }) {
+                    found = Some((*open, *close, elements, idx));
+                    break;
+                }
+            }
+        }
+
+        let (open, close, elements, idx) = match found {
+            Some(f) => f,
+            None => {
+                if !changed
+                    && file
+                        .deps_values(arg)?
+                        .1
+                        .iter()
+                        .any(|v| canonical_label(v, package) == canonical)
+                {
+                    return Err(EditError::NotInLiteralList(label.to_string()));
+                }
+                break;
+            }
+        };
+
+        let element = &elements[idx];
+        let has_comments = (open..close).any(|i| file.kind(i) == TokenKind::Comment);
+        let edit = if elements.len() == 1
+            && !has_comments
+            && operands.len() == 1
+            && file.alone_on_line(arg.first, arg.comma.unwrap_or(arg.value_end))
+        {
+            // Like buildozer, drop the attribute entirely once it is empty.
+            let start = file.line_start(file.tokens[arg.first].start);
+            let end = file.line_end(file.tokens[arg.comma.unwrap_or(arg.value_end)].end);
+            Edit {
+                start,
+                end: (end + 1).min(current.len()),
+                replacement: String::default(),
+            }
+        } else if file.alone_on_line(element.first, element.comma.unwrap_or(element.last)) {
+            let start = file.line_start(file.tokens[element.first].start);
+            let end = file.line_end(file.tokens[element.comma.unwrap_or(element.last)].end);
+            Edit {
+                start,
+                end: (end + 1).min(current.len()),
+                replacement: String::default(),
+            }
+        } else if let Some(comma) = element.comma {
+            let mut end = file.tokens[comma].end;
+            while current[end..].starts_with(' ') {
+                end += 1;
+            }
+            Edit {
+                start: file.tokens[element.first].start,
+                end,
+                replacement: String::default(),
+            }
+        } else if idx > 0 {
+            let previous = &elements[idx - 1];
+            Edit {
+                start: previous
+                    .comma
+                    .map(|c| file.tokens[c].start)
+                    .unwrap_or(file.tokens[previous.last].end),
+                end: file.tokens[element.last].end,
+                replacement: String::default(),
+            }
+        } else {
+            Edit {
+                start: file.tokens[element.first].start,
+                end: file.tokens[element.last].end,
+                replacement: String::default(),
+            }
+        };
+        current = apply_edits(&current, vec![edit]);
+        changed = true;
+    }
+
+    Ok(if changed { Some(current) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILD_FILE: &str = r#"load("@io_bazel_rules_scala//scala:scala.bzl", "scala_library")
+
+# The main library
+java_library(
+    name = "a",
+    srcs = glob(["*.java"]),
+    deps = [
+        # Local deps
+        ":b",
+        "//src/main/java/com/example/d",  # trailing comment
+        "@maven//:guava",
+    ] + select({
+        "//conditions:default": ["//src/main/java/com/example/sel"],
+    }),
+)
+
+java_library(name = "b", srcs = ["B.java"], deps = [":c", "//x"])
+
+java_library(
+    name = "c",
+    srcs = ["C.java"],
+)
+
+java_library(
+    name = "only_select",
+    deps = select({
+        "//conditions:default": [":b"],
+    }),
+)
+"#;
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(canonical_label(":b", "src/a"), "//src/a:b");
+        assert_eq!(canonical_label("b", "src/a"), "//src/a:b");
+        assert_eq!(canonical_label("//src/a", "x"), "//src/a:a");
+        assert_eq!(canonical_label("@maven//:guava", "x"), "@maven//:guava");
+        assert_eq!(canonical_label("@maven", "x"), "@maven//:maven");
+        assert_eq!(shorten_label("//src/a:b", "src/a"), ":b");
+        assert_eq!(shorten_label("//src/a:a", "src/b"), "//src/a");
+        assert_eq!(shorten_label("//src/a:b", "src/b"), "//src/a:b");
+        assert_eq!(shorten_label("@r//src/a:a", "src/a"), "@r//src/a");
+        assert_eq!(shorten_label("@z//:z", "src/a"), "@z");
+    }
+
+    #[test]
+    fn test_sort_order() {
+        let mut entries = vec![
+            "@maven//:guava",
+            "//src/b",
+            ":local",
+            "//src/a:z",
+            "//src/a",
+            "plain",
+        ];
+        entries.sort_by(|a, b| compare_list_entries(a, b));
+        assert_eq!(
+            entries,
+            vec![
+                "plain",
+                ":local",
+                "//src/a",
+                "//src/a:z",
+                "//src/b",
+                "@maven//:guava"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_deps() {
+        assert_eq!(
+            list_attribute(BUILD_FILE, "a", "deps").unwrap(),
+            vec![
+                ":b",
+                "//src/main/java/com/example/d",
+                "@maven//:guava",
+                "//src/main/java/com/example/sel"
+            ]
+        );
+        assert_eq!(
+            list_attribute(BUILD_FILE, "c", "deps").unwrap(),
+            Vec::<String>::default()
+        );
+        assert_eq!(
+            list_attribute(BUILD_FILE, "missing", "deps"),
+            Err(EditError::RuleNotFound(String::from("missing")))
+        );
+    }
+
+    #[test]
+    fn test_add_to_multi_line_list() {
+        let updated = add_to_attribute(
+            BUILD_FILE,
+            "a",
+            "deps",
+            "src/main/java/com/example/a",
+            "//src/main/java/com/example/c:c",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(updated.contains(
+            r#"        ":b",
+        "//src/main/java/com/example/c",
+        "//src/main/java/com/example/d",  # trailing comment
+        "@maven//:guava",
+    ] + select({"#
+        ));
+
+        // Sorting before the first element keeps the comment attached to it.
+        let updated = add_to_attribute(
+            BUILD_FILE,
+            "a",
+            "deps",
+            "src/main/java/com/example/a",
+            "//a/b",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(updated.contains(
+            r#"        # Local deps
+        ":b",
+        "//a/b",
+        "//src/main/java/com/example/d","#
+        ));
+
+        let updated = add_to_attribute(
+            BUILD_FILE,
+            "a",
+            "deps",
+            "src/main/java/com/example/a",
+            "@z//:z",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(updated.contains(
+            r#"        "@maven//:guava",
+        "@z",
+    ] + select({"#
+        ));
+    }
+
+    #[test]
+    fn test_add_existing_is_noop() {
+        assert_eq!(
+            add_to_attribute(
+                BUILD_FILE,
+                "a",
+                "deps",
+                "src/main/java/com/example/a",
+                "//src/main/java/com/example/a:b"
+            ),
+            Ok(None)
+        );
+        assert_eq!(
+            add_to_attribute(
+                BUILD_FILE,
+                "a",
+                "deps",
+                "src/main/java/com/example/a",
+                "//src/main/java/com/example/sel"
+            ),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_add_to_single_line_list() {
+        let updated =
+            add_to_attribute(BUILD_FILE, "b", "deps", "src/main/java/com/example/a", ":a")
+                .unwrap()
+                .unwrap();
+        assert!(updated.contains(
+            r#"java_library(name = "b", srcs = ["B.java"], deps = [
+    ":a",
+    ":c",
+    "//x",
+])"#
+        ));
+    }
+
+    #[test]
+    fn test_add_after_last_element() {
+        let add = |content: &str| {
+            add_to_attribute(content, "a", "deps", "src/main/java/com/example/a", "//zz")
+                .unwrap()
+                .unwrap()
+        };
+
+        assert!(add(
+            "java_library(\n    name = \"a\",\n    deps = [\n        \":a\", \":b\",\n    ],\n)\n"
+        )
+        .contains("        \":a\", \":b\", \"//zz\",\n    ],"));
+        assert!(add(
+            "java_library(\n    name = \"a\",\n    deps = [\":a\",\n        \":b\"],\n)\n"
+        )
+        .contains("deps = [\":a\",\n        \":b\", \"//zz\"],"));
+        assert!(add(
+            "java_library(\n    name = \"a\",\n    deps = [\n        \":a\",\n        \":b\"\n    ],\n)\n"
+        )
+        .contains("        \":b\",\n        \"//zz\",\n    ],"));
+    }
+
+    #[test]
+    fn test_add_missing_attribute() {
+        let updated =
+            add_to_attribute(BUILD_FILE, "c", "deps", "src/main/java/com/example/a", ":b")
+                .unwrap()
+                .unwrap();
+        assert!(updated.contains(
+            r#"    name = "c",
+    srcs = ["C.java"],
+    deps = [":b"],
+)"#
+        ));
+    }
+
+    #[test]
+    fn test_add_to_select_only() {
+        let updated = add_to_attribute(BUILD_FILE, "only_select", "deps", "pkg", "//d")
+            .unwrap()
+            .unwrap();
+        assert!(updated.contains(
+            r#"        "//conditions:default": [":b"],
+    }) + ["//d"],"#
+        ));
+    }
+
+    #[test]
+    fn test_remove_from_multi_line_list() {
+        let updated = remove_from_attribute(
+            BUILD_FILE,
+            "a",
+            "deps",
+            "src/main/java/com/example/a",
+            "//src/main/java/com/example/d:d",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(updated.contains(
+            r#"        # Local deps
+        ":b",
+        "@maven//:guava",
+    ] + select({"#
+        ));
+        assert_eq!(
+            remove_from_attribute(
+                BUILD_FILE,
+                "a",
+                "deps",
+                "src/main/java/com/example/a",
+                "//not/present"
+            ),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_remove_inline() {
+        let updated = remove_from_attribute(
+            BUILD_FILE,
+            "b",
+            "deps",
+            "src/main/java/com/example/a",
+            "//x:x",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(updated.contains(r#"java_library(name = "b", srcs = ["B.java"], deps = [":c"])"#));
+
+        let updated =
+            remove_from_attribute(BUILD_FILE, "b", "deps", "src/main/java/com/example/a", ":c")
+                .unwrap()
+                .unwrap();
+        assert!(updated.contains(r#"java_library(name = "b", srcs = ["B.java"], deps = ["//x"])"#));
+    }
+
+    #[test]
+    fn test_remove_last_drops_attribute() {
+        let content =
+            "java_library(\n    name = \"a\",\n    deps = [\n        \":b\",\n    ],\n)\n";
+        assert_eq!(
+            remove_from_attribute(content, "a", "deps", "pkg", "//pkg:b").unwrap(),
+            Some(String::from("java_library(\n    name = \"a\",\n)\n"))
+        );
+    }
+
+    #[test]
+    fn test_other_attribute() {
+        let content = "scala_suite(\n    name = \"a\",\n    runtime_deps = [\":b\"],\n)\n";
+        assert_eq!(
+            list_attribute(content, "a", "runtime_deps").unwrap(),
+            vec![String::from(":b")]
+        );
+        assert_eq!(
+            add_to_attribute(content, "a", "runtime_deps", "pkg", "//c").unwrap(),
+            Some(String::from(
+                "scala_suite(\n    name = \"a\",\n    runtime_deps = [\n        \":b\",\n        \"//c\",\n    ],\n)\n"
+            ))
+        );
+        assert_eq!(
+            add_to_attribute(content, "a", "exports", "pkg", "//c").unwrap(),
+            Some(String::from(
+                "scala_suite(\n    name = \"a\",\n    exports = [\"//c\"],\n    runtime_deps = [\":b\"],\n)\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_remove_refuses_select() {
+        assert_eq!(
+            remove_from_attribute(
+                BUILD_FILE,
+                "only_select",
+                "deps",
+                "src/main/java/com/example/a",
+                ":b"
+            ),
+            Err(EditError::NotInLiteralList(String::from(":b")))
+        );
+    }
+}
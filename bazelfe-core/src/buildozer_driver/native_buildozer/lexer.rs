@@ -0,0 +1,223 @@
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident,
+    String,
+    Number,
+    Comment,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Equals,
+    Plus,
+    Colon,
+    Dot,
+    Other,
+}
+
+/// A token along with its byte span in the source, we never copy the source so edits
+/// can be made as byte range replacements which leave everything else untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LexError {
+    #[error("Unterminated string starting at byte {0}")]
+    UnterminatedString(usize),
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+fn is_ident_continue(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// Returns the length of a string prefix (r, b, rb, br) if a quote follows it.
+fn string_prefix_len(bytes: &[u8], idx: usize) -> Option<usize> {
+    let mut len = 0;
+    while len < 2 && idx + len < bytes.len() && b"rRbB".contains(&bytes[idx + len]) {
+        len += 1;
+    }
+    match bytes.get(idx + len) {
+        Some(b'"') | Some(b'\'') => Some(len),
+        _ => None,
+    }
+}
+
+fn lex_string(bytes: &[u8], start: usize, prefix_len: usize) -> Result<usize, LexError> {
+    let raw = bytes[start..start + prefix_len]
+        .iter()
+        .any(|c| *c == b'r' || *c == b'R');
+    let quote_start = start + prefix_len;
+    let quote = bytes[quote_start];
+    let triple = bytes.len() >= quote_start + 3
+        && bytes[quote_start + 1] == quote
+        && bytes[quote_start + 2] == quote;
+    let mut idx = quote_start + if triple { 3 } else { 1 };
+
+    while idx < bytes.len() {
+        let c = bytes[idx];
+        if c == b'\\' {
+            // Even in raw strings a backslash stops the following quote terminating the string.
+            idx += 2;
+            continue;
+        }
+        if !triple && c == b'\n' && !raw {
+            return Err(LexError::UnterminatedString(start));
+        }
+        if c == quote {
+            if !triple {
+                return Ok(idx + 1);
+            }
+            if bytes.len() >= idx + 3 && bytes[idx + 1] == quote && bytes[idx + 2] == quote {
+                return Ok(idx + 3);
+            }
+        }
+        idx += 1;
+    }
+    Err(LexError::UnterminatedString(start))
+}
+
+pub fn tokenize(content: &str) -> Result<Vec<Token>, LexError> {
+    let bytes = content.as_bytes();
+    let mut tokens = Vec::default();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let c = bytes[idx];
+        if c.is_ascii_whitespace() || (c == b'\\' && bytes.get(idx + 1) == Some(&b'\n')) {
+            idx += 1;
+            continue;
+        }
+        let start = idx;
+        let kind = if c == b'#' {
+            while idx < bytes.len() && bytes[idx] != b'\n' {
+                idx += 1;
+            }
+            TokenKind::Comment
+        } else if let Some(prefix_len) = string_prefix_len(bytes, idx) {
+            idx = lex_string(bytes, idx, prefix_len)?;
+            TokenKind::String
+        } else if is_ident_start(c) {
+            while idx < bytes.len() && is_ident_continue(bytes[idx]) {
+                idx += 1;
+            }
+            TokenKind::Ident
+        } else if c.is_ascii_digit() {
+            while idx < bytes.len() && (bytes[idx].is_ascii_alphanumeric() || bytes[idx] == b'.') {
+                idx += 1;
+            }
+            TokenKind::Number
+        } else {
+            idx += 1;
+            match c {
+                b'(' => TokenKind::LParen,
+                b')' => TokenKind::RParen,
+                b'[' => TokenKind::LBracket,
+                b']' => TokenKind::RBracket,
+                b'{' => TokenKind::LBrace,
+                b'}' => TokenKind::RBrace,
+                b',' => TokenKind::Comma,
+                b':' => TokenKind::Colon,
+                b'.' => TokenKind::Dot,
+                b'+' => {
+                    if bytes.get(idx) == Some(&b'=') {
+                        idx += 1;
+                        TokenKind::Other
+                    } else {
+                        TokenKind::Plus
+                    }
+                }
+                b'=' => {
+                    if bytes.get(idx) == Some(&b'=') {
+                        idx += 1;
+                        TokenKind::Other
+                    } else {
+                        TokenKind::Equals
+                    }
+                }
+                _ => {
+                    // Multi byte utf-8 characters can only appear in strings/comments in valid files,
+                    // but make sure we always land on a character boundary.
+                    while idx < bytes.len() && !content.is_char_boundary(idx) {
+                        idx += 1;
+                    }
+                    TokenKind::Other
+                }
+            }
+        };
+        tokens.push(Token {
+            kind,
+            start,
+            end: idx,
+        });
+    }
+    Ok(tokens)
+}
+
+/// The value of a string literal token, labels never need escapes so we don't attempt to decode them.
+pub fn string_value<'a>(content: &'a str, token: &Token) -> &'a str {
+    let text = &content[token.start..token.end];
+    let text = text.trim_start_matches(|c| "rRbB".contains(c));
+    let quote_len = if text.starts_with("\"\"\"") || text.starts_with("'''") {
+        3
+    } else {
+        1
+    };
+    &text[quote_len..text.len() - quote_len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(content: &str) -> Vec<TokenKind> {
+        tokenize(content)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_call() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("java_library(\n    name = \"a\",  # comment (\n    deps = [\":b\"] + select({}),\n)"),
+            vec![
+                Ident, LParen, Ident, Equals, String, Comma, Comment, Ident, Equals, LBracket,
+                String, RBracket, Plus, Ident, LParen, LBrace, RBrace, RParen, Comma, RParen
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_values() {
+        let content = r#"["a", 'b', r"c\d", """e"f""", "g\"h"]"#;
+        let tokens = tokenize(content).unwrap();
+        let values: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.kind == TokenKind::String)
+            .map(|t| string_value(content, t))
+            .collect();
+        assert_eq!(values, vec!["a", "b", r"c\d", "e\"f", r#"g\"h"#]);
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        assert_eq!(
+            tokenize("name = \"abc\n"),
+            Err(LexError::UnterminatedString(7))
+        );
+    }
+}
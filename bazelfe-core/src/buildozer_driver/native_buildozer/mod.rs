@@ -0,0 +1,290 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use super::{Buildozer, EditKind, ExecuteResultError, ProposedEdit, Result, DEPS_ATTRIBUTE};
+
+mod build_file;
+mod lexer;
+
+pub use build_file::EditError;
+
+const WORKSPACE_MARKERS: &[&str] = &["WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"];
+
+/// Edits BUILD files directly rather than shelling out to buildozer.
+/// Any attribute holding a list of labels can be printed, added to or removed from, e.g. deps, exports or
+/// runtime_deps. Entries inside a select() are printed but never edited. Edits are applied as text patches so
+/// formatting and comments elsewhere in the file are left untouched.
+#[derive(Clone, Debug)]
+pub struct NativeBuildozer {
+    workspace_root: PathBuf,
+    // Processors can edit concurrently, serialize edits so we never lose an update to the same file.
+    edit_lock: Arc<Mutex<()>>,
+}
+
+fn error(message: String) -> ExecuteResultError {
+    ExecuteResultError {
+        exit_code: 2,
+        stdout: String::default(),
+        stderr: message,
+    }
+}
+
+impl From<EditError> for ExecuteResultError {
+    fn from(e: EditError) -> Self {
+        error(e.to_string())
+    }
+}
+
+/// Walk up from the given directory to the root of the bazel workspace.
+pub fn find_workspace_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| WORKSPACE_MARKERS.iter().any(|m| dir.join(m).is_file()))
+        .map(|dir| dir.to_path_buf())
+}
+
+impl NativeBuildozer {
+    pub fn new(workspace_root: PathBuf) -> Self {
+        Self {
+            workspace_root,
+            edit_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn from_current_dir() -> std::io::Result<Self> {
+        let current_dir = std::env::current_dir()?;
+        Ok(Self::new(
+            find_workspace_root(&current_dir).unwrap_or(current_dir),
+        ))
+    }
+
+    /// Find the BUILD file, package and rule name for a target label.
+    fn locate(&self, label: &str) -> Result<(PathBuf, String, String)> {
+        let sanitized = crate::label_utils::sanitize_label(label.to_string());
+        let (package, name) = sanitized
+            .strip_prefix("//")
+            .and_then(|l| l.split_once(':'))
+            .ok_or_else(|| {
+                error(format!(
+                    "Only labels in the main repository can be edited, got {}",
+                    label
+                ))
+            })?;
+
+        let package_dir = self.workspace_root.join(package);
+        ["BUILD.bazel", "BUILD"]
+            .iter()
+            .map(|f| package_dir.join(f))
+            .find(|f| f.is_file())
+            .map(|f| (f, package.to_string(), name.to_string()))
+            .ok_or_else(|| error(format!("No BUILD file found for package //{}", package)))
+    }
+
+    fn apply(
+        content: &str,
+        package: &str,
+        name: &str,
+        attribute: &str,
+        kind: EditKind,
+        label: &str,
+    ) -> Result<Option<String>> {
+        Ok(match kind {
+            EditKind::AddDependency => {
+                build_file::add_to_attribute(content, name, attribute, package, label)?
+            }
+            EditKind::RemoveDependency => {
+                build_file::remove_from_attribute(content, name, attribute, package, label)?
+            }
+        })
+    }
+
+    async fn edit(&self, target: &str, attribute: &str, kind: EditKind, label: &str) -> Result<()> {
+        let (path, package, name) = self.locate(target)?;
+        let _guard = self.edit_lock.lock().await;
+        let content = tokio::fs::read_to_string(&path).await?;
+        if let Some(updated) = Self::apply(&content, &package, &name, attribute, kind, label)? {
+            // Write alongside and rename so a concurrent bazel never sees a partial BUILD file.
+            let tmp_path = path.with_extension("bazelfe_tmp");
+            tokio::fs::write(&tmp_path, updated).await?;
+            tokio::fs::rename(&tmp_path, &path).await?;
+        }
+        Ok(())
+    }
+
+    /// Render what the BUILD file would look like with these edits applied, they should all target one BUILD file.
+    pub async fn preview_edits(&self, edits: &[&ProposedEdit]) -> Result<String> {
+        let first = edits
+            .first()
+            .ok_or_else(|| error(String::from("No edits to preview")))?;
+        let (path, package, _) = self.locate(&first.target)?;
+        let mut content = tokio::fs::read_to_string(&path).await?;
+        for edit in edits.iter() {
+            let (_, _, name) = self.locate(&edit.target)?;
+            if let Some(updated) = Self::apply(
+                &content,
+                &package,
+                &name,
+                &edit.attribute,
+                edit.kind,
+                &edit.label,
+            )? {
+                content = updated;
+            }
+        }
+        Ok(content)
+    }
+}
+
+#[async_trait]
+impl Buildozer for NativeBuildozer {
+    async fn print_deps(&self, label: &String) -> Result<Vec<String>> {
+        self.print_attribute(label, DEPS_ATTRIBUTE).await
+    }
+
+    async fn add_dependency(
+        &self,
+        target_to_operate_on: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.add_to_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn remove_dependency(
+        &self,
+        target_to_operate_on: &String,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.remove_from_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn print_attribute(&self, label: &String, attribute: &str) -> Result<Vec<String>> {
+        let (path, _package, name) = self.locate(label)?;
+        let content = tokio::fs::read_to_string(&path).await?;
+        Ok(build_file::list_attribute(&content, &name, attribute)?
+            .into_iter()
+            .map(crate::label_utils::sanitize_label)
+            .collect())
+    }
+
+    async fn add_to_attribute(
+        &self,
+        target_to_operate_on: &str,
+        attribute: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.edit(
+            target_to_operate_on,
+            attribute,
+            EditKind::AddDependency,
+            label_to_add,
+        )
+        .await
+    }
+
+    async fn remove_from_attribute(
+        &self,
+        target_to_operate_on: &String,
+        attribute: &str,
+        label_to_remove: &String,
+    ) -> Result<()> {
+        self.edit(
+            target_to_operate_on,
+            attribute,
+            EditKind::RemoveDependency,
+            label_to_remove,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace() -> (tempfile::TempDir, NativeBuildozer) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("WORKSPACE"), "").unwrap();
+        std::fs::create_dir_all(dir.path().join("src/a")).unwrap();
+        std::fs::write(
+            dir.path().join("src/a/BUILD.bazel"),
+            "# Keep me\njava_library(\n    name = \"a\",\n    srcs = [\"A.java\"],\n    deps = [\n        \":b\",\n    ],\n)\n",
+        )
+        .unwrap();
+        let buildozer =
+            NativeBuildozer::new(find_workspace_root(&dir.path().join("src/a")).unwrap());
+        (dir, buildozer)
+    }
+
+    #[tokio::test]
+    async fn test_edit_round_trip() {
+        let (dir, buildozer) = workspace();
+
+        buildozer
+            .add_dependency("//src/a:a", &String::from("//src/c:c"))
+            .await
+            .unwrap();
+        assert_eq!(
+            buildozer
+                .print_deps(&String::from("//src/a"))
+                .await
+                .unwrap(),
+            vec![String::from(":b"), String::from("//src/c:c")]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("src/a/BUILD.bazel")).unwrap(),
+            "# Keep me\njava_library(\n    name = \"a\",\n    srcs = [\"A.java\"],\n    deps = [\n        \":b\",\n        \"//src/c\",\n    ],\n)\n"
+        );
+
+        buildozer
+            .remove_dependency(&String::from("//src/a:a"), &String::from("//src/a:b"))
+            .await
+            .unwrap();
+        assert_eq!(
+            buildozer
+                .print_deps(&String::from("//src/a:a"))
+                .await
+                .unwrap(),
+            vec![String::from("//src/c:c")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let (_dir, buildozer) = workspace();
+        assert!(buildozer
+            .print_deps(&String::from("//src/missing:missing"))
+            .await
+            .is_err());
+        assert!(buildozer
+            .add_dependency("//src/a:nope", &String::from("//b"))
+            .await
+            .is_err());
+        assert!(buildozer
+            .print_deps(&String::from("@ext//src/a:a"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_preview_leaves_file_alone() {
+        let (dir, buildozer) = workspace();
+        let edit = ProposedEdit {
+            target: String::from("//src/a:a"),
+            attribute: String::from("deps"),
+            kind: EditKind::AddDependency,
+            label: String::from("//src/c"),
+        };
+        let preview = buildozer.preview_edits(&[&edit]).await.unwrap();
+        assert!(preview.contains("\"//src/c\","));
+        assert!(
+            !std::fs::read_to_string(dir.path().join("src/a/BUILD.bazel"))
+                .unwrap()
+                .contains("//src/c")
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{Buildozer, Result, DEPS_ATTRIBUTE};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EditKind {
+    AddDependency,
+    RemoveDependency,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum EditLogRecord {
+    Edit {
+        invocation_id: String,
+        timestamp_ms: u64,
+        target: String,
+        // Logs written before attributes were recorded only ever edited deps.
+        #[serde(default = "default_attribute")]
+        attribute: String,
+        kind: EditKind,
+        label: String,
+    },
+    /// Written once an invocation's edits have been reverted, so it isn't undone twice.
+    Undone { invocation_id: String },
+}
+
+fn default_attribute() -> String {
+    DEPS_ATTRIBUTE.to_string()
+}
+
+/// A single edit from the log, as needed to revert it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEdit {
+    pub target: String,
+    pub attribute: String,
+    pub kind: EditKind,
+    pub label: String,
+}
+
+/// An append only, json lines, log of every BUILD file edit made through a `RecordingBuildozer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditLog {
+    path: PathBuf,
+}
+
+impl EditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, record: &EditLogRecord) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)
+    }
+
+    pub fn read_all(&self) -> std::io::Result<Vec<EditLogRecord>> {
+        if !self.path.exists() {
+            return Ok(Vec::default());
+        }
+        let content = std::fs::read_to_string(&self.path)?;
+        Ok(content
+            .lines()
+            .filter(|ln| !ln.trim().is_empty())
+            .filter_map(|ln| serde_json::from_str(ln).ok())
+            .collect())
+    }
+
+    /// The most recent invocation which made edits and hasn't been undone yet.
+    pub fn last_undoable_invocation(&self) -> std::io::Result<Option<String>> {
+        let records = self.read_all()?;
+        let undone: HashSet<&String> = records
+            .iter()
+            .filter_map(|r| match r {
+                EditLogRecord::Undone { invocation_id } => Some(invocation_id),
+                EditLogRecord::Edit { .. } => None,
+            })
+            .collect();
+        Ok(records.iter().rev().find_map(|r| match r {
+            EditLogRecord::Edit { invocation_id, .. } if !undone.contains(invocation_id) => {
+                Some(invocation_id.clone())
+            }
+            _ => None,
+        }))
+    }
+
+    pub fn is_undone(&self, invocation_id: &str) -> std::io::Result<bool> {
+        Ok(self.read_all()?.iter().any(|r| match r {
+            EditLogRecord::Undone {
+                invocation_id: undone,
+            } => undone == invocation_id,
+            EditLogRecord::Edit { .. } => false,
+        }))
+    }
+
+    /// All edits made by the given invocation, in the order they were applied.
+    pub fn edits_for_invocation(&self, invocation_id: &str) -> std::io::Result<Vec<RecordedEdit>> {
+        Ok(self
+            .read_all()?
+            .into_iter()
+            .filter_map(|r| match r {
+                EditLogRecord::Edit {
+                    invocation_id: id,
+                    target,
+                    attribute,
+                    kind,
+                    label,
+                    ..
+                } if id == invocation_id => Some(RecordedEdit {
+                    target,
+                    attribute,
+                    kind,
+                    label,
+                }),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+/// Wraps another buildozer recording each successful edit against the current invocation.
+#[derive(Debug, Clone)]
+pub struct RecordingBuildozer<B: Buildozer> {
+    inner: B,
+    edit_log: EditLog,
+    invocation_id: String,
+}
+
+impl<B: Buildozer> RecordingBuildozer<B> {
+    pub fn new(inner: B, edit_log: EditLog, invocation_id: String) -> Self {
+        Self {
+            inner,
+            edit_log,
+            invocation_id,
+        }
+    }
+
+    fn record(&self, target: &str, attribute: &str, kind: EditKind, label: &str) {
+        let record = EditLogRecord::Edit {
+            invocation_id: self.invocation_id.clone(),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            target: target.to_string(),
+            attribute: attribute.to_string(),
+            kind,
+            label: label.to_string(),
+        };
+        if let Err(e) = self.edit_log.append(&record) {
+            warn!(
+                "Unable to record buildozer edit to {}: {}",
+                self.edit_log.path().to_string_lossy(),
+                e
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl<B: Buildozer> Buildozer for RecordingBuildozer<B> {
+    async fn print_deps(&self, label: &String) -> Result<Vec<String>> {
+        self.inner.print_deps(label).await
+    }
+
+    async fn add_dependency(
+        &self,
+        target_to_operate_on: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.add_to_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn remove_dependency(
+        &self,
+        target_to_operate_on: &String,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.remove_from_attribute(target_to_operate_on, DEPS_ATTRIBUTE, label_to_add)
+            .await
+    }
+
+    async fn print_attribute(&self, label: &String, attribute: &str) -> Result<Vec<String>> {
+        self.inner.print_attribute(label, attribute).await
+    }
+
+    async fn add_to_attribute(
+        &self,
+        target_to_operate_on: &str,
+        attribute: &str,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.inner
+            .add_to_attribute(target_to_operate_on, attribute, label_to_add)
+            .await?;
+        self.record(
+            target_to_operate_on,
+            attribute,
+            EditKind::AddDependency,
+            label_to_add,
+        );
+        Ok(())
+    }
+
+    async fn remove_from_attribute(
+        &self,
+        target_to_operate_on: &String,
+        attribute: &str,
+        label_to_remove: &String,
+    ) -> Result<()> {
+        self.inner
+            .remove_from_attribute(target_to_operate_on, attribute, label_to_remove)
+            .await?;
+        self.record(
+            target_to_operate_on,
+            attribute,
+            EditKind::RemoveDependency,
+            label_to_remove,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildozer_driver::ExecuteResultError;
+
+    #[derive(Clone, Debug)]
+    struct NoopBuildozer;
+
+    #[async_trait]
+    impl Buildozer for NoopBuildozer {
+        async fn print_deps(&self, _label: &String) -> Result<Vec<String>> {
+            Ok(Vec::default())
+        }
+
+        async fn add_dependency(&self, _target: &str, label_to_add: &String) -> Result<()> {
+            if label_to_add == "//fails:fails" {
+                Err(ExecuteResultError {
+                    exit_code: 2,
+                    stdout: String::default(),
+                    stderr: String::default(),
+                })
+            } else {
+                Ok(())
+            }
+        }
+
+        async fn remove_dependency(&self, _target: &String, _label: &String) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_reads_records_without_attribute() {
+        let record: EditLogRecord = serde_json::from_str(
+            r#"{"record":"edit","invocation_id":"a","timestamp_ms":1,"target":"//a:a","kind":"add_dependency","label":"//b:b"}"#,
+        )
+        .unwrap();
+        match record {
+            EditLogRecord::Edit { attribute, .. } => assert_eq!(attribute, "deps"),
+            EditLogRecord::Undone { .. } => panic!("Expected an edit record"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_edits_per_invocation() {
+        let dir = tempfile::tempdir().unwrap();
+        let edit_log = EditLog::new(dir.path().join("buildozer_edits.jsonl"));
+
+        let first = RecordingBuildozer::new(NoopBuildozer, edit_log.clone(), String::from("first"));
+        first
+            .add_dependency("//a:a", &String::from("//b:b"))
+            .await
+            .unwrap();
+        assert!(first
+            .add_dependency("//a:a", &String::from("//fails:fails"))
+            .await
+            .is_err());
+
+        let second =
+            RecordingBuildozer::new(NoopBuildozer, edit_log.clone(), String::from("second"));
+        second
+            .remove_dependency(&String::from("//a:a"), &String::from("//c:c"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            edit_log.edits_for_invocation("first").unwrap(),
+            vec![RecordedEdit {
+                target: String::from("//a:a"),
+                attribute: String::from("deps"),
+                kind: EditKind::AddDependency,
+                label: String::from("//b:b"),
+            }]
+        );
+        assert_eq!(
+            edit_log.last_undoable_invocation().unwrap(),
+            Some(String::from("second"))
+        );
+
+        edit_log
+            .append(&EditLogRecord::Undone {
+                invocation_id: String::from("second"),
+            })
+            .unwrap();
+        assert!(edit_log.is_undone("second").unwrap());
+        assert_eq!(
+            edit_log.last_undoable_invocation().unwrap(),
+            Some(String::from("first"))
+        );
+    }
+}
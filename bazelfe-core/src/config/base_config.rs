@@ -1,5 +1,8 @@
 use super::error_processor::ErrorProcessor;
-use super::{command_line_rewriter::CommandLineRewriter, DaemonConfig};
+use super::label_rewrite_rule::LabelRewriteRule;
+use super::{
+    command_line_rewriter::CommandLineRewriter, BesUpstream, DaemonConfig, RemoteCache, RetryPolicy,
+};
 use serde::{Deserialize, Deserializer};
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
@@ -7,6 +10,11 @@ pub struct Config {
     /// List of custom user processors to run over the stdout/stderr streams
     #[serde(alias = "ErrorProcessors")]
     pub error_processors: Option<Vec<ErrorProcessor>>,
+    /// Rules mapping labels of targets generated by macros back to the target to edit.
+    /// Labels containing `_auto_gen_` are always collapsed back to their call site.
+    #[serde(default, alias = "LabelRewriteRules")]
+    pub label_rewrite_rules: Vec<LabelRewriteRule>,
+
     /// Where to load/store the index on disk
     /// In several use cases this might be dynamically fetched/generated, this can be overridden on the command line.
     pub index_input_location: Option<std::path::PathBuf>,
@@ -14,6 +22,10 @@ pub struct Config {
     /// Where to find buildozer on disk
     pub buildozer_path: Option<std::path::PathBuf>,
 
+    /// How BUILD files are edited, either by invoking buildozer or with the built in editor.
+    #[serde(default)]
+    pub buildozer_mode: BuildozerMode,
+
     /// where to bind the local port to listen to BES from bazel.
     /// If left empty this will default to a random port on localhost.
     #[serde(default, deserialize_with = "parse_bes_bind_address")]
@@ -24,6 +36,32 @@ pub struct Config {
     #[serde(default)]
     pub disable_action_stories_on_success: bool,
 
+    /// Diagnose failures and propose fixes, but never edit BUILD files.
+    /// The proposed edits are written out as a buildozer command file and a unified diff.
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// Where the dry run suggestions are written, defaults to the daemon communication folder.
+    pub dry_run_output_directory: Option<std::path::PathBuf>,
+
+    /// Write a machine readable report of each run here, replacing any previous report.
+    pub report_path: Option<std::path::PathBuf>,
+
+    #[serde(default)]
+    pub report_format: ReportFormat,
+
+    /// Print where the build spent its time after every run, not only when the runner report is shown.
+    #[serde(default)]
+    pub print_build_timing: bool,
+
+    /// Record every build event stream bazel sends us into this directory, one file per bazel invocation.
+    /// These can be fed back through bazelfe with the replay command. Not supported with build_event_binary_file.
+    pub build_event_recording_directory: Option<std::path::PathBuf>,
+
+    /// Have bazel write build events to this file, which we tail, rather than connecting to our BES server.
+    /// For environments where bazel can't reach a local gRPC port.
+    pub build_event_binary_file: Option<std::path::PathBuf>,
+
     #[serde(
         rename = "CommandLineRewriter",
         default = "CommandLineRewriter::default"
@@ -32,6 +70,57 @@ pub struct Config {
 
     #[serde(rename = "DaemonConfig", default = "DaemonConfig::default")]
     pub daemon_config: DaemonConfig,
+
+    #[serde(rename = "RetryPolicy", default = "RetryPolicy::default")]
+    pub retry_policy: RetryPolicy,
+
+    /// Also forward the build events bazel sends us on to this Build Event Service.
+    #[serde(rename = "BesUpstream", default)]
+    pub bes_upstream: Option<BesUpstream>,
+
+    /// Mnemonics of the actions linting or analysis aspects run, e.g. Scalafix.
+    /// Their failures are left to the error processors rather than treated as missing JVM dependencies.
+    #[serde(default)]
+    pub aspect_mnemonics: Vec<String>,
+
+    /// Used to fetch action outputs bazel only reports as `bytestream://` uris.
+    #[serde(rename = "RemoteCache", default)]
+    pub remote_cache: RemoteCache,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildozerMode {
+    /// Shell out to the buildozer binary at `buildozer_path`.
+    #[default]
+    Binary,
+    /// Edit BUILD files in process, no external binary required.
+    InProcess,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    /// A single json document.
+    #[default]
+    Json,
+    /// A summary line followed by a line per target story.
+    JsonLines,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            "json_lines" | "jsonl" => Ok(ReportFormat::JsonLines),
+            other => Err(format!(
+                "Unknown report format {}, expected json or json_lines",
+                other
+            )),
+        }
+    }
 }
 
 // We want to use the serde configured defaults for our default implemenation to not be
@@ -81,7 +170,10 @@ mod tests {
                 active_action_type: String::from("proto_library"),
                 run_on_success: false,
                 regex_match: String::from(r#"^(.*):(\d+):(\d+): warning: Import (.*) is unused.$"#),
-                target_command_line: String::from(r#""/bin/foo" '$1' "$2" "$3""#)
+                target_command_line: String::from(r#""/bin/foo" '$1' "$2" "$3""#),
+                mnemonics: Vec::default(),
+                tags: Vec::default(),
+                configurations: Vec::default(),
             }])
         );
     }
@@ -91,5 +183,56 @@ mod tests {
         let config: Config = toml::from_str("").unwrap();
 
         assert_eq!(config.error_processors, None);
+        assert!(!config.dry_run);
+    }
+
+    #[test]
+    fn test_dry_run_parse() {
+        let config: Config = toml::from_str(
+            r#"
+        dry_run = true
+        dry_run_output_directory = "/tmp/suggestions"
+        "#,
+        )
+        .unwrap();
+
+        assert!(config.dry_run);
+        assert_eq!(
+            config.dry_run_output_directory,
+            Some(std::path::PathBuf::from("/tmp/suggestions"))
+        );
+        assert_eq!(config.buildozer_mode, BuildozerMode::Binary);
+    }
+
+    #[test]
+    fn test_buildozer_mode_parse() {
+        let config: Config = toml::from_str(
+            r#"
+        buildozer_mode = "in_process"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.buildozer_mode, BuildozerMode::InProcess);
+        assert_eq!(config.buildozer_path, None);
+    }
+
+    #[test]
+    fn test_report_parse() {
+        let config: Config = toml::from_str(
+            r#"
+        report_path = "/tmp/bazelfe_report.jsonl"
+        report_format = "json_lines"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.report_path,
+            Some(std::path::PathBuf::from("/tmp/bazelfe_report.jsonl"))
+        );
+        assert_eq!(config.report_format, ReportFormat::JsonLines);
+        assert_eq!(Config::default().report_format, ReportFormat::Json);
+        assert_eq!("jsonl".parse(), Ok(ReportFormat::JsonLines));
     }
 }
@@ -0,0 +1,88 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use serde::Deserialize;
+
+use super::parse_duration;
+
+/// A Build Event Service we forward every event bazel sends us to, so dashboards keep working under bazelfe.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct BesUpstream {
+    /// e.g. grpc://bes.example.com:1985 or grpcs://bes.example.com
+    pub endpoint: String,
+
+    /// Extra gRPC headers sent with every call, e.g. an api key.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// PEM encoded CA certificate used to verify grpcs endpoints.
+    #[serde(default)]
+    pub tls_ca_certificate: Option<PathBuf>,
+
+    /// Events buffered per stream while the upstream is slow, once full we stop forwarding that stream.
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+
+    #[serde(
+        default = "default_connect_timeout",
+        deserialize_with = "parse_duration"
+    )]
+    pub connect_timeout: Duration,
+
+    /// How long we wait at exit for buffered events to reach the upstream.
+    #[serde(default = "default_flush_timeout", deserialize_with = "parse_duration")]
+    pub flush_timeout: Duration,
+}
+
+impl BesUpstream {
+    pub fn with_endpoint(endpoint: String) -> Self {
+        Self {
+            endpoint,
+            headers: HashMap::default(),
+            tls_ca_certificate: None,
+            buffer_size: default_buffer_size(),
+            connect_timeout: default_connect_timeout(),
+            flush_timeout: default_flush_timeout(),
+        }
+    }
+}
+
+fn default_buffer_size() -> usize {
+    10000
+}
+
+fn default_connect_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_flush_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_simple_parse() {
+        let bes_upstream: BesUpstream = toml::from_str(
+            r#"
+        endpoint = "grpcs://bes.example.com"
+        tls_ca_certificate = "/etc/ssl/bes.pem"
+        connect_timeout = "2s"
+        [headers]
+        x-api-key = "secret"
+        "#,
+        )
+        .unwrap();
+
+        let mut expected = BesUpstream::with_endpoint(String::from("grpcs://bes.example.com"));
+        expected
+            .headers
+            .insert(String::from("x-api-key"), String::from("secret"));
+        expected.tls_ca_certificate = Some(PathBuf::from("/etc/ssl/bes.pem"));
+        expected.connect_timeout = Duration::from_secs(2);
+        assert_eq!(bes_upstream, expected);
+
+        assert!(toml::from_str::<BesUpstream>("buffer_size = 10").is_err());
+    }
+}
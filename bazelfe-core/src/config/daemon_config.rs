@@ -1,8 +1,10 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use regex::Regex;
 use serde::{ser::SerializeSeq, Deserialize, Serialize};
 
+use super::{parse_duration, serialize_duration};
+
 #[derive(Debug, Clone)]
 pub struct NotifyRegexes(pub Vec<Regex>);
 impl PartialEq for NotifyRegexes {
@@ -35,6 +37,31 @@ pub struct DaemonConfig {
         serialize_with = "serialize_regex"
     )]
     pub inotify_ignore_regexes: NotifyRegexes,
+
+    /// Have bazel send its build events to the daemon's BES server rather than one we start per run,
+    /// the daemon routes them back to us by invocation id.
+    #[serde(default = "default_share_build_event_service")]
+    pub share_build_event_service: bool,
+
+    /// How long `watch` waits for file changes to settle before building, e.g. 300ms.
+    #[serde(
+        default = "default_watch_debounce",
+        deserialize_with = "parse_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub watch_debounce: Duration,
+
+    /// Keep the daemon's target graph on disk so a restarted daemon only re-queries changed packages.
+    #[serde(default = "default_persist_target_state")]
+    pub persist_target_state: bool,
+
+    /// The daemon exits once no client has talked to it for this long, e.g. 30m.
+    #[serde(
+        default = "default_idle_timeout",
+        deserialize_with = "parse_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub idle_timeout: Duration,
 }
 
 impl Default for DaemonConfig {
@@ -47,6 +74,22 @@ fn default_enabled() -> bool {
     false
 }
 
+fn default_share_build_event_service() -> bool {
+    true
+}
+
+fn default_watch_debounce() -> Duration {
+    Duration::from_millis(300)
+}
+
+fn default_persist_target_state() -> bool {
+    true
+}
+
+fn default_idle_timeout() -> Duration {
+    Duration::from_secs(3600)
+}
+
 fn serialize_regex<'de, S>(regexes: &NotifyRegexes, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -110,7 +153,11 @@ mod tests {
             DaemonConfig {
                 enabled: false,
                 daemon_communication_folder: default_communication_folder(),
-                inotify_ignore_regexes: default_inotify_ignore()
+                inotify_ignore_regexes: default_inotify_ignore(),
+                share_build_event_service: true,
+                watch_debounce: default_watch_debounce(),
+                persist_target_state: true,
+                idle_timeout: default_idle_timeout(),
             }
         );
     }
@@ -129,11 +176,33 @@ mod tests {
             DaemonConfig {
                 enabled: false,
                 daemon_communication_folder: PathBuf::from("/tmp/foo"),
-                inotify_ignore_regexes: default_inotify_ignore()
+                inotify_ignore_regexes: default_inotify_ignore(),
+                share_build_event_service: true,
+                watch_debounce: default_watch_debounce(),
+                persist_target_state: true,
+                idle_timeout: default_idle_timeout(),
             }
         );
     }
 
+    #[test]
+    fn with_idle_timeout_specified() {
+        let daemon_config: DaemonConfig = toml::from_str(
+            r#"
+            idle_timeout = "15m"
+            watch_debounce = "1s 500ms"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(daemon_config.idle_timeout, Duration::from_secs(900));
+        assert_eq!(daemon_config.watch_debounce, Duration::from_millis(1500));
+
+        // The daemon reads its config back from json.
+        let round_tripped: DaemonConfig =
+            serde_json::from_str(&serde_json::to_string(&daemon_config).unwrap()).unwrap();
+        assert_eq!(round_tripped, daemon_config);
+    }
+
     #[test]
     fn empty_config() {
         let command_line_rewriter: DaemonConfig = toml::from_str(
@@ -147,7 +216,11 @@ mod tests {
             DaemonConfig {
                 enabled: false,
                 daemon_communication_folder: default_communication_folder(),
-                inotify_ignore_regexes: default_inotify_ignore()
+                inotify_ignore_regexes: default_inotify_ignore(),
+                share_build_event_service: true,
+                watch_debounce: default_watch_debounce(),
+                persist_target_state: true,
+                idle_timeout: default_idle_timeout(),
             }
         );
     }
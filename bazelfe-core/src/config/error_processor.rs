@@ -17,6 +17,15 @@ pub struct ErrorProcessor {
     pub regex_match: String,
     #[serde(deserialize_with = "clean_command_line")]
     pub target_command_line: String,
+    /// Only run for actions with one of these mnemonics, e.g. the Scalafix actions of a lint aspect.
+    #[serde(default)]
+    pub mnemonics: Vec<String>,
+    /// Only run for targets with every one of these tags.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Only run in configurations whose mnemonic contains one of these, e.g. fastbuild.
+    #[serde(default)]
+    pub configurations: Vec<String>,
 }
 
 #[cfg(test)]
@@ -44,8 +53,33 @@ mod tests {
                 active_action_type: String::from("proto_library"),
                 run_on_success: false,
                 regex_match: String::from(r#"^(.*):(\d+):(\d+): warning: Import (.*) is unused.$"#),
-                target_command_line: String::from(r#""/bin/foo" '$1' "$2" "$3""#)
+                target_command_line: String::from(r#""/bin/foo" '$1' "$2" "$3""#),
+                mnemonics: Vec::default(),
+                tags: Vec::default(),
+                configurations: Vec::default(),
             }
         );
     }
+
+    #[test]
+    fn test_parse_filters() {
+        let error_processor: ErrorProcessor = toml::from_str(
+            r#"
+        name = "Apply scalafix fixes"
+        active_action_type = "scala_library"
+        regex_match = '^(.*): \[scalafix\] (.*)$'
+        target_command_line = "/bin/fix '$1'"
+        mnemonics = ["Scalafix"]
+        configurations = ["fastbuild"]
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(error_processor.mnemonics, vec![String::from("Scalafix")]);
+        assert!(error_processor.tags.is_empty());
+        assert_eq!(
+            error_processor.configurations,
+            vec![String::from("fastbuild")]
+        );
+    }
 }
@@ -0,0 +1,52 @@
+use serde::Deserialize;
+
+fn default_attribute() -> String {
+    String::from("deps")
+}
+
+/// Maps the label of a macro generated target back to the target written in the BUILD file.
+/// The first rule whose regex matches a label is applied, the label is replaced using the
+/// regex crate's replacement syntax, so `$1` refers to the first capture group.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct LabelRewriteRule {
+    pub regex_match: String,
+    pub replacement: String,
+    /// The attribute of the rewritten target which holds its dependencies.
+    #[serde(default = "default_attribute")]
+    pub attribute: String,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_simple_parse() {
+        let label_rewrite_rule: LabelRewriteRule = toml::from_str(
+            r#"
+        regex_match = '^(.*)_scala_suite_[0-9]+$'
+        replacement = '$1'
+        attribute = "suite_deps"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            label_rewrite_rule,
+            LabelRewriteRule {
+                regex_match: String::from("^(.*)_scala_suite_[0-9]+$"),
+                replacement: String::from("$1"),
+                attribute: String::from("suite_deps"),
+            }
+        );
+
+        let label_rewrite_rule: LabelRewriteRule = toml::from_str(
+            r#"
+        regex_match = '^(.*)_gen$'
+        replacement = '$1'
+        "#,
+        )
+        .unwrap();
+        assert_eq!(label_rewrite_rule.attribute, "deps");
+    }
+}
@@ -1,7 +1,14 @@
 mod error_processor;
 pub use error_processor::ErrorProcessor;
+mod label_rewrite_rule;
+pub use label_rewrite_rule::LabelRewriteRule;
 mod base_config;
-pub use base_config::Config;
+pub mod bes_upstream;
+pub use base_config::{BuildozerMode, Config, ReportFormat};
+pub use bes_upstream::BesUpstream;
+
+pub mod remote_cache;
+pub use remote_cache::RemoteCache;
 
 pub mod command_line_rewriter;
 pub use command_line_rewriter::CommandLineRewriter;
@@ -9,10 +16,38 @@ pub use command_line_rewriter::CommandLineRewriter;
 pub mod daemon_config;
 pub use daemon_config::DaemonConfig;
 
+pub mod retry_policy;
+pub use retry_policy::RetryPolicy;
+
 pub fn parse_config(input: &str) -> Result<Config, toml::de::Error> {
     toml::from_str(input)
 }
 
+// Durations are written the humantime way in our config, e.g. "300ms" or "10m".
+fn parse_duration<'de, D>(deserializer: D) -> Result<std::time::Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s: String = serde::Deserialize::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+fn parse_optional_duration<'de, D>(deserializer: D) -> Result<Option<std::time::Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    s.map(|s| humantime::parse_duration(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn serialize_duration<S>(duration: &std::time::Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -39,7 +74,10 @@ mod tests {
                 active_action_type: String::from("proto_library"),
                 run_on_success: false,
                 regex_match: String::from(r#"^(.*):(\d+):(\d+): warning: Import (.*) is unused.$"#),
-                target_command_line: String::from(r#""/bin/foo" '$1' "$2" "$3""#)
+                target_command_line: String::from(r#""/bin/foo" '$1' "$2" "$3""#),
+                mnemonics: Vec::default(),
+                tags: Vec::default(),
+                configurations: Vec::default(),
             }])
         );
     }
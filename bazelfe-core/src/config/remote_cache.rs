@@ -0,0 +1,85 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use serde::Deserialize;
+
+use super::parse_duration;
+
+/// How we talk to the CAS behind `bytestream://` uris in the build event stream.
+/// Bazel points at these instead of local files when building with `--remote_download_minimal`,
+/// the host comes from the uri itself so this only carries what the uri can't.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RemoteCache {
+    /// Extra gRPC headers sent with every call, e.g. an api key.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// PEM encoded CA certificate, when set we connect to the cache over TLS.
+    #[serde(default)]
+    pub tls_ca_certificate: Option<PathBuf>,
+
+    #[serde(
+        default = "default_connect_timeout",
+        deserialize_with = "parse_duration"
+    )]
+    pub connect_timeout: Duration,
+
+    /// A RequestFilesService, e.g. grpc://127.0.0.1:50052, asked for blobs before we go to the cache ourselves.
+    /// It hands back paths in its local CAS, so blobs are only downloaded once across runs.
+    #[serde(default)]
+    pub request_files_endpoint: Option<String>,
+
+    /// Files bigger than this are skipped rather than fetched, logs this size are rarely useful to parse.
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: u64,
+}
+
+impl Default for RemoteCache {
+    fn default() -> Self {
+        Self {
+            headers: HashMap::default(),
+            tls_ca_certificate: None,
+            request_files_endpoint: None,
+            connect_timeout: default_connect_timeout(),
+            max_file_size: default_max_file_size(),
+        }
+    }
+}
+
+fn default_connect_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_max_file_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_simple_parse() {
+        let remote_cache: RemoteCache = toml::from_str(
+            r#"
+        tls_ca_certificate = "/etc/ssl/cache.pem"
+        max_file_size = 1024
+        [headers]
+        x-api-key = "secret"
+        "#,
+        )
+        .unwrap();
+
+        let mut expected = RemoteCache::default();
+        expected
+            .headers
+            .insert(String::from("x-api-key"), String::from("secret"));
+        expected.tls_ca_certificate = Some(PathBuf::from("/etc/ssl/cache.pem"));
+        expected.max_file_size = 1024;
+        assert_eq!(remote_cache, expected);
+
+        assert_eq!(
+            toml::from_str::<RemoteCache>("").unwrap(),
+            RemoteCache::default()
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::parse_optional_duration;
+
+/// Limits on how many times bazel is re-run while we are still taking actions to repair the build.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u16,
+
+    /// Stop starting new attempts once this much time has passed, e.g. "10m".
+    #[serde(default, deserialize_with = "parse_optional_duration")]
+    pub max_duration: Option<Duration>,
+
+    /// If a single attempt takes more actions than this we stop, it's likely to be going off the rails.
+    #[serde(default)]
+    pub max_actions_per_attempt: Option<u32>,
+
+    /// Stop when a dependency is added and removed from the same target repeatedly across attempts.
+    #[serde(default = "default_detect_oscillation")]
+    pub detect_oscillation: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+fn default_max_attempts() -> u16 {
+    15
+}
+
+fn default_detect_oscillation() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_defaults() {
+        assert_eq!(
+            RetryPolicy::default(),
+            RetryPolicy {
+                max_attempts: 15,
+                max_duration: None,
+                max_actions_per_attempt: None,
+                detect_oscillation: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_simple_parse() {
+        let retry_policy: RetryPolicy = toml::from_str(
+            r#"
+        max_attempts = 4
+        max_duration = "5m 30s"
+        max_actions_per_attempt = 20
+        detect_oscillation = false
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            retry_policy,
+            RetryPolicy {
+                max_attempts: 4,
+                max_duration: Some(Duration::from_secs(330)),
+                max_actions_per_attempt: Some(20),
+                detect_oscillation: false,
+            }
+        );
+
+        assert!(toml::from_str::<RetryPolicy>(r#"max_duration = "soon""#).is_err());
+    }
+}
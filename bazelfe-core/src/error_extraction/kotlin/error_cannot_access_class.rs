@@ -0,0 +1,79 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::KotlinClassImportRequest;
+
+// Example usage:
+// KOTLIN:
+// class Foo : SomeBaseFromAnIndirectDependency()
+
+fn build_class_import_request(
+    source_file_name: String,
+    class_name: String,
+) -> KotlinClassImportRequest {
+    KotlinClassImportRequest {
+        src_file_name: source_file_name,
+        class_name,
+        exact_only: false,
+        src_fn: "cannot_access_class",
+        priority: 1,
+    }
+}
+
+pub(in crate::error_extraction::kotlin) fn extract(input: &str) -> Vec<KotlinClassImportRequest> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"^(?:e: )?(?:file://)?(.*\.kts?):\d+:.*[Cc]annot access class '([A-Za-z0-9_./$]+)'.*$"
+        )
+        .unwrap();
+    }
+
+    let mut result = Vec::default();
+    for ln in input.lines() {
+        if let Some(captures) = RE.captures(ln) {
+            let src_file_name = captures.get(1).unwrap().as_str();
+            // K2 reports class ids with slashes for the package, e.g. `com/example/Foo`
+            let class_name = captures.get(2).unwrap().as_str().replace(['/', '$'], ".");
+            result.push(build_class_import_request(
+                src_file_name.to_string(),
+                class_name,
+            ));
+        }
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_cannot_access_class() {
+        let sample_output =
+            "src/main/kotlin/com/example/Example.kt:10:7: error: cannot access class 'com.example.base.Base'. Check your module classpath for missing or conflicting dependencies
+class Foo : Bar()
+      ^";
+        assert_eq!(
+            extract(sample_output),
+            vec![build_class_import_request(
+                String::from("src/main/kotlin/com/example/Example.kt"),
+                "com.example.base.Base".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_cannot_access_class_k2() {
+        let sample_output =
+            "e: file:///src/main/kotlin/com/example/Example.kt:10:7 Cannot access class 'com/example/base/Base.Inner'. Check your module classpath for missing or conflicting dependencies.";
+        assert_eq!(
+            extract(sample_output),
+            vec![build_class_import_request(
+                String::from("/src/main/kotlin/com/example/Example.kt"),
+                "com.example.base.Base.Inner".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::source_dependencies::SelectorType;
+
+use super::KotlinClassImportRequest;
+
+// Example usage:
+// KOTLIN:
+// package com.example
+// import com.example.foo.bar.Baz
+
+fn build_class_import_request(
+    source_file_name: String,
+    class_name: String,
+    priority: i32,
+) -> KotlinClassImportRequest {
+    KotlinClassImportRequest {
+        src_file_name: source_file_name,
+        class_name,
+        exact_only: false,
+        src_fn: "unresolved_reference",
+        priority,
+    }
+}
+
+pub(in crate::error_extraction::kotlin) fn extract(
+    input: &str,
+    file_parse_cache: &mut super::FileParseCache,
+) -> Vec<KotlinClassImportRequest> {
+    lazy_static! {
+        // Older kotlinc reports `Foo.kt:3:8: error: unresolved reference: Bar`,
+        // K2 reports `e: file:///Foo.kt:3:8 Unresolved reference 'Bar'.`
+        static ref RE: Regex = Regex::new(
+            r"^(?:e: )?(?:file://)?(.*\.kts?):(\d+):(?:\d+)?:?\s*(?:error: )?[Uu]nresolved reference:?\s*'?([A-Za-z0-9_]+)'?.*$"
+        )
+        .unwrap();
+    }
+
+    let mut result = Vec::default();
+    for ln in input.lines() {
+        let captures = match RE.captures(ln) {
+            None => continue,
+            Some(captures) => captures,
+        };
+
+        let src_file_name = captures.get(1).unwrap().as_str();
+        let src_line_number: u32 = captures.get(2).unwrap().as_str().parse().unwrap();
+        let reference = captures.get(3).unwrap().as_str();
+
+        let mut found = Vec::default();
+        if let Some(file_data) = file_parse_cache.load_file(src_file_name) {
+            for e in file_data.imports.iter() {
+                if e.line_number == src_line_number {
+                    // The failure is on the import itself, so the whole import is our best guess.
+                    match &e.suffix {
+                        SelectorType::SelectorList(lst) => {
+                            for (orig, _) in lst {
+                                found.push(build_class_import_request(
+                                    src_file_name.to_string(),
+                                    format!("{}.{}", e.prefix_section, orig),
+                                    10,
+                                ));
+                            }
+                        }
+                        SelectorType::WildcardSelector => found.push(build_class_import_request(
+                            src_file_name.to_string(),
+                            e.prefix_section.to_string(),
+                            1,
+                        )),
+                        SelectorType::NoSelector => found.push(build_class_import_request(
+                            src_file_name.to_string(),
+                            e.prefix_section.to_string(),
+                            50,
+                        )),
+                    }
+                } else {
+                    // The reference is used in the body, see if it matches something we imported.
+                    match &e.suffix {
+                        SelectorType::SelectorList(lst) => {
+                            for (orig, alias) in lst {
+                                if alias.as_deref().unwrap_or(orig) == reference {
+                                    found.push(build_class_import_request(
+                                        src_file_name.to_string(),
+                                        format!("{}.{}", e.prefix_section, orig),
+                                        6,
+                                    ));
+                                }
+                            }
+                        }
+                        SelectorType::WildcardSelector => (),
+                        SelectorType::NoSelector => {
+                            if e.prefix_section.rsplit('.').next() == Some(reference) {
+                                found.push(build_class_import_request(
+                                    src_file_name.to_string(),
+                                    e.prefix_section.to_string(),
+                                    6,
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if found.is_empty() {
+            found.push(build_class_import_request(
+                src_file_name.to_string(),
+                reference.to_string(),
+                1,
+            ));
+        }
+        result.extend(found);
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_unresolved_reference_on_import() {
+        let mut file_cache = super::super::FileParseCache::init_from_par(
+            String::from("src/main/kotlin/com/example/Example.kt"),
+            crate::source_dependencies::ParsedFile {
+                package_name: Some(String::from("com.example")),
+                imports: vec![crate::source_dependencies::Import {
+                    line_number: 3,
+                    prefix_section: String::from("com.example.foo.bar.Baz"),
+                    suffix: SelectorType::NoSelector,
+                }],
+            },
+        );
+        let sample_output =
+            "src/main/kotlin/com/example/Example.kt:3:20: error: unresolved reference: foo
+import com.example.foo.bar.Baz
+                   ^";
+        assert_eq!(
+            extract(sample_output, &mut file_cache),
+            vec![build_class_import_request(
+                String::from("src/main/kotlin/com/example/Example.kt"),
+                "com.example.foo.bar.Baz".to_string(),
+                50
+            )]
+        );
+    }
+
+    #[test]
+    fn test_unresolved_reference_matching_import() {
+        let mut file_cache = super::super::FileParseCache::init_from_par(
+            String::from("/tmp/src/main/kotlin/com/example/Example.kt"),
+            crate::source_dependencies::ParsedFile {
+                package_name: Some(String::from("com.example")),
+                imports: vec![
+                    crate::source_dependencies::Import {
+                        line_number: 3,
+                        prefix_section: String::from("com.example.foo.bar.Baz"),
+                        suffix: SelectorType::NoSelector,
+                    },
+                    crate::source_dependencies::Import {
+                        line_number: 4,
+                        prefix_section: String::from("com.example.foo.bar"),
+                        suffix: SelectorType::SelectorList(vec![(
+                            String::from("Other"),
+                            Some(String::from("MyOther")),
+                        )]),
+                    },
+                ],
+            },
+        );
+        let sample_output =
+            "e: file:///tmp/src/main/kotlin/com/example/Example.kt:12:5 Unresolved reference 'MyOther'.";
+        assert_eq!(
+            extract(sample_output, &mut file_cache),
+            vec![build_class_import_request(
+                String::from("/tmp/src/main/kotlin/com/example/Example.kt"),
+                "com.example.foo.bar.Other".to_string(),
+                6
+            )]
+        );
+    }
+
+    #[test]
+    fn test_unresolved_reference_no_file() {
+        let mut file_cache = super::super::FileParseCache::new();
+        let sample_output =
+            "src/main/kotlin/com/example/Example.kt:8:17: error: unresolved reference: Foop
+    val myVal = Foop()
+                ^";
+        assert_eq!(
+            extract(sample_output, &mut file_cache),
+            vec![build_class_import_request(
+                String::from("src/main/kotlin/com/example/Example.kt"),
+                "Foop".to_string(),
+                1
+            )]
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::KotlinClassImportRequest;
+
+// Example usage:
+// KOTLIN:
+// class Foo : Bar() // where Bar extends a class from a missing dependency
+
+fn build_class_import_request(
+    source_file_name: String,
+    class_name: String,
+) -> KotlinClassImportRequest {
+    KotlinClassImportRequest {
+        src_file_name: source_file_name,
+        class_name,
+        exact_only: false,
+        src_fn: "unresolved_supertypes",
+        priority: 1,
+    }
+}
+
+pub(in crate::error_extraction::kotlin) fn extract(input: &str) -> Vec<KotlinClassImportRequest> {
+    lazy_static! {
+        static ref HEADER_RE: Regex = Regex::new(
+            r"^(?:e: )?(?:file://)?(.*\.kts?):\d+:.*supertypes of the following classes cannot be resolved.*$"
+        )
+        .unwrap();
+        static ref SUPERTYPES_RE: Regex =
+            Regex::new(r"^\s*class [A-Za-z0-9_./$]+, unresolved supertypes: (.*)$").unwrap();
+    }
+
+    let mut result = Vec::default();
+    let mut current_src_file: Option<&str> = None;
+    for ln in input.lines() {
+        if let Some(captures) = HEADER_RE.captures(ln) {
+            current_src_file = Some(captures.get(1).unwrap().as_str());
+            continue;
+        }
+
+        match (current_src_file, SUPERTYPES_RE.captures(ln)) {
+            (Some(src_file_name), Some(captures)) => {
+                for class_name in captures.get(1).unwrap().as_str().split(',') {
+                    let class_name = class_name.trim();
+                    if !class_name.is_empty() {
+                        result.push(build_class_import_request(
+                            src_file_name.to_string(),
+                            class_name.replace('/', "."),
+                        ));
+                    }
+                }
+            }
+            _ => current_src_file = None,
+        }
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_unresolved_supertypes() {
+        let sample_output =
+            "src/main/kotlin/com/example/Example.kt:5:1: error: supertypes of the following classes cannot be resolved. Please make sure you have the required dependencies in the classpath:
+    class com.example.Bar, unresolved supertypes: com.example.base.Base, com.example.base.Other
+class Foo : Bar()
+^";
+        assert_eq!(
+            extract(sample_output),
+            vec![
+                build_class_import_request(
+                    String::from("src/main/kotlin/com/example/Example.kt"),
+                    "com.example.base.Base".to_string()
+                ),
+                build_class_import_request(
+                    String::from("src/main/kotlin/com/example/Example.kt"),
+                    "com.example.base.Other".to_string()
+                )
+            ]
+        );
+    }
+}
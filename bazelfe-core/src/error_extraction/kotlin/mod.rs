@@ -0,0 +1,128 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::source_dependencies::ParsedFile;
+
+use super::ClassSuffixMatch;
+
+mod error_cannot_access_class;
+mod error_unresolved_reference;
+mod error_unresolved_supertypes;
+
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+pub struct KotlinClassImportRequest {
+    pub src_file_name: String,
+    pub class_name: String,
+    pub exact_only: bool,
+    pub src_fn: &'static str,
+    pub priority: i32,
+}
+
+impl KotlinClassImportRequest {
+    pub fn to_class_import_request(self) -> super::ClassImportRequest {
+        super::ClassImportRequest {
+            class_name: self.class_name,
+            exact_only: self.exact_only,
+            src_fn: format!("kotlin::{}", self.src_fn),
+            priority: self.priority,
+        }
+    }
+}
+
+fn do_load_file(path_str: &str) -> Option<ParsedFile> {
+    let path = Path::new(path_str);
+
+    if path.exists() {
+        let file_contents = std::fs::read_to_string(path).unwrap();
+        crate::source_dependencies::kotlin::parse_file(&file_contents).ok()
+    } else {
+        None
+    }
+}
+
+pub(in crate::error_extraction) struct FileParseCache {
+    file_parse_cache: HashMap<String, ParsedFile>,
+}
+impl FileParseCache {
+    pub fn new() -> Self {
+        Self {
+            file_parse_cache: HashMap::new(),
+        }
+    }
+    // used in tests
+    #[allow(dead_code)]
+    pub fn init_from_par(key: String, v: ParsedFile) -> Self {
+        let mut map = HashMap::new();
+        map.insert(key, v);
+        Self {
+            file_parse_cache: map,
+        }
+    }
+    pub fn load_file(&mut self, file_path: &str) -> Option<&ParsedFile> {
+        if !self.file_parse_cache.contains_key(file_path) {
+            if let Some(parsed_file) = do_load_file(file_path) {
+                self.file_parse_cache
+                    .insert(file_path.to_string(), parsed_file);
+            }
+        }
+        self.file_parse_cache.get(file_path)
+    }
+}
+
+pub fn extract_errors(input: &str) -> Vec<super::ActionRequest> {
+    let mut file_parse_cache: FileParseCache = FileParseCache::new();
+    let combined_vec: Vec<super::ActionRequest> = vec![
+        error_unresolved_reference::extract(input, &mut file_parse_cache),
+        error_cannot_access_class::extract(input),
+        error_unresolved_supertypes::extract(input),
+    ]
+    .into_iter()
+    .flat_map(|e| e.into_iter())
+    .flat_map(|e| {
+        let cached_file_data = file_parse_cache.load_file(&e.src_file_name);
+
+        // Bare names may come from a wildcard import or from our own package, so we
+        // try those as exact lookups before falling back to a suffix match.
+        let mut expanded: Vec<KotlinClassImportRequest> = Vec::default();
+        if !e.class_name.contains('.') {
+            if let Some(file_data) = cached_file_data {
+                expanded.extend(
+                    file_data
+                        .imports
+                        .iter()
+                        .filter_map(|i| match i.suffix {
+                            crate::source_dependencies::SelectorType::WildcardSelector => {
+                                Some(&i.prefix_section)
+                            }
+                            _ => None,
+                        })
+                        .chain(file_data.package_name.iter())
+                        .map(|prefix| KotlinClassImportRequest {
+                            class_name: format!("{}.{}", prefix, e.class_name),
+                            priority: -3,
+                            exact_only: true,
+                            ..e.clone()
+                        }),
+                );
+            }
+        }
+        expanded.push(e);
+
+        expanded.into_iter().map(|o| {
+            if !o.class_name.contains('.') {
+                let suffix = ClassSuffixMatch {
+                    suffix: o.class_name,
+                    src_fn: format!("kotlin::{}", o.src_fn),
+                };
+                debug!("Found class suffix request: {:#?}", suffix);
+                super::ActionRequest::Suffix(suffix)
+            } else {
+                let r = o.to_class_import_request();
+                debug!("Found class import request: {:#?}", r);
+                super::ActionRequest::Prefix(r)
+            }
+        })
+    })
+    .collect();
+
+    combined_vec
+}
@@ -35,6 +35,7 @@ pub enum ActionRequest {
 }
 
 pub mod java;
+pub mod kotlin;
 pub mod scala;
 
 pub fn extract_errors(target_kind: &Option<String>, input: &str) -> Vec<ActionRequest> {
@@ -43,6 +44,8 @@ pub fn extract_errors(target_kind: &Option<String>, input: &str) -> Vec<ActionRe
         "scala_test" => Some(scala::extract_errors(input)),
         "java_library" => Some(java::extract_errors(input)),
         "java_test" => Some(java::extract_errors(input)),
+        "kt_jvm_library" => Some(kotlin::extract_errors(input)),
+        "kt_jvm_test" => Some(kotlin::extract_errors(input)),
         _ => None,
     });
 
@@ -51,6 +54,7 @@ pub fn extract_errors(target_kind: &Option<String>, input: &str) -> Vec<ActionRe
     } else {
         let mut v = scala::extract_errors(input);
         v.extend(java::extract_errors(input).into_iter());
+        v.extend(kotlin::extract_errors(input));
         v
     }
 }
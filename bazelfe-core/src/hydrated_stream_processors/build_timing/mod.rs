@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use bazelfe_protos::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::build_events::build_event_server::bazel_event::BuildTimingEvt;
+use crate::build_events::hydrated_stream;
+
+/// How many of the slowest mnemonics, targets and actions we keep.
+const SLOWEST_REPORTED: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MnemonicTiming {
+    pub mnemonic: String,
+    pub actions_executed: i64,
+    /// From the first action of this kind starting to the last one finishing.
+    pub duration_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TargetTiming {
+    pub label: String,
+    /// From bazel configuring the target to it completing, going by when bazel sent those events.
+    pub duration_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ActionTiming {
+    pub description: String,
+    pub duration_ms: u64,
+}
+
+/// Where a single bazel invocation spent its time, pieced together from the build metrics,
+/// tool logs and target events in the BEP.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct BuildTimingSummary {
+    pub wall_time_ms: Option<u64>,
+    pub cpu_time_ms: Option<u64>,
+    pub critical_path_ms: Option<u64>,
+    pub actions_created: Option<i64>,
+    pub actions_executed: Option<i64>,
+    /// Spawns served from a remote or disk cache, out of those which could have been.
+    pub cache_hits: Option<i64>,
+    pub cacheable: Option<i64>,
+    pub slowest_mnemonics: Vec<MnemonicTiming>,
+    pub slowest_targets: Vec<TargetTiming>,
+    /// Only known when bazel includes the critical path components in its tool logs.
+    pub slowest_actions: Vec<ActionTiming>,
+}
+
+fn fmt_ms(ms: u64) -> String {
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+
+impl fmt::Display for BuildTimingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut totals = Vec::default();
+        if let Some(ms) = self.wall_time_ms {
+            totals.push(format!("wall {}", fmt_ms(ms)));
+        }
+        if let Some(ms) = self.cpu_time_ms {
+            totals.push(format!("cpu {}", fmt_ms(ms)));
+        }
+        if let Some(ms) = self.critical_path_ms {
+            totals.push(format!("critical path {}", fmt_ms(ms)));
+        }
+        if !totals.is_empty() {
+            writeln!(f, "Build time: {}", totals.join(", "))?;
+        }
+
+        let mut actions = Vec::default();
+        if let (Some(executed), Some(created)) = (self.actions_executed, self.actions_created) {
+            actions.push(format!("{} executed of {} created", executed, created));
+        }
+        if let (Some(hits), Some(cacheable)) = (self.cache_hits, self.cacheable) {
+            if cacheable > 0 {
+                actions.push(format!(
+                    "{}% cache hits ({}/{})",
+                    hits * 100 / cacheable,
+                    hits,
+                    cacheable
+                ));
+            }
+        }
+        if !actions.is_empty() {
+            writeln!(f, "Actions: {}", actions.join(", "))?;
+        }
+
+        if !self.slowest_mnemonics.is_empty() {
+            let entries: Vec<String> = self
+                .slowest_mnemonics
+                .iter()
+                .map(|m| {
+                    format!(
+                        "{} {} ({})",
+                        m.mnemonic,
+                        fmt_ms(m.duration_ms),
+                        m.actions_executed
+                    )
+                })
+                .collect();
+            writeln!(f, "Slowest mnemonics: {}", entries.join(", "))?;
+        }
+        if !self.slowest_targets.is_empty() {
+            writeln!(f, "Slowest targets:")?;
+            for t in self.slowest_targets.iter() {
+                writeln!(f, "\t{}\t{}", fmt_ms(t.duration_ms), t.label)?;
+            }
+        }
+        if !self.slowest_actions.is_empty() {
+            writeln!(f, "Slowest actions on the critical path:")?;
+            for a in self.slowest_actions.iter() {
+                writeln!(f, "\t{}\t{}", fmt_ms(a.duration_ms), a.description)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_ms(value: &str, unit: &str) -> Option<u64> {
+    let value: f64 = value.parse().ok()?;
+    Some(match unit {
+        "ms" => value,
+        _ => value * 1000.0,
+    } as u64)
+}
+
+/// Bazel's `critical path` tool log starts with the total, e.g. `Critical Path: 12.34s, Remote ...`,
+/// optionally followed by a line per component such as `  5.12 s   41.49%   action 'Compiling a.cc'`.
+fn parse_critical_path(contents: &str) -> (Option<u64>, Vec<ActionTiming>) {
+    lazy_static! {
+        static ref TOTAL_RE: Regex =
+            Regex::new(r"(?i)^\s*critical path(?:\s*\(|:)\s*([0-9.]+)\s*(ms|s)\b").unwrap();
+        static ref COMPONENT_RE: Regex =
+            Regex::new(r"^\s*([0-9.]+)\s*(ms|s)\s+(?:[0-9.]+%\s+)?(\S.*?)\s*$").unwrap();
+    }
+    let mut total = None;
+    let mut actions = Vec::default();
+    for line in contents.lines() {
+        if let Some(captures) = TOTAL_RE.captures(line) {
+            total = to_ms(&captures[1], &captures[2]);
+        } else if let Some(captures) = COMPONENT_RE.captures(line) {
+            if let Some(duration_ms) = to_ms(&captures[1], &captures[2]) {
+                actions.push(ActionTiming {
+                    description: captures[3].to_string(),
+                    duration_ms,
+                });
+            }
+        }
+    }
+    (total, actions)
+}
+
+/// Bazel's `process stats` tool log, e.g. `12 processes: 8 remote cache hit, 3 internal, 1 linux-sandbox.`
+/// Returns the cache hits and how many processes could have been cached, internal ones never are.
+fn parse_process_stats(contents: &str) -> Option<(i64, i64)> {
+    lazy_static! {
+        static ref TOTAL_RE: Regex = Regex::new(r"([0-9]+) process(?:es)?\s*:(.*)").unwrap();
+        static ref ENTRY_RE: Regex = Regex::new(r"([0-9]+) ([^,.]+)").unwrap();
+    }
+    let captures = TOTAL_RE.captures(contents)?;
+    let total: i64 = captures[1].parse().ok()?;
+    let mut hits = 0;
+    let mut internal = 0;
+    for entry in ENTRY_RE.captures_iter(&captures[2]) {
+        let count: i64 = entry[1].parse().unwrap_or_default();
+        let kind = entry[2].trim();
+        if kind.ends_with("cache hit") {
+            hits += count;
+        } else if kind == "internal" {
+            internal += count;
+        }
+    }
+    Some((hits, total - internal))
+}
+
+#[derive(Debug, Default)]
+struct RunTiming {
+    start_time_millis: Option<i64>,
+    finish_time_millis: Option<i64>,
+    build_metrics: Option<build_event_stream::BuildMetrics>,
+    tool_logs: Vec<build_event_stream::File>,
+    configured_at_millis: HashMap<String, i64>,
+    target_durations_ms: Vec<(String, u64)>,
+}
+
+impl RunTiming {
+    fn summary(&self) -> BuildTimingSummary {
+        let mut summary = BuildTimingSummary::default();
+
+        let timing_metrics = self
+            .build_metrics
+            .as_ref()
+            .and_then(|m| m.timing_metrics.as_ref());
+        summary.wall_time_ms = timing_metrics
+            .map(|t| t.wall_time_in_ms)
+            .filter(|ms| *ms > 0)
+            .or_else(|| match (self.start_time_millis, self.finish_time_millis) {
+                (Some(start), Some(finish)) if finish >= start => Some(finish - start),
+                _ => None,
+            })
+            .map(|ms| ms as u64);
+        summary.cpu_time_ms = timing_metrics
+            .map(|t| t.cpu_time_in_ms)
+            .filter(|ms| *ms > 0)
+            .map(|ms| ms as u64);
+
+        if let Some(action_summary) = self
+            .build_metrics
+            .as_ref()
+            .and_then(|m| m.action_summary.as_ref())
+        {
+            summary.actions_created = Some(action_summary.actions_created);
+            summary.actions_executed = Some(action_summary.actions_executed);
+            if action_summary.actions_executed > 0 {
+                summary.cache_hits = Some(action_summary.remote_cache_hits);
+                summary.cacheable = Some(action_summary.actions_executed);
+            }
+            let mut mnemonics: Vec<MnemonicTiming> = action_summary
+                .action_data
+                .iter()
+                .map(|d| MnemonicTiming {
+                    mnemonic: d.mnemonic.clone(),
+                    actions_executed: d.actions_executed,
+                    duration_ms: (d.last_ended_ms - d.first_started_ms).max(0) as u64,
+                })
+                .collect();
+            mnemonics.sort_by_key(|m| std::cmp::Reverse(m.duration_ms));
+            mnemonics.truncate(SLOWEST_REPORTED);
+            summary.slowest_mnemonics = mnemonics;
+        }
+
+        for log in self.tool_logs.iter() {
+            let contents = match &log.file {
+                Some(build_event_stream::file::File::Contents(contents)) => {
+                    String::from_utf8_lossy(contents)
+                }
+                _ => continue,
+            };
+            match log.name.as_str() {
+                "critical path" => {
+                    let (total, mut actions) = parse_critical_path(&contents);
+                    summary.critical_path_ms = total;
+                    actions.sort_by_key(|a| std::cmp::Reverse(a.duration_ms));
+                    actions.truncate(SLOWEST_REPORTED);
+                    summary.slowest_actions = actions;
+                }
+                // More precise than the build metrics as it counts disk cache hits too.
+                "process stats" => {
+                    if let Some((hits, cacheable)) = parse_process_stats(&contents) {
+                        summary.cache_hits = Some(hits);
+                        summary.cacheable = Some(cacheable);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut targets = self.target_durations_ms.clone();
+        targets.sort_by_key(|t| std::cmp::Reverse(t.1));
+        summary.slowest_targets = targets
+            .into_iter()
+            .take(SLOWEST_REPORTED)
+            .map(|(label, duration_ms)| TargetTiming { label, duration_ms })
+            .collect();
+        summary
+    }
+}
+
+/// Collects timing information for each bazel invocation, the runner asks for the latest once bazel is done.
+#[derive(Clone, Debug, Default)]
+pub struct BuildTimingTracker {
+    runs: Arc<Mutex<HashMap<usize, RunTiming>>>,
+}
+
+#[async_trait::async_trait]
+impl super::BazelEventHandler for BuildTimingTracker {
+    async fn process_event(
+        &self,
+        bazel_run_id: usize,
+        event: &hydrated_stream::HydratedInfo,
+    ) -> Vec<super::BuildEventResponse> {
+        self.process(bazel_run_id, event).await;
+        Vec::default()
+    }
+}
+
+impl BuildTimingTracker {
+    pub async fn process(&self, bazel_run_id: usize, event: &hydrated_stream::HydratedInfo) {
+        let mut runs = self.runs.lock().await;
+        // Only the latest invocation is ever reported, so don't hold on to older ones.
+        if !runs.contains_key(&bazel_run_id) {
+            if runs.keys().any(|id| *id > bazel_run_id) {
+                return;
+            }
+            runs.clear();
+        }
+        let run = runs.entry(bazel_run_id).or_default();
+        match event {
+            // Events read from a file carry no timestamps, we'd only be timing how fast we read them.
+            hydrated_stream::HydratedInfo::TargetConfigured(tce) if tce.aspect.is_none() => {
+                if let Some(event_time_millis) = tce.event_time_millis {
+                    run.configured_at_millis
+                        .insert(tce.label.clone(), event_time_millis);
+                }
+            }
+            hydrated_stream::HydratedInfo::TargetComplete(tce) if tce.aspect.is_none() => {
+                let configured_at_millis = run.configured_at_millis.remove(&tce.label);
+                if let (Some(configured_at), Some(completed_at)) =
+                    (configured_at_millis, tce.event_time_millis)
+                {
+                    run.target_durations_ms.push((
+                        tce.label.clone(),
+                        (completed_at - configured_at).max(0) as u64,
+                    ));
+                }
+            }
+            hydrated_stream::HydratedInfo::BuildTiming(build_timing) => match build_timing {
+                BuildTimingEvt::BuildStarted { start_time_millis } => {
+                    run.start_time_millis = Some(*start_time_millis)
+                }
+                BuildTimingEvt::BuildFinished { finish_time_millis } => {
+                    run.finish_time_millis = Some(*finish_time_millis)
+                }
+                BuildTimingEvt::BuildMetrics(build_metrics) => {
+                    run.build_metrics = Some(build_metrics.clone())
+                }
+                BuildTimingEvt::BuildToolLogs(logs) => run.tool_logs.extend(logs.iter().cloned()),
+            },
+            _ => (),
+        }
+    }
+
+    /// Summary of the most recent bazel invocation, if it told us anything about timing.
+    pub async fn latest_summary(&self) -> Option<BuildTimingSummary> {
+        let runs = self.runs.lock().await;
+        runs.iter()
+            .max_by_key(|(id, _)| **id)
+            .map(|(_, run)| run.summary())
+            .filter(|summary| summary != &BuildTimingSummary::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_events::build_event_server::bazel_event::TargetConfiguredEvt;
+    use crate::build_events::hydrated_stream::{HydratedInfo, TargetCompleteInfo};
+
+    fn tool_log(name: &str, contents: &str) -> build_event_stream::File {
+        build_event_stream::File {
+            path_prefix: Vec::default(),
+            name: name.to_string(),
+            file: Some(build_event_stream::file::File::Contents(
+                contents.as_bytes().to_vec(),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_parse_tool_logs() {
+        assert_eq!(
+            parse_critical_path(
+                "Critical Path: 12.34s, Remote (0.00% of the time): [queue: 0.00%, setup: 0.00%, process: 0.00%]"
+            ),
+            (Some(12340), Vec::default())
+        );
+        assert_eq!(
+            parse_critical_path(
+                "Critical path (3.100 s):\n       Time Percentage   Description\n    250 ms    8.06%   action 'Writing file a.txt'\n    2.85 s   91.94%   action 'Compiling a.cc'\n"
+            ),
+            (
+                Some(3100),
+                vec![
+                    ActionTiming {
+                        description: String::from("action 'Writing file a.txt'"),
+                        duration_ms: 250
+                    },
+                    ActionTiming {
+                        description: String::from("action 'Compiling a.cc'"),
+                        duration_ms: 2850
+                    },
+                ]
+            )
+        );
+
+        assert_eq!(
+            parse_process_stats(
+                "12 processes: 6 remote cache hit, 2 disk cache hit, 3 internal, 1 linux-sandbox."
+            ),
+            Some((8, 9))
+        );
+        assert_eq!(parse_process_stats("1 process: 1 internal."), Some((0, 0)));
+        assert_eq!(parse_process_stats("nothing to see"), None);
+    }
+
+    #[tokio::test]
+    async fn test_summary_from_events() {
+        let tracker = BuildTimingTracker::default();
+        assert_eq!(tracker.latest_summary().await, None);
+
+        let events = [
+            HydratedInfo::BuildTiming(BuildTimingEvt::BuildStarted {
+                start_time_millis: 1_000,
+            }),
+            HydratedInfo::TargetConfigured(TargetConfiguredEvt {
+                event_time_millis: Some(2_000),
+                aspect: None,
+                tags: Vec::default(),
+                test_size: None,
+                label: String::from("//a:a"),
+                rule_kind: String::from("scala_library"),
+            }),
+            HydratedInfo::TargetComplete(TargetCompleteInfo {
+                event_time_millis: Some(6_500),
+                details: hydrated_stream::TargetDetails::default(),
+                label: String::from("//a:a"),
+                aspect: None,
+                success: true,
+                target_kind: Some(String::from("scala_library")),
+                output_files: Vec::default(),
+            }),
+            HydratedInfo::BuildTiming(BuildTimingEvt::BuildFinished {
+                finish_time_millis: 9_000,
+            }),
+            HydratedInfo::BuildTiming(BuildTimingEvt::BuildMetrics(
+                build_event_stream::BuildMetrics {
+                    action_summary: Some(build_event_stream::build_metrics::ActionSummary {
+                        actions_created: 20,
+                        actions_executed: 10,
+                        remote_cache_hits: 4,
+                        action_data: vec![
+                            build_event_stream::build_metrics::action_summary::ActionData {
+                                mnemonic: String::from("Javac"),
+                                actions_executed: 3,
+                                first_started_ms: 2_000,
+                                last_ended_ms: 3_000,
+                            },
+                            build_event_stream::build_metrics::action_summary::ActionData {
+                                mnemonic: String::from("Scalac"),
+                                actions_executed: 7,
+                                first_started_ms: 2_000,
+                                last_ended_ms: 7_500,
+                            },
+                        ],
+                    }),
+                    timing_metrics: Some(build_event_stream::build_metrics::TimingMetrics {
+                        cpu_time_in_ms: 20_000,
+                        wall_time_in_ms: 0,
+                    }),
+                    ..Default::default()
+                },
+            )),
+            HydratedInfo::BuildTiming(BuildTimingEvt::BuildToolLogs(vec![
+                tool_log(
+                    "critical path",
+                    "Critical Path: 5.50s, Remote (0.00% of the time)",
+                ),
+                tool_log(
+                    "process stats",
+                    "10 processes: 5 remote cache hit, 2 internal, 3 linux-sandbox.",
+                ),
+            ])),
+        ];
+        for event in events.iter() {
+            tracker.process(0, event).await;
+        }
+
+        let summary = tracker.latest_summary().await.unwrap();
+        assert_eq!(summary.wall_time_ms, Some(8_000));
+        assert_eq!(summary.cpu_time_ms, Some(20_000));
+        assert_eq!(summary.critical_path_ms, Some(5_500));
+        assert_eq!(summary.actions_executed, Some(10));
+        // The process stats count disk cache hits too, so win over the build metrics.
+        assert_eq!((summary.cache_hits, summary.cacheable), (Some(5), Some(8)));
+        assert_eq!(
+            summary
+                .slowest_mnemonics
+                .iter()
+                .map(|m| (m.mnemonic.as_str(), m.duration_ms))
+                .collect::<Vec<_>>(),
+            vec![("Scalac", 5_500), ("Javac", 1_000)]
+        );
+        assert_eq!(summary.slowest_targets.len(), 1);
+        assert_eq!(
+            summary.slowest_targets[0],
+            TargetTiming {
+                label: String::from("//a:a"),
+                duration_ms: 4_500
+            }
+        );
+
+        let rendered = summary.to_string();
+        assert!(rendered.starts_with("Build time: wall 8.0s, cpu 20.0s, critical path 5.5s\n"));
+        assert!(rendered.contains("Actions: 10 executed of 20 created, 62% cache hits (5/8)\n"));
+        assert!(rendered.contains("Slowest mnemonics: Scalac 5.5s (7), Javac 1.0s (3)\n"));
+
+        // A new invocation replaces the old one.
+        tracker
+            .process(
+                1,
+                &HydratedInfo::BuildTiming(BuildTimingEvt::BuildStarted {
+                    start_time_millis: 10_000,
+                }),
+            )
+            .await;
+        tracker
+            .process(
+                0,
+                &HydratedInfo::BuildTiming(BuildTimingEvt::BuildFinished {
+                    finish_time_millis: 11_000,
+                }),
+            )
+            .await;
+        assert_eq!(tracker.latest_summary().await, None);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::build_events::hydrated_stream::{HydratedInfo, TargetDetails};
+
+/// Picks out the events a handler cares about, by aspect, action mnemonic, tags and configuration.
+/// Empty lists match everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// None matches with or without an aspect, Some(empty) only events that aren't from an aspect,
+    /// otherwise aspects containing one of these, e.g. scalafix_aspect.
+    /// Bazel doesn't say which aspect an action ran for, so action events are only ever seen as not from an aspect,
+    /// use the mnemonics to pick those out.
+    pub aspects: Option<Vec<String>>,
+    pub mnemonics: Vec<String>,
+    pub excluded_mnemonics: Vec<String>,
+    /// The target must have every one of these tags.
+    pub tags: Vec<String>,
+    pub excluded_tags: Vec<String>,
+    /// Substrings of the configuration mnemonic, e.g. fastbuild.
+    pub configurations: Vec<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &HydratedInfo) -> bool {
+        self.matches_parts(event.aspect(), event.mnemonic(), event.details())
+    }
+
+    /// As matches, for when we only have the pieces of an event.
+    pub fn matches_parts(
+        &self,
+        aspect: Option<&str>,
+        mnemonic: Option<&str>,
+        details: Option<&TargetDetails>,
+    ) -> bool {
+        let aspect_matches = match (&self.aspects, aspect) {
+            (None, _) => true,
+            (Some(aspects), None) => aspects.is_empty(),
+            (Some(aspects), Some(aspect)) => aspects.iter().any(|a| aspect.contains(a.as_str())),
+        };
+
+        let mnemonic_matches = (self.mnemonics.is_empty()
+            || mnemonic.is_some_and(|m| self.mnemonics.iter().any(|e| e == m)))
+            && !mnemonic.is_some_and(|m| self.excluded_mnemonics.iter().any(|e| e == m));
+
+        let tags_match = self
+            .tags
+            .iter()
+            .all(|t| details.is_some_and(|d| d.has_tag(t)))
+            && !self
+                .excluded_tags
+                .iter()
+                .any(|t| details.is_some_and(|d| d.has_tag(t)));
+
+        let configuration_matches = self.configurations.is_empty()
+            || details
+                .and_then(|d| d.configuration.as_ref())
+                .is_some_and(|c| self.configurations.iter().any(|e| c.contains(e.as_str())));
+
+        aspect_matches && mnemonic_matches && tags_match && configuration_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_events::hydrated_stream::{ActionFailedErrorInfo, TargetCompleteInfo};
+
+    fn details(tags: &[&str], configuration: &str) -> TargetDetails {
+        TargetDetails {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            test_size: None,
+            configuration: Some(configuration.to_string()),
+        }
+    }
+
+    fn action_failed(mnemonic: &str, details: TargetDetails) -> HydratedInfo {
+        HydratedInfo::ActionFailed(ActionFailedErrorInfo {
+            label: String::from("//src/main/scala/com/example:lib"),
+            stdout: None,
+            stderr: None,
+            target_kind: Some(String::from("scala_library")),
+            mnemonic: Some(mnemonic.to_string()),
+            details,
+        })
+    }
+
+    fn target_complete(aspect: Option<&str>) -> HydratedInfo {
+        HydratedInfo::TargetComplete(TargetCompleteInfo {
+            event_time_millis: None,
+            label: String::from("//src/main/scala/com/example:lib"),
+            aspect: aspect.map(|a| a.to_string()),
+            success: false,
+            target_kind: Some(String::from("scala_library")),
+            output_files: Vec::default(),
+            details: details(&[], "k8-fastbuild"),
+        })
+    }
+
+    #[test]
+    fn test_filters_on_aspects_mnemonics_tags_and_configuration() {
+        let lint_aspect = Some("//tools/lint:scalafix.bzl%scalafix_aspect");
+
+        let targets_only = EventFilter {
+            aspects: Some(Vec::default()),
+            excluded_mnemonics: vec![String::from("Scalafix")],
+            ..Default::default()
+        };
+        assert!(targets_only.matches(&target_complete(None)));
+        assert!(!targets_only.matches(&target_complete(lint_aspect)));
+        assert!(targets_only.matches(&action_failed("Scalac", TargetDetails::default())));
+        assert!(!targets_only.matches(&action_failed("Scalafix", TargetDetails::default())));
+
+        let lint_only = EventFilter {
+            aspects: Some(vec![String::from("scalafix_aspect")]),
+            ..Default::default()
+        };
+        assert!(lint_only.matches(&target_complete(lint_aspect)));
+        assert!(!lint_only.matches(&target_complete(None)));
+
+        let tagged = EventFilter {
+            mnemonics: vec![String::from("Scalac")],
+            tags: vec![String::from("strict")],
+            excluded_tags: vec![String::from("manual")],
+            configurations: vec![String::from("fastbuild")],
+            ..Default::default()
+        };
+        assert!(tagged.matches(&action_failed(
+            "Scalac",
+            details(&["strict"], "k8-fastbuild")
+        )));
+        assert!(!tagged.matches(&action_failed(
+            "Javac",
+            details(&["strict"], "k8-fastbuild")
+        )));
+        assert!(!tagged.matches(&action_failed(
+            "Scalac",
+            details(&["strict", "manual"], "k8-fastbuild")
+        )));
+        assert!(!tagged.matches(&action_failed("Scalac", details(&["strict"], "k8-opt"))));
+        assert!(!tagged.matches(&action_failed("Scalac", TargetDetails::default())));
+    }
+}
@@ -0,0 +1,123 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use bazelfe_protos::*;
+use tokio::sync::Mutex;
+
+use crate::build_events::hydrated_stream;
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct CompletedTargetJdeps {
+    pub success: bool,
+    pub target_kind: Option<String>,
+    pub jdeps_files: Vec<PathBuf>,
+}
+
+/// Tracks the .jdeps files produced alongside the output jars of completed targets.
+/// The compilers write these next to the jar, so we derive the path from the jar
+/// rather than requesting any extra output groups.
+#[derive(Clone, Debug, Default)]
+pub struct JdepsTracker {
+    pub completed_targets: Arc<Mutex<HashMap<String, CompletedTargetJdeps>>>,
+}
+
+#[async_trait::async_trait]
+impl super::BazelEventHandler for JdepsTracker {
+    async fn process_event(
+        &self,
+        _bazel_run_id: usize,
+        event: &hydrated_stream::HydratedInfo,
+    ) -> Vec<super::BuildEventResponse> {
+        self.process(event).await
+    }
+}
+
+fn jdeps_path_for_output(output_file: &build_event_stream::File) -> Option<PathBuf> {
+    let uri = match output_file.file.as_ref()? {
+        build_event_stream::file::File::Uri(uri) => uri,
+        build_event_stream::file::File::Contents(_) => return None,
+    };
+    let local_path = uri.strip_prefix("file://").unwrap_or(uri);
+    let stem = local_path.strip_suffix(".jar")?;
+    if stem.ends_with("-src") || stem.ends_with("_deploy") {
+        return None;
+    }
+    Some(PathBuf::from(format!("{}.jdeps", stem)))
+}
+
+impl JdepsTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn process(
+        &self,
+        event: &hydrated_stream::HydratedInfo,
+    ) -> Vec<super::BuildEventResponse> {
+        if let hydrated_stream::HydratedInfo::TargetComplete(tce) = event {
+            if tce.aspect.is_none() {
+                let jdeps_files: Vec<PathBuf> = tce
+                    .output_files
+                    .iter()
+                    .filter_map(jdeps_path_for_output)
+                    .collect();
+                let mut guard = self.completed_targets.lock().await;
+                guard.insert(
+                    tce.label.clone(),
+                    CompletedTargetJdeps {
+                        success: tce.success,
+                        target_kind: tce.target_kind.clone(),
+                        jdeps_files,
+                    },
+                );
+            }
+        }
+        Vec::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri_file(uri: &str) -> build_event_stream::File {
+        build_event_stream::File {
+            path_prefix: vec![],
+            name: String::from("out"),
+            file: Some(build_event_stream::file::File::Uri(uri.to_string())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tracks_jdeps_for_jars() {
+        let tracker = JdepsTracker::new();
+        tracker
+            .process(&hydrated_stream::HydratedInfo::TargetComplete(
+                hydrated_stream::TargetCompleteInfo {
+                    event_time_millis: None,
+                    details: hydrated_stream::TargetDetails::default(),
+                    label: String::from("//src/main/java/com/example:example"),
+                    aspect: None,
+                    success: true,
+                    target_kind: Some(String::from("java_library")),
+                    output_files: vec![
+                        uri_file("file:///out/bin/src/main/java/com/example/libexample.jar"),
+                        uri_file("file:///out/bin/src/main/java/com/example/libexample-src.jar"),
+                        uri_file("file:///out/bin/src/main/java/com/example/README.md"),
+                    ],
+                },
+            ))
+            .await;
+
+        let guard = tracker.completed_targets.lock().await;
+        assert_eq!(
+            guard.get("//src/main/java/com/example:example"),
+            Some(&CompletedTargetJdeps {
+                success: true,
+                target_kind: Some(String::from("java_library")),
+                jdeps_files: vec![PathBuf::from(
+                    "/out/bin/src/main/java/com/example/libexample.jdeps"
+                )],
+            })
+        );
+    }
+}
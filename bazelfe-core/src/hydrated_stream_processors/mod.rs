@@ -1,9 +1,13 @@
 use crate::build_events::hydrated_stream;
 
+pub mod build_timing;
+pub mod event_filter;
 pub mod event_stream_listener;
 pub mod index_new_results;
+pub mod jdeps_tracker;
 pub mod process_bazel_failures;
 pub mod target_completed_tracker;
+pub mod test_results;
 
 #[derive(Clone, Debug)]
 pub enum BuildEventResponse {
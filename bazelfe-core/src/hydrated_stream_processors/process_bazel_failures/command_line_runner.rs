@@ -1,13 +1,26 @@
 use async_trait::async_trait;
 use std::ffi::OsString;
+use std::sync::Arc;
 use tokio::process::Command;
+use tokio::sync::Mutex;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct ExecutionResult {
     pub exit_success: bool,
+    /// None if the process was killed by a signal, or never ran.
+    pub exit_code: Option<i32>,
     pub stdout: String,
     pub stderr: String,
 }
+
+impl ExecutionResult {
+    pub fn exit_code_description(&self) -> String {
+        match self.exit_code {
+            Some(exit_code) => format!("exit code {}", exit_code),
+            None => String::from("no exit code"),
+        }
+    }
+}
 #[async_trait]
 pub trait CommandLineRunner: Clone + Send + Sync + std::fmt::Debug + 'static {
     async fn execute_command_line<S: Into<String> + Clone + Send>(
@@ -39,6 +52,7 @@ impl CommandLineRunnerImpl {
             Err(err) => {
                 return ExecutionResult {
                     exit_success: false,
+                    exit_code: None,
                     stdout: String::default(),
                     stderr: err.to_string(),
                 }
@@ -50,6 +64,7 @@ impl CommandLineRunnerImpl {
         if command_line.is_empty() {
             return ExecutionResult {
                 exit_success: false,
+                exit_code: None,
                 stdout: String::from(""),
                 stderr: String::from("No command line supplied"),
             };
@@ -57,16 +72,15 @@ impl CommandLineRunnerImpl {
         let mut cmd = Command::new(&command_line[0]);
 
         match cmd.args(&command_line[1..]).output().await {
-            Ok(command_line_run_result) => {
-                let exit_code = command_line_run_result.status.code().unwrap_or(-1);
-                ExecutionResult {
-                    stdout: CommandLineRunnerImpl::decode_str(&command_line_run_result.stdout),
-                    stderr: CommandLineRunnerImpl::decode_str(&command_line_run_result.stderr),
-                    exit_success: exit_code == 0,
-                }
-            }
+            Ok(command_line_run_result) => ExecutionResult {
+                stdout: CommandLineRunnerImpl::decode_str(&command_line_run_result.stdout),
+                stderr: CommandLineRunnerImpl::decode_str(&command_line_run_result.stderr),
+                exit_success: command_line_run_result.status.success(),
+                exit_code: command_line_run_result.status.code(),
+            },
             Err(err) => ExecutionResult {
                 exit_success: false,
+                exit_code: None,
                 stdout: String::default(),
                 stderr: err.to_string(),
             },
@@ -84,8 +98,53 @@ impl CommandLineRunner for CommandLineRunnerImpl {
     }
 }
 
+/// Wraps another runner, when enabled user defined actions are only collected as proposals and never run.
+#[derive(Clone, Debug)]
+pub struct DryRunCommandLineRunner<U: CommandLineRunner> {
+    inner: U,
+    enabled: bool,
+    proposed_command_lines: Arc<Mutex<Vec<String>>>,
+}
+
+impl<U: CommandLineRunner> DryRunCommandLineRunner<U> {
+    pub fn new(inner: U, enabled: bool) -> Self {
+        Self {
+            inner,
+            enabled,
+            proposed_command_lines: Arc::new(Mutex::new(Vec::default())),
+        }
+    }
+
+    pub async fn proposed_command_lines(&self) -> Vec<String> {
+        self.proposed_command_lines.lock().await.clone()
+    }
+}
+
+#[async_trait]
+impl<U: CommandLineRunner> CommandLineRunner for DryRunCommandLineRunner<U> {
+    async fn execute_command_line<S>(&self, command_line: S) -> ExecutionResult
+    where
+        S: Into<String> + Clone + Send,
+    {
+        if !self.enabled {
+            return self.inner.execute_command_line(command_line).await;
+        }
+        let command_line = command_line.into();
+        self.proposed_command_lines
+            .lock()
+            .await
+            .push(command_line.clone());
+        ExecutionResult {
+            exit_success: true,
+            exit_code: None,
+            stdout: String::default(),
+            stderr: format!("Not run during a dry run: {}", command_line),
+        }
+    }
+}
+
 #[cfg(test)]
-pub(in crate) mod test_tools {
+pub(crate) mod test_tools {
     use std::{collections::HashSet, sync::Arc};
 
     use tokio::sync::Mutex;
@@ -137,9 +196,51 @@ pub(in crate) mod test_tools {
 
             ExecutionResult {
                 exit_success: run_success,
+                exit_code: Some(if run_success { 0 } else { 1 }),
                 stdout: String::default(),
                 stderr: String::default(),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::test_tools::{ActionLogEntry, FakeCommandLineRunner};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dry_run_only_proposes_command_lines() {
+        let fake = FakeCommandLineRunner::default();
+        let dry_run = DryRunCommandLineRunner::new(fake.clone(), true);
+        let result = dry_run.execute_command_line("touch a").await;
+        assert!(result.exit_success);
+        assert_eq!(result.exit_code, None);
+        assert!(fake.to_vec().await.is_empty());
+        assert_eq!(
+            dry_run.proposed_command_lines().await,
+            vec![String::from("touch a")]
+        );
+
+        let not_dry_run = DryRunCommandLineRunner::new(fake.clone(), false);
+        let result = not_dry_run.execute_command_line("touch b").await;
+        assert_eq!(result.exit_code, Some(0));
+        assert_eq!(
+            fake.to_vec().await,
+            vec![ActionLogEntry::ExecuteCommandLine {
+                command_line: String::from("touch b")
+            }]
+        );
+        assert!(not_dry_run.proposed_command_lines().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reports_the_process_exit_code() {
+        let result = CommandLineRunnerImpl()
+            .execute_command_line("sh -c 'exit 3'")
+            .await;
+        assert!(!result.exit_success);
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.exit_code_description(), "exit code 3");
+    }
+}
@@ -3,23 +3,28 @@ use std::{collections::HashMap, collections::HashSet, sync::Arc, time::Instant};
 use tokio::sync::{Mutex, RwLock};
 
 use crate::{
-    build_events::hydrated_stream, buildozer_driver::Buildozer, config::Config, index_table,
+    build_events::{file_fetcher::FileFetcher, hydrated_stream},
+    buildozer_driver::{Buildozer, LabelRewritingBuildozer},
+    config::Config,
+    index_table,
+    label_utils::LabelRewriter,
 };
 
-use self::{
-    command_line_runner::ExecutionResult,
-    process_user_defined_actions::UserDefinedActionsStateCache,
-};
+use self::process_user_defined_actions::UserDefinedActionsStateCache;
+use super::event_filter::EventFilter;
 
 mod command_line_runner;
 mod process_action_failure_error;
 mod process_build_abort_errors;
 mod process_missing_dependency_errors;
+mod process_missing_proto_imports;
 mod process_user_defined_actions;
 mod shared_utils;
 
 pub use command_line_runner::CommandLineRunner;
 pub use command_line_runner::CommandLineRunnerImpl;
+pub use command_line_runner::DryRunCommandLineRunner;
+pub use command_line_runner::ExecutionResult;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum TargetStoryAction {
@@ -78,10 +83,14 @@ pub struct ProcessBazelFailures<T: Buildozer, U: CommandLineRunner> {
     index_table: index_table::IndexTable,
     previous_global_seen: Arc<RwLock<HashMap<String, Arc<Mutex<CurrentState>>>>>,
     epoch: Arc<RwLock<usize>>,
-    buildozer: T,
+    // Every edit goes through the label rewrite rules so macro generated targets are edited at their call site.
+    buildozer: LabelRewritingBuildozer<T>,
     command_line_runner: U,
     config: Arc<Config>,
     user_defined_action_cache: Arc<UserDefinedActionsStateCache>,
+    file_fetcher: FileFetcher,
+    // Aspect failures aren't missing dependencies of the target, only the user defined processors see those.
+    jvm_failure_filter: EventFilter,
 }
 
 #[async_trait::async_trait]
@@ -103,17 +112,36 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let user_defined_action_cache =
             Arc::new(UserDefinedActionsStateCache::from_config(&config)?);
+        let label_rewriter = LabelRewriter::from_config(&config)?;
+        let file_fetcher = FileFetcher::new(&config.remote_cache)?;
+        let jvm_failure_filter = EventFilter {
+            aspects: Some(Vec::default()),
+            excluded_mnemonics: config.aspect_mnemonics.clone(),
+            ..Default::default()
+        };
         Ok(Self {
             previous_global_seen: Arc::new(RwLock::new(HashMap::default())),
             index_table,
-            buildozer,
+            buildozer: LabelRewritingBuildozer::new(buildozer, label_rewriter),
             command_line_runner,
             epoch: Arc::new(RwLock::new(0)),
             config,
             user_defined_action_cache,
+            file_fetcher,
+            jvm_failure_filter,
         })
     }
 
+    /// The buildozer edits go through, with the label rewrite rules applied.
+    pub fn buildozer(&self) -> &LabelRewritingBuildozer<T> {
+        &self.buildozer
+    }
+
+    /// Shared with the other processors fetching bazel's outputs, so they reuse its connections.
+    pub fn file_fetcher(&self) -> &FileFetcher {
+        &self.file_fetcher
+    }
+
     pub async fn advance_epoch(&self) {
         let mut e = self.epoch.write().await;
         *e += 1;
@@ -144,35 +172,58 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
                 let mut prev_data = prev_data_arc.lock().await;
                 let epoch = *self.epoch.read().await;
 
-                let action_failed_response = process_action_failure_error::process_action_failed(
-                    self.buildozer.clone(),
+                // Every processor reads the same action output, fetch it once.
+                let error_streams = shared_utils::text_logs_from_failure(
+                    &self.file_fetcher,
                     action_failed_error_info,
                 )
                 .await;
 
-                let missing_dependencies_response =
-                    process_missing_dependency_errors::process_missing_dependency_errors(
-                        &mut *prev_data,
-                        self.buildozer.clone(),
-                        action_failed_error_info,
-                        &self.index_table,
-                        epoch,
-                    )
-                    .await;
+                let mut responses = Vec::default();
+                if self.jvm_failure_filter.matches(event) {
+                    responses.push(
+                        process_action_failure_error::process_action_failed(
+                            self.buildozer.clone(),
+                            action_failed_error_info,
+                            &error_streams,
+                        )
+                        .await,
+                    );
+
+                    responses.push(
+                        process_missing_dependency_errors::process_missing_dependency_errors(
+                            &mut prev_data,
+                            self.buildozer.clone(),
+                            action_failed_error_info,
+                            &error_streams,
+                            &self.index_table,
+                            epoch,
+                        )
+                        .await,
+                    );
+
+                    responses.push(
+                        process_missing_proto_imports::process_missing_proto_imports(
+                            self.buildozer.clone(),
+                            action_failed_error_info,
+                            &error_streams,
+                            &self.index_table,
+                        )
+                        .await,
+                    );
+                }
 
                 let user_defined_action_failure =
                     process_user_defined_actions::process_action_failed(
                         self.command_line_runner.clone(),
                         action_failed_error_info,
+                        &error_streams,
                         &self.user_defined_action_cache,
                     )
                     .await;
 
-                vec![
-                    action_failed_response,
-                    missing_dependencies_response,
-                    user_defined_action_failure,
-                ]
+                responses.push(user_defined_action_failure);
+                responses
             }
 
             hydrated_stream::HydratedInfo::BazelAbort(bazel_abort_error_info) => vec![
@@ -183,7 +234,8 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
                 .await,
             ],
             hydrated_stream::HydratedInfo::TargetComplete(tce) => {
-                if tce.success && !tce.label.is_empty() {
+                // An aspect succeeding says nothing about the target building.
+                if tce.success && !tce.label.is_empty() && tce.aspect.is_none() {
                     vec![Response::new(vec![TargetStory {
                         target: tce.label.clone(),
                         action: TargetStoryAction::Success,
@@ -198,6 +250,7 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
             hydrated_stream::HydratedInfo::ActionSuccess(action_success_info) => {
                 let action_success_response = process_user_defined_actions::process_action_success(
                     self.command_line_runner.clone(),
+                    &self.file_fetcher,
                     action_success_info,
                     &self.user_defined_action_cache,
                 )
@@ -217,7 +270,9 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
                     .await,
                 ]
             }
-            hydrated_stream::HydratedInfo::TestResult(_) => {
+            hydrated_stream::HydratedInfo::TestResult(_)
+            | hydrated_stream::HydratedInfo::TargetConfigured(_)
+            | hydrated_stream::HydratedInfo::BuildTiming(_) => {
                 vec![]
             }
         };
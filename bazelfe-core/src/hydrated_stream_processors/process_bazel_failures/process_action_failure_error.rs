@@ -4,8 +4,6 @@ use crate::{build_events::hydrated_stream, buildozer_driver::Buildozer};
 use regex::Regex;
 use std::time::Instant;
 
-use super::shared_utils::text_logs_from_failure;
-
 #[derive(Clone, PartialEq, Debug)]
 
 enum BazelCorrectionCommand {
@@ -20,7 +18,7 @@ struct BuildozerRemoveDepCmd {
 
 fn extract_dependency_isnt_used(
     _action_failed_error_info: &hydrated_stream::ActionFailedErrorInfo,
-    input_error_streams: &Vec<String>,
+    input_error_streams: &[String],
     command_stream: &mut Vec<BazelCorrectionCommand>,
 ) {
     lazy_static! {
@@ -97,13 +95,13 @@ async fn apply_candidates<T: Buildozer + Clone + Send + Sync + 'static>(
 pub async fn process_action_failed<T: Buildozer + Clone + Send + Sync + 'static>(
     buildozer: T,
     action_failed_error_info: &hydrated_stream::ActionFailedErrorInfo,
+    error_streams: &[String],
 ) -> super::Response {
     let mut candidate_correction_commands: Vec<BazelCorrectionCommand> = vec![];
 
-    let error_streams = text_logs_from_failure(action_failed_error_info).await;
     extract_dependency_isnt_used(
         action_failed_error_info,
-        &error_streams,
+        error_streams,
         &mut candidate_correction_commands,
     );
     apply_candidates(candidate_correction_commands, buildozer).await
@@ -118,6 +116,8 @@ mod tests {
     fn test_extract_dependency_isnt_used() {
         // This was referring to a random string put into the dependencies list of the target
         let action_failed_error_info = ActionFailedErrorInfo {
+            mnemonic: None,
+            details: hydrated_stream::TargetDetails::default(),
             label: String::from("//src/main/com/example/foo:Bar"),
             target_kind: Some(String::from("scala_library")),
             stdout: None,
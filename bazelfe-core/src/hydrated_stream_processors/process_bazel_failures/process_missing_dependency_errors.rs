@@ -1,7 +1,6 @@
 use std::{
     collections::{HashMap, HashSet},
     path::Path,
-    path::PathBuf,
     time::Instant,
 };
 
@@ -16,8 +15,6 @@ use crate::{
 
 use super::CurrentState;
 
-use super::shared_utils::output_error_paths;
-
 fn is_potentially_valid_target(target_kind: &Option<String>, label: &str) -> bool {
     lazy_static! {
       // These are things that are already implicit dependencencies so we should ensure they are not included
@@ -61,19 +58,6 @@ fn is_potentially_valid_target(target_kind: &Option<String>, label: &str) -> boo
     }
 }
 
-async fn path_to_import_requests(
-    error_info: &ActionFailedErrorInfo,
-    path_to_use: &PathBuf,
-    action_requests: &mut Vec<ActionRequest>,
-) {
-    let loaded_path = tokio::fs::read_to_string(path_to_use).await.unwrap();
-
-    action_requests.extend(error_extraction::extract_errors(
-        &error_info.target_kind,
-        &loaded_path,
-    ));
-}
-
 pub async fn load_up_ignore_references<T: Buildozer + Clone + Send + Sync + 'static>(
     global_previous_seen: &mut HashSet<String>,
     buildozer: &T,
@@ -147,12 +131,24 @@ pub fn expand_candidate_import_requests(action_requests: Vec<ActionRequest>) ->
     res_action_requests
 }
 
-async fn generate_all_action_requests(
+fn log_to_import_requests(
+    error_info: &ActionFailedErrorInfo,
+    log: &str,
+    action_requests: &mut Vec<ActionRequest>,
+) {
+    action_requests.extend(error_extraction::extract_errors(
+        &error_info.target_kind,
+        log,
+    ));
+}
+
+fn generate_all_action_requests(
     action_failed_error_info: &ActionFailedErrorInfo,
+    error_streams: &[String],
 ) -> Vec<ActionRequest> {
     let mut action_requests: Vec<ActionRequest> = vec![];
-    for path in output_error_paths(action_failed_error_info).into_iter() {
-        path_to_import_requests(action_failed_error_info, &path, &mut action_requests).await
+    for log in error_streams.iter() {
+        log_to_import_requests(action_failed_error_info, log, &mut action_requests);
     }
     expand_candidate_import_requests(action_requests)
 }
@@ -160,6 +156,7 @@ pub async fn process_missing_dependency_errors<T: Buildozer>(
     current_state: &mut CurrentState,
     buildozer: T,
     action_failed_error_info: &ActionFailedErrorInfo,
+    error_streams: &[String],
     index_table: &index_table::IndexTable,
     epoch: usize,
 ) -> super::Response {
@@ -173,7 +170,7 @@ pub async fn process_missing_dependency_errors<T: Buildozer>(
     )
     .await;
     let all_requests: Vec<ActionRequest> =
-        generate_all_action_requests(action_failed_error_info).await;
+        generate_all_action_requests(action_failed_error_info, error_streams);
     debug!("generate_all_action_requests: {:#?}", all_requests);
     let (response, local_previous_seen, remove_from_ignore_list) =
         inner_process_missing_dependency_errors(
@@ -356,7 +353,9 @@ mod tests {
     use std::{path::PathBuf, sync::Arc};
     use tokio::sync::Mutex;
 
+    use super::super::shared_utils::text_logs_from_failure;
     use crate::{
+        build_events::{file_fetcher::FileFetcher, hydrated_stream::TargetDetails},
         buildozer_driver::ExecuteResultError,
         error_extraction::{ActionRequest, ClassImportRequest, ClassSuffixMatch},
     };
@@ -388,9 +387,15 @@ mod tests {
         );
     }
 
-    #[test]
-    fn test_output_error_paths() {
+    #[tokio::test]
+    async fn test_output_error_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let stdout_path = dir.path().join("stdout");
+        std::fs::write(&stdout_path, "error: oops").unwrap();
+
         let action_failed_error_info = ActionFailedErrorInfo {
+            mnemonic: None,
+            details: TargetDetails::default(),
             label: String::from("//src/main/com/example/foo:Bar"),
             stderr: Some(build_event_stream::File {
                 name: String::default(),
@@ -403,16 +408,20 @@ mod tests {
             stdout: Some(build_event_stream::File {
                 name: String::default(),
                 path_prefix: Vec::default(),
-                file: Some(build_event_stream::file::File::Uri(String::from(
-                    "file:///foo/bar/baz",
+                file: Some(build_event_stream::file::File::Uri(format!(
+                    "file://{}",
+                    stdout_path.to_string_lossy()
                 ))),
             }),
             target_kind: Some(String::from("scala_library")),
         };
 
-        let result: Vec<PathBuf> = output_error_paths(&action_failed_error_info);
-        let expected: Vec<PathBuf> = vec![Path::new("/foo/bar/baz").to_path_buf()];
-        assert_eq!(result, expected);
+        let result: Vec<String> = text_logs_from_failure(
+            &FileFetcher::new(&Default::default()).unwrap(),
+            &action_failed_error_info,
+        )
+        .await;
+        assert_eq!(result, vec![String::from("error: oops")]);
     }
     use std::io::prelude::*;
 
@@ -423,25 +432,16 @@ mod tests {
         expected_suffix_requests: Vec<error_extraction::ClassSuffixMatch>,
     ) {
         let action_failed_error_info = ActionFailedErrorInfo {
+            mnemonic: None,
+            details: TargetDetails::default(),
             label: String::from("//src/main/com/example/foo:Bar"),
             stdout: None,
             stderr: None,
             target_kind: Some(String::from(target_kind)),
         };
 
-        let mut tempfile = tempfile::NamedTempFile::new().expect("Can make a temp file");
-        tempfile
-            .write_all(content.as_bytes())
-            .expect("Should be able to write to temp file");
-        let tempfile_path = tempfile.into_temp_path();
-
         let mut action_requests: Vec<ActionRequest> = Vec::default();
-        path_to_import_requests(
-            &action_failed_error_info,
-            &(*tempfile_path).to_path_buf(),
-            &mut action_requests,
-        )
-        .await;
+        log_to_import_requests(&action_failed_error_info, content, &mut action_requests);
 
         let mut candidate_import_requests: Vec<error_extraction::ClassImportRequest> =
             Vec::default();
@@ -498,6 +498,8 @@ mod tests {
             let tempfile_path = tempfile.into_temp_path();
 
             let action_failed_error_info = ActionFailedErrorInfo {
+                mnemonic: None,
+                details: TargetDetails::default(),
                 label: String::from("//src/main/com/example/foo:Bar"),
                 stderr: Some(build_event_stream::File {
                     name: String::default(),
@@ -519,7 +521,13 @@ mod tests {
                 target_kind: Some(String::from(target_kind)),
             };
 
-            let generated_requests = generate_all_action_requests(&action_failed_error_info).await;
+            let error_streams = text_logs_from_failure(
+                &FileFetcher::new(&Default::default()).unwrap(),
+                &action_failed_error_info,
+            )
+            .await;
+            let generated_requests =
+                generate_all_action_requests(&action_failed_error_info, &error_streams);
 
             assert_eq!(generated_requests, expected_requests);
         }
@@ -619,6 +627,8 @@ mod tests {
         let tempfile_path = tempfile.into_temp_path();
 
         let action_failed_error_info = ActionFailedErrorInfo {
+            mnemonic: None,
+            details: TargetDetails::default(),
             label: String::from("//src/main/com/example/foo:Bar"),
 
             stderr: Some(build_event_stream::File {
@@ -664,10 +674,16 @@ mod tests {
         std::fs::write("src/main/scala/com/example/foo/BUILD", "java_librar(...)")
             .expect("Should be able to write file");
 
+        let error_streams = text_logs_from_failure(
+            &FileFetcher::new(&Default::default()).unwrap(),
+            &action_failed_error_info,
+        )
+        .await;
         let response = process_missing_dependency_errors(
             &mut global_previous_seen,
             buildozer.clone(),
             &action_failed_error_info,
+            &error_streams,
             &index_table,
             1,
         )
@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{
+    build_events::hydrated_stream::ActionFailedErrorInfo, buildozer_driver::Buildozer, index_table,
+};
+
+// Example protoc output:
+// src/main/proto/com/example/foo.proto:5:1: Import "com/example/bar.proto" was not found or had errors.
+// src/main/proto/com/example/foo.proto: Import "com/example/bar.proto" was not found or had errors.
+fn extract_missing_proto_imports(input_error_streams: &[String]) -> Vec<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r#"^.*\.proto:.*Import "([^"]+\.proto)" was not found or had errors\.?\s*$"#
+        )
+        .unwrap();
+    }
+
+    let mut result = Vec::default();
+    for stream in input_error_streams {
+        for ln in stream.lines() {
+            if let Some(captures) = RE.captures(ln) {
+                result.push(captures.get(1).unwrap().as_str().to_string());
+            }
+        }
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+async fn inner_process_missing_proto_imports<T: Buildozer>(
+    buildozer: T,
+    label: &str,
+    index_table: &index_table::IndexTable,
+    missing_imports: Vec<String>,
+) -> super::Response {
+    let mut target_stories = Vec::default();
+    if missing_imports.is_empty() {
+        return super::Response::new(target_stories);
+    }
+
+    let sanitized_label = crate::label_utils::sanitize_label(String::from(label));
+    let existing_deps: HashSet<String> = buildozer
+        .print_deps(&sanitized_label)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+
+    let mut added: HashSet<String> = HashSet::default();
+    for missing_import in missing_imports.into_iter() {
+        let candidates = match index_table.get(missing_import.as_str()).await {
+            Some(candidates) => candidates,
+            None => {
+                debug!("No proto_library found in index for {}", missing_import);
+                continue;
+            }
+        };
+
+        let mut target_to_add = None;
+        for target_entry in &candidates.read_iter().await {
+            let target: String = index_table
+                .decode_string(target_entry.target)
+                .await
+                .unwrap();
+            let target = crate::label_utils::sanitize_label(target);
+            if target != sanitized_label && !existing_deps.contains(&target) {
+                target_to_add = Some(target);
+                break;
+            }
+        }
+
+        if let Some(target) = target_to_add {
+            if added.contains(&target) {
+                continue;
+            }
+            debug!(
+                "Buildozer action: add dependency {:?} to {:?}",
+                target, &sanitized_label
+            );
+            match buildozer.add_dependency(&sanitized_label, &target).await {
+                Ok(_) => {
+                    target_stories.push(super::TargetStory {
+                        target: label.to_string(),
+                        action: super::TargetStoryAction::AddedDependency {
+                            added_what: target.clone(),
+                            why: format!("Saw missing proto import: {}", missing_import),
+                        },
+                        when: Instant::now(),
+                    });
+                    added.insert(target);
+                }
+                Err(_) => warn!("Buildozer command failed"),
+            }
+        }
+    }
+    super::Response::new(target_stories)
+}
+
+pub async fn process_missing_proto_imports<T: Buildozer>(
+    buildozer: T,
+    action_failed_error_info: &ActionFailedErrorInfo,
+    error_streams: &[String],
+    index_table: &index_table::IndexTable,
+) -> super::Response {
+    if action_failed_error_info.target_kind.as_deref() != Some("proto_library") {
+        return super::Response::new(Vec::default());
+    }
+
+    let missing_imports = extract_missing_proto_imports(error_streams);
+    inner_process_missing_proto_imports(
+        buildozer,
+        &action_failed_error_info.label,
+        index_table,
+        missing_imports,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildozer_driver::ExecuteResultError;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[test]
+    fn test_extract_missing_proto_imports() {
+        let error_streams = vec![String::from(
+            "src/main/proto/com/example/foo.proto:5:1: Import \"com/example/bar.proto\" was not found or had errors.
+src/main/proto/com/example/foo.proto:12:3: \"com.example.Bar\" is not defined.
+src/main/proto/com/example/foo.proto: Import \"google/protobuf/any.proto\" was not found or had errors.",
+        )];
+
+        assert_eq!(
+            extract_missing_proto_imports(&error_streams),
+            vec![
+                String::from("com/example/bar.proto"),
+                String::from("google/protobuf/any.proto"),
+            ]
+        );
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum ActionLogEntry {
+        AddDependency {
+            target_to_operate_on: String,
+            label_to_add: String,
+        },
+    }
+
+    #[derive(Clone, Debug)]
+    struct FakeBuildozer {
+        existing_deps: Vec<String>,
+        action_log: Arc<Mutex<Vec<ActionLogEntry>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Buildozer for FakeBuildozer {
+        async fn print_deps(&self, _label: &String) -> Result<Vec<String>, ExecuteResultError> {
+            Ok(self.existing_deps.clone())
+        }
+
+        async fn add_dependency(
+            &self,
+            target_to_operate_on: &str,
+            label_to_add: &String,
+        ) -> Result<(), ExecuteResultError> {
+            let mut lock = self.action_log.lock().await;
+            lock.push(ActionLogEntry::AddDependency {
+                target_to_operate_on: target_to_operate_on.to_string(),
+                label_to_add: label_to_add.clone(),
+            });
+            Ok(())
+        }
+
+        async fn remove_dependency(
+            &self,
+            _target_to_operate_on: &String,
+            _label_to_add: &String,
+        ) -> Result<(), ExecuteResultError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inner_process_missing_proto_imports() {
+        let mut tbl_map = std::collections::HashMap::new();
+        tbl_map.insert(
+            String::from("com/example/bar.proto"),
+            vec![(1, String::from("//src/main/proto/com/example:bar_proto"))],
+        );
+        tbl_map.insert(
+            String::from("google/protobuf/any.proto"),
+            vec![(1, String::from("@com_google_protobuf//:any_proto"))],
+        );
+        let index_table = index_table::IndexTable::from_hashmap(tbl_map);
+
+        let buildozer = FakeBuildozer {
+            existing_deps: vec![String::from("@com_google_protobuf//:any_proto")],
+            action_log: Arc::new(Mutex::new(Vec::default())),
+        };
+
+        let response = inner_process_missing_proto_imports(
+            buildozer.clone(),
+            "//src/main/proto/com/example:foo_proto",
+            &index_table,
+            vec![
+                String::from("com/example/bar.proto"),
+                String::from("google/protobuf/any.proto"),
+                String::from("not/in/the/index.proto"),
+            ],
+        )
+        .await;
+
+        assert_eq!(response.target_story_entries.len(), 1);
+        assert_eq!(
+            *buildozer.action_log.lock().await,
+            vec![ActionLogEntry::AddDependency {
+                target_to_operate_on: String::from("//src/main/proto/com/example:foo_proto"),
+                label_to_add: String::from("//src/main/proto/com/example:bar_proto"),
+            }]
+        );
+    }
+}
@@ -1,28 +1,27 @@
 use crate::config::{Config, ErrorProcessor};
 
-use crate::build_events::hydrated_stream;
+use crate::build_events::{file_fetcher::FileFetcher, hydrated_stream};
+use crate::hydrated_stream_processors::event_filter::EventFilter;
 use regex::Regex;
 use std::time::Instant;
 use std::{collections::HashMap, sync::Arc};
 
-use super::{
-    command_line_runner::CommandLineRunner,
-    shared_utils::{text_logs_from_failure, text_logs_from_success},
-};
+use super::{command_line_runner::CommandLineRunner, shared_utils::text_logs_from_success};
+
+type UserDefinedAction = Arc<(Regex, ErrorProcessor, EventFilter)>;
 
 #[derive(Clone, Debug)]
 pub struct UserDefinedActionsStateCache {
-    run_always: HashMap<String, Vec<Arc<(Regex, ErrorProcessor)>>>,
-    failure_only_action: HashMap<String, Vec<Arc<(Regex, ErrorProcessor)>>>,
+    run_always: HashMap<String, Vec<UserDefinedAction>>,
+    failure_only_action: HashMap<String, Vec<UserDefinedAction>>,
 }
 
 impl UserDefinedActionsStateCache {
     pub fn from_config(
         config: &Config,
     ) -> Result<UserDefinedActionsStateCache, Box<dyn std::error::Error>> {
-        let mut failure_only: HashMap<String, Vec<Arc<(Regex, ErrorProcessor)>>> =
-            HashMap::default();
-        let mut run_always: HashMap<String, Vec<Arc<(Regex, ErrorProcessor)>>> = HashMap::default();
+        let mut failure_only: HashMap<String, Vec<UserDefinedAction>> = HashMap::default();
+        let mut run_always: HashMap<String, Vec<UserDefinedAction>> = HashMap::default();
         for ep in config
             .error_processors
             .as_ref()
@@ -31,14 +30,20 @@ impl UserDefinedActionsStateCache {
         {
             let ep = ep.clone();
             let regexp = Regex::new(&ep.regex_match)?;
+            let filter = EventFilter {
+                mnemonics: ep.mnemonics.clone(),
+                tags: ep.tags.clone(),
+                configurations: ep.configurations.clone(),
+                ..Default::default()
+            };
             let r = if ep.run_on_success {
                 &mut run_always
             } else {
                 &mut failure_only
             };
             let entry = r.entry(ep.active_action_type.clone());
-            let vec: &mut Vec<Arc<(Regex, ErrorProcessor)>> = entry.or_default();
-            vec.push(Arc::new((regexp, ep)));
+            let vec: &mut Vec<UserDefinedAction> = entry.or_default();
+            vec.push(Arc::new((regexp, ep, filter)));
         }
         Ok(UserDefinedActionsStateCache {
             run_always,
@@ -57,14 +62,14 @@ struct CommandLineAction {
 
 fn extract_configured_regexes<'a, 'b, 'c>(
     target_label: &'a String,
-    input_error_streams: &'a Vec<String>,
+    input_error_streams: &'a [String],
     command_stream: &'a mut Vec<CommandLineAction>,
-    process_state: &Vec<Arc<(Regex, ErrorProcessor)>>,
+    process_state: &[UserDefinedAction],
 ) {
     for stream in input_error_streams {
         for ln in stream.lines() {
             for e in process_state {
-                let (regex, ep) = e.as_ref();
+                let (regex, ep, _) = e.as_ref();
                 let captures = regex.captures(ln);
                 match captures {
                     None => (),
@@ -125,30 +130,44 @@ async fn apply_candidates<T: CommandLineRunner + Clone + Send + Sync + 'static>(
     super::Response::new(target_stories)
 }
 
+/// The user defined actions whose mnemonic, tag and configuration filters let them run for this action.
+fn matching_actions<'a>(
+    candidates: impl Iterator<Item = &'a UserDefinedAction>,
+    mnemonic: Option<&str>,
+    details: &hydrated_stream::TargetDetails,
+) -> Vec<UserDefinedAction> {
+    candidates
+        .filter(|e| e.2.matches_parts(None, mnemonic, Some(details)))
+        .cloned()
+        .collect()
+}
+
 pub async fn process_action_failed<T: CommandLineRunner + Clone + Send + Sync + 'static>(
     command_line_runner: T,
     action_failed_info: &hydrated_stream::ActionFailedErrorInfo,
+    error_streams: &[String],
     user_defined_action_state: &UserDefinedActionsStateCache,
 ) -> super::Response {
     if let Some(tpe) = &action_failed_info.target_kind {
-        let failure_action_data = user_defined_action_state.failure_only_action.get(tpe);
-        let remaining_action_data = user_defined_action_state.run_always.get(tpe);
-
-        let action_data = failure_action_data
-            .and_then(|e| remaining_action_data.map(|r| e.iter().chain(r.iter())));
-        if let Some(action_data) = action_data {
-            let action_data: Vec<Arc<(Regex, ErrorProcessor)>> = action_data.cloned().collect();
-            if !action_data.is_empty() {
-                let mut candidate_correction_commands: Vec<CommandLineAction> = vec![];
-                let error_streams = text_logs_from_failure(action_failed_info).await;
-                extract_configured_regexes(
-                    &action_failed_info.label,
-                    &error_streams,
-                    &mut candidate_correction_commands,
-                    &action_data,
-                );
-                return apply_candidates(candidate_correction_commands, command_line_runner).await;
-            }
+        let action_data = matching_actions(
+            user_defined_action_state
+                .failure_only_action
+                .get(tpe)
+                .into_iter()
+                .chain(user_defined_action_state.run_always.get(tpe))
+                .flatten(),
+            action_failed_info.mnemonic.as_deref(),
+            &action_failed_info.details,
+        );
+        if !action_data.is_empty() {
+            let mut candidate_correction_commands: Vec<CommandLineAction> = vec![];
+            extract_configured_regexes(
+                &action_failed_info.label,
+                error_streams,
+                &mut candidate_correction_commands,
+                &action_data,
+            );
+            return apply_candidates(candidate_correction_commands, command_line_runner).await;
         }
     }
     super::Response::new(Vec::default())
@@ -156,23 +175,30 @@ pub async fn process_action_failed<T: CommandLineRunner + Clone + Send + Sync +
 
 pub async fn process_action_success<T: CommandLineRunner + Clone + Send + Sync + 'static>(
     command_line_runner: T,
+    file_fetcher: &FileFetcher,
     action_success_info: &hydrated_stream::ActionSuccessInfo,
     user_defined_action_state: &UserDefinedActionsStateCache,
 ) -> super::Response {
     if let Some(tpe) = &action_success_info.target_kind {
-        let action_data = user_defined_action_state.run_always.get(tpe);
-        if let Some(action_data) = action_data {
-            if !action_data.is_empty() {
-                let mut candidate_correction_commands: Vec<CommandLineAction> = vec![];
-                let error_streams = text_logs_from_success(action_success_info).await;
-                extract_configured_regexes(
-                    &action_success_info.label,
-                    &error_streams,
-                    &mut candidate_correction_commands,
-                    action_data,
-                );
-                return apply_candidates(candidate_correction_commands, command_line_runner).await;
-            }
+        let action_data = matching_actions(
+            user_defined_action_state
+                .run_always
+                .get(tpe)
+                .into_iter()
+                .flatten(),
+            action_success_info.mnemonic.as_deref(),
+            &action_success_info.details,
+        );
+        if !action_data.is_empty() {
+            let mut candidate_correction_commands: Vec<CommandLineAction> = vec![];
+            let error_streams = text_logs_from_success(file_fetcher, action_success_info).await;
+            extract_configured_regexes(
+                &action_success_info.label,
+                &error_streams,
+                &mut candidate_correction_commands,
+                &action_data,
+            );
+            return apply_candidates(candidate_correction_commands, command_line_runner).await;
         }
     }
     super::Response::new(Vec::default())
@@ -187,6 +213,8 @@ mod tests {
     fn test_regex_parsing() {
         // This was referring to a random string put into the dependencies list of the target
         let action_failed_error_info = hydrated_stream::ActionFailedErrorInfo {
+            mnemonic: None,
+            details: hydrated_stream::TargetDetails::default(),
             label: String::from("//src/main/com/example/foo:Bar"),
             stderr: None,
             stdout: None,
@@ -209,7 +237,11 @@ buildozer 'remove deps @foo_bar_baz//example/foo/bar:baz' //example/foo/bar/baz:
                 run_on_success: true,
                 regex_match: String::from("not used"),
                 target_command_line: String::from("my commands: {1}"),
+                mnemonics: Vec::default(),
+                tags: Vec::default(),
+                configurations: Vec::default(),
             },
+            EventFilter::default(),
         ))];
         let mut results = vec![];
         extract_configured_regexes(
@@ -267,6 +299,7 @@ buildozer 'remove deps @foo_bar_baz//example/foo/bar:baz' //example/foo/bar/baz:
                 command_line: "a b c".to_string(),
                 execution_result: ExecutionResult {
                     exit_success: true,
+                    exit_code: Some(0),
                     stdout: "".to_string(),
                     stderr: "".to_string()
                 }
@@ -274,4 +307,53 @@ buildozer 'remove deps @foo_bar_baz//example/foo/bar:baz' //example/foo/bar/baz:
         );
         assert_eq!(e.target, "//foo/bar/baz");
     }
+
+    #[tokio::test]
+    async fn test_actions_filtered_by_mnemonic() {
+        let config: Config = toml::from_str(
+            r#"
+        [[error_processors]]
+        name = "scalafix"
+        active_action_type = "scala_library"
+        regex_match = '^lint: (.*)$'
+        target_command_line = "fix {1}"
+        mnemonics = ["Scalafix"]
+
+        [[error_processors]]
+        name = "always"
+        active_action_type = "scala_library"
+        run_on_success = true
+        regex_match = '^lint: (.*)$'
+        target_command_line = "note {1}"
+        "#,
+        )
+        .unwrap();
+        let state = UserDefinedActionsStateCache::from_config(&config).unwrap();
+        let error_streams = vec![String::from("lint: A.scala")];
+
+        for (mnemonic, expected) in [
+            ("Scalafix", vec!["fix A.scala", "note A.scala"]),
+            ("Scalac", vec!["note A.scala"]),
+        ] {
+            let action_failed_info = hydrated_stream::ActionFailedErrorInfo {
+                mnemonic: Some(String::from(mnemonic)),
+                details: hydrated_stream::TargetDetails::default(),
+                label: String::from("//src/main/com/example/foo:Bar"),
+                stderr: None,
+                stdout: None,
+                target_kind: Some(String::from("scala_library")),
+            };
+            let fake = FakeCommandLineRunner::default();
+            process_action_failed(fake.clone(), &action_failed_info, &error_streams, &state).await;
+            assert_eq!(
+                fake.to_vec().await,
+                expected
+                    .into_iter()
+                    .map(|command_line| ExecuteCommandLine {
+                        command_line: command_line.to_string()
+                    })
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
 }
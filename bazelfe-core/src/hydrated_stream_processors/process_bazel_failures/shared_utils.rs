@@ -1,76 +1,41 @@
-use std::path::PathBuf;
-
 use bazelfe_protos::build_event_stream;
 
+use crate::build_events::file_fetcher::{FetchError, FileFetcher};
 use crate::build_events::hydrated_stream::{ActionFailedErrorInfo, ActionSuccessInfo};
 
-pub(in crate::hydrated_stream_processors::process_bazel_failures) fn output_from_paths<'a>(
-    files: impl Iterator<Item = &'a build_event_stream::file::File>,
-) -> Vec<std::path::PathBuf> {
-    files
-        .flat_map(|e| match e {
-            build_event_stream::file::File::Uri(e) => {
-                if e.starts_with("file://") {
-                    let u: PathBuf = e.strip_prefix("file://").unwrap().into();
-                    Some(u)
-                } else {
-                    warn!("Path isn't a file, so skipping...{:?}", e);
-
-                    None
-                }
-            }
-            build_event_stream::file::File::Contents(_) => None,
-        })
-        .collect()
+pub(in crate::hydrated_stream_processors::process_bazel_failures) async fn text_logs_from_files(
+    file_fetcher: &FileFetcher,
+    files: impl Iterator<Item = &build_event_stream::file::File>,
+) -> Vec<String> {
+    let mut error_data = Vec::default();
+    for file in files {
+        match file_fetcher.fetch_string(file).await {
+            Ok(content) => error_data.push(content),
+            Err(e @ FetchError::TooLarge(..)) => debug!("Skipping log, {}", e),
+            Err(e) => warn!("Unable to load action output, so skipping...{}", e),
+        }
+    }
+    error_data
 }
 
-pub(in crate::hydrated_stream_processors::process_bazel_failures) fn output_success_paths(
-    data: &ActionSuccessInfo,
-) -> Vec<std::path::PathBuf> {
-    output_from_paths(
-        vec![&data.stdout, &data.stderr]
+pub(in crate::hydrated_stream_processors::process_bazel_failures) async fn text_logs_from_success(
+    file_fetcher: &FileFetcher,
+    action_success_info: &ActionSuccessInfo,
+) -> Vec<String> {
+    text_logs_from_files(
+        file_fetcher,
+        [&action_success_info.stdout, &action_success_info.stderr]
             .iter()
             .filter_map(|&e| e.as_ref())
             .filter_map(|e| e.file.as_ref()),
     )
-}
-
-pub(in crate::hydrated_stream_processors::process_bazel_failures) fn output_error_paths(
-    err_data: &ActionFailedErrorInfo,
-) -> Vec<std::path::PathBuf> {
-    output_from_paths(err_data.files().iter().filter_map(|e| e.file.as_ref()))
-}
-
-pub(in crate::hydrated_stream_processors::process_bazel_failures) async fn text_logs_from_success(
-    action_success_info: &ActionSuccessInfo,
-) -> Vec<String> {
-    let mut error_data = Vec::default();
-    for path_str in output_success_paths(action_success_info).into_iter() {
-        let path: PathBuf = path_str;
-        if path.exists() {
-            let file_len = std::fs::metadata(&path).unwrap().len();
-            if file_len < 10 * 1024 * 1024 {
-                // 10 MB
-                error_data.push(tokio::fs::read_to_string(&path).await.unwrap());
-            }
-        }
-    }
-    error_data
+    .await
 }
 
 pub(in crate::hydrated_stream_processors::process_bazel_failures) async fn text_logs_from_failure(
+    file_fetcher: &FileFetcher,
     action_failed_error_info: &ActionFailedErrorInfo,
 ) -> Vec<String> {
-    let mut error_data = Vec::default();
-    for path_str in output_error_paths(action_failed_error_info).into_iter() {
-        let path: PathBuf = path_str;
-        if path.exists() {
-            let file_len = std::fs::metadata(&path).unwrap().len();
-            if file_len < 10 * 1024 * 1024 {
-                // 10 MB
-                error_data.push(tokio::fs::read_to_string(&path).await.unwrap());
-            }
-        }
-    }
-    error_data
+    let files = action_failed_error_info.files();
+    text_logs_from_files(file_fetcher, files.iter().filter_map(|e| e.file.as_ref())).await
 }
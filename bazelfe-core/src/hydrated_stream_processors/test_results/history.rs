@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::TestOutcome;
+use crate::config::DaemonConfig;
+use crate::jsonl_file::BoundedJsonLines;
+
+const HISTORY_FILE_NAME: &str = "test_history.jsonl";
+
+/// Outcomes we keep across all tests, older ones are dropped as new ones are recorded.
+const MAX_HISTORY_ENTRIES: usize = 50_000;
+
+/// How many of a test's most recent outcomes we look at when deciding if it flip-flops.
+const HISTORY_WINDOW: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TestHistoryEntry {
+    /// Milliseconds since the unix epoch.
+    pub timestamp_ms: u64,
+    pub invocation_id: String,
+    pub label: String,
+    pub outcome: TestOutcome,
+}
+
+/// Record of the final outcome of the tests we have seen run recently, stored as json lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestHistory {
+    file: BoundedJsonLines,
+}
+
+impl TestHistory {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            file: BoundedJsonLines::new(path, MAX_HISTORY_ENTRIES),
+        }
+    }
+
+    pub fn from_daemon_config(daemon_config: &DaemonConfig) -> Self {
+        Self::new(
+            daemon_config
+                .daemon_communication_folder
+                .join(HISTORY_FILE_NAME),
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    pub fn append(&self, entries: &[TestHistoryEntry]) -> Result<(), Box<dyn std::error::Error>> {
+        self.file.append(entries)
+    }
+
+    /// Read the most recent entries in the history, lines which fail to parse are skipped.
+    pub fn read_recent(&self) -> Result<Vec<TestHistoryEntry>, Box<dyn std::error::Error>> {
+        self.file.read_recent()
+    }
+}
+
+/// How many times each test flipped between passing and failing over its recent history,
+/// a flaky result from bazel counts as a flip on its own. Tests which never flipped are left out.
+pub fn flip_flops(entries: &[TestHistoryEntry]) -> HashMap<String, u32> {
+    let mut by_label: HashMap<&str, Vec<&TestHistoryEntry>> = HashMap::default();
+    for entry in entries.iter() {
+        by_label.entry(&entry.label).or_default().push(entry);
+    }
+
+    let mut result = HashMap::default();
+    for (label, mut history) in by_label.into_iter() {
+        history.sort_by_key(|e| e.timestamp_ms);
+        let recent = &history[history.len().saturating_sub(HISTORY_WINDOW)..];
+
+        let mut flips = 0;
+        let mut previous_passed: Option<bool> = None;
+        for entry in recent.iter() {
+            let passed = match entry.outcome {
+                TestOutcome::Passed => true,
+                TestOutcome::Failed | TestOutcome::Timeout => false,
+                TestOutcome::Flaky => {
+                    flips += 1;
+                    continue;
+                }
+                TestOutcome::NotRun => continue,
+            };
+            if previous_passed.map(|p| p != passed).unwrap_or(false) {
+                flips += 1;
+            }
+            previous_passed = Some(passed);
+        }
+        if flips > 0 {
+            result.insert(label.to_string(), flips);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp_ms: u64, label: &str, outcome: TestOutcome) -> TestHistoryEntry {
+        TestHistoryEntry {
+            timestamp_ms,
+            invocation_id: format!("inv-{}", timestamp_ms),
+            label: label.to_string(),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_and_flip_flops() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = TestHistory::new(dir.path().join("nested/test_history.jsonl"));
+        assert!(history.read_recent().unwrap().is_empty());
+
+        history
+            .append(&[
+                entry(1, "//a:test", TestOutcome::Passed),
+                entry(1, "//b:test", TestOutcome::Failed),
+                entry(2, "//a:test", TestOutcome::Failed),
+            ])
+            .unwrap();
+        history
+            .append(&[
+                entry(3, "//a:test", TestOutcome::NotRun),
+                entry(3, "//b:test", TestOutcome::Failed),
+                entry(4, "//a:test", TestOutcome::Passed),
+                entry(4, "//c:test", TestOutcome::Flaky),
+            ])
+            .unwrap();
+
+        let entries = history.read_recent().unwrap();
+        assert_eq!(entries.len(), 7);
+
+        let flips = flip_flops(&entries);
+        assert_eq!(flips.get("//a:test"), Some(&2));
+        // Consistently failing isn't flip-flopping.
+        assert_eq!(flips.get("//b:test"), None);
+        assert_eq!(flips.get("//c:test"), Some(&1));
+    }
+}
@@ -0,0 +1,201 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Lines of a test.log we show for a failing test, enough to see the assertion and where it came from.
+const MAX_EXCERPT_LINES: usize = 12;
+
+/// Logs can be huge, the interesting part is almost always near the start of the failure output.
+pub const MAX_LOG_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Stack frames from the test framework and reflection, which never help explain a failure.
+fn is_framework_frame(line: &str) -> bool {
+    let frame = line.trim_start().trim_start_matches("at ");
+    [
+        "org.junit.",
+        "junit.framework.",
+        "sun.reflect.",
+        "java.lang.reflect.",
+        "jdk.internal.",
+        "org.scalatest.",
+    ]
+    .iter()
+    .any(|prefix| frame.starts_with(prefix))
+}
+
+fn is_stack_frame(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("at ") || trimmed.starts_with("... ") || trimmed.starts_with("Caused by:")
+}
+
+/// Bazel prefixes each test.log with how it ran the test, which isn't part of the failure.
+fn is_bazel_header(line: &str) -> bool {
+    line.starts_with("exec ${PAGER:-/usr/bin/less}")
+        || line.starts_with("Executing tests from ")
+        || (!line.is_empty() && line.chars().all(|c| c == '-'))
+}
+
+fn finish(lines: Vec<&str>) -> Option<String> {
+    let lines: Vec<&str> = lines
+        .into_iter()
+        .map(|ln| ln.trim_end())
+        .filter(|ln| !ln.is_empty())
+        .take(MAX_EXCERPT_LINES)
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// JUnit's text runner lists failures as `1) name(class)` followed by the exception and its stack.
+fn junit_excerpt(lines: &[&str]) -> Option<String> {
+    lazy_static! {
+        static ref JUNIT_FAILURE_RE: Regex = Regex::new(r"^\d+\) \S+\(\S+\)").unwrap();
+    }
+    let start = lines.iter().position(|ln| JUNIT_FAILURE_RE.is_match(ln))?;
+    let mut excerpt = vec![lines[start]];
+    for line in lines[start + 1..].iter() {
+        if line.trim().is_empty()
+            || JUNIT_FAILURE_RE.is_match(line)
+            || line.starts_with("FAILURES!!!")
+        {
+            break;
+        }
+        if !is_framework_frame(line) {
+            excerpt.push(line);
+        }
+    }
+    finish(excerpt)
+}
+
+/// ScalaTest marks failures inline with `*** FAILED ***`, the detail follows indented underneath.
+fn scalatest_excerpt(lines: &[&str]) -> Option<String> {
+    let mut excerpt = Vec::default();
+    let mut in_failure = false;
+    for line in lines.iter() {
+        let is_failure = line.contains("*** FAILED ***") || line.contains("*** ABORTED ***");
+        if is_failure {
+            in_failure = true;
+            excerpt.push(*line);
+        } else if in_failure && (line.starts_with("  ") || line.starts_with('\t')) {
+            if !is_framework_frame(line) {
+                excerpt.push(*line);
+            }
+        } else {
+            in_failure = false;
+        }
+        if excerpt.len() >= MAX_EXCERPT_LINES {
+            break;
+        }
+    }
+    finish(excerpt)
+}
+
+/// Uncaught exceptions, python tracebacks and rust panics.
+fn exception_excerpt(lines: &[&str]) -> Option<String> {
+    lazy_static! {
+        static ref EXCEPTION_RE: Regex = Regex::new(
+            r"^(Exception in thread|Traceback \(most recent call last\)|thread '.*' panicked at|[\w$.]+(Exception|Error)(: |$))"
+        )
+        .unwrap();
+    }
+    let start = lines.iter().position(|ln| EXCEPTION_RE.is_match(ln))?;
+    let mut excerpt = vec![lines[start]];
+    for line in lines[start + 1..].iter() {
+        if line.trim().is_empty() {
+            break;
+        }
+        if !(is_stack_frame(line) && is_framework_frame(line)) {
+            excerpt.push(line);
+        }
+    }
+    finish(excerpt)
+}
+
+fn error_line_excerpt(lines: &[&str]) -> Option<String> {
+    lazy_static! {
+        static ref ERROR_RE: Regex = Regex::new(r"(?i)\b(error|fail(ed|ure)?)\b").unwrap();
+    }
+    lines
+        .iter()
+        .find(|ln| ERROR_RE.is_match(ln))
+        .and_then(|ln| finish(vec![ln]))
+}
+
+/// Find the part of a test.log that explains why the test failed: the assertion, the top of the
+/// stack trace or failing that the first line mentioning an error.
+pub fn extract_excerpt(log: &str) -> Option<String> {
+    let lines: Vec<&str> = log.lines().filter(|ln| !is_bazel_header(ln)).collect();
+    junit_excerpt(&lines)
+        .or_else(|| scalatest_excerpt(&lines))
+        .or_else(|| exception_excerpt(&lines))
+        .or_else(|| error_line_excerpt(&lines))
+}
+
+/// Reads up to MAX_LOG_BYTES of the log at path and extracts its excerpt.
+pub async fn excerpt_from_file(path: &std::path::Path) -> Option<String> {
+    use tokio::io::AsyncReadExt;
+    let file = match tokio::fs::File::open(path).await {
+        Ok(f) => f,
+        Err(e) => {
+            debug!("Unable to read {}: {}", path.to_string_lossy(), e);
+            return None;
+        }
+    };
+    let mut buffer = Vec::default();
+    if let Err(e) = file.take(MAX_LOG_BYTES).read_to_end(&mut buffer).await {
+        debug!("Unable to read {}: {}", path.to_string_lossy(), e);
+        return None;
+    }
+    extract_excerpt(&String::from_utf8_lossy(&buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "exec ${PAGER:-/usr/bin/less} \"$0\" || exit 1\nExecuting tests from //a:test\n-----------------------------------------------------------------------------\n";
+
+    #[test]
+    fn test_junit_excerpt() {
+        let log = format!(
+            "{}JUnit4 Test Runner\n.E\nTime: 0.012\nThere was 1 failure:\n1) compares(com.example.FooTest)\njava.lang.AssertionError: expected:<1> but was:<2>\n\tat org.junit.Assert.fail(Assert.java:89)\n\tat com.example.FooTest.compares(FooTest.java:12)\n\tat sun.reflect.NativeMethodAccessorImpl.invoke0(Native Method)\n\nFAILURES!!!\nTests run: 2,  Failures: 1\n",
+            HEADER
+        );
+        assert_eq!(
+            extract_excerpt(&log).unwrap(),
+            "1) compares(com.example.FooTest)\njava.lang.AssertionError: expected:<1> but was:<2>\n\tat com.example.FooTest.compares(FooTest.java:12)"
+        );
+    }
+
+    #[test]
+    fn test_scalatest_excerpt() {
+        let log = format!(
+            "{}Discovery starting.\nFooSpec:\n- adds\n- compares *** FAILED ***\n  2 did not equal 1 (FooSpec.scala:14)\n- other\nRun completed in 120 milliseconds.\n*** 1 TEST FAILED ***\n",
+            HEADER
+        );
+        assert_eq!(
+            extract_excerpt(&log).unwrap(),
+            "- compares *** FAILED ***\n  2 did not equal 1 (FooSpec.scala:14)"
+        );
+    }
+
+    #[test]
+    fn test_exception_and_fallback_excerpts() {
+        let log = "starting\nthread 'main' panicked at 'assertion failed: x == 2', src/main.rs:4:5\nnote: run with `RUST_BACKTRACE=1`\n\ndone\n";
+        assert_eq!(
+            extract_excerpt(log).unwrap(),
+            "thread 'main' panicked at 'assertion failed: x == 2', src/main.rs:4:5\nnote: run with `RUST_BACKTRACE=1`"
+        );
+
+        let log = "Exception in thread \"main\" java.lang.IllegalStateException: nope\n\tat com.example.Main.main(Main.java:3)\n";
+        assert_eq!(extract_excerpt(log).unwrap(), log.trim_end());
+
+        assert_eq!(
+            extract_excerpt("running\nsetup ok\ncheck FAILED: 3 != 4\n").unwrap(),
+            "check FAILED: 3 != 4"
+        );
+        assert_eq!(extract_excerpt(&format!("{}all good\n", HEADER)), None);
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use bazelfe_protos::build_event_stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::build_events::build_event_server::bazel_event::TestStatus;
+use crate::build_events::file_fetcher::{self, FileFetcher};
+use crate::build_events::hydrated_stream::{self, TestResultInfo};
+
+pub mod history;
+pub mod log_excerpt;
+pub mod test_xml;
+
+use test_xml::FailedTestCase;
+
+/// Failing testcases kept per test target, enough to point at the problem without flooding the report.
+const MAX_FAILED_TESTCASES: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    /// Failed, then passed when bazel retried it.
+    Flaky,
+    Timeout,
+    /// Didn't get to run, e.g. it failed to build or bazel was interrupted.
+    NotRun,
+}
+
+impl TestOutcome {
+    fn from_status(test_status: &TestStatus) -> Self {
+        match test_status {
+            TestStatus::Passed => TestOutcome::Passed,
+            TestStatus::Flaky => TestOutcome::Flaky,
+            TestStatus::Timeout => TestOutcome::Timeout,
+            TestStatus::Failed | TestStatus::RemoteFailure => TestOutcome::Failed,
+            TestStatus::Incomplete
+            | TestStatus::FailedToBuild
+            | TestStatus::ToolHaltedBeforeTesting => TestOutcome::NotRun,
+        }
+    }
+
+    /// When combining shards, the worst outcome wins.
+    fn severity(&self) -> u8 {
+        match self {
+            TestOutcome::Passed => 0,
+            TestOutcome::Flaky => 1,
+            TestOutcome::NotRun => 2,
+            TestOutcome::Timeout => 3,
+            TestOutcome::Failed => 4,
+        }
+    }
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TestOutcome::Passed => "PASSED",
+            TestOutcome::Failed => "FAILED",
+            TestOutcome::Flaky => "FLAKY",
+            TestOutcome::Timeout => "TIMEOUT",
+            TestOutcome::NotRun => "NO STATUS",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TestTargetResult {
+    pub label: String,
+    pub outcome: TestOutcome,
+    /// Every attempt bazel reported came from a cache.
+    pub cached: bool,
+    /// How many of our bazel attempts ran this test.
+    pub attempts: u16,
+    pub failed_testcases: Vec<FailedTestCase>,
+    /// Times this test flipped between passing and failing in its recent history.
+    #[serde(default)]
+    pub flip_flops: u32,
+    /// The interesting part of the first failing attempt's test.log.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_excerpt: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TestResultsSummary {
+    pub passed: u32,
+    pub failed: u32,
+    pub flaky: u32,
+    pub timeout: u32,
+    pub not_run: u32,
+    pub cached: u32,
+    /// Every test which didn't cleanly pass, or has been flip-flopping.
+    pub tests: Vec<TestTargetResult>,
+}
+
+impl TestResultsSummary {
+    /// Something a developer should look at, not just a count of passing tests.
+    pub fn needs_attention(&self) -> bool {
+        !self.tests.is_empty()
+    }
+}
+
+impl fmt::Display for TestResultsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Tests: {} passed, {} failed, {} flaky, {} timed out, {} not run, {} cached",
+            self.passed, self.failed, self.flaky, self.timeout, self.not_run, self.cached
+        )?;
+        for test in self.tests.iter() {
+            write!(f, "{} {}", test.outcome, test.label)?;
+            if test.flip_flops > 0 {
+                write!(f, " (flip-flopped {} times recently)", test.flip_flops)?;
+            }
+            writeln!(f)?;
+            for testcase in test.failed_testcases.iter() {
+                writeln!(
+                    f,
+                    "\t{}: {}",
+                    testcase.qualified_name(),
+                    testcase.first_line
+                )?;
+            }
+            if let Some(excerpt) = test.log_excerpt.as_ref() {
+                for line in excerpt.lines() {
+                    writeln!(f, "\t| {}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct TargetState {
+    bazel_run_id: usize,
+    attempts: u16,
+    /// Keyed by (run, shard), the status of each attempt bazel made at it.
+    shards: HashMap<(i32, i32), Vec<(i32, TestOutcome)>>,
+    all_cached: bool,
+    failed_testcases: Vec<FailedTestCase>,
+    log_excerpt: Option<String>,
+}
+
+impl TargetState {
+    fn outcome(&self) -> TestOutcome {
+        self.shards
+            .values()
+            .map(|attempts| {
+                let mut attempts = attempts.clone();
+                attempts.sort_by_key(|(attempt, _)| *attempt);
+                let last = attempts
+                    .last()
+                    .map(|(_, outcome)| *outcome)
+                    .unwrap_or(TestOutcome::NotRun);
+                if last == TestOutcome::Passed
+                    && attempts.iter().any(|(_, o)| *o != TestOutcome::Passed)
+                {
+                    TestOutcome::Flaky
+                } else {
+                    last
+                }
+            })
+            .max_by_key(|o| o.severity())
+            .unwrap_or(TestOutcome::NotRun)
+    }
+}
+
+/// Aggregates test results over every bazel attempt we make, the latest attempt to run a test decides its outcome.
+#[derive(Clone, Debug)]
+pub struct TestResultTracker {
+    targets: Arc<Mutex<HashMap<String, TargetState>>>,
+    file_fetcher: FileFetcher,
+}
+
+#[async_trait::async_trait]
+impl super::BazelEventHandler for TestResultTracker {
+    async fn process_event(
+        &self,
+        bazel_run_id: usize,
+        event: &hydrated_stream::HydratedInfo,
+    ) -> Vec<super::BuildEventResponse> {
+        if let hydrated_stream::HydratedInfo::TestResult(tst) = event {
+            self.process(bazel_run_id, tst).await;
+        }
+        Vec::default()
+    }
+}
+
+impl TestResultTracker {
+    pub fn new(file_fetcher: FileFetcher) -> Self {
+        Self {
+            targets: Arc::default(),
+            file_fetcher,
+        }
+    }
+
+    async fn read_log_excerpt(&self, test_log: &build_event_stream::file::File) -> Option<String> {
+        if let build_event_stream::file::File::Uri(uri) = test_log {
+            if let Some(path) = file_fetcher::local_path(uri) {
+                // Only the start of a local log is read, however large the test made it.
+                return log_excerpt::excerpt_from_file(&path).await;
+            }
+        }
+        match self.file_fetcher.fetch_string(test_log).await {
+            Ok(log) => log_excerpt::extract_excerpt(&log),
+            Err(e) => {
+                debug!("Unable to fetch the test log: {}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn process(&self, bazel_run_id: usize, tst_info: &TestResultInfo) {
+        let tst = &tst_info.test_summary_event;
+        let outcome = TestOutcome::from_status(&tst.test_status);
+        let mut failed_testcases = Vec::default();
+        let mut log_excerpt = None;
+        if outcome != TestOutcome::Passed {
+            if let Some(file) = tst_info.test_xml.as_ref() {
+                match self.file_fetcher.fetch_string(file).await {
+                    Ok(xml) => failed_testcases.extend(test_xml::parse_failed_testcases(&xml)),
+                    Err(e) => debug!("Unable to fetch the test xml: {}", e),
+                }
+            }
+            if let Some(file) = tst_info.test_log.as_ref() {
+                log_excerpt = self.read_log_excerpt(file).await;
+            }
+        }
+
+        let mut targets = self.targets.lock().await;
+        let state = targets.entry(tst.label.clone()).or_default();
+        if state.attempts > 0 && bazel_run_id < state.bazel_run_id {
+            return;
+        }
+        if state.attempts == 0 || bazel_run_id > state.bazel_run_id {
+            *state = TargetState {
+                bazel_run_id,
+                attempts: state.attempts + 1,
+                all_cached: true,
+                ..Default::default()
+            };
+        }
+        state
+            .shards
+            .entry((tst.run, tst.shard))
+            .or_default()
+            .push((tst.attempt, outcome));
+        state.all_cached &= tst.cached;
+        if state.log_excerpt.is_none() {
+            state.log_excerpt = log_excerpt;
+        }
+        for testcase in failed_testcases.into_iter() {
+            if state.failed_testcases.len() >= MAX_FAILED_TESTCASES {
+                break;
+            }
+            if !state.failed_testcases.contains(&testcase) {
+                state.failed_testcases.push(testcase);
+            }
+        }
+    }
+
+    /// Forget every test seen so far, so a long running watch only reports on its latest build.
+    pub async fn clear(&self) {
+        self.targets.lock().await.clear();
+    }
+
+    /// The outcome of every test run so far, for the test history.
+    pub async fn outcomes(&self) -> Vec<(String, TestOutcome)> {
+        let targets = self.targets.lock().await;
+        let mut outcomes: Vec<(String, TestOutcome)> = targets
+            .iter()
+            .map(|(label, state)| (label.clone(), state.outcome()))
+            .collect();
+        outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+        outcomes
+    }
+
+    /// None if no tests have run.
+    pub async fn summary(&self) -> Option<TestResultsSummary> {
+        self.summary_with_flip_flops(&HashMap::default()).await
+    }
+
+    /// As summary, also calling out tests which have been flip-flopping even if they passed this time.
+    pub async fn summary_with_flip_flops(
+        &self,
+        flip_flops: &HashMap<String, u32>,
+    ) -> Option<TestResultsSummary> {
+        let targets = self.targets.lock().await;
+        if targets.is_empty() {
+            return None;
+        }
+        let mut summary = TestResultsSummary::default();
+        for (label, state) in targets.iter() {
+            let outcome = state.outcome();
+            match outcome {
+                TestOutcome::Passed => summary.passed += 1,
+                TestOutcome::Failed => summary.failed += 1,
+                TestOutcome::Flaky => summary.flaky += 1,
+                TestOutcome::Timeout => summary.timeout += 1,
+                TestOutcome::NotRun => summary.not_run += 1,
+            }
+            if state.all_cached {
+                summary.cached += 1;
+            }
+            let flips = flip_flops.get(label).copied().unwrap_or_default();
+            if outcome != TestOutcome::Passed || flips > 0 {
+                summary.tests.push(TestTargetResult {
+                    label: label.clone(),
+                    outcome,
+                    cached: state.all_cached,
+                    attempts: state.attempts,
+                    // A flaky test eventually passed, what failed first is still worth showing.
+                    failed_testcases: state.failed_testcases.clone(),
+                    flip_flops: flips,
+                    log_excerpt: state.log_excerpt.clone(),
+                });
+            }
+        }
+        summary.tests.sort_by(|a, b| {
+            (b.outcome.severity(), &a.label).cmp(&(a.outcome.severity(), &b.label))
+        });
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_events::build_event_server::bazel_event::TestResultEvt;
+    use crate::config::RemoteCache;
+
+    fn result(label: &str, status: TestStatus, shard: i32, attempt: i32) -> TestResultInfo {
+        TestResultInfo::new(
+            TestResultEvt {
+                configuration: None,
+                label: label.to_string(),
+                test_status: status,
+                failed_files: Vec::default(),
+                cached: false,
+                run: 1,
+                shard,
+                attempt,
+            },
+            None,
+            hydrated_stream::TargetDetails::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_aggregates_attempts_and_shards() {
+        let tracker = TestResultTracker::new(FileFetcher::new(&RemoteCache::default()).unwrap());
+        assert_eq!(tracker.summary().await, None);
+
+        let dir = tempfile::tempdir().unwrap();
+        // Bazel percent encodes the space in the uris.
+        let outputs = dir.path().join("my test");
+        std::fs::create_dir(&outputs).unwrap();
+        let xml_path = outputs.join("test.xml");
+        std::fs::write(
+            &xml_path,
+            r#"<testsuite><testcase classname="a.ATest" name="works"><failure message="boom"/></testcase></testsuite>"#,
+        )
+        .unwrap();
+        let log_path = outputs.join("test.log");
+        std::fs::write(
+            &log_path,
+            "Executing tests from //a:test\nsetting up\nassertion FAILED: boom\n",
+        )
+        .unwrap();
+        let mut failing = result("//a:test", TestStatus::Failed, 0, 1);
+        failing.test_xml = Some(build_event_stream::file::File::Uri(format!(
+            "file://{}",
+            xml_path.to_string_lossy().replace(' ', "%20")
+        )));
+        failing.test_log = Some(build_event_stream::file::File::Uri(format!(
+            "file://{}",
+            log_path.to_string_lossy().replace(' ', "%20")
+        )));
+
+        // Our first bazel attempt, //a:test fails and //b:test needs a retry from bazel.
+        tracker.process(0, &failing).await;
+        tracker
+            .process(0, &result("//b:test", TestStatus::Passed, 0, 2))
+            .await;
+        tracker
+            .process(0, &result("//b:test", TestStatus::Failed, 0, 1))
+            .await;
+        // A sharded test with one failing shard failed.
+        tracker
+            .process(0, &result("//c:test", TestStatus::Passed, 0, 1))
+            .await;
+        tracker
+            .process(0, &result("//c:test", TestStatus::Timeout, 1, 1))
+            .await;
+        let mut cached = result("//d:test", TestStatus::Passed, 0, 1);
+        cached.test_summary_event.cached = true;
+        tracker.process(0, &cached).await;
+
+        let summary = tracker.summary().await.unwrap();
+        assert_eq!(
+            (
+                summary.passed,
+                summary.failed,
+                summary.flaky,
+                summary.timeout,
+                summary.cached
+            ),
+            (1, 1, 1, 1, 1)
+        );
+        assert_eq!(
+            summary
+                .tests
+                .iter()
+                .map(|t| (t.label.as_str(), t.outcome))
+                .collect::<Vec<_>>(),
+            vec![
+                ("//a:test", TestOutcome::Failed),
+                ("//c:test", TestOutcome::Timeout),
+                ("//b:test", TestOutcome::Flaky),
+            ]
+        );
+        assert_eq!(
+            summary.tests[0].failed_testcases,
+            vec![FailedTestCase {
+                class_name: String::from("a.ATest"),
+                name: String::from("works"),
+                first_line: String::from("boom"),
+            }]
+        );
+        assert!(summary
+            .to_string()
+            .contains("FAILED //a:test\n\ta.ATest.works: boom\n\t| assertion FAILED: boom\n"));
+
+        // Once we've fixed things up, the next bazel attempt decides the outcome.
+        tracker
+            .process(1, &result("//a:test", TestStatus::Passed, 0, 1))
+            .await;
+        let summary = tracker.summary().await.unwrap();
+        assert_eq!(summary.passed, 2);
+        assert_eq!(summary.failed, 0);
+        assert!(summary.tests.iter().all(|t| t.label != "//a:test"));
+
+        let mut flips = HashMap::default();
+        flips.insert(String::from("//a:test"), 2);
+        let summary = tracker.summary_with_flip_flops(&flips).await.unwrap();
+        assert!(summary
+            .to_string()
+            .contains("PASSED //a:test (flip-flopped 2 times recently)\n"));
+        assert_eq!(
+            tracker.outcomes().await[0],
+            (String::from("//a:test"), TestOutcome::Passed)
+        );
+
+        tracker.clear().await;
+        assert_eq!(tracker.summary().await, None);
+        assert!(tracker.outcomes().await.is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FailedTestCase {
+    pub class_name: String,
+    pub name: String,
+    /// The failure message, or failing that the first line of the failure body.
+    pub first_line: String,
+}
+
+impl FailedTestCase {
+    pub fn qualified_name(&self) -> String {
+        if self.class_name.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}.{}", self.class_name, self.name)
+        }
+    }
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#10;", "\n")
+        .replace("&amp;", "&")
+}
+
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    lazy_static! {
+        static ref ATTRIBUTE_RE: Regex = Regex::new(r#"([A-Za-z_:-]+)\s*=\s*"([^"]*)""#).unwrap();
+    }
+    ATTRIBUTE_RE
+        .captures_iter(attributes)
+        .find(|c| &c[1] == name)
+        .map(|c| unescape(&c[2]))
+}
+
+fn first_non_empty_line(s: &str) -> Option<String> {
+    let body = s
+        .trim()
+        .trim_start_matches("<![CDATA[")
+        .trim_end_matches("]]>");
+    unescape(body)
+        .lines()
+        .map(|ln| ln.trim())
+        .find(|ln| !ln.is_empty())
+        .map(|ln| ln.to_string())
+}
+
+/// Pulls the failing testcases out of a JUnit style test.xml, as written by the java, scala and
+/// most other bazel test runners. This is deliberately forgiving, a malformed file just yields fewer cases.
+pub fn parse_failed_testcases(xml: &str) -> Vec<FailedTestCase> {
+    lazy_static! {
+        static ref TESTCASE_RE: Regex =
+            Regex::new(r"(?s)<testcase\b([^>]*?)(?:/>|>(.*?)</testcase>)").unwrap();
+        static ref FAILURE_RE: Regex =
+            Regex::new(r"(?s)<(failure|error)\b([^>]*?)(?:/>|>(.*?)</(?:failure|error)>)").unwrap();
+    }
+    let mut failed = Vec::default();
+    for testcase in TESTCASE_RE.captures_iter(xml) {
+        let body = match testcase.get(2) {
+            Some(body) => body.as_str(),
+            None => continue,
+        };
+        let failure = match FAILURE_RE.captures(body) {
+            Some(failure) => failure,
+            None => continue,
+        };
+        let first_line = attribute(&failure[2], "message")
+            .and_then(|m| first_non_empty_line(&m))
+            .or_else(|| {
+                failure
+                    .get(3)
+                    .and_then(|b| first_non_empty_line(b.as_str()))
+            })
+            .unwrap_or_else(|| failure[1].to_string());
+        failed.push(FailedTestCase {
+            class_name: attribute(&testcase[1], "classname").unwrap_or_default(),
+            name: attribute(&testcase[1], "name").unwrap_or_default(),
+            first_line,
+        });
+    }
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_junit_xml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites>
+  <testsuite name="com.example.FooTest" tests="3" failures="1" errors="1">
+    <testcase name="passes" classname="com.example.FooTest" time="0.01"/>
+    <testcase name="compares" classname="com.example.FooTest" time="0.02">
+      <failure message="expected:&lt;1&gt; but was:&lt;2&gt;" type="java.lang.AssertionError">java.lang.AssertionError: expected:&lt;1&gt; but was:&lt;2&gt;
+	at com.example.FooTest.compares(FooTest.java:12)</failure>
+    </testcase>
+    <testcase name="blows up" classname="com.example.FooTest" time="0.00">
+      <error type="java.lang.NullPointerException"><![CDATA[
+java.lang.NullPointerException
+	at com.example.Foo.bar(Foo.java:3)]]></error>
+    </testcase>
+    <testcase name="skipped" classname="com.example.FooTest"><skipped/></testcase>
+  </testsuite>
+</testsuites>"#;
+
+        assert_eq!(
+            parse_failed_testcases(xml),
+            vec![
+                FailedTestCase {
+                    class_name: String::from("com.example.FooTest"),
+                    name: String::from("compares"),
+                    first_line: String::from("expected:<1> but was:<2>"),
+                },
+                FailedTestCase {
+                    class_name: String::from("com.example.FooTest"),
+                    name: String::from("blows up"),
+                    first_line: String::from("java.lang.NullPointerException"),
+                },
+            ]
+        );
+        assert!(parse_failed_testcases("not xml at all").is_empty());
+    }
+}
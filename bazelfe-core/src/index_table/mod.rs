@@ -425,6 +425,22 @@ impl<'a> IndexTable {
             0
         }
     }
+
+    /// Proto sources are keyed by the path they would be imported by, e.g. `google/protobuf/any.proto`.
+    pub async fn index_proto_srcs(&self, target_name: String, import_paths: Vec<String>) -> u32 {
+        let key_id = self.maybe_insert_target_string(target_name).await;
+        let key_id = self.maybe_update_id(key_id).await;
+        let popularity = self.get_popularity(key_id).await;
+
+        let mut proto_srcs_indexed = 0;
+        for import_path in import_paths.into_iter() {
+            if self.insert_with_id(import_path, key_id, popularity).await {
+                proto_srcs_indexed += 1;
+            }
+        }
+        proto_srcs_indexed
+    }
+
     async fn maybe_insert_target_string(&self, str: String) -> usize {
         self.maybe_insert_target_bytes(str.as_bytes().to_vec())
             .await
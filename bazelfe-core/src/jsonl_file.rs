@@ -0,0 +1,132 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A file of json lines we append to which only keeps its most recent entries. Once it has grown a quarter past
+/// the limit it is rewritten with just the newest, so the rewrite happens rarely rather than on every append.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundedJsonLines {
+    path: PathBuf,
+    max_entries: usize,
+}
+
+fn lines(content: &str) -> impl DoubleEndedIterator<Item = &str> {
+    content.lines().filter(|ln| !ln.trim().is_empty())
+}
+
+impl BoundedJsonLines {
+    pub fn new(path: PathBuf, max_entries: usize) -> Self {
+        Self { path, max_entries }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append<T: Serialize>(&self, entries: &[T]) -> Result<(), Box<dyn std::error::Error>> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Build the whole batch up front so concurrent runners don't interleave partial lines.
+        let mut buffer = Vec::default();
+        for entry in entries.iter() {
+            serde_json::to_writer(&mut buffer, entry)?;
+            buffer.push(b'\n');
+        }
+
+        let existing = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let existing_entries = lines(&existing).count();
+        if existing_entries + entries.len() <= self.max_entries + self.max_entries / 4 {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            file.write_all(&buffer)?;
+            return Ok(());
+        }
+
+        // Lines another runner appends while we rewrite are lost, which is fine for a record of recent history.
+        let keep = self.max_entries.saturating_sub(entries.len());
+        let mut content = Vec::default();
+        for ln in lines(&existing).skip(existing_entries.saturating_sub(keep)) {
+            content.extend_from_slice(ln.as_bytes());
+            content.push(b'\n');
+        }
+        content.extend_from_slice(&buffer);
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, &content)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Read the most recent entries, lines which fail to parse are skipped.
+    pub fn read_recent<T: DeserializeOwned>(&self) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+        if !self.path.exists() {
+            return Ok(Vec::default());
+        }
+        let content = std::fs::read_to_string(&self.path)?;
+        let mut recent: Vec<&str> = lines(&content).rev().take(self.max_entries).collect();
+        recent.reverse();
+        Ok(recent
+            .into_iter()
+            .filter_map(|ln| match serde_json::from_str(ln) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    debug!(
+                        "Skipping unparsable line in {}: {:?}",
+                        self.path.to_string_lossy(),
+                        e
+                    );
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_only_recent_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = BoundedJsonLines::new(dir.path().join("nested/entries.jsonl"), 4);
+        assert!(file.read_recent::<u32>().unwrap().is_empty());
+
+        file.append(&[1, 2, 3]).unwrap();
+        file.append(&[4, 5]).unwrap();
+        // Within the slack the file is only appended to, but reads stay within the limit.
+        assert_eq!(
+            std::fs::read_to_string(file.path())
+                .unwrap()
+                .lines()
+                .count(),
+            5
+        );
+        assert_eq!(file.read_recent::<u32>().unwrap(), vec![2, 3, 4, 5]);
+
+        file.append(&[6]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(file.path()).unwrap(),
+            "3\n4\n5\n6\n"
+        );
+        assert_eq!(file.read_recent::<u32>().unwrap(), vec![3, 4, 5, 6]);
+
+        file.append(&[7, 8, 9, 10, 11]).unwrap();
+        assert_eq!(file.read_recent::<u32>().unwrap(), vec![8, 9, 10, 11]);
+    }
+}
@@ -252,6 +252,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "scala_macro_library",
         "java_proto_library",
         "_java_grpc_library",
+        "kt_jvm_library",
+        "kt_jvm_import",
     ]
     .into_iter()
     .map(|e| e.to_string())
@@ -339,7 +341,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let union_with_spaces_bytes = " union ".as_bytes();
 
-    let all_targets_to_use = if opt.refresh_bazel_deps_only {
+    let (all_targets_to_use, target_roots) = if opt.refresh_bazel_deps_only {
         running_refresh_mode = true;
         let mut all_targets_to_use: HashMap<String, HashSet<String>> = HashMap::default();
         let merged = {
@@ -375,7 +377,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 entry.insert(entries[2].to_string());
             }
         }
-        all_targets_to_use
+        // The index we refresh may be missing the workspace's proto_library srcs, or have stale ones, so they are
+        // indexed again, the external repos are left as they were.
+        (all_targets_to_use, vec![String::from("//...")])
     } else {
         info!("Executing initial query to find all external repos in this bazel repository");
 
@@ -550,7 +554,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         }
-        all_targets_to_use
+        (all_targets_to_use, target_roots)
     };
 
     info!("Found targets");
@@ -575,6 +579,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         index_table.add_target_to_blacklist(e).await
     }
 
+    if !target_roots.is_empty() {
+        info!("Indexing proto_library srcs to their import paths");
+        let proto_srcs_indexed = bazelfe_core::jvm_indexer::proto_index::index_proto_libraries(
+            &bazel_query,
+            &target_roots,
+            &index_table,
+        )
+        .await;
+        info!("Indexed {} proto import paths", proto_srcs_indexed);
+    }
+
     let target_completed_tracker = TargetCompletedTracker::new(all_found_targets);
 
     let processors: Vec<Arc<dyn BazelEventHandler>> = vec![
@@ -1,2 +1,3 @@
 pub mod bazel_query;
 pub mod popularity_parser;
+pub mod proto_index;
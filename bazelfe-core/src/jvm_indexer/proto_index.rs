@@ -0,0 +1,216 @@
+use ::prost::Message;
+use bazelfe_protos::*;
+
+use crate::index_table::IndexTable;
+
+use super::bazel_query::BazelQuery;
+
+fn attribute<'a>(rule: &'a blaze_query::Rule, name: &str) -> Option<&'a blaze_query::Attribute> {
+    rule.attribute.iter().find(|a| a.name == name)
+}
+
+/// Given a proto_library rule from a query, compute the paths other protos would use
+/// to import its srcs. This follows the same rules bazel uses for the virtual import
+/// directory, honoring strip_import_prefix and import_prefix.
+pub fn proto_import_paths(rule: &blaze_query::Rule) -> Vec<String> {
+    let package = rule
+        .name
+        .split(':')
+        .next()
+        .and_then(|e| e.split("//").nth(1))
+        .unwrap_or("");
+
+    let strip_import_prefix = attribute(rule, "strip_import_prefix")
+        .map(|a| a.string_value())
+        .unwrap_or("");
+    let import_prefix = attribute(rule, "import_prefix")
+        .map(|a| a.string_value())
+        .unwrap_or("");
+
+    let strip_prefix = if let Some(absolute) = strip_import_prefix.strip_prefix('/') {
+        absolute.to_string()
+    } else if strip_import_prefix.is_empty() {
+        String::default()
+    } else if package.is_empty() {
+        strip_import_prefix.to_string()
+    } else {
+        format!("{}/{}", package, strip_import_prefix)
+    };
+
+    let mut results = Vec::default();
+    for src in attribute(rule, "srcs")
+        .map(|a| a.string_list_value.iter())
+        .into_iter()
+        .flatten()
+    {
+        let (src_package, src_name) = match src.split("//").nth(1).and_then(|e| e.split_once(':')) {
+            Some(e) => e,
+            None => continue,
+        };
+
+        let path = if src_package.is_empty() {
+            src_name.to_string()
+        } else {
+            format!("{}/{}", src_package, src_name)
+        };
+
+        let path = if strip_prefix.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(strip_prefix.as_str())
+                .and_then(|e| e.strip_prefix('/'))
+            {
+                Some(p) => p.to_string(),
+                None => continue,
+            }
+        };
+
+        let path = if import_prefix.is_empty() {
+            path
+        } else {
+            format!("{}/{}", import_prefix.trim_end_matches('/'), path)
+        };
+        results.push(path);
+    }
+    results
+}
+
+/// Query for all proto_library rules under the given roots and add their import paths
+/// to the index, so a missing proto import can be mapped back to a label.
+pub async fn index_proto_libraries<B: BazelQuery>(
+    bazel_query: &B,
+    target_roots: &[String],
+    index_table: &IndexTable,
+) -> u32 {
+    let mut indexed = 0;
+    for chunk in target_roots.chunks(10) {
+        let merged = chunk
+            .iter()
+            .map(|root| format!("kind(proto_library, {})", root))
+            .collect::<Vec<String>>()
+            .join(" union ");
+
+        let res = bazel_query
+            .execute(&vec![
+                String::from("query"),
+                String::from("--keep_going"),
+                String::from("--noimplicit_deps"),
+                String::from("--output"),
+                String::from("proto"),
+                merged,
+            ])
+            .await;
+
+        let query_result = match blaze_query::QueryResult::decode(&*res.stdout_raw) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!(
+                    "Unable to decode proto_library query result, skipping chunk: {:?}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        for target in query_result.target.iter() {
+            if let Some(rule) = target.rule.as_ref() {
+                indexed += index_table
+                    .index_proto_srcs(rule.name.clone(), proto_import_paths(rule))
+                    .await;
+            }
+        }
+    }
+    indexed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_attr(name: &str, value: &str) -> blaze_query::Attribute {
+        blaze_query::Attribute {
+            name: name.to_string(),
+            r#type: blaze_query::attribute::Discriminator::String as i32,
+            string_value: Some(value.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn proto_rule(
+        name: &str,
+        srcs: Vec<&str>,
+        extra: Vec<blaze_query::Attribute>,
+    ) -> blaze_query::Rule {
+        let mut attribute = vec![blaze_query::Attribute {
+            name: String::from("srcs"),
+            r#type: blaze_query::attribute::Discriminator::LabelList as i32,
+            string_list_value: srcs.into_iter().map(|e| e.to_string()).collect(),
+            ..Default::default()
+        }];
+        attribute.extend(extra);
+        blaze_query::Rule {
+            name: name.to_string(),
+            rule_class: String::from("proto_library"),
+            attribute,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plain_import_paths() {
+        let rule = proto_rule(
+            "//src/main/proto/com/example:example_proto",
+            vec![
+                "//src/main/proto/com/example:foo.proto",
+                "//src/main/proto/com/example:sub/bar.proto",
+            ],
+            vec![],
+        );
+        assert_eq!(
+            proto_import_paths(&rule),
+            vec![
+                String::from("src/main/proto/com/example/foo.proto"),
+                String::from("src/main/proto/com/example/sub/bar.proto"),
+            ]
+        );
+
+        let rule = proto_rule(
+            "@com_google_protobuf//:any_proto",
+            vec!["@com_google_protobuf//:src/google/protobuf/any.proto"],
+            vec![string_attr("strip_import_prefix", "/src")],
+        );
+        assert_eq!(
+            proto_import_paths(&rule),
+            vec![String::from("google/protobuf/any.proto")]
+        );
+    }
+
+    #[test]
+    fn test_prefixed_import_paths() {
+        let rule = proto_rule(
+            "//src/main/proto/com/example:example_proto",
+            vec!["//src/main/proto/com/example:foo.proto"],
+            vec![
+                string_attr("strip_import_prefix", ""),
+                string_attr("import_prefix", "external/"),
+            ],
+        );
+        assert_eq!(
+            proto_import_paths(&rule),
+            vec![String::from(
+                "external/src/main/proto/com/example/foo.proto"
+            )]
+        );
+
+        let rule = proto_rule(
+            "//src/main/proto:example_proto",
+            vec!["//src/main/proto:com/example/foo.proto"],
+            vec![string_attr("strip_import_prefix", "com")],
+        );
+        assert_eq!(
+            proto_import_paths(&rule),
+            vec![String::from("example/foo.proto")]
+        );
+    }
+}
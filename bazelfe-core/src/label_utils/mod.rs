@@ -1,7 +1,9 @@
 use std::collections::HashSet;
 
+use crate::config::{Config, LabelRewriteRule};
 use crate::error_extraction;
 use error_extraction::ClassImportRequest;
+use regex::Regex;
 
 pub fn sanitize_label(label: String) -> String {
     // If you use macros, say the scala_library suite or similar
@@ -28,6 +30,50 @@ pub fn sanitize_label(label: String) -> String {
     label
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewrittenTarget {
+    pub label: String,
+    pub attribute: String,
+}
+
+/// The configured label rewrite rules, compiled.
+#[derive(Debug, Clone, Default)]
+pub struct LabelRewriter {
+    rules: Vec<(Regex, LabelRewriteRule)>,
+}
+
+impl LabelRewriter {
+    pub fn new(rules: &[LabelRewriteRule]) -> Result<Self, regex::Error> {
+        let mut compiled = Vec::default();
+        for rule in rules.iter() {
+            compiled.push((Regex::new(&rule.regex_match)?, rule.clone()));
+        }
+        Ok(Self { rules: compiled })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, regex::Error> {
+        Self::new(&config.label_rewrite_rules)
+    }
+
+    /// The target, and attribute, which should be edited to change the dependencies of `label`.
+    pub fn rewrite(&self, label: &str) -> RewrittenTarget {
+        for (regex, rule) in self.rules.iter() {
+            if regex.is_match(label) {
+                return RewrittenTarget {
+                    label: sanitize_label(
+                        regex.replace(label, rule.replacement.as_str()).to_string(),
+                    ),
+                    attribute: rule.attribute.clone(),
+                };
+            }
+        }
+        RewrittenTarget {
+            label: sanitize_label(label.to_string()),
+            attribute: String::from(crate::buildozer_driver::DEPS_ATTRIBUTE),
+        }
+    }
+}
+
 pub fn prepare_class_import_requests(
     mut class_import_requests: Vec<ClassImportRequest>,
 ) -> Vec<ClassImportRequest> {
@@ -146,6 +192,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_label_rewriter() {
+        let rewriter = LabelRewriter::new(&[
+            LabelRewriteRule {
+                regex_match: String::from("^(//[^:]*:.*)_scala_suite_[0-9]+$"),
+                replacement: String::from("$1"),
+                attribute: String::from("suite_deps"),
+            },
+            LabelRewriteRule {
+                regex_match: String::from("^(//[^:]*):gen_(.*)$"),
+                replacement: String::from("$1:$2"),
+                attribute: String::from("deps"),
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(
+            rewriter.rewrite("//foo/bar:baz_scala_suite_12"),
+            RewrittenTarget {
+                label: String::from("//foo/bar:baz"),
+                attribute: String::from("suite_deps"),
+            }
+        );
+        assert_eq!(
+            rewriter.rewrite("//foo/bar:gen_thing"),
+            RewrittenTarget {
+                label: String::from("//foo/bar:thing"),
+                attribute: String::from("deps"),
+            }
+        );
+        assert_eq!(
+            rewriter.rewrite("//foo/bar:werwe_auto_gen_werewr"),
+            RewrittenTarget {
+                label: String::from("//foo/bar:werwe"),
+                attribute: String::from("deps"),
+            }
+        );
+
+        assert!(LabelRewriter::new(&[LabelRewriteRule {
+            regex_match: String::from("(unclosed"),
+            replacement: String::default(),
+            attribute: String::from("deps"),
+        }])
+        .is_err());
+    }
+
     #[test]
     fn test_prepare_class_import_requests() {
         let input = vec![
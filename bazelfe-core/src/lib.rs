@@ -13,8 +13,10 @@ pub mod config;
 pub mod error_extraction;
 pub mod hydrated_stream_processors;
 pub mod index_table;
+pub mod jsonl_file;
 pub mod jvm_indexer;
 pub mod label_utils;
+pub mod remote_cache_proxy;
 pub mod source_dependencies;
 pub mod tokioext;
 pub mod zip_parse;
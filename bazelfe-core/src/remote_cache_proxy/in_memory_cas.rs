@@ -0,0 +1,198 @@
+//! Stand-ins for tests: a remote cache serving ByteStream and CAS reads of blobs held in memory,
+//! and helpers to serve our own RequestFilesService and UpstreamService.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use bazel_tools::request_files_service_server::RequestFilesServiceServer;
+use bazel_tools::upstream_service_server::UpstreamServiceServer;
+use bazelfe_protos::*;
+use build::bazel::remote::execution::v2::content_addressable_storage_server::{
+    ContentAddressableStorage, ContentAddressableStorageServer,
+};
+use build::bazel::remote::execution::v2::{
+    batch_read_blobs_response, BatchReadBlobsRequest, BatchReadBlobsResponse,
+    BatchUpdateBlobsRequest, BatchUpdateBlobsResponse, FindMissingBlobsRequest,
+    FindMissingBlobsResponse, GetTreeRequest, GetTreeResponse,
+};
+use futures::Stream;
+use google::bytestream::byte_stream_server::{ByteStream, ByteStreamServer};
+use google::bytestream::{
+    QueryWriteStatusRequest, QueryWriteStatusResponse, ReadRequest, ReadResponse, WriteRequest,
+    WriteResponse,
+};
+use tonic::{Code, Request, Response, Status};
+
+use super::{RequestFilesProxy, UpstreamProxy};
+use crate::build_events::file_fetcher::ByteStreamUri;
+
+/// A CAS stand-in holding its blobs in memory, keyed by hash.
+#[derive(Clone, Default)]
+pub(crate) struct InMemoryCas {
+    blobs: Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>,
+    pub(crate) api_keys: Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+impl InMemoryCas {
+    pub(crate) fn insert(&self, hash: &str, data: &[u8]) {
+        self.blobs
+            .lock()
+            .unwrap()
+            .insert(hash.to_string(), data.to_vec());
+    }
+
+    fn get<T>(&self, request: &Request<T>, hash: &str) -> Option<Vec<u8>> {
+        if let Some(v) = request.metadata().get("x-api-key") {
+            self.api_keys
+                .lock()
+                .unwrap()
+                .push(v.to_str().unwrap().to_string());
+        }
+        self.blobs.lock().unwrap().get(hash).cloned()
+    }
+}
+
+type TestStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
+
+#[tonic::async_trait]
+impl ByteStream for InMemoryCas {
+    type ReadStream = TestStream<ReadResponse>;
+
+    async fn read(
+        &self,
+        request: Request<ReadRequest>,
+    ) -> Result<Response<Self::ReadStream>, Status> {
+        let uri = ByteStreamUri::parse(&format!(
+            "bytestream://cas/{}",
+            request.get_ref().resource_name
+        ))
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let data = self
+            .get(&request, &uri.hash)
+            .ok_or_else(|| Status::not_found(uri.hash.clone()))?;
+        // Serve in a few chunks so we exercise reassembling the stream.
+        let chunks: Vec<Result<ReadResponse, Status>> = data
+            .chunks(data.len() / 4 + 1)
+            .map(|c| Ok(ReadResponse { data: c.to_vec() }))
+            .collect();
+        Ok(Response::new(
+            Box::pin(futures::stream::iter(chunks)) as Self::ReadStream
+        ))
+    }
+
+    async fn write(
+        &self,
+        _request: Request<tonic::Streaming<WriteRequest>>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        Err(Status::unimplemented("read only"))
+    }
+
+    async fn query_write_status(
+        &self,
+        _request: Request<QueryWriteStatusRequest>,
+    ) -> Result<Response<QueryWriteStatusResponse>, Status> {
+        Err(Status::unimplemented("read only"))
+    }
+}
+
+#[tonic::async_trait]
+impl ContentAddressableStorage for InMemoryCas {
+    type GetTreeStream = TestStream<GetTreeResponse>;
+
+    async fn find_missing_blobs(
+        &self,
+        _request: Request<FindMissingBlobsRequest>,
+    ) -> Result<Response<FindMissingBlobsResponse>, Status> {
+        Err(Status::unimplemented("read only"))
+    }
+
+    async fn batch_update_blobs(
+        &self,
+        _request: Request<BatchUpdateBlobsRequest>,
+    ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
+        Err(Status::unimplemented("read only"))
+    }
+
+    async fn batch_read_blobs(
+        &self,
+        request: Request<BatchReadBlobsRequest>,
+    ) -> Result<Response<BatchReadBlobsResponse>, Status> {
+        let responses = request
+            .get_ref()
+            .digests
+            .iter()
+            .map(|digest| {
+                let data = self.get(&request, &digest.hash);
+                batch_read_blobs_response::Response {
+                    digest: Some(digest.clone()),
+                    status: Some(google::rpc::Status {
+                        code: if data.is_some() {
+                            Code::Ok
+                        } else {
+                            Code::NotFound
+                        } as i32,
+                        ..Default::default()
+                    }),
+                    data: data.unwrap_or_default(),
+                }
+            })
+            .collect();
+        Ok(Response::new(BatchReadBlobsResponse { responses }))
+    }
+
+    async fn get_tree(
+        &self,
+        _request: Request<GetTreeRequest>,
+    ) -> Result<Response<Self::GetTreeStream>, Status> {
+        Err(Status::unimplemented("read only"))
+    }
+}
+
+/// Starts the stand-in, returning the authority to put in bytestream uris.
+pub(crate) async fn start_cas(cas: InMemoryCas, serve_bytestream: bool) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let authority = listener.local_addr().unwrap().to_string();
+    let router = tonic::transport::Server::builder()
+        .add_service(ContentAddressableStorageServer::new(cas.clone()))
+        .add_optional_service(if serve_bytestream {
+            Some(ByteStreamServer::new(cas))
+        } else {
+            None
+        });
+    tokio::spawn(async move {
+        router
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+            .await
+            .unwrap();
+    });
+    authority
+}
+
+/// Serves the proxy, returning the endpoint to give a RequestFilesClient.
+pub(crate) async fn start_request_files(proxy: RequestFilesProxy) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("grpc://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(RequestFilesServiceServer::new(proxy))
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+            .await
+            .unwrap();
+    });
+    endpoint
+}
+
+/// Serves the proxy, returning the endpoint to give an UpstreamClient.
+pub(crate) async fn start_upstream(proxy: UpstreamProxy) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("grpc://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(UpstreamServiceServer::new(proxy))
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+            .await
+            .unwrap();
+    });
+    endpoint
+}
@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use bazelfe_protos::build::bazel::remote::execution::v2::Digest;
+use sha2::Sha256;
+
+use super::ProxyError;
+
+/// Blobs stored on disk under `<root>/sha256/<first two hash chars>/<hash>`.
+/// Writes go through a temporary file and a rename, so a path we hand out is always complete.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalCas {
+    root: PathBuf,
+}
+
+impl LocalCas {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn validate(digest: &Digest) -> Result<(), ProxyError> {
+        // The hash ends up in a path, so it must be nothing but hex.
+        if digest.hash.len() == 64
+            && digest.hash.chars().all(|c| c.is_ascii_hexdigit())
+            && digest.size_bytes >= 0
+        {
+            Ok(())
+        } else {
+            Err(ProxyError::InvalidDigest(
+                digest.hash.clone(),
+                digest.size_bytes,
+            ))
+        }
+    }
+
+    pub fn path_for(&self, digest: &Digest) -> Result<PathBuf, ProxyError> {
+        Self::validate(digest)?;
+        let hash = digest.hash.to_ascii_lowercase();
+        Ok(self.root.join("sha256").join(&hash[0..2]).join(hash))
+    }
+
+    /// The path of the blob, if we already have it.
+    pub async fn get(&self, digest: &Digest) -> Result<Option<PathBuf>, ProxyError> {
+        let path = self.path_for(digest)?;
+        match tokio::fs::metadata(&path).await {
+            Ok(m) if m.len() == digest.size_bytes as u64 => Ok(Some(path)),
+            Ok(_) => {
+                warn!(
+                    "Removing truncated blob {} from the local CAS",
+                    path.to_string_lossy()
+                );
+                tokio::fs::remove_file(&path).await?;
+                Ok(None)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Stores the blob after checking it matches its digest, returning where it now lives.
+    pub async fn put(&self, digest: &Digest, data: &[u8]) -> Result<PathBuf, ProxyError> {
+        use sha2::Digest as _;
+        let path = self.path_for(digest)?;
+        let actual_hash = format!("{:x}", Sha256::digest(data));
+        if data.len() as i64 != digest.size_bytes || !actual_hash.eq_ignore_ascii_case(&digest.hash)
+        {
+            return Err(ProxyError::DigestMismatch(
+                digest.hash.clone(),
+                digest.size_bytes,
+            ));
+        }
+
+        let tmp_dir = self.root.join("tmp");
+        tokio::fs::create_dir_all(&tmp_dir).await?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = tmp_dir.join(format!("{}.{}", actual_hash, rand::random::<u64>()));
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn digest_of(data: &[u8]) -> Digest {
+    use sha2::Digest as _;
+    Digest {
+        hash: format!("{:x}", Sha256::digest(data)),
+        size_bytes: data.len() as i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_put_and_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let cas = LocalCas::new(dir.path().to_path_buf());
+        let digest = digest_of(b"hello");
+
+        assert_eq!(cas.get(&digest).await.unwrap(), None);
+        let path = cas.put(&digest, b"hello").await.unwrap();
+        assert!(path.starts_with(dir.path().join("sha256").join(&digest.hash[0..2])));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello".to_vec());
+        assert_eq!(cas.get(&digest).await.unwrap(), Some(path));
+
+        assert!(matches!(
+            cas.put(&digest, b"goodbye").await,
+            Err(ProxyError::DigestMismatch(..))
+        ));
+        let traversal = Digest {
+            hash: String::from("../../etc/passwd"),
+            size_bytes: 5,
+        };
+        assert!(matches!(
+            cas.get(&traversal).await,
+            Err(ProxyError::InvalidDigest(..))
+        ));
+    }
+}
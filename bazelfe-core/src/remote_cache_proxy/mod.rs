@@ -0,0 +1,111 @@
+//! A local proxy in front of a remote cache, handing out blobs as paths on disk rather than bytes.
+//! Blobs are kept in a local content addressed directory, so error processing and index building
+//! can read the handful of outputs they need without bazel downloading whole artifacts.
+
+use bazelfe_protos::build::bazel::remote::execution::v2::Digest;
+use thiserror::Error;
+
+#[cfg(test)]
+pub(crate) mod in_memory_cas;
+pub mod local_cas;
+pub mod request_files;
+pub mod upstream;
+
+pub use local_cas::LocalCas;
+pub use request_files::{RequestFilesClient, RequestFilesProxy};
+pub use upstream::{UpstreamClient, UpstreamProxy};
+
+#[derive(Error, Debug)]
+pub enum ProxyError {
+    #[error("Invalid digest {0}/{1}")]
+    InvalidDigest(String, i64),
+    #[error("Blob content doesn't match its digest {0}/{1}")]
+    DigestMismatch(String, i64),
+    #[error("Local CAS io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid endpoint {0}: {1}")]
+    InvalidEndpoint(String, String),
+    #[error("Invalid remote cache header {0}")]
+    InvalidHeader(String),
+    #[error("Unable to read remote cache CA certificate: {0}")]
+    CaCertificate(std::io::Error),
+}
+
+impl From<ProxyError> for tonic::Status {
+    fn from(e: ProxyError) -> Self {
+        match e {
+            ProxyError::InvalidDigest(..) | ProxyError::DigestMismatch(..) => {
+                tonic::Status::invalid_argument(e.to_string())
+            }
+            ProxyError::Io(_)
+            | ProxyError::InvalidEndpoint(..)
+            | ProxyError::InvalidHeader(_)
+            | ProxyError::CaCertificate(_) => tonic::Status::internal(e.to_string()),
+        }
+    }
+}
+
+/// Our endpoints are given as grpc(s):// or http(s):// uris, tonic only understands the latter.
+pub(crate) fn endpoint_from_uri(uri: &str) -> Result<tonic::transport::Endpoint, ProxyError> {
+    let normalized = if let Some(rest) = uri.strip_prefix("grpc://") {
+        format!("http://{}", rest)
+    } else if let Some(rest) = uri.strip_prefix("grpcs://") {
+        format!("https://{}", rest)
+    } else {
+        uri.to_string()
+    };
+    tonic::transport::Endpoint::from_shared(normalized)
+        .map_err(|e| ProxyError::InvalidEndpoint(uri.to_string(), e.to_string()))
+}
+
+pub(crate) fn rpc_status(status: &tonic::Status) -> bazelfe_protos::google::rpc::Status {
+    bazelfe_protos::google::rpc::Status {
+        code: status.code() as i32,
+        message: status.message().to_string(),
+        details: Vec::default(),
+    }
+}
+
+pub(crate) fn rpc_ok() -> bazelfe_protos::google::rpc::Status {
+    bazelfe_protos::google::rpc::Status {
+        code: tonic::Code::Ok as i32,
+        message: String::default(),
+        details: Vec::default(),
+    }
+}
+
+fn status_error(status: Option<bazelfe_protos::google::rpc::Status>) -> Option<tonic::Status> {
+    match status {
+        Some(status) if status.code != tonic::Code::Ok as i32 => Some(tonic::Status::new(
+            tonic::Code::from(status.code),
+            status.message,
+        )),
+        _ => None,
+    }
+}
+
+/// Pairs the per blob responses of a batch call back up with the digests asked for, in the same order.
+/// Services may answer in any order, a digest without a response is not found.
+pub(crate) fn results_in_digest_order<R, T>(
+    digests: &[Digest],
+    mut responses: Vec<R>,
+    digest_of: impl Fn(&R) -> Option<&Digest>,
+    into_result: impl Fn(R) -> (Option<bazelfe_protos::google::rpc::Status>, T),
+) -> Vec<Result<T, tonic::Status>> {
+    let mut results = Vec::with_capacity(digests.len());
+    for digest in digests.iter() {
+        let response = match responses.iter().position(|r| digest_of(r) == Some(digest)) {
+            Some(idx) => responses.swap_remove(idx),
+            None => {
+                results.push(Err(tonic::Status::not_found(digest.hash.clone())));
+                continue;
+            }
+        };
+        let (status, value) = into_result(response);
+        results.push(match status_error(status) {
+            Some(e) => Err(e),
+            None => Ok(value),
+        });
+    }
+    results
+}
@@ -0,0 +1,84 @@
+use clap::Parser;
+#[macro_use]
+extern crate log;
+
+use std::path::PathBuf;
+
+use tonic::transport::Server;
+
+use bazel_tools::request_files_service_server::RequestFilesServiceServer;
+use bazel_tools::upstream_service_server::UpstreamServiceServer;
+use bazelfe_core::config::RemoteCache;
+use bazelfe_core::remote_cache_proxy::{LocalCas, RequestFilesProxy, UpstreamProxy};
+use bazelfe_protos::*;
+
+#[derive(Parser, Debug)]
+#[clap(name = "basic")]
+struct Opt {
+    /// Where to listen for RequestFiles and Upstream calls.
+    #[clap(long, env = "BIND_ADDRESS", default_value = "127.0.0.1:50052")]
+    bind_address: String,
+
+    /// The content addressed directory blobs are stored in and served from.
+    #[clap(long, env = "LOCAL_CAS_DIRECTORY", parse(from_os_str))]
+    local_cas_directory: PathBuf,
+
+    /// The remote cache to read missing blobs from, e.g. grpc://cache.example.com:9092
+    /// Without one we only serve what is already in the local CAS directory.
+    #[clap(long, env = "REMOTE_CACHE")]
+    upstream: Option<String>,
+
+    /// Extra gRPC header sent with every call to the remote cache, as name=value, e.g. x-api-key=secret
+    #[clap(long = "upstream-header", parse(try_from_str = parse_header))]
+    upstream_headers: Vec<(String, String)>,
+
+    /// PEM encoded CA certificate, when set we connect to the remote cache over TLS.
+    /// The remote cache is then given as grpcs://
+    #[clap(long, env = "REMOTE_CACHE_TLS_CA_CERTIFICATE", parse(from_os_str))]
+    upstream_tls_ca_certificate: Option<PathBuf>,
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    match header.split_once('=') {
+        Some((name, value)) => Ok((name.to_string(), value.to_string())),
+        None => Err(format!("Expected name=value, got {}", header)),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    pretty_env_logger::init();
+    let opt = Opt::parse();
+
+    let addr = opt
+        .bind_address
+        .parse()
+        .expect("can't parse BIND_ADDRESS variable");
+
+    let cas = LocalCas::new(opt.local_cas_directory);
+    let remote_cache = RemoteCache {
+        headers: opt.upstream_headers.into_iter().collect(),
+        tls_ca_certificate: opt.upstream_tls_ca_certificate,
+        ..Default::default()
+    };
+    let upstream = match opt.upstream.as_ref() {
+        Some(endpoint) => Some(UpstreamProxy::connect_lazy(
+            endpoint,
+            cas.clone(),
+            &remote_cache,
+        )?),
+        None => None,
+    };
+
+    info!("Serving {} on {}", cas.root().to_string_lossy(), addr);
+    Server::builder()
+        .add_service(RequestFilesServiceServer::new(RequestFilesProxy::new(
+            cas,
+            upstream.clone(),
+        )))
+        .add_optional_service(upstream.map(UpstreamServiceServer::new))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
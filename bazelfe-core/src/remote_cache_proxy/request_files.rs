@@ -0,0 +1,190 @@
+use std::path::PathBuf;
+
+use bazel_tools::request_files_service_client::RequestFilesServiceClient;
+use bazel_tools::request_files_service_server::RequestFilesService;
+use bazel_tools::{request_files_response, RequestFilesResponse};
+use bazelfe_protos::*;
+use build::bazel::remote::execution::v2::{BatchReadBlobsRequest, Digest};
+use tonic::transport::Channel;
+use tonic::{Request, Response, Status};
+
+use super::{
+    endpoint_from_uri, results_in_digest_order, rpc_ok, rpc_status, LocalCas, ProxyError,
+    UpstreamProxy,
+};
+
+/// Answers RequestFiles from the local CAS, going to the upstream for anything we don't have yet.
+/// Without an upstream only blobs already on disk can be served.
+#[derive(Clone, Debug)]
+pub struct RequestFilesProxy {
+    cas: LocalCas,
+    upstream: Option<UpstreamProxy>,
+}
+
+impl RequestFilesProxy {
+    pub fn new(cas: LocalCas, upstream: Option<UpstreamProxy>) -> Self {
+        Self { cas, upstream }
+    }
+
+    async fn local_paths(&self, digests: &[Digest]) -> Vec<Result<PathBuf, Status>> {
+        let mut results = Vec::with_capacity(digests.len());
+        for digest in digests.iter() {
+            results.push(match self.cas.get(digest).await {
+                Ok(Some(path)) => Ok(path),
+                Ok(None) => Err(Status::not_found(format!(
+                    "{}/{} isn't in the local CAS",
+                    digest.hash, digest.size_bytes
+                ))),
+                Err(e) => Err(e.into()),
+            });
+        }
+        results
+    }
+}
+
+#[tonic::async_trait]
+impl RequestFilesService for RequestFilesProxy {
+    async fn request_files(
+        &self,
+        request: Request<BatchReadBlobsRequest>,
+    ) -> Result<Response<RequestFilesResponse>, Status> {
+        let request = request.into_inner();
+        let results = match self.upstream.as_ref() {
+            Some(upstream) => {
+                upstream
+                    .read_blobs(&request.instance_name, &request.digests)
+                    .await
+            }
+            None => self.local_paths(&request.digests).await,
+        };
+        let responses = request
+            .digests
+            .into_iter()
+            .zip(results)
+            .map(|(digest, result)| {
+                let (path, status) = match result {
+                    Ok(path) => (path.to_string_lossy().to_string(), rpc_ok()),
+                    Err(status) => (String::default(), rpc_status(&status)),
+                };
+                request_files_response::Response {
+                    digest: Some(digest),
+                    path,
+                    status: Some(status),
+                }
+            })
+            .collect();
+        Ok(Response::new(RequestFilesResponse { responses }))
+    }
+}
+
+/// Asks a RequestFilesService, normally one on this machine, where it has put blobs.
+#[derive(Clone, Debug)]
+pub struct RequestFilesClient {
+    client: RequestFilesServiceClient<Channel>,
+}
+
+impl RequestFilesClient {
+    /// e.g. grpc://127.0.0.1:50052, the connection is made on first use.
+    pub fn connect_lazy(endpoint: &str) -> Result<Self, ProxyError> {
+        Ok(Self {
+            client: RequestFilesServiceClient::new(endpoint_from_uri(endpoint)?.connect_lazy()),
+        })
+    }
+
+    /// The local path of each digest, in the same order as the digests.
+    pub async fn request_files(
+        &self,
+        instance_name: &str,
+        digests: &[Digest],
+    ) -> Result<Vec<Result<PathBuf, Status>>, Status> {
+        let mut client = self.client.clone();
+        let responses = client
+            .request_files(BatchReadBlobsRequest {
+                instance_name: instance_name.to_string(),
+                digests: digests.to_vec(),
+            })
+            .await?
+            .into_inner()
+            .responses;
+        Ok(results_in_digest_order(
+            digests,
+            responses,
+            |r| r.digest.as_ref(),
+            |r| (r.status, PathBuf::from(r.path)),
+        ))
+    }
+
+    pub async fn request_file(
+        &self,
+        instance_name: &str,
+        digest: &Digest,
+    ) -> Result<PathBuf, Status> {
+        match self
+            .request_files(instance_name, std::slice::from_ref(digest))
+            .await?
+            .pop()
+        {
+            Some(result) => result,
+            None => Err(Status::not_found(digest.hash.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RemoteCache;
+    use crate::remote_cache_proxy::in_memory_cas::{start_cas, start_request_files, InMemoryCas};
+    use crate::remote_cache_proxy::local_cas::digest_of;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn test_request_files_through_upstream() {
+        let remote = InMemoryCas::default();
+        let present = digest_of(b"error: not found: type Bar");
+        remote.insert(&present.hash, b"error: not found: type Bar");
+        let missing = digest_of(b"never uploaded");
+        let remote_authority = start_cas(remote, true).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cas = LocalCas::new(dir.path().to_path_buf());
+        let upstream = UpstreamProxy::connect_lazy(
+            &format!("grpc://{}", remote_authority),
+            cas.clone(),
+            &RemoteCache::default(),
+        )
+        .unwrap();
+        let client = RequestFilesClient::connect_lazy(
+            &start_request_files(RequestFilesProxy::new(cas.clone(), Some(upstream))).await,
+        )
+        .unwrap();
+
+        let results = client
+            .request_files("", &[present.clone(), missing.clone()])
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        let path = results[0].as_ref().unwrap();
+        assert!(path.starts_with(dir.path()));
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "error: not found: type Bar"
+        );
+        assert_eq!(results[1].as_ref().unwrap_err().code(), Code::NotFound);
+
+        // Once fetched, it's served from disk without needing the upstream.
+        let local_only = RequestFilesClient::connect_lazy(
+            &start_request_files(RequestFilesProxy::new(cas, None)).await,
+        )
+        .unwrap();
+        assert_eq!(local_only.request_file("", &present).await.unwrap(), *path);
+        assert_eq!(
+            local_only
+                .request_file("", &missing)
+                .await
+                .unwrap_err()
+                .code(),
+            Code::NotFound
+        );
+    }
+}
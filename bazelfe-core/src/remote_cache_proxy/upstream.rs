@@ -0,0 +1,422 @@
+use std::path::PathBuf;
+
+use bazel_tools::upstream_service_client::UpstreamServiceClient;
+use bazel_tools::upstream_service_server::UpstreamService;
+use bazelfe_protos::*;
+use build::bazel::remote::execution::v2::{
+    action_cache_client::ActionCacheClient,
+    content_addressable_storage_client::ContentAddressableStorageClient,
+    execution_client::ExecutionClient, ActionResult, BatchReadBlobsRequest, Digest, ExecuteRequest,
+    ExecuteResponse, GetActionResultRequest,
+};
+use google::bytestream::byte_stream_client::ByteStreamClient;
+use google::bytestream::ReadRequest;
+use prost::Message;
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tonic::{Request, Response, Status};
+
+use super::{endpoint_from_uri, results_in_digest_order, rpc_ok, rpc_status, LocalCas, ProxyError};
+use crate::config::RemoteCache;
+
+/// Blobs bigger than this are streamed over ByteStream, caches commonly cap batch calls at around 4MB.
+const MAX_BATCH_BLOB_SIZE: i64 = 1024 * 1024;
+/// Batch reads are split so their blobs add up to no more than this, leaving room under the cap for the framing.
+const MAX_BATCH_TOTAL_SIZE: i64 = 3 * 1024 * 1024;
+
+/// Splits digests into batches whose blobs stay within MAX_BATCH_TOTAL_SIZE, keeping their order.
+fn batches(digests: Vec<(usize, Digest)>) -> Vec<Vec<(usize, Digest)>> {
+    let mut batches: Vec<Vec<(usize, Digest)>> = Vec::default();
+    let mut batch_size = 0;
+    for (idx, digest) in digests.into_iter() {
+        if batches.is_empty() || batch_size + digest.size_bytes > MAX_BATCH_TOTAL_SIZE {
+            batches.push(Vec::default());
+            batch_size = 0;
+        }
+        batch_size += digest.size_bytes;
+        batches
+            .last_mut()
+            .expect("We just ensured there is a batch")
+            .push((idx, digest));
+    }
+    batches
+}
+
+/// Serves the UpstreamService on top of a remote cache, blobs read through it land in our local CAS
+/// and are handed back as paths.
+#[derive(Clone, Debug)]
+pub struct UpstreamProxy {
+    cas: LocalCas,
+    channel: Channel,
+    headers: MetadataMap,
+}
+
+impl UpstreamProxy {
+    pub fn new(cas: LocalCas, channel: Channel) -> Self {
+        Self {
+            cas,
+            channel,
+            headers: MetadataMap::new(),
+        }
+    }
+
+    /// e.g. grpc://cache.example.com:9092, the connection is made on first use.
+    /// The headers go with every call to the cache, and a CA certificate has us connect over TLS, as with
+    /// the remote cache we fetch build event files from.
+    pub fn connect_lazy(
+        endpoint: &str,
+        cas: LocalCas,
+        config: &RemoteCache,
+    ) -> Result<Self, ProxyError> {
+        let mut headers = MetadataMap::new();
+        for (k, v) in config.headers.iter() {
+            let key = AsciiMetadataKey::from_bytes(k.as_bytes())
+                .map_err(|_| ProxyError::InvalidHeader(k.clone()))?;
+            let value = AsciiMetadataValue::from_str(v)
+                .map_err(|_| ProxyError::InvalidHeader(k.clone()))?;
+            headers.insert(key, value);
+        }
+
+        let mut upstream = endpoint_from_uri(endpoint)?.connect_timeout(config.connect_timeout);
+        if let Some(ca_path) = config.tls_ca_certificate.as_ref() {
+            let ca =
+                Certificate::from_pem(std::fs::read(ca_path).map_err(ProxyError::CaCertificate)?);
+            upstream = upstream
+                .tls_config(ClientTlsConfig::new().ca_certificate(ca))
+                .map_err(|e| ProxyError::InvalidEndpoint(endpoint.to_string(), e.to_string()))?;
+        }
+
+        Ok(Self {
+            cas,
+            channel: upstream.connect_lazy(),
+            headers,
+        })
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        *request.metadata_mut() = self.headers.clone();
+        request
+    }
+
+    pub fn cas(&self) -> &LocalCas {
+        &self.cas
+    }
+
+    async fn read_via_bytestream(
+        &self,
+        instance_name: &str,
+        digest: &Digest,
+    ) -> Result<Vec<u8>, Status> {
+        let resource_name = if instance_name.is_empty() {
+            format!("blobs/{}/{}", digest.hash, digest.size_bytes)
+        } else {
+            format!(
+                "{}/blobs/{}/{}",
+                instance_name, digest.hash, digest.size_bytes
+            )
+        };
+        let mut client = ByteStreamClient::new(self.channel.clone());
+        let mut stream = client
+            .read(self.request(ReadRequest {
+                resource_name,
+                read_offset: 0,
+                read_limit: 0,
+            }))
+            .await?
+            .into_inner();
+        let mut data = Vec::with_capacity(digest.size_bytes as usize);
+        while let Some(chunk) = stream.message().await? {
+            data.extend(chunk.data);
+        }
+        Ok(data)
+    }
+
+    async fn read_batch(
+        &self,
+        instance_name: &str,
+        digests: Vec<Digest>,
+    ) -> Vec<Result<Vec<u8>, Status>> {
+        let mut client = ContentAddressableStorageClient::new(self.channel.clone());
+        let response = client
+            .batch_read_blobs(self.request(BatchReadBlobsRequest {
+                instance_name: instance_name.to_string(),
+                digests: digests.clone(),
+            }))
+            .await;
+        match response {
+            Ok(response) => results_in_digest_order(
+                &digests,
+                response.into_inner().responses,
+                |b| b.digest.as_ref(),
+                |b| (b.status, b.data),
+            ),
+            Err(status) => {
+                let mut results = Vec::with_capacity(digests.len());
+                for _ in digests.iter() {
+                    results.push(Err(Status::new(status.code(), status.message())));
+                }
+                results
+            }
+        }
+    }
+
+    /// Resolves each digest to a path in our local CAS, reading any we don't already have from the remote cache.
+    /// The results are in the same order as the digests.
+    pub async fn read_blobs(
+        &self,
+        instance_name: &str,
+        digests: &[Digest],
+    ) -> Vec<Result<PathBuf, Status>> {
+        let mut results: Vec<Option<Result<PathBuf, Status>>> = Vec::with_capacity(digests.len());
+        let mut batched = Vec::default();
+        for (idx, digest) in digests.iter().enumerate() {
+            match self.cas.get(digest).await {
+                Ok(Some(path)) => results.push(Some(Ok(path))),
+                Ok(None) if digest.size_bytes > MAX_BATCH_BLOB_SIZE => {
+                    let path = match self.read_via_bytestream(instance_name, digest).await {
+                        Ok(data) => self.cas.put(digest, &data).await.map_err(|e| e.into()),
+                        Err(status) => Err(status),
+                    };
+                    results.push(Some(path));
+                }
+                Ok(None) => {
+                    batched.push((idx, digest.clone()));
+                    results.push(None);
+                }
+                Err(e) => results.push(Some(Err(e.into()))),
+            }
+        }
+
+        for batch in batches(batched).into_iter() {
+            let (indices, batch): (Vec<usize>, Vec<Digest>) = batch.into_iter().unzip();
+            let fetched = self.read_batch(instance_name, batch.clone()).await;
+            for ((idx, digest), data) in indices.into_iter().zip(batch.iter()).zip(fetched) {
+                results[idx] = Some(match data {
+                    Ok(data) => self.cas.put(digest, &data).await.map_err(|e| e.into()),
+                    Err(status) => Err(status),
+                });
+            }
+        }
+        results.into_iter().flatten().collect()
+    }
+}
+
+#[tonic::async_trait]
+impl UpstreamService for UpstreamProxy {
+    async fn get_action_result(
+        &self,
+        request: Request<GetActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        let mut client = ActionCacheClient::new(self.channel.clone());
+        client
+            .get_action_result(self.request(request.into_inner()))
+            .await
+    }
+
+    /// Runs the action remotely, waiting for it to finish rather than streaming back progress.
+    async fn execute(
+        &self,
+        request: Request<ExecuteRequest>,
+    ) -> Result<Response<ExecuteResponse>, Status> {
+        let mut client = ExecutionClient::new(self.channel.clone());
+        let mut operations = client
+            .execute(self.request(request.into_inner()))
+            .await?
+            .into_inner();
+        while let Some(operation) = operations.message().await? {
+            if !operation.done {
+                continue;
+            }
+            return match operation.result {
+                Some(google::longrunning::operation::Result::Response(any)) => {
+                    ExecuteResponse::decode(&any.value[..])
+                        .map(Response::new)
+                        .map_err(|e| Status::internal(e.to_string()))
+                }
+                Some(google::longrunning::operation::Result::Error(status)) => {
+                    Ok(Response::new(ExecuteResponse {
+                        status: Some(status),
+                        ..Default::default()
+                    }))
+                }
+                None => Err(Status::internal(format!(
+                    "Operation {} finished without a result",
+                    operation.name
+                ))),
+            };
+        }
+        Err(Status::unavailable(
+            "Execution stream ended before the action finished",
+        ))
+    }
+
+    async fn batch_read_blobs(
+        &self,
+        request: Request<BatchReadBlobsRequest>,
+    ) -> Result<Response<bazel_tools::BatchReadBlobsResponse>, Status> {
+        let request = request.into_inner();
+        let results = self
+            .read_blobs(&request.instance_name, &request.digests)
+            .await;
+        let responses = request
+            .digests
+            .into_iter()
+            .zip(results)
+            .map(|(digest, result)| {
+                let (data_path, status) = match result {
+                    Ok(path) => (path.to_string_lossy().to_string(), rpc_ok()),
+                    Err(status) => (String::default(), rpc_status(&status)),
+                };
+                bazel_tools::batch_read_blobs_response::Response {
+                    digest: Some(digest),
+                    data_path,
+                    status: Some(status),
+                }
+            })
+            .collect();
+        Ok(Response::new(bazel_tools::BatchReadBlobsResponse {
+            responses,
+        }))
+    }
+}
+
+/// Talks to an UpstreamService, normally the proxy on this machine.
+#[derive(Clone, Debug)]
+pub struct UpstreamClient {
+    client: UpstreamServiceClient<Channel>,
+}
+
+impl UpstreamClient {
+    /// e.g. grpc://127.0.0.1:50052, the connection is made on first use.
+    pub fn connect_lazy(endpoint: &str) -> Result<Self, ProxyError> {
+        Ok(Self {
+            client: UpstreamServiceClient::new(endpoint_from_uri(endpoint)?.connect_lazy()),
+        })
+    }
+
+    pub async fn get_action_result(
+        &self,
+        request: GetActionResultRequest,
+    ) -> Result<ActionResult, Status> {
+        let mut client = self.client.clone();
+        Ok(client.get_action_result(request).await?.into_inner())
+    }
+
+    pub async fn execute(&self, request: ExecuteRequest) -> Result<ExecuteResponse, Status> {
+        let mut client = self.client.clone();
+        Ok(client.execute(request).await?.into_inner())
+    }
+
+    /// The local path of each digest, in the same order as the digests.
+    pub async fn read_blobs(
+        &self,
+        instance_name: &str,
+        digests: &[Digest],
+    ) -> Result<Vec<Result<PathBuf, Status>>, Status> {
+        let mut client = self.client.clone();
+        let responses = client
+            .batch_read_blobs(BatchReadBlobsRequest {
+                instance_name: instance_name.to_string(),
+                digests: digests.to_vec(),
+            })
+            .await?
+            .into_inner()
+            .responses;
+        Ok(results_in_digest_order(
+            digests,
+            responses,
+            |r| r.digest.as_ref(),
+            |r| (r.status, PathBuf::from(r.data_path)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote_cache_proxy::in_memory_cas::{start_cas, start_upstream, InMemoryCas};
+    use crate::remote_cache_proxy::local_cas::digest_of;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn test_batch_read_blobs_to_paths() {
+        let remote = InMemoryCas::default();
+        let small = b"small blob".to_vec();
+        // Big enough that it has to be streamed rather than batched.
+        let large = vec![7u8; MAX_BATCH_BLOB_SIZE as usize + 1];
+        let (small_digest, large_digest) = (digest_of(&small), digest_of(&large));
+        remote.insert(&small_digest.hash, &small);
+        remote.insert(&large_digest.hash, &large);
+        // The remote returning something other than what was asked for is refused.
+        let corrupt_digest = digest_of(b"expected");
+        remote.insert(&corrupt_digest.hash, b"corrupted");
+        let authority = start_cas(remote.clone(), true).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = RemoteCache::default();
+        config
+            .headers
+            .insert(String::from("x-api-key"), String::from("secret"));
+        let proxy = UpstreamProxy::connect_lazy(
+            &format!("grpc://{}", authority),
+            LocalCas::new(dir.path().to_path_buf()),
+            &config,
+        )
+        .unwrap();
+        let client = UpstreamClient::connect_lazy(&start_upstream(proxy).await).unwrap();
+
+        let results = client
+            .read_blobs(
+                "",
+                &[
+                    large_digest.clone(),
+                    corrupt_digest.clone(),
+                    small_digest.clone(),
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(std::fs::read(results[0].as_ref().unwrap()).unwrap(), large);
+        assert_eq!(
+            results[1].as_ref().unwrap_err().code(),
+            Code::InvalidArgument
+        );
+        assert_eq!(std::fs::read(results[2].as_ref().unwrap()).unwrap(), small);
+        assert!(remote
+            .api_keys
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|key| key == "secret"));
+        assert_eq!(remote.api_keys.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_batches_stay_under_the_total_size() {
+        let digest = |size_bytes: i64| Digest {
+            hash: String::from("abc"),
+            size_bytes,
+        };
+        let sizes = |batches: Vec<Vec<(usize, Digest)>>| -> Vec<Vec<(usize, i64)>> {
+            batches
+                .into_iter()
+                .map(|b| b.into_iter().map(|(i, d)| (i, d.size_bytes)).collect())
+                .collect()
+        };
+        let mb = 1024 * 1024;
+        assert_eq!(
+            sizes(batches(vec![
+                (0, digest(mb)),
+                (2, digest(mb)),
+                (3, digest(mb)),
+                (5, digest(1)),
+                (6, digest(mb)),
+            ])),
+            vec![vec![(0, mb), (2, mb), (3, mb)], vec![(5, 1), (6, mb)]]
+        );
+        assert!(batches(Vec::default()).is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+use crate::source_dependencies::parser_helpers::*;
+use crate::source_dependencies::{Error, Import, ParsedFile, Result, SelectorType};
+
+use nom::branch::alt;
+use nom::bytes::complete::{take_while, take_while1};
+use nom::character::complete::{space0, space1};
+use nom::combinator::recognize;
+use nom::multi::many0;
+use nom::{
+    bytes::complete::tag,
+    combinator::{map, opt},
+    sequence::tuple,
+    IResult,
+};
+
+fn is_valid_import_segment_item(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+enum ImportSuffix<'a> {
+    Wildcard,
+    Alias(&'a str),
+    Plain,
+}
+
+pub fn parse_import(line_number: u32, input: &str) -> IResult<&str, Import> {
+    let (input, _) = tuple((space0, tag("import"), space1))(input)?;
+
+    let (input, extracted) = recognize(tuple((
+        take_while1(is_valid_import_segment_item),
+        many0(tuple((tag("."), take_while1(is_valid_import_segment_item)))),
+    )))(input)?;
+
+    let (input, suffix) = alt((
+        map(tag(".*"), |_| ImportSuffix::Wildcard),
+        map(
+            tuple((
+                space1,
+                tag("as"),
+                space1,
+                take_while1(is_valid_import_segment_item),
+            )),
+            |r| ImportSuffix::Alias(r.3),
+        ),
+        map(space0, |_| ImportSuffix::Plain),
+    ))(input)?;
+
+    let (input, _) = tuple((space0, opt(tag(";"))))(input)?;
+
+    let import = match (suffix, extracted.rsplit_once('.')) {
+        (ImportSuffix::Wildcard, _) => Import {
+            line_number,
+            prefix_section: extracted.to_string(),
+            suffix: SelectorType::WildcardSelector,
+        },
+        (ImportSuffix::Alias(alias), Some((prefix, name))) => Import {
+            line_number,
+            prefix_section: prefix.to_string(),
+            suffix: SelectorType::SelectorList(vec![(name.to_string(), Some(alias.to_string()))]),
+        },
+        _ => Import {
+            line_number,
+            prefix_section: extracted.to_string(),
+            suffix: SelectorType::NoSelector,
+        },
+    };
+
+    Ok((input, import))
+}
+
+// END UTILITIES FOR IMPORT PARSING
+
+// START UTILITIES FOR PACAKGE PARSING
+fn extract_package_from_line(ln: &str) -> Result<&str> {
+    let (remaining, res) = map(
+        nom::combinator::complete(tuple((
+            space0,
+            tag("package"),
+            space1,
+            take_while1(|chr: char| chr.is_alphanumeric() || chr == '.' || chr == '_'),
+            space0,
+            opt(tag(";")),
+            space0,
+            opt(tuple((
+                alt((
+                    parser_to_unit(tag("//")),
+                    parser_to_unit(tuple((tag("/"), space0, tag("*")))),
+                )),
+                take_while(not_end_of_line),
+            ))),
+        ))),
+        |tup| tup.3,
+    )(ln)?;
+    if !remaining.is_empty() {
+        return Err(Error::UnexpectedRemainingData(remaining.to_string()));
+    }
+    Ok(res)
+}
+
+fn extract_package_from_file(file_lines: &str) -> Result<Option<&str>> {
+    for ln in file_lines.lines() {
+        if ln.contains("package") {
+            if let Ok(pkg) = extract_package_from_line(ln) {
+                return Ok(Some(pkg));
+            }
+        }
+    }
+    Ok(None)
+}
+// END UTILITIES FOR PACAKGE PARSING
+
+// PUBLIC METHODS
+pub fn parse_imports(input: &str) -> Result<Vec<Import>> {
+    let mut results_vec = Vec::new();
+    let mut line_number = 1;
+    let mut remaining_input = input;
+    while remaining_input.len() > 3 {
+        match eat_till_end_of_line(remaining_input) {
+            Ok((r, (current_line, end_of_line_eaten))) => {
+                if !current_line.is_empty() && current_line.contains("import") {
+                    if let Ok((_, found)) = parse_import(line_number, current_line) {
+                        results_vec.push(found)
+                    };
+                }
+
+                // if we never found an end of line, must be end of file.
+                if !end_of_line_eaten.is_empty() {
+                    remaining_input = r;
+                } else {
+                    remaining_input = "";
+                }
+            }
+            Err(_) => {
+                remaining_input = "";
+            }
+        }
+        line_number += 1;
+    }
+
+    Ok(results_vec)
+}
+
+pub fn parse_file(input: &str) -> Result<ParsedFile> {
+    let package = extract_package_from_file(input)?;
+
+    let imports = parse_imports(input)?;
+
+    Ok(ParsedFile {
+        package_name: package.map(|e| e.to_string()),
+        imports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_header_line() {
+        assert_eq!(
+            extract_package_from_file(
+                "
+            @file:JvmName(\"Foo\")
+            asdf
+            package foo.bar.baz
+            asdf
+            asdf"
+            )
+            .unwrap(),
+            Some("foo.bar.baz")
+        );
+    }
+
+    #[test]
+    fn parse_header_line_with_comments() {
+        assert_eq!(
+            extract_package_from_file(
+                "
+            package foo.bar.baz // have end of line comments here
+            asdf"
+            )
+            .unwrap(),
+            Some("foo.bar.baz")
+        );
+
+        assert_eq!(
+            extract_package_from_file(
+                "
+            package foo.bar.baz;
+            asdf"
+            )
+            .unwrap(),
+            Some("foo.bar.baz")
+        );
+
+        assert_eq!(
+            extract_package_from_file(
+                "
+            asdf
+            package foo.bar.baz i am totally invalid and not a package line
+            asdf"
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_multiple_lines_input() {
+        let sample_input = "
+        import com.twitter.scalding.RichDate
+        import com.twitter.scalding.RichDate;
+
+        import com.twitter.scalding.RichDate
+        ";
+        let expected_results = vec![
+            Import {
+                line_number: 2,
+                prefix_section: "com.twitter.scalding.RichDate".to_string(),
+                suffix: SelectorType::NoSelector,
+            },
+            Import {
+                line_number: 3,
+                prefix_section: "com.twitter.scalding.RichDate".to_string(),
+                suffix: SelectorType::NoSelector,
+            },
+            Import {
+                line_number: 5,
+                prefix_section: "com.twitter.scalding.RichDate".to_string(),
+                suffix: SelectorType::NoSelector,
+            },
+        ];
+
+        let parsed_result = parse_imports(sample_input).unwrap();
+        assert_eq!(parsed_result, expected_results);
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let sample_input = "import com.twitter.scalding.*";
+        let expected_results = vec![Import {
+            line_number: 1,
+            prefix_section: "com.twitter.scalding".to_string(),
+            suffix: SelectorType::WildcardSelector,
+        }];
+
+        let parsed_result = parse_imports(sample_input).unwrap();
+        assert_eq!(parsed_result, expected_results);
+    }
+
+    #[test]
+    fn test_alias() {
+        let sample_input = "import com.twitter.scalding.RichDate as MyDate";
+        let expected_results = vec![Import {
+            line_number: 1,
+            prefix_section: "com.twitter.scalding".to_string(),
+            suffix: SelectorType::SelectorList(vec![(
+                "RichDate".to_string(),
+                Some("MyDate".to_string()),
+            )]),
+        }];
+
+        let parsed_result = parse_imports(sample_input).unwrap();
+        assert_eq!(parsed_result, expected_results);
+    }
+}
@@ -30,5 +30,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tonic_build::configure().compile(&["proto/blaze_query/build.proto"], &["proto"])?;
 
+    tonic_build::configure().compile(&["proto/blaze_deps/deps.proto"], &["proto"])?;
+
     Ok(())
 }
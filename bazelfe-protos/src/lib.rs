@@ -61,6 +61,10 @@ pub mod blaze_query {
     tonic::include_proto!("blaze_query");
 }
 
+pub mod blaze_deps {
+    tonic::include_proto!("blaze_deps");
+}
+
 pub mod devtools {
     pub mod buildozer {
         tonic::include_proto!("devtools.buildozer");